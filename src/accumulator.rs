@@ -0,0 +1,99 @@
+use settings::Accumulation;
+
+//Sums up the samples of one pixel channel. With many thousand samples per pixel a plain f32
+//sum stops growing once the samples are small compared to the sum, so the precision can be
+//selected in the output settings.
+pub struct Accumulator {
+    mode: Accumulation,
+    sum: f32,
+    //Running rounding error of the compensated sum
+    compensation: f32,
+    sum64: f64,
+}
+
+impl Accumulator {
+    pub fn new(mode: Accumulation) -> Accumulator {
+        Accumulator {
+            mode,
+            sum: 0.0,
+            compensation: 0.0,
+            sum64: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.sum = 0.0;
+        self.compensation = 0.0;
+        self.sum64 = 0.0;
+    }
+
+    pub fn add(&mut self, value: f32) {
+        match self.mode {
+            Accumulation::F32 => self.sum += value,
+            Accumulation::Compensated => {
+                //Kahan-Babuska summation, also correct if the value is larger than the sum
+                let t = self.sum + value;
+                if self.sum.abs() >= value.abs() {
+                    self.compensation += (self.sum - t) + value;
+                } else {
+                    self.compensation += (value - t) + self.sum;
+                }
+                self.sum = t;
+            }
+            Accumulation::F64 => self.sum64 += value as f64,
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        match self.mode {
+            Accumulation::F32 => self.sum,
+            Accumulation::Compensated => self.sum + self.compensation,
+            Accumulation::F64 => self.sum64 as f32,
+        }
+    }
+}
+
+//Running mean and variance of the samples of one pixel (Welford's algorithm), used to find
+//noisy pixels
+pub struct Variance {
+    count: u32,
+    mean: f64,
+    //Sum of the squared differences from the mean
+    m2: f64,
+}
+
+impl Variance {
+    pub fn new() -> Variance {
+        Variance {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.mean = 0.0;
+        self.m2 = 0.0;
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    //Standard deviation of the mean of the samples so far
+    pub fn standard_error(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let n = self.count as f64;
+        (self.m2 / (n - 1.0) / n).sqrt()
+    }
+}
@@ -0,0 +1,126 @@
+use random::Random;
+use settings::Color;
+
+//Changes of the color and intensity of a light over the frames of an animation, for candles, fire
+//and neon signs. Between the keys the values are interpolated linearly, before the first and after
+//the last key they stay the same. Without keys the values of the light are used.
+pub struct LightAnimation {
+    //Values of the light itself, used where there are no keys
+    pub color: Color,
+    pub intensity: f64,
+    //(frame, intensity), sorted by frame. In the same units as the intensity of the light.
+    pub intensity_keys: Vec<(f64, f64)>,
+    //(frame, color), sorted by frame
+    pub color_keys: Vec<(f64, Color)>,
+    //(frame, temperature in kelvin), sorted by frame. Tints the color with the color of a black body.
+    pub temperature_keys: Vec<(f64, f64)>,
+    pub flicker: Option<Flicker>,
+}
+
+//Random changes of the intensity from frame to frame. The noise is smooth over time, so higher
+//speeds flicker faster, and the same for every run.
+pub struct Flicker {
+    //Largest change of the intensity, relative to the unflickered intensity
+    pub amount: f64,
+    //Number of random changes per frame
+    pub speed: f64,
+    pub seed: u64,
+}
+
+impl LightAnimation {
+    //Color and intensity of the light at the frame
+    pub fn at(&self, frame: f64) -> (Color, f64) {
+        let mut intensity = interpolate(&self.intensity_keys, frame, |a, b, t| a + (b - a) * t)
+            .unwrap_or(self.intensity);
+        let mut color = interpolate(&self.color_keys, frame, |a, b, t| {
+            let t = t as f32;
+            Color::new(
+                a.r + (b.r - a.r) * t,
+                a.g + (b.g - a.g) * t,
+                a.b + (b.b - a.b) * t,
+            )
+        })
+        .unwrap_or_else(|| self.color.clone());
+
+        if let Some(kelvin) = interpolate(&self.temperature_keys, frame, |a, b, t| a + (b - a) * t)
+        {
+            let tint = black_body(kelvin);
+            color = Color::new(color.r * tint.r, color.g * tint.g, color.b * tint.b);
+        }
+        if let Some(flicker) = &self.flicker {
+            intensity *= flicker.at(frame);
+        }
+
+        (color, intensity)
+    }
+}
+
+impl Flicker {
+    //Factor for the intensity at the frame, between 1 - amount and 1 + amount but never negative
+    pub fn at(&self, frame: f64) -> f64 {
+        //Two octaves, so the slow changes get some faster jitter on top
+        let t = frame * self.speed;
+        let noise = value_noise(self.seed, t) * 0.7 + value_noise(self.seed ^ 1, t * 2.3) * 0.3;
+        (1.0 + noise * self.amount).max(0.0)
+    }
+}
+
+//Value at the frame of keys sorted by frame, None if there are no keys
+fn interpolate<T, F>(keys: &[(f64, T)], frame: f64, lerp: F) -> Option<T>
+where
+    T: Clone,
+    F: Fn(&T, &T, f64) -> T,
+{
+    match keys.iter().position(|k| k.0 > frame) {
+        None => keys.last().map(|k| k.1.clone()),
+        Some(0) => Some(keys[0].1.clone()),
+        Some(i) => {
+            let (f0, v0) = &keys[i - 1];
+            let (f1, v1) = &keys[i];
+            Some(lerp(v0, v1, (frame - f0) / (f1 - f0)))
+        }
+    }
+}
+
+//Smooth noise between -1 and 1, with random values at the integers interpolated in between
+fn value_noise(seed: u64, t: f64) -> f64 {
+    let i = t.floor();
+    let f = t - i;
+    let knot = |i: f64| {
+        let mut random =
+            Random::with_seed(seed ^ (i as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        random.random_f() * 2.0 - 1.0
+    };
+    let s = f * f * (3.0 - 2.0 * f);
+    knot(i) + (knot(i + 1.0) - knot(i)) * s
+}
+
+//Linear color of a black body at the temperature in kelvin, scaled so the brightest channel is 1.
+//Fit by Tanner Helland to the CIE data, good from 1000 to 40000 kelvin.
+fn black_body(kelvin: f64) -> Color {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2)
+    };
+    let g = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+
+    //The fit is for gamma corrected values
+    let linear = |v: f64| (v.clamp(0.0, 255.0) / 255.0).powf(2.2);
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let max = r.max(g).max(b);
+    Color::new((r / max) as f32, (g / max) as f32, (b / max) as f32)
+}
@@ -0,0 +1,56 @@
+use json::JsonValue;
+use std::collections::HashMap;
+use validate;
+
+//Turns an asset path with a custom scheme, like "asset://props/chair.obj", into the name of a
+//local file with its content. Asset management systems fetch or locate the file here. An error
+//is a message that is reported as a problem with the scene.
+pub type AssetResolver<'a> = Fn(&str) -> std::result::Result<String, String> + Sync + 'a;
+
+//True for paths that start with a scheme like "asset://". Drive letters and plain relative paths
+//are local files.
+pub fn is_asset_uri(path: &str) -> bool {
+    match path.find("://") {
+        Some(end) if end > 1 => {
+            let scheme = &path[..end];
+            scheme.chars().next().unwrap().is_ascii_alphabetic()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+//Replaces all asset paths among the files referenced by the settings with the local files the
+//resolver returns, so everything after this only deals with local files. Every asset is
+//resolved once, even if several objects use it. Assets that can't be resolved keep their path
+//and are returned as problems.
+pub fn resolve_assets(json: &mut JsonValue, resolver: Option<&AssetResolver>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut resolved: HashMap<String, std::result::Result<String, String>> = HashMap::new();
+
+    validate::for_each_file(json, &mut |what, file| {
+        if !is_asset_uri(file) {
+            return;
+        }
+
+        let result = resolved.entry(file.clone()).or_insert_with(|| {
+            let result = match resolver {
+                Some(resolver) => resolver(file),
+                None => Err("no asset resolver is set".to_string()),
+            };
+            if let Ok(local) = &result {
+                eprintln!("Resolved asset '{}' to '{}'", file, local);
+            }
+            result
+        });
+
+        match result {
+            Ok(local) => *file = local.clone(),
+            Err(e) => problems.push(format!("{}: can't resolve {}: {}", what, file, e)),
+        }
+    });
+
+    problems
+}
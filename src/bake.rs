@@ -0,0 +1,304 @@
+use linear::Vector4F;
+use linear::Vertex4F;
+use num_cpus;
+use random::Random;
+use render;
+use scratch::Scratch;
+use settings::Bake;
+use settings::Intersectable;
+use settings::Settings;
+use std::thread;
+
+//Number of texels empty texels next to covered ones are filled in, so filtering and mip maps
+//don't pull the background into the seams of the UV layout
+const DILATE_STEPS: u32 = 4;
+
+//Normal and ambient occlusion maps baked by bake(). Both have size x size texels with the
+//bottom row first, which is the order write_tga expects. (0, 0) in texture space is the bottom
+//left corner, like for textures.
+pub struct BakedMaps {
+    pub size: u32,
+    //Tangent space normals, three values per texel mapped from -1...1 to 0...1
+    pub normals: Vec<f32>,
+    pub ao: Vec<f32>,
+}
+
+//Low poly triangle in world space with the tangent frame of its texture coordinates
+struct BakeTriangle {
+    v: [Vertex4F; 3],
+    tangent: Vector4F,
+    bitangent: Vector4F,
+}
+
+//Bakes the detail of the high poly mesh into maps in the texture space of the low poly mesh.
+//For every texel covered by a low poly triangle, a ray is cast from outside of the low poly surface
+//inwards along its normal, and the closest hit on the high poly mesh within twice the bake distance
+//is used. Texels whose ray misses get a flat normal and no occlusion.
+pub fn bake(settings: &Settings) -> BakedMaps {
+    let bake = settings.bake.as_ref().expect("No bake settings");
+    let scene = &settings.scene;
+    let size = bake.size;
+
+    let mut triangles = Vec::new();
+    for mesh in scene.meshes.iter().filter(|m| m.name == bake.low) {
+        for tri in &mesh.triangles {
+            let world = |v: &Vertex4F| {
+                let mut result = v.clone();
+                result.pos = mesh.transform.object_to_world.transform_point(&v.pos);
+                result.normal = mesh.transform.transform_normal(&v.normal);
+                result
+            };
+            let (v1, v2, v3) = tri.corners(&mesh.vertices);
+            triangles.push(bake_triangle(
+                [world(v1), world(v2), world(v3)],
+                bake.uv_set,
+            ));
+        }
+    }
+
+    let objects: Vec<&Intersectable> = scene
+        .meshes
+        .iter()
+        .filter(|m| m.name == bake.high)
+        .map(|m| m as &Intersectable)
+        .collect();
+
+    let texels = rasterize(&triangles, size, bake.uv_set);
+
+    let num_texels = (size * size) as usize;
+    let mut normals = vec![0.0f32; num_texels * 3];
+    let mut ao = vec![0.0f32; num_texels];
+
+    //Rows are split evenly between the threads, every texel has its own random sequence
+    let rows_per_thread = (size as usize).div_ceil(num_cpus::get()).max(1);
+    let chunk = rows_per_thread * size as usize;
+
+    thread::scope(|scope| {
+        let normal_chunks = normals.chunks_mut(chunk * 3);
+        let ao_chunks = ao.chunks_mut(chunk);
+
+        for (i, (normal_chunk, ao_chunk)) in normal_chunks.zip(ao_chunks).enumerate() {
+            let first = i * chunk;
+            let texels = &texels;
+            let triangles = &triangles;
+            let objects = &objects;
+
+            scope.spawn(move || {
+                let mut random = Random::with_seed(settings.output.seed);
+                random.set_sampler(settings.output.sampler);
+                let mut scratch = Scratch::new();
+
+                for (j, texel) in texels[first..(first + ao_chunk.len())].iter().enumerate() {
+                    let index = first + j;
+                    let x = index as u32 % size;
+                    let y = index as u32 / size;
+                    random.reseed(render::pixel_seed(settings.output.seed, x, y, size));
+
+                    let (n, occlusion) = match texel {
+                        Some((t, b1, b2)) => bake_texel(
+                            &triangles[*t],
+                            *b1,
+                            *b2,
+                            bake,
+                            objects,
+                            settings,
+                            &mut random,
+                            &mut scratch,
+                        ),
+                        None => ((0.0, 0.0, 1.0), 1.0),
+                    };
+
+                    normal_chunk[j * 3] = (0.5 + 0.5 * n.0) as f32;
+                    normal_chunk[j * 3 + 1] = (0.5 + 0.5 * n.1) as f32;
+                    normal_chunk[j * 3 + 2] = (0.5 + 0.5 * n.2) as f32;
+                    ao_chunk[j] = occlusion;
+                }
+            });
+        }
+    });
+
+    let mut covered: Vec<bool> = texels.iter().map(|t| t.is_some()).collect();
+    for _ in 0..DILATE_STEPS {
+        dilate(&mut normals, &mut ao, &mut covered, size);
+    }
+
+    BakedMaps { size, normals, ao }
+}
+
+//Tangent space normal of the high poly surface and the ambient occlusion there, for the point
+//on the low poly triangle with the given barycentric coordinates
+fn bake_texel(
+    tri: &BakeTriangle,
+    b1: f64,
+    b2: f64,
+    bake: &Bake,
+    objects: &Vec<&Intersectable>,
+    settings: &Settings,
+    random: &mut Random,
+    scratch: &mut Scratch,
+) -> ((f64, f64, f64), f32) {
+    let b0 = 1.0 - b1 - b2;
+    let v = &tri.v;
+    let interpolate = |a: &Vector4F, b: &Vector4F, c: &Vector4F| {
+        Vector4F::new(
+            a.x * b0 + b.x * b1 + c.x * b2,
+            a.y * b0 + b.y * b1 + c.y * b2,
+            a.z * b0 + b.z * b1 + c.z * b2,
+        )
+    };
+
+    let pos = interpolate(&v[0].pos, &v[1].pos, &v[2].pos);
+    let normal = interpolate(&v[0].normal, &v[1].normal, &v[2].normal).normalize();
+
+    //Gram-Schmidt, the bitangent keeps the handedness of the texture coordinates
+    let tangent =
+        (&tri.tangent - &scaled(&normal, Vector4F::dot(&normal, &tri.tangent))).normalize();
+    let mut bitangent = Vector4F::cross(&normal, &tangent);
+    if Vector4F::dot(&bitangent, &tri.bitangent) < 0.0 {
+        bitangent = bitangent.invert();
+    }
+
+    let org = &pos + &scaled(&normal, bake.distance);
+    let dir = normal.invert();
+
+    match render::intersect(&org, &dir, objects, &[], scratch) {
+        Some(hit) if hit.inter.ray_t <= bake.distance * 2.0 => {
+            let n = &hit.inter.normal;
+            let tangent_normal = (
+                Vector4F::dot(n, &tangent),
+                Vector4F::dot(n, &bitangent),
+                Vector4F::dot(n, &normal),
+            );
+
+            let occlusion = if bake.ao_map.is_empty() {
+                1.0
+            } else {
+                render::ambient_occlusion(
+                    &hit.inter,
+                    objects,
+                    &[],
+                    &settings.output,
+                    random,
+                    scratch,
+                )
+            };
+
+            (tangent_normal, occlusion)
+        }
+        _ => ((0.0, 0.0, 1.0), 1.0),
+    }
+}
+
+//Tangent and bitangent point along the texture u and v directions on the triangle. They are 0
+//if the texture coordinates of the triangle are degenerate.
+fn bake_triangle(v: [Vertex4F; 3], uv_set: u32) -> BakeTriangle {
+    let dp1 = &v[1].pos - &v[0].pos;
+    let dp2 = &v[2].pos - &v[0].pos;
+    let uv: Vec<(f64, f64)> = v.iter().map(|v| v.uv(uv_set)).collect();
+    let (du1, dv1) = (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1);
+    let (du2, dv2) = (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1);
+
+    let det = du1 * dv2 - du2 * dv1;
+    let (tangent, bitangent) = if det.abs() > 1e-12 {
+        let r = 1.0 / det;
+        (
+            scaled(&(&scaled(&dp1, dv2) - &scaled(&dp2, dv1)), r),
+            scaled(&(&scaled(&dp2, du1) - &scaled(&dp1, du2)), r),
+        )
+    } else {
+        (Vector4F::null(), Vector4F::null())
+    };
+
+    BakeTriangle {
+        v,
+        tangent,
+        bitangent,
+    }
+}
+
+//Finds the triangle covering the center of each texel and the barycentric coordinates of the
+//center on it. Where triangles overlap in texture space, the last one wins.
+fn rasterize(triangles: &[BakeTriangle], size: u32, uv_set: u32) -> Vec<Option<(usize, f64, f64)>> {
+    let mut texels = vec![None; (size * size) as usize];
+    let s = size as f64;
+
+    for (t, tri) in triangles.iter().enumerate() {
+        let uv: Vec<(f64, f64)> = tri
+            .v
+            .iter()
+            .map(|v| v.uv(uv_set))
+            .map(|(u, v)| (u * s, v * s))
+            .collect();
+
+        let det =
+            (uv[1].0 - uv[0].0) * (uv[2].1 - uv[0].1) - (uv[2].0 - uv[0].0) * (uv[1].1 - uv[0].1);
+        if det.abs() < 1e-12 {
+            continue;
+        }
+
+        let min_x = uv.iter().map(|p| p.0).fold(s, f64::min).floor().max(0.0) as u32;
+        let max_x = uv.iter().map(|p| p.0).fold(0.0, f64::max).ceil().min(s) as u32;
+        let min_y = uv.iter().map(|p| p.1).fold(s, f64::min).floor().max(0.0) as u32;
+        let max_y = uv.iter().map(|p| p.1).fold(0.0, f64::max).ceil().min(s) as u32;
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f64 + 0.5 - uv[0].0, y as f64 + 0.5 - uv[0].1);
+                let b1 = (px * (uv[2].1 - uv[0].1) - py * (uv[2].0 - uv[0].0)) / det;
+                let b2 = (py * (uv[1].0 - uv[0].0) - px * (uv[1].1 - uv[0].1)) / det;
+
+                if b1 >= 0.0 && b2 >= 0.0 && b1 + b2 <= 1.0 {
+                    texels[(y * size + x) as usize] = Some((t, b1, b2));
+                }
+            }
+        }
+    }
+
+    texels
+}
+
+//Fills every empty texel next to covered ones with the average of those, one texel wide
+fn dilate(normals: &mut [f32], ao: &mut [f32], covered: &mut [bool], size: u32) {
+    let before = covered.to_vec();
+    let size = size as i64;
+
+    for y in 0..size {
+        for x in 0..size {
+            let index = (y * size + x) as usize;
+            if before[index] {
+                continue;
+            }
+
+            let mut sum = [0.0f32; 4];
+            let mut count = 0;
+            for (dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= size || ny >= size {
+                    continue;
+                }
+
+                let n = (ny * size + nx) as usize;
+                if before[n] {
+                    sum[0] += normals[n * 3];
+                    sum[1] += normals[n * 3 + 1];
+                    sum[2] += normals[n * 3 + 2];
+                    sum[3] += ao[n];
+                    count += 1;
+                }
+            }
+
+            if count > 0 {
+                let c = count as f32;
+                normals[index * 3] = sum[0] / c;
+                normals[index * 3 + 1] = sum[1] / c;
+                normals[index * 3 + 2] = sum[2] / c;
+                ao[index] = sum[3] / c;
+                covered[index] = true;
+            }
+        }
+    }
+}
+
+fn scaled(v: &Vector4F, s: f64) -> Vector4F {
+    Vector4F::new(v.x * s, v.y * s, v.z * s)
+}
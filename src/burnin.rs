@@ -0,0 +1,131 @@
+use settings::Corner;
+
+//Width and height of a glyph in font pixels
+const GLYPH_W: u32 = 5;
+const GLYPH_H: u32 = 7;
+
+//Stamps lines of text on a dark strip into a corner of an image. The pixels are in the BGR order
+//written by write_tga, with the bottom row first. Text is drawn with a built-in 5x7 bitmap font
+//that is scaled up for larger images. Letters are always upper case, characters the font does
+//not have are drawn as '?'. Text that does not fit into the image is cut off.
+pub fn stamp(pixels: &mut [u8], width: u32, height: u32, corner: &Corner, lines: &[String]) {
+    let scale = (height / 540).max(1);
+    let advance = (GLYPH_W + 1) * scale;
+    let line_height = (GLYPH_H + 2) * scale;
+    let padding = 2 * scale;
+
+    let max_chars = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as u32;
+    if max_chars == 0 {
+        return;
+    }
+
+    let strip_w = (max_chars * advance - scale + padding * 2).min(width);
+    let strip_h = (lines.len() as u32 * line_height - 2 * scale + padding * 2).min(height);
+
+    //Position of the strip, measured from the top left
+    let (left, top) = match corner {
+        Corner::TopLeft => (0, 0),
+        Corner::TopRight => (width - strip_w, 0),
+        Corner::BottomLeft => (0, height - strip_h),
+        Corner::BottomRight => (width - strip_w, height - strip_h),
+    };
+
+    for y in top..(top + strip_h) {
+        for x in left..(left + strip_w) {
+            let i = pixel_index(width, height, x, y);
+            for c in &mut pixels[i..(i + 3)] {
+                *c /= 4;
+            }
+        }
+    }
+
+    for (l, line) in lines.iter().enumerate() {
+        let line_top = top + padding + l as u32 * line_height;
+
+        for (c, ch) in line.chars().enumerate() {
+            let glyph = glyph(ch);
+            let char_left = left + padding + c as u32 * advance;
+
+            for gy in 0..(GLYPH_H * scale) {
+                let row = glyph[(gy / scale) as usize];
+                for gx in 0..(GLYPH_W * scale) {
+                    if row & (0x10 >> (gx / scale)) == 0 {
+                        continue;
+                    }
+
+                    let x = char_left + gx;
+                    let y = line_top + gy;
+                    if x >= left + strip_w || y >= top + strip_h {
+                        continue;
+                    }
+
+                    let i = pixel_index(width, height, x, y);
+                    for c in &mut pixels[i..(i + 3)] {
+                        *c = 255;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pixel_index(width: u32, height: u32, x: u32, y: u32) -> usize {
+    (((height - 1 - y) * width + x) * 3) as usize
+}
+
+//Rows of the glyph from top to bottom, the highest of the 5 bits is the leftmost pixel
+fn glyph(ch: char) -> [u8; 7] {
+    match ch.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '%' => [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03],
+        '|' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
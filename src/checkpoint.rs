@@ -0,0 +1,291 @@
+use framebuffer::Framebuffer;
+use settings::Settings;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+
+const MAGIC: &[u8; 8] = b"XTRCKPT1";
+
+//State of a progressive render after a number of finished passes, enough to continue it later.
+//The random numbers of a pass only depend on the seed and the number of the pass, so the number
+//of passes is all that is needed of them.
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    //The render the checkpoint belongs to: the settings hash and the values command line flags
+    //can change without changing the hash
+    pub hash: u64,
+    pub seed: u64,
+    pub samples: u32,
+    pub passes: u32,
+    //Sums of the radiance of all finished passes, in the order of the framebuffer
+    pub sums: Vec<f64>,
+    //Sums of the AOV values, one buffer per AOV like in the framebuffer
+    pub aov_sums: Vec<Vec<f64>>,
+}
+
+impl Checkpoint {
+    //Checkpoint of a render without finished passes
+    pub fn new(settings: &Settings) -> Checkpoint {
+        let output = &settings.output;
+        let num_pixels = (output.width * output.height) as usize;
+
+        Checkpoint {
+            width: output.width,
+            height: output.height,
+            hash: settings.hash,
+            seed: output.frame_seed(),
+            samples: output.samples,
+            passes: 0,
+            sums: vec![0.0; num_pixels * 3],
+            aov_sums: output
+                .aovs
+                .iter()
+                .map(|aov| vec![0.0; num_pixels * aov.channels()])
+                .collect(),
+        }
+    }
+
+    //Adds a finished pass
+    pub fn add(&mut self, framebuffer: &Framebuffer) {
+        for (i, sum) in self.sums.iter_mut().enumerate() {
+            *sum += framebuffer.pixels.get(i) as f64;
+        }
+        for (aov_sum, aov) in self.aov_sums.iter_mut().zip(&framebuffer.aovs) {
+            for (sum, v) in aov_sum.iter_mut().zip(aov) {
+                *sum += *v as f64;
+            }
+        }
+        self.passes += 1;
+    }
+
+    //Average of the finished passes
+    pub fn average(&self, settings: &Settings) -> Framebuffer {
+        let output = &settings.output;
+        let passes = self.passes.max(1) as f64;
+
+        let mut result =
+            Framebuffer::new(self.width, self.height, &output.aovs, &output.pixel_format);
+
+        //The sums are in f64, so half floats are only rounded once for the average
+        let line_values = (self.width * 3) as usize;
+        for (y, line) in self.sums.chunks(line_values).enumerate() {
+            let values: Vec<f32> = line.iter().map(|v| (v / passes) as f32).collect();
+            result.pixels.add_line(y * line_values, &values);
+        }
+        for (aov, aov_sum) in result.aovs.iter_mut().zip(&self.aov_sums) {
+            for (v, sum) in aov.iter_mut().zip(aov_sum) {
+                *v = (sum / passes) as f32;
+            }
+        }
+
+        result
+    }
+
+    //Returns why the checkpoint can't be used to continue rendering the given settings
+    pub fn check(&self, settings: &Settings) -> Result<(), String> {
+        let output = &settings.output;
+        if self.hash != settings.hash {
+            return Err("the scene has changed since the checkpoint was written".to_string());
+        }
+        if self.width != output.width || self.height != output.height {
+            return Err(format!(
+                "the checkpoint is {}x{}, but the output is {}x{}",
+                self.width, self.height, output.width, output.height
+            ));
+        }
+        if self.seed != output.frame_seed() || self.samples != output.samples {
+            return Err("the seed, frame or samples are different".to_string());
+        }
+        let channels: Vec<usize> = output.aovs.iter().map(|aov| aov.channels()).collect();
+        let num_pixels = (self.width * self.height) as usize;
+        if self.aov_sums.len() != channels.len()
+            || self
+                .aov_sums
+                .iter()
+                .zip(&channels)
+                .any(|(sum, c)| sum.len() != num_pixels * c)
+        {
+            return Err("the AOVs are different".to_string());
+        }
+        Ok(())
+    }
+
+    //Writes the checkpoint to a temporary file first and renames it, so a crash while writing
+    //keeps the previous checkpoint
+    pub fn write(&self, filename: &str) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(64 + self.sums.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.hash.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.samples.to_le_bytes());
+        bytes.extend_from_slice(&self.passes.to_le_bytes());
+        bytes.extend_from_slice(&(self.aov_sums.len() as u32).to_le_bytes());
+        for aov_sum in &self.aov_sums {
+            bytes.extend_from_slice(&(aov_sum.len() as u64).to_le_bytes());
+        }
+        for v in self.sums.iter().chain(self.aov_sums.iter().flatten()) {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let temp = format!("{}.tmp", filename);
+        let written = File::create(&temp)
+            .and_then(|mut file| file.write_all(&bytes).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temp, filename));
+        written.map_err(|e| format!("Cannot write checkpoint {}: {}", filename, e))
+    }
+
+    pub fn read(filename: &str) -> Result<Checkpoint, String> {
+        let mut bytes = Vec::new();
+        File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Cannot read checkpoint {}: {}", filename, e))?;
+
+        let broken = || format!("Checkpoint {} is broken", filename);
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(format!("{} is not a checkpoint", filename));
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes,
+            pos: MAGIC.len(),
+        };
+        let width = reader.u32().ok_or_else(broken)?;
+        let height = reader.u32().ok_or_else(broken)?;
+        let hash = reader.u64().ok_or_else(broken)?;
+        let seed = reader.u64().ok_or_else(broken)?;
+        let samples = reader.u32().ok_or_else(broken)?;
+        let passes = reader.u32().ok_or_else(broken)?;
+        let num_aovs = reader.u32().ok_or_else(broken)?;
+
+        let mut aov_lengths = Vec::new();
+        for _ in 0..num_aovs {
+            aov_lengths.push(reader.u64().ok_or_else(broken)? as usize);
+        }
+
+        //The lengths are checked against the file size before allocating anything
+        let num_sums = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|n| n.checked_mul(3))
+            .ok_or_else(broken)?;
+        let num_values = aov_lengths
+            .iter()
+            .try_fold(num_sums, |sum, len| sum.checked_add(*len));
+        if num_values.and_then(|n| n.checked_mul(8)) != Some(reader.remaining()) {
+            return Err(broken());
+        }
+
+        let sums = reader.f64s(num_sums);
+        let aov_sums = aov_lengths.iter().map(|len| reader.f64s(*len)).collect();
+
+        Ok(Checkpoint {
+            width,
+            height,
+            hash,
+            seed,
+            samples,
+            passes,
+            sums,
+            aov_sums,
+        })
+    }
+}
+
+//Reads little endian values from a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.remaining() < count {
+            return None;
+        }
+        let result = &self.bytes[self.pos..(self.pos + count)];
+        self.pos += count;
+        Some(result)
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(buf))
+    }
+
+    //Only called after the size was checked
+    fn f64s(&mut self, count: usize) -> Vec<f64> {
+        (0..count)
+            .map(|_| f64::from_bits(self.u64().unwrap()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn test_checkpoint() -> Checkpoint {
+        Checkpoint {
+            width: 3,
+            height: 2,
+            hash: 0x0123_4567_89ab_cdef,
+            seed: 42,
+            samples: 4,
+            passes: 7,
+            sums: (0..18).map(|i| i as f64 * 0.1 - 0.5).collect(),
+            aov_sums: vec![vec![1.5; 6], (0..18).map(|i| i as f64).collect()],
+        }
+    }
+
+    fn temp_file(name: &str) -> String {
+        let path = env::temp_dir().join(format!("xtracer_{}_{}.ckpt", name, process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn written_checkpoint_reads_back_the_same() {
+        let filename = temp_file("round_trip");
+        let written = test_checkpoint();
+        written.write(&filename).unwrap();
+        let read = Checkpoint::read(&filename).unwrap();
+        fs::remove_file(&filename).ok();
+
+        assert_eq!(read.width, written.width);
+        assert_eq!(read.height, written.height);
+        assert_eq!(read.hash, written.hash);
+        assert_eq!(read.seed, written.seed);
+        assert_eq!(read.samples, written.samples);
+        assert_eq!(read.passes, written.passes);
+        assert_eq!(read.sums, written.sums);
+        assert_eq!(read.aov_sums, written.aov_sums);
+    }
+
+    #[test]
+    fn truncated_checkpoint_is_broken() {
+        let filename = temp_file("truncated");
+        test_checkpoint().write(&filename).unwrap();
+        let mut bytes = fs::read(&filename).unwrap();
+        bytes.truncate(bytes.len() - 8);
+        fs::write(&filename, &bytes).unwrap();
+        let read = Checkpoint::read(&filename);
+        fs::remove_file(&filename).ok();
+
+        assert!(read.is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use linear::Vector4F;
+use linear::Vertex4F;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::mem;
+
+//Weight of the planes that keep open borders in place, relative to the surface planes
+const BORDER_WEIGHT: f64 = 1000.0;
+
+//Reduces the triangles, three vertices per triangle as loaded from OBJ files, to at most
+//max_triangles with quadric error metric edge collapses (Garland & Heckbert). Corners at exactly
+//the same position are welded to find the edges. Only the positions move, normals, texture
+//coordinates and colors of the corners are kept. Collapses that would flip a triangle are
+//skipped, so the result can have more triangles than requested.
+pub fn decimate(vertices: Vec<Vertex4F>, max_triangles: usize) -> Vec<Vertex4F> {
+    let num_tris = vertices.len() / 3;
+    if num_tris <= max_triangles {
+        return vertices;
+    }
+
+    let mut positions = Vec::new();
+    let mut index_of = HashMap::new();
+    let mut tris = Vec::with_capacity(num_tris);
+    for t in 0..num_tris {
+        let mut tri = [0; 3];
+        for c in 0..3 {
+            let p = &vertices[t * 3 + c].pos;
+            let key = (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+            let next = positions.len();
+            tri[c] = *index_of.entry(key).or_insert(next);
+            if tri[c] == next {
+                positions.push(p.clone());
+            }
+        }
+        tris.push(tri);
+    }
+
+    let mut removed = vec![false; num_tris];
+    let mut adjacent = vec![Vec::new(); positions.len()];
+    let mut quadrics = vec![[0.0; 10]; positions.len()];
+    //Number of triangles using each edge and one of them, to find the borders
+    let mut edges = HashMap::new();
+    let mut live = num_tris;
+
+    for (t, tri) in tris.iter().enumerate() {
+        if tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] {
+            removed[t] = true;
+            live -= 1;
+            continue;
+        }
+
+        for c in 0..3 {
+            adjacent[tri[c]].push(t);
+            let edge = edge_key(tri[c], tri[(c + 1) % 3]);
+            edges.entry(edge).or_insert((0, t)).0 += 1;
+        }
+
+        let n = face_normal(&positions[tri[0]], &positions[tri[1]], &positions[tri[2]]);
+        if n.len() > 0.0 {
+            let plane = plane_quadric(&n.normalize(), &positions[tri[0]], 1.0);
+            for v in tri {
+                add_quadric(&mut quadrics[*v], &plane);
+            }
+        }
+    }
+
+    //Borders get a plane through the edge, perpendicular to the triangle, so they don't shrink
+    for (&(a, b), &(count, t)) in &edges {
+        if count != 1 {
+            continue;
+        }
+
+        let tri = &tris[t];
+        let n = face_normal(&positions[tri[0]], &positions[tri[1]], &positions[tri[2]]);
+        let edge = &positions[b] - &positions[a];
+        let border = Vector4F::cross(&edge, &n).normalize();
+        if border.len() > 0.0 {
+            let plane = plane_quadric(&border, &positions[a], BORDER_WEIGHT);
+            add_quadric(&mut quadrics[a], &plane);
+            add_quadric(&mut quadrics[b], &plane);
+        }
+    }
+
+    //Collapses in the heap are outdated when one of their vertices changed since
+    let mut stamps = vec![0; positions.len()];
+    let mut dead = vec![false; positions.len()];
+    let mut heap = BinaryHeap::new();
+    for &(a, b) in edges.keys() {
+        heap.push(collapse(a, b, &positions, &quadrics, &stamps));
+    }
+
+    while live > max_triangles {
+        let c = match heap.pop() {
+            Some(c) => c,
+            None => break,
+        };
+
+        if dead[c.a] || dead[c.b] || stamps[c.a] != c.stamp_a || stamps[c.b] != c.stamp_b {
+            continue;
+        }
+
+        let target = Vector4F::new(c.target.0, c.target.1, c.target.2);
+        if flips(
+            &positions,
+            &tris,
+            &removed,
+            &adjacent[c.a],
+            c.a,
+            c.b,
+            &target,
+        ) || flips(
+            &positions,
+            &tris,
+            &removed,
+            &adjacent[c.b],
+            c.b,
+            c.a,
+            &target,
+        ) {
+            continue;
+        }
+
+        //Move a to the target and let the triangles of b use a instead
+        positions[c.a] = target;
+        let q = quadrics[c.b];
+        add_quadric(&mut quadrics[c.a], &q);
+
+        for t in mem::take(&mut adjacent[c.b]) {
+            if removed[t] {
+                continue;
+            }
+
+            if tris[t].contains(&c.a) {
+                removed[t] = true;
+                live -= 1;
+            } else {
+                for v in tris[t].iter_mut() {
+                    if *v == c.b {
+                        *v = c.a;
+                    }
+                }
+                adjacent[c.a].push(t);
+            }
+        }
+
+        adjacent[c.a].retain(|t| !removed[*t]);
+        dead[c.b] = true;
+        stamps[c.a] += 1;
+
+        let mut neighbors = Vec::new();
+        for t in &adjacent[c.a] {
+            for v in &tris[*t] {
+                if *v != c.a && !neighbors.contains(v) {
+                    neighbors.push(*v);
+                }
+            }
+        }
+        for v in neighbors {
+            heap.push(collapse(c.a, v, &positions, &quadrics, &stamps));
+        }
+    }
+
+    let mut result = Vec::with_capacity(live * 3);
+    for (t, tri) in tris.iter().enumerate() {
+        if removed[t] {
+            continue;
+        }
+
+        for c in 0..3 {
+            let mut vertex = vertices[t * 3 + c].clone();
+            vertex.pos = positions[tri[c]].clone();
+            result.push(vertex);
+        }
+    }
+
+    result
+}
+
+//Collapse of the edge between a and b into a single vertex at the target position
+struct Collapse {
+    cost: f64,
+    a: usize,
+    b: usize,
+    stamp_a: u32,
+    stamp_b: u32,
+    target: (f64, f64, f64),
+}
+
+//Ordered by cost, reversed so the BinaryHeap returns the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+//Finds the position with the smallest error for the combined quadric of both vertices. The
+//solution of the quadric is only used if it is close to the edge, as it is unstable on flat areas.
+//Otherwise the best of both ends and the middle is used.
+fn collapse(
+    a: usize,
+    b: usize,
+    positions: &[Vector4F],
+    quadrics: &[[f64; 10]],
+    stamps: &[u32],
+) -> Collapse {
+    let mut q = quadrics[a];
+    add_quadric(&mut q, &quadrics[b]);
+
+    let pa = &positions[a];
+    let pb = &positions[b];
+    let mid = Vector4F::new(
+        (pa.x + pb.x) * 0.5,
+        (pa.y + pb.y) * 0.5,
+        (pa.z + pb.z) * 0.5,
+    );
+
+    let mut candidates = vec![pa.clone(), pb.clone()];
+    if let Some(p) = solve(&q) {
+        if (&p - &mid).len() <= (pb - pa).len() {
+            candidates.push(p);
+        }
+    }
+
+    let mut target = mid;
+    for p in candidates {
+        if quadric_error(&q, &p) < quadric_error(&q, &target) {
+            target = p;
+        }
+    }
+
+    Collapse {
+        cost: quadric_error(&q, &target),
+        a,
+        b,
+        stamp_a: stamps[a],
+        stamp_b: stamps[b],
+        target: (target.x, target.y, target.z),
+    }
+}
+
+//True if moving vertex v to the target turns one of its triangles around. Triangles that also
+//use "other" are removed by the collapse and not checked.
+fn flips(
+    positions: &[Vector4F],
+    tris: &[[usize; 3]],
+    removed: &[bool],
+    adjacent: &[usize],
+    v: usize,
+    other: usize,
+    target: &Vector4F,
+) -> bool {
+    for t in adjacent {
+        let tri = &tris[*t];
+        if removed[*t] || tri.contains(&other) {
+            continue;
+        }
+
+        let p = |i: usize| {
+            if tri[i] == v {
+                target
+            } else {
+                &positions[tri[i]]
+            }
+        };
+        let before = face_normal(&positions[tri[0]], &positions[tri[1]], &positions[tri[2]]);
+        let after = face_normal(p(0), p(1), p(2));
+        if Vector4F::dot(&before, &after) <= 0.0 {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+//Not normalized, the length is twice the area of the triangle
+fn face_normal(p0: &Vector4F, p1: &Vector4F, p2: &Vector4F) -> Vector4F {
+    Vector4F::cross(&(p1 - p0), &(p2 - p0))
+}
+
+//Quadrics are symmetric 4x4 matrices, stored as the upper triangle:
+//[0 1 2 3]
+//[  4 5 6]
+//[    7 8]
+//[      9]
+fn plane_quadric(normal: &Vector4F, point: &Vector4F, weight: f64) -> [f64; 10] {
+    let (a, b, c) = (normal.x, normal.y, normal.z);
+    let d = -Vector4F::dot(normal, point);
+
+    [
+        a * a * weight,
+        a * b * weight,
+        a * c * weight,
+        a * d * weight,
+        b * b * weight,
+        b * c * weight,
+        b * d * weight,
+        c * c * weight,
+        c * d * weight,
+        d * d * weight,
+    ]
+}
+
+fn add_quadric(q: &mut [f64; 10], other: &[f64; 10]) {
+    for i in 0..10 {
+        q[i] += other[i];
+    }
+}
+
+//Sum of the squared distances of the point to the planes of the quadric
+fn quadric_error(q: &[f64; 10], p: &Vector4F) -> f64 {
+    let (x, y, z) = (p.x, p.y, p.z);
+
+    q[0] * x * x
+        + 2.0 * q[1] * x * y
+        + 2.0 * q[2] * x * z
+        + 2.0 * q[3] * x
+        + q[4] * y * y
+        + 2.0 * q[5] * y * z
+        + 2.0 * q[6] * y
+        + q[7] * z * z
+        + 2.0 * q[8] * z
+        + q[9]
+}
+
+//Point with the minimal error, where the gradient of the quadric is 0. Solved with Cramer's rule.
+fn solve(q: &[f64; 10]) -> Option<Vector4F> {
+    let det3 = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+
+    let m = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+    let r = [-q[3], -q[6], -q[8]];
+
+    let det = det3(m);
+    if det.abs() < 1e-10 {
+        return None;
+    }
+
+    let mut result = [0.0; 3];
+    for i in 0..3 {
+        let mut mi = m;
+        for row in 0..3 {
+            mi[row][i] = r[row];
+        }
+        result[i] = det3(mi) / det;
+    }
+
+    Some(Vector4F::new(result[0], result[1], result[2]))
+}
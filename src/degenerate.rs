@@ -0,0 +1,68 @@
+use linear::Vector4F;
+use linear::Vertex4F;
+
+//What remove_degenerate() had to remove or repair
+pub struct Repairs {
+    //Triangles with a position that is NaN or infinite
+    pub invalid: usize,
+    //Triangles without area, which have no normal and can't be hit
+    pub zero_area: usize,
+    //Corners with a normal that is NaN, infinite or zero, which got the face normal instead
+    pub normals: usize,
+}
+
+impl Repairs {
+    pub fn any(&self) -> bool {
+        self.invalid > 0 || self.zero_area > 0 || self.normals > 0
+    }
+}
+
+//Removes the triangles, three vertices per triangle as loaded from OBJ files, that would put NaNs
+//into the shading: triangles with invalid positions and triangles without area. Corners with a
+//normal that can't be used get the normal of their triangle.
+pub fn remove_degenerate(vertices: &mut Vec<Vertex4F>) -> Repairs {
+    let mut repairs = Repairs {
+        invalid: 0,
+        zero_area: 0,
+        normals: 0,
+    };
+
+    let num_tris = vertices.len() / 3;
+    let mut kept = 0;
+
+    for t in 0..num_tris {
+        let corners = &vertices[t * 3..t * 3 + 3];
+        if !corners.iter().all(|v| is_finite(&v.pos)) {
+            repairs.invalid += 1;
+            continue;
+        }
+
+        //Same orientation as the face normals of the OBJ loader
+        let edge1 = &corners[0].pos - &corners[1].pos;
+        let edge2 = &corners[2].pos - &corners[1].pos;
+        let face = Vector4F::cross(&edge2, &edge1);
+        let area = face.len();
+        if !(area > 0.0 && area.is_finite()) {
+            repairs.zero_area += 1;
+            continue;
+        }
+        let face = face.normalize();
+
+        for c in 0..3 {
+            let mut v = vertices[t * 3 + c].clone();
+            if !(is_finite(&v.normal) && v.normal.len() > 0.0) {
+                v.normal = face.clone();
+                repairs.normals += 1;
+            }
+            vertices[kept * 3 + c] = v;
+        }
+        kept += 1;
+    }
+
+    vertices.truncate(kept * 3);
+    repairs
+}
+
+fn is_finite(v: &Vector4F) -> bool {
+    v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+}
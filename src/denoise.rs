@@ -0,0 +1,117 @@
+use framebuffer::Framebuffer;
+use framebuffer::PixelBuffer;
+use settings::Settings;
+
+//Filters the radiance of the framebuffer with the denoise settings of the output, before it is
+//exposed and tone mapped. Does nothing if denoising is off. The AOVs are kept as they are.
+pub fn denoise(framebuffer: &mut Framebuffer, settings: &Settings) {
+    let denoise = match &settings.output.denoise {
+        Some(denoise) => denoise,
+        None => return,
+    };
+
+    let width = framebuffer.width as i64;
+    let height = framebuffer.height as i64;
+    let num_values = (width * height * 3) as usize;
+    let radiance: Vec<f32> = (0..num_values).map(|i| framebuffer.pixels.get(i)).collect();
+
+    //Colors are compared after compressing them to 0...1 like a simple tone mapping, so the
+    //difference between bright pixels doesn't outweigh everything else
+    let colors: Vec<f32> = radiance
+        .iter()
+        .map(|v| v.max(0.0) / (1.0 + v.max(0.0)))
+        .collect();
+    let normals = guide(framebuffer, settings, "normal");
+    let albedos = guide(framebuffer, settings, "albedo");
+
+    let radius = denoise.radius as i64;
+    let spatial = factor((denoise.radius as f64 * 0.5).max(0.5));
+    let color = factor(denoise.color_sigma);
+    let normal = factor(denoise.normal_sigma);
+    let albedo = factor(denoise.albedo_sigma);
+
+    let mut result = vec![0.0f32; num_values];
+    for y in 0..height {
+        for x in 0..width {
+            let p = ((y * width + x) * 3) as usize;
+            let mut sum = [0.0f32; 3];
+            let mut weights = 0.0f32;
+
+            for ny in (y - radius).max(0)..(y + radius + 1).min(height) {
+                for nx in (x - radius).max(0)..(x + radius + 1).min(width) {
+                    let (dx, dy) = (nx - x, ny - y);
+                    let d2 = (dx * dx + dy * dy) as f32;
+                    if d2 > (radius * radius) as f32 {
+                        continue;
+                    }
+
+                    let q = ((ny * width + nx) * 3) as usize;
+                    let mut exponent = d2 * spatial + distance2(&colors, p, q) * color;
+                    if let Some(normals) = normals {
+                        exponent += distance2(normals, p, q) * normal;
+                    }
+                    if let Some(albedos) = albedos {
+                        exponent += distance2(albedos, p, q) * albedo;
+                    }
+
+                    //Pixels that went wrong while rendering would spread into their neighbors
+                    let value = &radiance[q..(q + 3)];
+                    if value.iter().any(|v| !v.is_finite()) {
+                        continue;
+                    }
+
+                    let w = (-exponent).exp();
+                    sum[0] += value[0] * w;
+                    sum[1] += value[1] * w;
+                    sum[2] += value[2] * w;
+                    weights += w;
+                }
+            }
+
+            //Only pixels without finite neighbors and value have no weights, they are kept
+            for c in 0..3 {
+                result[p + c] = if weights > 0.0 {
+                    sum[c] / weights
+                } else {
+                    radiance[p + c]
+                };
+            }
+        }
+    }
+
+    //Stored line by line like a render, so half floats carry their rounding error the same way
+    let mut pixels = match framebuffer.pixels {
+        PixelBuffer::F32(_) => PixelBuffer::F32(vec![0.0; num_values]),
+        PixelBuffer::F16(_) => PixelBuffer::F16(vec![0; num_values]),
+    };
+    let line_values = (width * 3) as usize;
+    for (y, line) in result.chunks(line_values).enumerate() {
+        pixels.add_line(y * line_values, line);
+    }
+    framebuffer.pixels = pixels;
+}
+
+//Buffer of the AOV with the given name, if it was rendered. It must have three channels.
+fn guide<'a>(framebuffer: &'a Framebuffer, settings: &Settings, name: &str) -> Option<&'a [f32]> {
+    settings
+        .output
+        .aovs
+        .iter()
+        .position(|aov| aov.name() == name)
+        .map(|i| framebuffer.aovs[i].as_slice())
+}
+
+//Factor of a squared difference in the exponent of a gaussian with the given sigma
+fn factor(sigma: f64) -> f32 {
+    (1.0 / (2.0 * sigma * sigma)) as f32
+}
+
+//Squared distance of the three values starting at the indexes p and q
+fn distance2(values: &[f32], p: usize, q: usize) -> f32 {
+    let mut result = 0.0;
+    for c in 0..3 {
+        let d = values[p + c] - values[q + c];
+        result += d * d;
+    }
+    result
+}
@@ -0,0 +1,112 @@
+use linear::Intersection;
+use linear::Vector4F;
+use scratch::Scratch;
+use settings::Intersectable;
+use settings::Scene;
+
+//Distance a ray is moved past a dissolved hit before looking for the next one
+const EPSILON: f64 = 0.00001;
+//Surfaces of one object a ray passes through before it counts as a miss
+const MAX_LAYERS: u32 = 16;
+
+//Object of a scene whose visibility is animated. Each hit is kept with the probability of the
+//visibility, decided by a hash of the hit position, so the object dissolves into noise that
+//averages out over the samples of a pixel. Shadows and reflections dissolve the same way.
+pub struct Dissolved<'a> {
+    pub object: &'a Intersectable,
+    pub visibility: f64,
+}
+
+//All objects of the scene, in the same order as Scene::objects(), with their visibility at the
+//given frame
+pub fn dissolve_objects<'a>(scene: &'a Scene, frame: Option<u32>) -> Vec<Dissolved<'a>> {
+    scene
+        .objects()
+        .into_iter()
+        .zip(scene.object_visibility(frame))
+        .map(|(object, visibility)| Dissolved { object, visibility })
+        .collect()
+}
+
+//The objects to render: fully visible objects as they are, all others dissolved. Objects keep
+//their index, so their material variations don't change when another object disappears.
+pub fn visible_objects<'a>(dissolved: &'a [Dissolved<'a>]) -> Vec<&'a Intersectable> {
+    dissolved
+        .iter()
+        .map(|d| {
+            if d.visibility >= 1.0 {
+                d.object
+            } else {
+                d as &Intersectable
+            }
+        })
+        .collect()
+}
+
+impl<'a> Intersectable for Dissolved<'a> {
+    fn intersect(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        min_t: f64,
+        scratch: &mut Scratch,
+    ) -> Option<Intersection> {
+        if self.visibility <= 0.0 {
+            return None;
+        }
+
+        //Hits that are dissolved are skipped by continuing the ray behind them
+        let mut org = rorg.clone();
+        let mut travelled = 0.0;
+        for _ in 0..MAX_LAYERS {
+            let mut inter = self
+                .object
+                .intersect(&org, rdir, min_t - travelled, scratch)?;
+            if position_hash(&inter.pos) < self.visibility {
+                inter.ray_t += travelled;
+                return Some(inter);
+            }
+
+            travelled += inter.ray_t + EPSILON;
+            if travelled >= min_t {
+                return None;
+            }
+            org = Vector4F::new(
+                rorg.x + rdir.x * travelled,
+                rorg.y + rdir.y * travelled,
+                rorg.z + rdir.z * travelled,
+            );
+        }
+
+        None
+    }
+
+    fn material(&self) -> usize {
+        self.object.material()
+    }
+
+    fn material_name(&self) -> &str {
+        self.object.material_name()
+    }
+
+    fn contains(&self, pos: &Vector4F, scratch: &mut Scratch) -> bool {
+        self.visibility > 0.0 && self.object.contains(pos, scratch)
+    }
+
+    fn name(&self) -> &str {
+        self.object.name()
+    }
+
+    fn bounds(&self) -> Option<(Vector4F, Vector4F)> {
+        self.object.bounds()
+    }
+}
+
+//Number in 0...1 that looks random, but is always the same for the same position
+fn position_hash(pos: &Vector4F) -> f64 {
+    let mut h = pos.x.to_bits();
+    h = (h ^ (h >> 33) ^ pos.y.to_bits()).wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    h = (h ^ (h >> 33) ^ pos.z.to_bits()).wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+    h ^= h >> 33;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,280 @@
+use hdr;
+use linear::Vector4F;
+use random::Random;
+use settings::Color;
+use std::f64::consts::PI;
+use texture::Texture;
+
+//Size of the map of a dome without a texture, the colors are the same everywhere in each half
+const DOME_WIDTH: u32 = 16;
+const DOME_HEIGHT: u32 = 8;
+
+//Environment map in equirectangular (latitude/longitude) layout that surrounds the whole scene.
+//A luminance CDF is precomputed at load time so bright regions, like a small sun, can be
+//importance sampled for direct lighting.
+pub struct Environment {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+    pub intensity: f64,
+    //Rotation around the Y axis in degrees
+    pub rotation: f64,
+    //Number of importance samples taken for direct lighting at each shading point
+    pub samples: u32,
+    //Conditional CDFs, one per row with width + 1 entries each
+    row_cdfs: Vec<f64>,
+    //Marginal CDF over the rows with height + 1 entries
+    marginal_cdf: Vec<f64>,
+    //Sum of all sampling weights, 0.0 if the map is completely black
+    total_weight: f64,
+}
+
+impl Environment {
+    pub fn load(file_name: &str, intensity: f64, rotation: f64, samples: u32) -> Environment {
+        let (width, height, pixels) = hdr::read_hdr(file_name);
+        eprintln!("Environment map size: {}x{}", width, height);
+
+        Environment::from_pixels(width, height, pixels, intensity, rotation, samples)
+    }
+
+    //Environment of the given size with the radiance of the function in the direction of the
+    //center of each pixel, for skies that are computed instead of loaded
+    pub fn procedural<F>(
+        width: u32,
+        height: u32,
+        intensity: f64,
+        rotation: f64,
+        samples: u32,
+        radiance: F,
+    ) -> Environment
+    where
+        F: Fn(&Vector4F) -> Color,
+    {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f64 + 0.5) / width as f64;
+                let v = (y as f64 + 0.5) / height as f64;
+                //The rotation is applied when looking up, like for loaded maps
+                pixels.push(radiance(&uv_direction(u, v)));
+            }
+        }
+
+        Environment::from_pixels(width, height, pixels, intensity, rotation, samples)
+    }
+
+    //Dome light: the sky color in the upper hemisphere and the ground color below the horizon.
+    //A texture is stretched over the sky with its bottom row at the horizon and its top row at the
+    //zenith, tinted by the sky color. Much cheaper than an HDR map for quick outdoor lighting, but
+    //rendered the same way, so it is seen by the camera and importance sampled like one.
+    pub fn dome(
+        sky: &Color,
+        ground: &Color,
+        texture: Option<&Texture>,
+        intensity: f64,
+        rotation: f64,
+        samples: u32,
+    ) -> Environment {
+        //Twice the rows of the texture, so the horizon is between two rows
+        let (width, height) = match texture {
+            Some(texture) => (texture.width, texture.height * 2),
+            None => (DOME_WIDTH, DOME_HEIGHT),
+        };
+
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                if y >= height / 2 {
+                    pixels.push(ground.clone());
+                    continue;
+                }
+                let color = match texture {
+                    Some(texture) => {
+                        let u = (x as f64 + 0.5) / width as f64;
+                        let v = 1.0 - (y as f64 + 0.5) / (height / 2) as f64;
+                        texture.sample(u, v).0
+                    }
+                    None => Color::white(),
+                };
+                pixels.push(Color::new(
+                    color.r * sky.r,
+                    color.g * sky.g,
+                    color.b * sky.b,
+                ));
+            }
+        }
+
+        Environment::from_pixels(width, height, pixels, intensity, rotation, samples)
+    }
+
+    fn from_pixels(
+        width: u32,
+        height: u32,
+        pixels: Vec<Color>,
+        intensity: f64,
+        rotation: f64,
+        samples: u32,
+    ) -> Environment {
+        let mut env = Environment {
+            width,
+            height,
+            pixels,
+            intensity,
+            rotation,
+            samples,
+            row_cdfs: Vec::new(),
+            marginal_cdf: Vec::new(),
+            total_weight: 0.0,
+        };
+        env.build_cdf();
+        env
+    }
+
+    //Builds the 2D sampling distribution. Each pixel is weighted by its luminance and by
+    //sin(theta) to account for the stretching of the equirectangular projection at the poles.
+    fn build_cdf(&mut self) {
+        let w = self.width as usize;
+        let h = self.height as usize;
+
+        self.row_cdfs = Vec::with_capacity(h * (w + 1));
+        self.marginal_cdf = Vec::with_capacity(h + 1);
+        self.marginal_cdf.push(0.0);
+
+        let mut total = 0.0;
+        for y in 0..h {
+            let sin_theta = (PI * (y as f64 + 0.5) / h as f64).sin();
+
+            let mut row_sum = 0.0;
+            self.row_cdfs.push(0.0);
+            for x in 0..w {
+                row_sum += self.pixels[y * w + x].luminance() * sin_theta;
+                self.row_cdfs.push(row_sum);
+            }
+
+            //Normalize row, rows without any light are sampled uniformly
+            let row = &mut self.row_cdfs[y * (w + 1)..(y + 1) * (w + 1)];
+            for (x, v) in row.iter_mut().enumerate() {
+                if row_sum > 0.0 {
+                    *v /= row_sum;
+                } else {
+                    *v = x as f64 / w as f64;
+                }
+            }
+
+            total += row_sum;
+            self.marginal_cdf.push(total);
+        }
+
+        if total > 0.0 {
+            for v in &mut self.marginal_cdf {
+                *v /= total;
+            }
+        }
+
+        self.total_weight = total;
+    }
+
+    //Returns the radiance of the environment in the given direction.
+    pub fn lookup(&self, dir: &Vector4F) -> Color {
+        let (u, v) = self.direction_to_uv(dir);
+        let x = ((u * self.width as f64) as u32).min(self.width - 1);
+        let y = ((v * self.height as f64) as u32).min(self.height - 1);
+
+        let c = &self.pixels[(y * self.width + x) as usize];
+        let intens = self.intensity as f32;
+        Color::new(c.r * intens, c.g * intens, c.b * intens)
+    }
+
+    //Returns true if the environment emits any light and can be importance sampled.
+    pub fn can_sample(&self) -> bool {
+        self.total_weight > 0.0
+    }
+
+    //Picks a direction proportional to the luminance of the environment.
+    //Returns the direction and its PDF with respect to solid angle.
+    pub fn sample(&self, random: &mut Random) -> (Vector4F, f64) {
+        let w = self.width as usize;
+        let h = self.height as usize;
+
+        let y = find_interval(&self.marginal_cdf, random.random_f());
+        let row = &self.row_cdfs[y * (w + 1)..(y + 1) * (w + 1)];
+        let x = find_interval(row, random.random_f());
+
+        //Jitter inside of the chosen pixel
+        let u = (x as f64 + random.random_f()) / w as f64;
+        let v = (y as f64 + random.random_f()) / h as f64;
+
+        let dir = self.uv_to_direction(u, v);
+        let pdf = self.pdf_pixel(x, y);
+
+        (dir, pdf)
+    }
+
+    //Returns the PDF with respect to solid angle of sampling the given direction with sample().
+    pub fn pdf(&self, dir: &Vector4F) -> f64 {
+        let (u, v) = self.direction_to_uv(dir);
+        let x = ((u * self.width as f64) as usize).min(self.width as usize - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height as usize - 1);
+        self.pdf_pixel(x, y)
+    }
+
+    fn pdf_pixel(&self, x: usize, y: usize) -> f64 {
+        let w = self.width as usize;
+        let h = self.height as usize;
+
+        let row = &self.row_cdfs[y * (w + 1)..(y + 1) * (w + 1)];
+        let pdf_row = self.marginal_cdf[y + 1] - self.marginal_cdf[y];
+        let pdf_col = row[x + 1] - row[x];
+
+        //Probability of the pixel, converted to density over the image and then over the sphere
+        let pdf_uv = pdf_row * pdf_col * (w * h) as f64;
+        let sin_theta = (PI * (y as f64 + 0.5) / h as f64).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+
+    //Maps a direction to equirectangular coordinates. v = 0 is straight up (+Y), u = 0.5 is forward (+Z).
+    fn direction_to_uv(&self, dir: &Vector4F) -> (f64, f64) {
+        let d = dir.normalize().rotate_y(-self.rotation);
+        let u = 0.5 + d.x.atan2(d.z) / (2.0 * PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vector4F {
+        uv_direction(u, v).rotate_y(self.rotation)
+    }
+}
+
+//Direction of the equirectangular coordinates without rotation
+fn uv_direction(u: f64, v: f64) -> Vector4F {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    let sin_theta = theta.sin();
+
+    Vector4F {
+        x: sin_theta * phi.sin(),
+        y: theta.cos(),
+        z: sin_theta * phi.cos(),
+        w: 0.0,
+    }
+}
+
+//Returns the index i so that cdf[i] <= value < cdf[i + 1], skipping entries with zero probability.
+fn find_interval(cdf: &[f64], value: f64) -> usize {
+    let mut lo = 0;
+    let mut hi = cdf.len() - 1;
+    while lo + 1 < hi {
+        let mid = (lo + hi) / 2;
+        if cdf[mid] <= value {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    lo
+}
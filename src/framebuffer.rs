@@ -0,0 +1,228 @@
+use settings::Aov;
+use settings::PixelFormat;
+
+//Result of rendering an image. All values are linear and not tone mapped.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    //Radiance of all pixels in RGB order. Rows are stored from bottom to top.
+    pub pixels: PixelBuffer,
+    //One buffer per requested AOV, in the same order as the AOVs in the output settings.
+    //Each buffer has as many values per pixel as the AOV has channels.
+    pub aovs: Vec<Vec<f32>>,
+    //True if the render was cancelled before all lines were finished
+    pub cancelled: bool,
+    //Pixels that stay black because rendering them panicked, with 0, 0 at the top left
+    pub failed_pixels: Vec<(u32, u32)>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, aovs: &[Aov], format: &PixelFormat) -> Framebuffer {
+        let num_pixels = (width * height) as usize;
+
+        let pixels = match format {
+            PixelFormat::F32 => PixelBuffer::F32(vec![0.0; num_pixels * 3]),
+            PixelFormat::F16 => PixelBuffer::F16(vec![0; num_pixels * 3]),
+        };
+
+        Framebuffer {
+            width,
+            height,
+            pixels,
+            aovs: aovs
+                .iter()
+                .map(|aov| vec![0.0; num_pixels * aov.channels()])
+                .collect(),
+            cancelled: false,
+            failed_pixels: Vec::new(),
+        }
+    }
+
+    //Replaces the RGB and AOV values of the pixel with the given index by those of the same
+    //pixel in other, which must have the same size and AOVs
+    pub fn copy_pixel(&mut self, other: &Framebuffer, index: usize) {
+        self.pixels.copy_values(&other.pixels, index * 3, 3);
+
+        for (aov, other_aov) in self.aovs.iter_mut().zip(&other.aovs) {
+            let channels = aov.len() / (self.width * self.height) as usize;
+            let start = index * channels;
+            aov[start..(start + channels)].copy_from_slice(&other_aov[start..(start + channels)]);
+        }
+    }
+}
+
+//RGB values stored either as f32 or as half floats.
+#[derive(Clone)]
+pub enum PixelBuffer {
+    F32(Vec<f32>),
+    F16(Vec<u16>),
+}
+
+impl PixelBuffer {
+    pub fn get(&self, index: usize) -> f32 {
+        match self {
+            PixelBuffer::F32(values) => values[index],
+            PixelBuffer::F16(values) => f16_to_f32(values[index]),
+        }
+    }
+
+    //Copies count values starting at the given value index from other, which must have the same
+    //format
+    pub fn copy_values(&mut self, other: &PixelBuffer, start: usize, count: usize) {
+        let range = start..(start + count);
+        match (self, other) {
+            (PixelBuffer::F32(values), PixelBuffer::F32(from)) => {
+                values[range.clone()].copy_from_slice(&from[range])
+            }
+            (PixelBuffer::F16(values), PixelBuffer::F16(from)) => {
+                values[range.clone()].copy_from_slice(&from[range])
+            }
+            _ => panic!("Pixel buffers have different formats"),
+        }
+    }
+
+    //Adds the RGB values of a line of pixels, starting at the given value index.
+    //The samples of a pixel are summed in f32 by the tile and the passes in f64 by the checkpoint,
+    //so half floats are only rounded once when the finished pixel is stored, not once per sample.
+    pub fn add_line(&mut self, start: usize, line: &[f32]) {
+        match self {
+            PixelBuffer::F32(values) => {
+                for (i, v) in line.iter().enumerate() {
+                    values[start + i] += v;
+                }
+            }
+            PixelBuffer::F16(values) => {
+                for (i, v) in line.iter().enumerate() {
+                    let index = start + i;
+                    values[index] = f32_to_f16(f16_to_f32(values[index]) + v);
+                }
+            }
+        }
+    }
+}
+
+//Converts to half float, rounding to nearest even. Values that are too large to be
+//represented are clamped to the largest half float instead of becoming infinite.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+
+    //NaN stays NaN, infinity is clamped like all other large values
+    if exp == 0xff && mant != 0 {
+        return sign | 0x7e00;
+    }
+
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7bff;
+    }
+
+    if e <= 0 {
+        //Result is a subnormal half float or zero
+        if e < -10 {
+            return sign;
+        }
+
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let halfway = 1 << (shift - 1);
+        let rem = m & ((1 << shift) - 1);
+        let mut result = m >> shift;
+        if rem > halfway || (rem == halfway && (result & 1) == 1) {
+            result += 1;
+        }
+        return sign | result as u16;
+    }
+
+    let mut result = ((e as u32) << 10) | (mant >> 13);
+    let rem = mant & 0x1fff;
+    if rem > 0x1000 || (rem == 0x1000 && (result & 1) == 1) {
+        //Can carry into the exponent, which is still the correctly rounded value
+        result += 1;
+    }
+    if result >= 0x7c00 {
+        return sign | 0x7bff;
+    }
+
+    sign | result as u16
+}
+
+fn f16_to_f32(value: u16) -> f32 {
+    let sign = if value & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((value >> 10) & 0x1f) as i32;
+    let mant = (value & 0x3ff) as f32;
+
+    if exp == 0 {
+        sign * mant * 2.0f32.powi(-24)
+    } else if exp == 0x1f {
+        if mant == 0.0 {
+            sign * std::f32::INFINITY
+        } else {
+            std::f32::NAN
+        }
+    } else {
+        sign * (1.0 + mant / 1024.0) * 2.0f32.powi(exp - 15)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_values_round_trip() {
+        //The smallest normal half float is 2^-14
+        for v in &[0.0f32, 1.0, -2.25, 0.5, 1024.0, 65504.0, 2.0f32.powi(-14)] {
+            assert_eq!(f16_to_f32(f32_to_f16(*v)), *v);
+        }
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+
+        //Other values are rounded to the 11 significant bits of a half float
+        let v = 0.1f32;
+        assert!((f16_to_f32(f32_to_f16(v)) - v).abs() <= v * 2.0f32.powi(-11));
+    }
+
+    #[test]
+    fn ties_round_to_even() {
+        let ulp = 2.0f32.powi(-10);
+        assert_eq!(f16_to_f32(f32_to_f16(1.0 + ulp * 0.5)), 1.0);
+        assert_eq!(f16_to_f32(f32_to_f16(1.0 + ulp * 1.5)), 1.0 + ulp * 2.0);
+    }
+
+    #[test]
+    fn subnormal_values_round_trip() {
+        let smallest = 2.0f32.powi(-24);
+        assert_eq!(f32_to_f16(smallest), 0x0001);
+        for v in &[
+            smallest,
+            smallest * 3.0,
+            smallest * 1023.0,
+            -smallest * 512.0,
+        ] {
+            assert_eq!(f16_to_f32(f32_to_f16(*v)), *v);
+        }
+        //Below half of the smallest subnormal is zero
+        assert_eq!(f32_to_f16(smallest * 0.25), 0);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        assert_eq!(f16_to_f32(f32_to_f16(1.0e6)), 65504.0);
+        assert_eq!(f16_to_f32(f32_to_f16(-1.0e6)), -65504.0);
+        assert_eq!(f16_to_f32(f32_to_f16(std::f32::INFINITY)), 65504.0);
+        assert_eq!(f16_to_f32(f32_to_f16(65520.0)), 65504.0);
+        assert!(f16_to_f32(f32_to_f16(std::f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn half_float_lines_are_rounded_once() {
+        let mut pixels = PixelBuffer::F16(vec![0; 6]);
+        pixels.add_line(0, &[0.1, 0.2, 0.3, 1.0, 2.0, 3.0]);
+        for (i, v) in [0.1f32, 0.2, 0.3, 1.0, 2.0, 3.0].iter().enumerate() {
+            assert_eq!(pixels.get(i), f16_to_f32(f32_to_f16(*v)));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use settings::Color;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+
+//Reads a Radiance HDR (.hdr / RGBE) image.
+//Spec taken from http://paulbourke.net/dataformats/pic/
+//
+//Only the standard "-Y height +X width" orientation is supported. Scanlines can be
+//uncompressed or use the new run length encoding.
+//
+//Returns width, height and the pixels in rows from top to bottom.
+pub fn read_hdr(file_name: &str) -> (u32, u32, Vec<Color>) {
+    let file = File::open(file_name).unwrap();
+    let mut reader = BufReader::new(file);
+
+    //Read header lines until the empty line that separates header and resolution string
+    let magic = read_line(&mut reader);
+    assert!(magic.starts_with("#?"));
+    loop {
+        let line = read_line(&mut reader);
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            panic!("Unsupported HDR format: {}", line);
+        }
+    }
+
+    let resolution = read_line(&mut reader);
+    let tokens: Vec<&str> = resolution.split_whitespace().collect();
+    if tokens.len() != 4 || tokens[0] != "-Y" || tokens[2] != "+X" {
+        panic!("Unsupported HDR orientation: {}", resolution);
+    }
+
+    let height: u32 = tokens[1].parse().unwrap();
+    let width: u32 = tokens[3].parse().unwrap();
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _y in 0..height {
+        read_scanline(&mut reader, &mut scanline);
+        for rgbe in &scanline {
+            pixels.push(rgbe_to_color(rgbe));
+        }
+    }
+
+    (width, height, pixels)
+}
+
+fn read_scanline(reader: &mut BufReader<File>, scanline: &mut Vec<[u8; 4]>) {
+    let width = scanline.len();
+    let first = read_bytes(reader, 4);
+
+    //New RLE scanlines start with two bytes of value 2 followed by the width
+    let is_rle = width >= 8
+        && width < 0x8000
+        && first[0] == 2
+        && first[1] == 2
+        && (first[2] & 0x80) == 0;
+
+    if !is_rle {
+        //Flat scanline, first pixel has already been read
+        scanline[0] = [first[0], first[1], first[2], first[3]];
+        for x in 1..width {
+            let b = read_bytes(reader, 4);
+            scanline[x] = [b[0], b[1], b[2], b[3]];
+        }
+        return;
+    }
+
+    let encoded_width = ((first[2] as usize) << 8) | first[3] as usize;
+    if encoded_width != width {
+        panic!("Invalid HDR scanline width: {}", encoded_width);
+    }
+
+    //Each of the four channels is run length encoded separately
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = read_bytes(reader, 1)[0] as usize;
+            if count > 128 {
+                let run = count - 128;
+                let value = read_bytes(reader, 1)[0];
+                for _i in 0..run {
+                    scanline[x][channel] = value;
+                    x += 1;
+                }
+            } else {
+                let values = read_bytes(reader, count);
+                for value in values {
+                    scanline[x][channel] = value;
+                    x += 1;
+                }
+            }
+        }
+    }
+}
+
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::black();
+    }
+
+    let f = 2.0f32.powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(rgbe[0] as f32 * f, rgbe[1] as f32 * f, rgbe[2] as f32 * f)
+}
+
+fn read_line(reader: &mut BufReader<File>) -> String {
+    let mut result = String::new();
+    loop {
+        let b = read_bytes(reader, 1)[0];
+        if b == b'\n' {
+            break;
+        }
+        result.push(b as char);
+    }
+
+    result
+}
+
+fn read_bytes(reader: &mut BufReader<File>, count: usize) -> Vec<u8> {
+    let mut buffer = vec![0; count];
+    reader.read_exact(&mut buffer).unwrap();
+    buffer
+}
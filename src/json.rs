@@ -1,3 +1,5 @@
+use std::fmt;
+
 pub enum JsonValue {
     Null,
     Number(f64),
@@ -7,6 +9,30 @@ pub enum JsonValue {
     Object(Vec<(String, JsonValue)>),
 }
 
+//Error found while parsing, with the position of the offending character.
+//Line and column both start at 1.
+pub struct JsonError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (line {}, column {})",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl fmt::Debug for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
 //Need to write own iterate because none of the ones included in Rust is usable
 struct StringIterator {
     characters: Vec<char>,
@@ -38,12 +64,51 @@ impl StringIterator {
         self.position = self.position + 1;
         Some(result)
     }
+
+    //Creates an error at the current position
+    fn error(&self, message: String) -> JsonError {
+        let mut line = 1;
+        let mut column = 1;
+        for c in self.characters.iter().take(self.position) {
+            if *c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        JsonError {
+            message,
+            line,
+            column,
+        }
+    }
+
+    //Creates an error for the character at the current position, or for EOF if there is none
+    fn unexpected(&self, expected: &str) -> JsonError {
+        match self.peek() {
+            Some(c) => self.error(format!("Expected {} but found '{}'", expected, c)),
+            None => self.error(format!("Expected {} but found end of file", expected)),
+        }
+    }
 }
 
-pub fn parse_json(json: &str) -> Option<JsonValue> {
+pub fn parse_json(json: &str) -> Result<JsonValue, JsonError> {
     let mut chars = StringIterator::new(json);
     skip_white_spaces(&mut chars);
-    read_object(&mut chars)
+    if chars.peek().is_none() || !is_object_char(chars.peek().unwrap()) {
+        return Err(chars.unexpected("{ at start of document"));
+    }
+
+    let result = read_object(&mut chars)?;
+
+    skip_white_spaces(&mut chars);
+    if chars.peek().is_some() {
+        return Err(chars.unexpected("end of file after document"));
+    }
+
+    Ok(result)
 }
 
 fn skip_white_spaces(chars: &mut StringIterator) {
@@ -56,61 +121,76 @@ fn is_white_space(c: &char) -> bool {
     (*c == ' ') || (*c == '\n') || (*c == '\r') || (*c == '\t')
 }
 
-fn read_bool(chars: &mut StringIterator) -> Option<JsonValue> {
-    if chars.peek().is_some() {
-        let c = *(chars.peek().unwrap());
-
-        if c == 't' || c == 'T' {
-            chars.next();
-            chars.next();
-            chars.next();
-            chars.next();
-            return Some(JsonValue::Boolean(true));
-        } else if c == 'f' || c == 'F' {
-            chars.next();
-            chars.next();
-            chars.next();
-            chars.next();
-            chars.next();
-            return Some(JsonValue::Boolean(false));
+fn read_bool(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
+    if chars.peek() == Some(&'t') || chars.peek() == Some(&'T') {
+        read_word(chars, "true")?;
+        return Ok(JsonValue::Boolean(true));
+    }
+
+    read_word(chars, "false")?;
+    Ok(JsonValue::Boolean(false))
+}
+
+//Reads the given keyword, ignoring case
+fn read_word(chars: &mut StringIterator, word: &str) -> Result<(), JsonError> {
+    for expected in word.chars() {
+        match chars.peek() {
+            Some(c) if c.to_ascii_lowercase() == expected => {
+                chars.next();
+            }
+            _ => return Err(chars.unexpected(format!("'{}' in {}", expected, word).as_str())),
         }
     }
 
-    None
+    Ok(())
 }
 
-fn read_number(chars: &mut StringIterator) -> Option<JsonValue> {
+fn read_number(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
+    let start = chars.position;
     let mut number = String::new();
-    while chars.peek().is_some()
-        && (is_number_char(chars.peek().unwrap()) || *chars.peek().unwrap() == '.')
-    {
+
+    //Sign, integer part and fraction
+    read_sign(chars, &mut number);
+    read_digits(chars, &mut number);
+    if chars.peek() == Some(&'.') {
         number.push(chars.next().unwrap());
+        read_digits(chars, &mut number);
     }
 
-    if number.len() > 0 {
-        let result: f64 = number.parse().unwrap();
-        return Some(JsonValue::Number(result));
+    //Exponent, which can have its own sign like in 1e-3
+    if chars.peek() == Some(&'e') || chars.peek() == Some(&'E') {
+        number.push(chars.next().unwrap());
+        read_sign(chars, &mut number);
+        read_digits(chars, &mut number);
     }
 
-    None
+    match number.parse() {
+        Ok(result) => Ok(JsonValue::Number(result)),
+        Err(_) => {
+            chars.position = start;
+            Err(chars.error(format!("Invalid number: {}", number)))
+        }
+    }
 }
 
-fn read_null(chars: &mut StringIterator) -> Option<JsonValue> {
-    if chars.peek().is_some() {
-        let c = *(chars.peek().unwrap());
-        if c == 'N' || c == 'n' {
-            chars.next();
-            chars.next();
-            chars.next();
-            chars.next();
-            return Some(JsonValue::Null);
-        }
+fn read_sign(chars: &mut StringIterator, number: &mut String) {
+    if chars.peek() == Some(&'-') || chars.peek() == Some(&'+') {
+        number.push(chars.next().unwrap());
     }
+}
+
+fn read_digits(chars: &mut StringIterator, number: &mut String) {
+    while chars.peek().is_some() && chars.peek().unwrap().is_ascii_digit() {
+        number.push(chars.next().unwrap());
+    }
+}
 
-    None
+fn read_null(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
+    read_word(chars, "null")?;
+    Ok(JsonValue::Null)
 }
 
-fn read_string(chars: &mut StringIterator) -> Option<JsonValue> {
+fn read_string(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
     //Skip starting "
     chars.next();
 
@@ -118,153 +198,110 @@ fn read_string(chars: &mut StringIterator) -> Option<JsonValue> {
     while chars.peek().is_some() && *chars.peek().unwrap() != '"' {
         result.push(chars.next().unwrap());
     }
+
     //Skip trailing "
-    chars.next();
+    if chars.next().is_none() {
+        return Err(chars.error("Unexpected end of file in string".to_string()));
+    }
 
-    Some(JsonValue::String(result))
+    Ok(JsonValue::String(result))
 }
 
-fn read_value(chars: &mut StringIterator) -> Option<JsonValue> {
-    if chars.peek().is_some() {
-        if is_bool_char(chars.peek().unwrap()) {
-            return read_bool(chars);
-        } else if is_null_char(chars.peek().unwrap()) {
-            return read_null(chars);
-        } else if is_number_char(chars.peek().unwrap()) {
-            return read_number(chars);
-        } else if is_string_char(chars.peek().unwrap()) {
-            return read_string(chars);
-        } else if is_array_char(chars.peek().unwrap()) {
-            return read_array(chars);
-        } else if is_object_char(chars.peek().unwrap()) {
-            return read_object(chars);
-        } else {
-            let mut message = String::new();
-            message.push_str("Unexpected character found: '");
-            message.push(*chars.peek().unwrap());
-            message.push_str("'. Expected JSON value start character.");
-            panic!(message);
-        }
+fn read_value(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
+    match chars.peek() {
+        Some(c) if is_bool_char(c) => read_bool(chars),
+        Some(c) if is_null_char(c) => read_null(chars),
+        Some(c) if is_number_char(c) => read_number(chars),
+        Some(c) if is_string_char(c) => read_string(chars),
+        Some(c) if is_array_char(c) => read_array(chars),
+        Some(c) if is_object_char(c) => read_object(chars),
+        _ => Err(chars.unexpected("JSON value")),
     }
-
-    None
 }
 
-fn read_array(chars: &mut StringIterator) -> Option<JsonValue> {
+fn read_array(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
     //Skip leading [
     chars.next();
 
     let mut values = Vec::new();
 
+    skip_white_spaces(chars);
     while chars.peek().is_some() && *chars.peek().unwrap() != ']' {
+        let value = read_value(chars)?;
+        values.push(value);
+
         skip_white_spaces(chars);
-        let value = read_value(chars);
-
-        if value.is_some() {
-            values.push(value.unwrap());
-
-            skip_white_spaces(chars);
-
-            if chars.peek().is_some() {
-                let is_comma = *chars.peek().unwrap() == ',';
-                let is_array_end = *chars.peek().unwrap() == ']';
-                if !is_comma && !is_array_end {
-                    let mut message = String::new();
-                    message.push_str("Expected , or ] after array value but found: ");
-                    message.push(chars.next().unwrap());
-                    panic!(message);
-                }
-
-                //Skip , for next value
-                if is_comma {
-                    chars.next();
-                }
-            } else {
-                panic!("Unexpected EOF in array value!");
+
+        match chars.peek() {
+            //Skip , for next value
+            Some(&',') => {
+                chars.next();
+                skip_white_spaces(chars);
             }
-        } else {
-            panic!("Unexpected EOF in array value!");
+            Some(&']') => {}
+            _ => return Err(chars.unexpected(", or ] after array value")),
         }
     }
 
     //Skip trailing ] at the end of array
-    if *chars.peek().unwrap() == ']' {
-        chars.next();
-    } else {
-        panic!("Unexpected EOF in array value!");
+    if chars.next().is_none() {
+        return Err(chars.error("Unexpected end of file in array".to_string()));
     }
 
-    Some(JsonValue::Array(values))
+    Ok(JsonValue::Array(values))
 }
 
-fn read_object(chars: &mut StringIterator) -> Option<JsonValue> {
+fn read_object(chars: &mut StringIterator) -> Result<JsonValue, JsonError> {
     //Skip leading {
     chars.next();
 
     let mut values = Vec::new();
 
+    skip_white_spaces(chars);
     while chars.peek().is_some() && *chars.peek().unwrap() != '}' {
-        skip_white_spaces(chars);
-
-        let name_val = read_string(chars);
-        let mut name = String::new();
-        if let Some(JsonValue::String(n)) = name_val {
-            name.push_str(n.as_str());
-        } else {
-            panic!("Could not read name for object field");
+        if !is_string_char(chars.peek().unwrap()) {
+            return Err(chars.unexpected("\" at start of object field name"));
         }
 
+        let name = match read_string(chars)? {
+            JsonValue::String(n) => n,
+            _ => unreachable!(),
+        };
+
         skip_white_spaces(chars);
 
-        if chars.peek().is_some() && *chars.peek().unwrap() != ':' {
-            let mut message = String::new();
-            message.push_str("Expected : after object field name: ->");
-            message.push_str(chars.position.to_string().as_str());
-            message.push_str("<-");
-            panic!(message);
+        if chars.peek() != Some(&':') {
+            return Err(
+                chars.unexpected(format!(": after object field name \"{}\"", name).as_str())
+            );
         }
 
         //Skip :
         chars.next();
 
         skip_white_spaces(chars);
-        let value = read_value(chars);
-
-        if value.is_some() {
-            values.push((name, value.unwrap()));
-
-            skip_white_spaces(chars);
-
-            if chars.peek().is_some() {
-                let is_comma = *chars.peek().unwrap() == ',';
-                let is_object_end = *chars.peek().unwrap() == '}';
-                if !is_comma && !is_object_end {
-                    let mut message = String::new();
-                    message.push_str("Expected , or } after object field value but found: ");
-                    message.push(chars.next().unwrap());
-                    panic!(message);
-                }
-
-                //Skip , for next field
-                if is_comma {
-                    chars.next();
-                }
-            } else {
-                panic!("Unexpected EOF in object value!");
+        let value = read_value(chars)?;
+        values.push((name, value));
+
+        skip_white_spaces(chars);
+
+        match chars.peek() {
+            //Skip , for next field
+            Some(&',') => {
+                chars.next();
+                skip_white_spaces(chars);
             }
-        } else {
-            panic!("Unexpected EOF in object value!");
+            Some(&'}') => {}
+            _ => return Err(chars.unexpected(", or } after object field value")),
         }
     }
 
-    //Skip trailing } at the end of array
-    if *chars.peek().unwrap() == '}' {
-        chars.next();
-    } else {
-        panic!("Unexpected EOF in object value!");
+    //Skip trailing } at the end of object
+    if chars.next().is_none() {
+        return Err(chars.error("Unexpected end of file in object".to_string()));
     }
 
-    Some(JsonValue::Object(values))
+    Ok(JsonValue::Object(values))
 }
 
 fn is_bool_char(c: &char) -> bool {
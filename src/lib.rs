@@ -0,0 +1,79 @@
+//xtracer is a CPU path tracer. Scenes are described by Settings, which are usually read from a
+//JSON file, and rendered into a Framebuffer with render().
+extern crate num_cpus;
+extern crate time;
+
+pub mod burnin;
+pub mod environment;
+pub mod framebuffer;
+pub mod json;
+pub mod linear;
+pub mod pfm;
+pub mod random;
+pub mod settings;
+pub mod shade;
+pub mod stopwatch;
+pub mod tga;
+pub mod writer;
+
+mod accumulator;
+mod animation;
+mod assets;
+mod bake;
+mod checkpoint;
+mod decimate;
+mod degenerate;
+mod denoise;
+mod dissolve;
+mod hdr;
+mod obj;
+mod octree;
+mod ply;
+mod preset;
+mod render;
+mod scenehash;
+mod scratch;
+mod sdf;
+mod selftest;
+mod sky;
+mod smooth;
+mod studio;
+mod texture;
+mod tiles;
+mod validate;
+mod vox;
+
+pub use assets::AssetResolver;
+pub use bake::bake;
+pub use bake::BakedMaps;
+pub use checkpoint::Checkpoint;
+pub use denoise::denoise;
+pub use framebuffer::Framebuffer;
+pub use framebuffer::PixelBuffer;
+pub use preset::apply_preset;
+pub use random::Sampler;
+pub use render::render;
+pub use render::render_dirty;
+pub use render::render_progressive;
+pub use render::render_with_progress;
+pub use render::Progress;
+pub use selftest::selftest;
+pub use settings::Accumulation;
+pub use settings::AdaptiveSampling;
+pub use settings::BurnIn;
+pub use settings::Camera;
+pub use settings::Color;
+pub use settings::Denoise;
+pub use settings::Dirt;
+pub use settings::Falloff;
+pub use settings::Light;
+pub use settings::LightType;
+pub use settings::Material;
+pub use settings::Output;
+pub use settings::PixelFilter;
+pub use settings::PixelFormat;
+pub use settings::ProgressFormat;
+pub use settings::RenderOrder;
+pub use settings::Scene;
+pub use settings::Settings;
+pub use settings::ShadowSprite;
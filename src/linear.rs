@@ -306,6 +306,9 @@ pub struct Vertex4F {
     pub normal: Vector4F,
     pub tex_u: f64,
     pub tex_v: f64,
+    // Second UV set, like for lightmaps. Meshes that don't have one use the first set here.
+    pub tex_u2: f64,
+    pub tex_v2: f64,
     pub color: Color,
 }
 
@@ -316,6 +319,8 @@ impl Vertex4F {
             normal: Vector4F::null(),
             tex_u: 0.0,
             tex_v: 0.0,
+            tex_u2: 0.0,
+            tex_v2: 0.0,
             color: Color::black(),
         }
     }
@@ -326,37 +331,285 @@ impl Vertex4F {
             normal: self.normal.clone(),
             tex_u: self.tex_u,
             tex_v: self.tex_v,
+            tex_u2: self.tex_u2,
+            tex_v2: self.tex_v2,
             color: self.color.clone(),
         }
     }
+
+    // Texture coordinates of UV set 1 or 2
+    pub fn uv(&self, set: u32) -> (f64, f64) {
+        if set == 2 {
+            (self.tex_u2, self.tex_v2)
+        } else {
+            (self.tex_u, self.tex_v)
+        }
+    }
+}
+
+//############################# MATRIX #############################
+
+// Row major 4x4 matrix, vectors are multiplied as columns from the right.
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Matrix4 {
+        Matrix4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn translation(v: &Vector4F) -> Matrix4 {
+        let mut result = Matrix4::identity();
+        result.m[0][3] = v.x;
+        result.m[1][3] = v.y;
+        result.m[2][3] = v.z;
+        result
+    }
+
+    pub fn scale(v: &Vector4F) -> Matrix4 {
+        let mut result = Matrix4::identity();
+        result.m[0][0] = v.x;
+        result.m[1][1] = v.y;
+        result.m[2][2] = v.z;
+        result
+    }
+
+    // Rotations use the same direction of rotation as Vector4F::rotate_x/y/z, angles in degrees.
+    pub fn rotation_x(angle: f64) -> Matrix4 {
+        let rads = (angle / 180.0) * PI;
+        let mut result = Matrix4::identity();
+        result.m[1][1] = rads.cos();
+        result.m[1][2] = -rads.sin();
+        result.m[2][1] = rads.sin();
+        result.m[2][2] = rads.cos();
+        result
+    }
+
+    pub fn rotation_y(angle: f64) -> Matrix4 {
+        let rads = (angle / 180.0) * PI;
+        let mut result = Matrix4::identity();
+        result.m[0][0] = rads.cos();
+        result.m[0][2] = -rads.sin();
+        result.m[2][0] = rads.sin();
+        result.m[2][2] = rads.cos();
+        result
+    }
+
+    pub fn rotation_z(angle: f64) -> Matrix4 {
+        let rads = (angle / 180.0) * PI;
+        let mut result = Matrix4::identity();
+        result.m[0][0] = rads.cos();
+        result.m[0][1] = -rads.sin();
+        result.m[1][0] = rads.sin();
+        result.m[1][1] = rads.cos();
+        result
+    }
+
+    // Scales first, then rotates around X, Y and Z and finally translates.
+    pub fn from_trs(translation: &Vector4F, rotation: &Vector4F, scale: &Vector4F) -> Matrix4 {
+        Matrix4::translation(translation)
+            .mul(&Matrix4::rotation_z(rotation.z))
+            .mul(&Matrix4::rotation_y(rotation.y))
+            .mul(&Matrix4::rotation_x(rotation.x))
+            .mul(&Matrix4::scale(scale))
+    }
+
+    // Matrix product, the result applies "other" first and then "self".
+    pub fn mul(&self, other: &Matrix4) -> Matrix4 {
+        let mut result = Matrix4 { m: [[0.0; 4]; 4] };
+        for row in 0..4 {
+            for col in 0..4 {
+                let mut sum = 0.0;
+                for i in 0..4 {
+                    sum += self.m[row][i] * other.m[i][col];
+                }
+                result.m[row][col] = sum;
+            }
+        }
+        result
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = Matrix4 { m: [[0.0; 4]; 4] };
+        for row in 0..4 {
+            for col in 0..4 {
+                result.m[row][col] = self.m[col][row];
+            }
+        }
+        result
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. Returns None if the matrix is singular,
+    // for example if a scale component is 0.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+
+        for col in 0..4 {
+            let mut pivot = col;
+            for row in (col + 1)..4 {
+                if a[row][col].abs() > a[pivot][col].abs() {
+                    pivot = row;
+                }
+            }
+
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for i in 0..4 {
+                a[col][i] /= p;
+                inv[col][i] /= p;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for i in 0..4 {
+                        a[row][i] -= f * a[col][i];
+                        inv[row][i] -= f * inv[col][i];
+                    }
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Vector4F) -> Vector4F {
+        let m = &self.m;
+        Vector4F {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+            w: 1.0,
+        }
+    }
+
+    // Ignores the translation. Does not normalize, so ray parameters stay the same when
+    // transforming a ray into another space.
+    pub fn transform_vector(&self, v: &Vector4F) -> Vector4F {
+        let m = &self.m;
+        Vector4F {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+            w: v.w,
+        }
+    }
+
+    pub fn clone(&self) -> Matrix4 {
+        Matrix4 { m: self.m }
+    }
+}
+
+// Placement of an object in the world. Keeps the inverse matrices around so they don't have to
+// be calculated for every ray.
+pub struct Transform {
+    pub object_to_world: Matrix4,
+    pub world_to_object: Matrix4,
+    // Inverse transpose of object_to_world, keeps normals perpendicular under non-uniform scale
+    pub normal_to_world: Matrix4,
+}
+
+impl Transform {
+    pub fn new(object_to_world: Matrix4) -> Option<Transform> {
+        let world_to_object = object_to_world.inverse()?;
+        let normal_to_world = world_to_object.transpose();
+
+        Some(Transform {
+            object_to_world,
+            world_to_object,
+            normal_to_world,
+        })
+    }
+
+    // Transforms a normal from object space to world space and normalizes it
+    pub fn transform_normal(&self, n: &Vector4F) -> Vector4F {
+        self.normal_to_world.transform_vector(n).normalize()
+    }
 }
 
 //############################# INTERSECTIONS #############################
 
+// Ray with the reciprocal of its direction, so box tests multiply instead of dividing for
+// every box. Hits are only accepted between min_t and max_t, in units of the direction.
+pub struct Ray {
+    pub org: Vector4F,
+    pub dir: Vector4F,
+    pub inv_dir: Vector4F,
+    pub min_t: f64,
+    pub max_t: f64,
+}
+
+impl Ray {
+    // Ray that accepts all hits in front of the origin
+    pub fn new(org: Vector4F, dir: Vector4F) -> Ray {
+        Ray::with_max_t(org, dir, std::f64::INFINITY)
+    }
+
+    pub fn with_max_t(org: Vector4F, dir: Vector4F, max_t: f64) -> Ray {
+        let inv_dir = Vector4F {
+            x: 1.0 / dir.x,
+            y: 1.0 / dir.y,
+            z: 1.0 / dir.z,
+            w: 0.0,
+        };
+
+        Ray {
+            org,
+            dir,
+            inv_dir,
+            min_t: 0.0,
+            max_t,
+        }
+    }
+}
+
 pub struct Intersection {
     pub pos: Vector4F,
     pub normal: Vector4F,
     pub tex_u: f64,
     pub tex_v: f64,
+    pub tex_u2: f64,
+    pub tex_v2: f64,
+    // Weights of the three vertices for triangle hits, in the order of the vertices
     pub barycentric: Vector4F,
     pub ray_t: f64,
 }
 
-// Intersects ray with sphere.
+impl Intersection {
+    // Texture coordinates of UV set 1 or 2
+    pub fn uv(&self, set: u32) -> (f64, f64) {
+        if set == 2 {
+            (self.tex_u2, self.tex_v2)
+        } else {
+            (self.tex_u, self.tex_v)
+        }
+    }
+}
+
+// Intersects ray with sphere. The direction of the ray is normalized, so the t range and the
+// returned ray_t are distances.
 //
-// p0: ray origin
-// d: ray direction, scaled by ray length
+// ray: the ray
 // c: sphere center
 // r: sphere radius
-// mint_t: minimum T value of ray. If intersection is bigger than this None is returned
-pub fn intersect_ray_sphere(
-    p0: &Vector4F,
-    d: &Vector4F,
-    c: &Vector4F,
-    r: f64,
-    min_t: f64,
-) -> Option<Intersection> {
-    let dnorm = d.normalize();
+pub fn intersect_ray_sphere(ray: &Ray, c: &Vector4F, r: f64) -> Option<Intersection> {
+    let p0 = &ray.org;
+    let dnorm = ray.dir.normalize();
 
     let e = c - p0;
 
@@ -378,7 +631,7 @@ pub fn intersect_ray_sphere(
 
     let t = a - f;
 
-    if t <= 0.0 || t > min_t {
+    if t <= ray.min_t || t > ray.max_t {
         return None;
     }
 
@@ -396,6 +649,8 @@ pub fn intersect_ray_sphere(
         normal: normal,
         tex_u: 0.0,
         tex_v: 0.0,
+        tex_u2: 0.0,
+        tex_v2: 0.0,
         barycentric: Vector4F {
             x: 0.0,
             y: 0.0,
@@ -408,43 +663,68 @@ pub fn intersect_ray_sphere(
     Some(result)
 }
 
-// Intersects ray with triangle.
+// Plane of a triangle, which does not depend on the ray and can be shared by a packet of rays.
+pub struct TrianglePlane {
+    normal: Vector4F,
+    d: f64,
+}
+
+impl TrianglePlane {
+    pub fn new(p0: &Vector4F, p1: &Vector4F, p2: &Vector4F) -> TrianglePlane {
+        let e1 = p1 - p0;
+        let e2 = p2 - p1;
+        let normal = Vector4F::cross(&e1, &e2);
+        let d = Vector4F::dot(&normal, p0);
+
+        TrianglePlane { normal, d }
+    }
+}
+
+// Intersects ray with the front side of a triangle.
 //
-// p0: ray origin
-// d: ray direction, scaled by ray length
-// v1: first vertex of triangle
-// v2: second vertex of triangle
-// v3: third vertex of triangle
-// mint_t: minimum T value of ray. If intersection is bigger than this None is returned
+// ray: the ray, hits outside of its t range are ignored
+// t0: first vertex of triangle
+// t1: second vertex of triangle
+// t2: third vertex of triangle
 pub fn intersect_ray_triangle(
-    rorg: &Vector4F,
-    rdir: &Vector4F,
+    ray: &Ray,
+    t0: &Vertex4F,
+    t1: &Vertex4F,
+    t2: &Vertex4F,
+) -> Option<Intersection> {
+    let plane = TrianglePlane::new(&t0.pos, &t1.pos, &t2.pos);
+    intersect_ray_triangle_plane(ray, t0, t1, t2, &plane)
+}
+
+// Same as intersect_ray_triangle(), with the plane of the triangle already computed.
+pub fn intersect_ray_triangle_plane(
+    ray: &Ray,
     t0: &Vertex4F,
     t1: &Vertex4F,
     t2: &Vertex4F,
-    min_t: f64,
+    plane: &TrianglePlane,
 ) -> Option<Intersection> {
+    let rorg = &ray.org;
+    let rdir = &ray.dir;
     let p0 = &t0.pos;
     let p1 = &t1.pos;
     let p2 = &t2.pos;
+    let n = &plane.normal;
 
-    let e1 = p1 - p0;
-    let e2 = p2 - p1;
-    let n = Vector4F::cross(&e1, &e2);
-    let dot = Vector4F::dot(&n, rdir);
+    let dot = Vector4F::dot(n, rdir);
 
     if !(dot < 0.0) {
         return None;
     }
 
-    let d = Vector4F::dot(&n, &p0);
-    let mut t = d - Vector4F::dot(&n, rorg);
+    let mut t = plane.d - Vector4F::dot(n, rorg);
 
-    if !(t <= 0.0) {
+    // t is not divided by the negative dot yet, so the comparisons are flipped
+    if !(t <= dot * ray.min_t) {
         return None;
     }
 
-    if !(t >= dot * min_t) {
+    if !(t >= dot * ray.max_t) {
         return None;
     }
 
@@ -533,38 +813,74 @@ pub fn intersect_ray_triangle(
         return None;
     }
 
+    // alpha and beta are the weights of t1 and t2, gamma is the weight of t0
     let n0 = &t0.normal;
-    let n1 = &t0.normal;
-    let n2 = &t0.normal;
+    let n1 = &t1.normal;
+    let n2 = &t2.normal;
 
     let normal = Vector4F {
-        x: n0.x * alpha + n1.x * beta + n2.x * gamma,
-        y: n0.y * alpha + n1.y * beta + n2.y * gamma,
-        z: n0.z * alpha + n1.z * beta + n2.z * gamma,
+        x: n0.x * gamma + n1.x * alpha + n2.x * beta,
+        y: n0.y * gamma + n1.y * alpha + n2.y * beta,
+        z: n0.z * gamma + n1.z * alpha + n2.z * beta,
         w: 1.0,
     };
 
     let result = Intersection {
         pos: p,
         normal: normal.normalize(),
-        tex_u: 0.0,
-        tex_v: 0.0,
-        barycentric: Vector4F::new(alpha, beta, gamma),
+        tex_u: t0.tex_u * gamma + t1.tex_u * alpha + t2.tex_u * beta,
+        tex_v: t0.tex_v * gamma + t1.tex_v * alpha + t2.tex_v * beta,
+        tex_u2: t0.tex_u2 * gamma + t1.tex_u2 * alpha + t2.tex_u2 * beta,
+        tex_v2: t0.tex_v2 * gamma + t1.tex_v2 * alpha + t2.tex_v2 * beta,
+        barycentric: Vector4F::new(gamma, alpha, beta),
         ray_t: t,
     };
 
     Some(result)
 }
 
-pub fn ray_intersects_aabb(
-    rorg: &Vector4F,
-    rdir: &Vector4F,
-    min: &Vector4F,
-    max: &Vector4F,
-) -> bool {
+// Checks if the ray crosses the triangle, no matter which side it faces.
+//
+// ray: the ray, crossings outside of its t range are ignored
+// p0, p1, p2: vertex positions of triangle
+pub fn ray_crosses_triangle(ray: &Ray, p0: &Vector4F, p1: &Vector4F, p2: &Vector4F) -> bool {
+    let rorg = &ray.org;
+    let rdir = &ray.dir;
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let p = Vector4F::cross(rdir, &e2);
+    let det = Vector4F::dot(&e1, &p);
+
+    // Ray is parallel to the triangle
+    if det.abs() < 1e-12 {
+        return false;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = rorg - p0;
+    let u = Vector4F::dot(&s, &p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return false;
+    }
+
+    let q = Vector4F::cross(&s, &e1);
+    let v = Vector4F::dot(rdir, &q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+
+    let t = Vector4F::dot(&e2, &q) * inv_det;
+    t > ray.min_t && t <= ray.max_t
+}
+
+// Checks if the ray hits the box within its t range
+pub fn ray_intersects_aabb(ray: &Ray, min: &Vector4F, max: &Vector4F) -> bool {
     //Source: https://www.scratchapixel.com/lessons/3d-basic-rendering/minimal-ray-tracer-rendering-simple-shapes/ray-box-intersection
-    let mut tmin = (min.x - rorg.x) / rdir.x;
-    let mut tmax = (max.x - rorg.x) / rdir.x;
+    let rorg = &ray.org;
+    let inv = &ray.inv_dir;
+
+    let mut tmin = (min.x - rorg.x) * inv.x;
+    let mut tmax = (max.x - rorg.x) * inv.x;
 
     if tmin > tmax {
         let tmp = tmin;
@@ -572,8 +888,8 @@ pub fn ray_intersects_aabb(
         tmax = tmp;
     }
 
-    let mut tymin = (min.y - rorg.y) / rdir.y;
-    let mut tymax = (max.y - rorg.y) / rdir.y;
+    let mut tymin = (min.y - rorg.y) * inv.y;
+    let mut tymax = (max.y - rorg.y) * inv.y;
 
     if tymin > tymax {
         let tmp = tymin;
@@ -593,8 +909,8 @@ pub fn ray_intersects_aabb(
         tmax = tymax;
     }
 
-    let mut tzmin = (min.z - rorg.z) / rdir.z;
-    let mut tzmax = (max.z - rorg.z) / rdir.z;
+    let mut tzmin = (min.z - rorg.z) * inv.z;
+    let mut tzmax = (max.z - rorg.z) * inv.z;
 
     if tzmin > tzmax {
         let tmp = tzmin;
@@ -605,24 +921,24 @@ pub fn ray_intersects_aabb(
     if tmin > tzmax || tzmin > tmax {
         return false;
     }
-    /*
+
     if tzmin > tmin {
-      tmin = tzmin;
+        tmin = tzmin;
     }
 
     if tzmax < tmax {
-      tmax = tzmax;
+        tmax = tzmax;
     }
-    */
-    true
+
+    tmin <= ray.max_t && tmax >= ray.min_t
 }
 
-pub fn intersect_ray_aabb2(
-    rorg: &Vector4F,
-    rdir: &Vector4F,
-    min: &Vector4F,
-    max: &Vector4F,
-) -> Option<Intersection> {
+// Intersects ray with the box from the outside. The t range of the ray is not used. If the ray
+// starts inside of the box, the hit is at the origin.
+pub fn intersect_ray_aabb2(ray: &Ray, min: &Vector4F, max: &Vector4F) -> Option<Intersection> {
+    let rorg = &ray.org;
+    let rdir = &ray.dir;
+    let inv = &ray.inv_dir;
     let mut inside = true;
 
     let mut xt;
@@ -632,7 +948,7 @@ pub fn intersect_ray_aabb2(
         /*if xt > rdir.x {
             return None;
         }*/
-        xt /= rdir.x;
+        xt *= inv.x;
         inside = false;
         xn = -1.0;
     }
@@ -641,7 +957,7 @@ pub fn intersect_ray_aabb2(
         /*if xt < rdir.x {
             return None;
         }*/
-        xt /= rdir.x;
+        xt *= inv.x;
         inside = false;
         xn = 1.0;
     }
@@ -656,7 +972,7 @@ pub fn intersect_ray_aabb2(
         /*if yt > rdir.y {
             return None;
         }*/
-        yt /= rdir.y;
+        yt *= inv.y;
         inside = false;
         yn = -1.0;
     }
@@ -665,7 +981,7 @@ pub fn intersect_ray_aabb2(
         /*if yt < rdir.y {
             return None;
         }*/
-        yt /= rdir.y;
+        yt *= inv.y;
         inside = false;
         yn = 1.0;
     }
@@ -680,7 +996,7 @@ pub fn intersect_ray_aabb2(
         /*if zt > rdir.z {
             return None;
         }*/
-        zt /= rdir.z;
+        zt *= inv.z;
         inside = false;
         zn = -1.0;
     }
@@ -689,7 +1005,7 @@ pub fn intersect_ray_aabb2(
         /*if zt < rdir.z {
             return None;
         }*/
-        zt /= rdir.z;
+        zt *= inv.z;
         inside = false;
         zn = 1.0;
     }
@@ -703,6 +1019,8 @@ pub fn intersect_ray_aabb2(
             normal: rdir.invert(),
             tex_u: 0.0,
             tex_v: 0.0,
+            tex_u2: 0.0,
+            tex_v2: 0.0,
             barycentric: Vector4F::null(),
             ray_t: 0.0,
         });
@@ -773,13 +1091,15 @@ pub fn intersect_ray_aabb2(
     }
 
     //The 0.999... will offset the intersection point to be a bit outside the AABB, towards the ray origin. Otherwise, the AABB will shadow itself.
-    let p = point_on_ray(&rorg, &rdir, t * 0.999999999999);
+    let p = point_on_ray(rorg, rdir, t * 0.999999999999);
 
     let result = Intersection {
         pos: p,
         normal: normal,
         tex_u: 0.0,
         tex_v: 0.0,
+        tex_u2: 0.0,
+        tex_v2: 0.0,
         barycentric: Vector4F::null(),
         ray_t: t,
     };
@@ -795,18 +1115,11 @@ pub fn point_on_ray(rorg: &Vector4F, rdir: &Vector4F, t: f64) -> Vector4F {
     )
 }
 
-pub fn intersect_ray_aabb(
-    rorg: &Vector4F,
-    rdir: &Vector4F,
-    min: &Vector4F,
-    max: &Vector4F,
-) -> Option<Intersection> {
-    let ndir = Vector4F {
-        x: 1.0 / rdir.x,
-        y: 1.0 / rdir.y,
-        z: 1.0 / rdir.z,
-        w: 1.0,
-    };
+// Intersects ray with the box within the t range of the ray
+pub fn intersect_ray_aabb(ray: &Ray, min: &Vector4F, max: &Vector4F) -> Option<Intersection> {
+    let rorg = &ray.org;
+    let rdir = &ray.dir;
+    let ndir = &ray.inv_dir;
 
     let t1 = (min.x - rorg.x) * ndir.x;
     let t2 = (max.x - rorg.x) * ndir.x;
@@ -826,7 +1139,7 @@ pub fn intersect_ray_aabb(
     tmin = f64::max(tmin, f64::min(f64::min(t1, t2), tmax));
     tmax = f64::min(tmax, f64::max(f64::max(t1, t2), tmin));
 
-    if tmax < f64::max(tmin, 0.0) {
+    if tmax < f64::max(tmin, ray.min_t) || tmin > ray.max_t {
         return None;
     }
 
@@ -865,6 +1178,8 @@ pub fn intersect_ray_aabb(
         normal: n,
         tex_u: 0.0,
         tex_v: 0.0,
+        tex_u2: 0.0,
+        tex_v2: 0.0,
         barycentric: Vector4F::null(),
         ray_t: tmin,
     };
@@ -955,3 +1270,77 @@ pub fn point_in_aabb(p: &Vector4F, min: &Vector4F, max: &Vector4F) -> bool {
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    fn assert_identity(m: &Matrix4) {
+        for row in 0..4 {
+            for col in 0..4 {
+                assert_close(m.m[row][col], if row == col { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    fn test_matrix() -> Matrix4 {
+        Matrix4::from_trs(
+            &Vector4F::new(1.0, -2.0, 3.0),
+            &Vector4F::new(30.0, 45.0, 60.0),
+            &Vector4F::new(2.0, 0.5, 3.0),
+        )
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let m = test_matrix();
+        let inv = m.inverse().unwrap();
+        assert_identity(&m.mul(&inv));
+        assert_identity(&inv.mul(&m));
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        let m = Matrix4::scale(&Vector4F::new(1.0, 0.0, 1.0));
+        assert!(m.inverse().is_none());
+    }
+
+    #[test]
+    fn vectors_ignore_the_translation() {
+        let m = Matrix4::translation(&Vector4F::new(1.0, 2.0, 3.0));
+        let p = m.transform_point(&Vector4F::new(1.0, 1.0, 1.0));
+        let v = m.transform_vector(&Vector4F::new(1.0, 1.0, 1.0));
+        assert_close(p.x, 2.0);
+        assert_close(p.y, 3.0);
+        assert_close(p.z, 4.0);
+        assert_close(v.x, 1.0);
+        assert_close(v.y, 1.0);
+        assert_close(v.z, 1.0);
+    }
+
+    #[test]
+    fn inverse_transforms_points_back() {
+        let m = test_matrix();
+        let inv = m.inverse().unwrap();
+        let p = Vector4F::new(0.3, -1.5, 4.0);
+        let back = inv.transform_point(&m.transform_point(&p));
+        assert_close(back.x, p.x);
+        assert_close(back.y, p.y);
+        assert_close(back.z, p.z);
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Transform::new(Matrix4::scale(&Vector4F::new(4.0, 1.0, 1.0))).unwrap();
+        // Surface along the diagonal of the XY plane
+        let tangent = Matrix4::scale(&Vector4F::new(4.0, 1.0, 1.0))
+            .transform_vector(&Vector4F::new(1.0, -1.0, 0.0));
+        let normal = transform.transform_normal(&Vector4F::new(1.0, 1.0, 0.0));
+        assert_close(Vector4F::dot(&tangent, &normal), 0.0);
+        assert_close(normal.len(), 1.0);
+    }
+}
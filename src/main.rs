@@ -1,482 +1,577 @@
-extern crate num_cpus;
-extern crate rand;
-extern crate time;
-
-pub mod linear;
-pub mod random;
-pub mod settings;
-pub mod shade;
-
-mod json;
-mod obj;
-mod octree;
-mod stopwatch;
-mod tga;
-mod vox;
-
-use linear::Intersection;
-use linear::Vector4F;
-use random::Random;
-use settings::Color;
-use settings::Intersectable;
-use settings::LightType;
-use settings::Scene;
-use settings::Settings;
+extern crate xtracer;
+
 use std::fs::File;
 use std::io::Read;
-use std::sync::mpsc;
-use std::sync::Arc;
-use std::thread;
-use stopwatch::StopWatch;
-
-const HALF_SECOND: u64 = 500000000;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use xtracer::json;
+use xtracer::pfm;
+use xtracer::settings::Settings;
+use xtracer::stopwatch::StopWatch;
+use xtracer::tga;
+use xtracer::writer;
+use xtracer::Checkpoint;
+use xtracer::Framebuffer;
+use xtracer::ProgressFormat;
+
+//Failed pixels listed at the end of a render, the count includes all of them
+const MAX_FAILED_PIXELS_SHOWN: usize = 10;
 
 fn main() {
-    let settings = load_settings();
-
-    let cam_pos = Vector4F {
-        x: 0.0,
-        y: 0.0,
-        z: 0.0,
-        w: 1.0,
-    };
-
-    let img_plane_dist = 1.0;
-
-    let img_w = settings.output.width;
-    let img_h = settings.output.height;
-
-    //Calculate image plane dimensions
-    let img_ratio = img_w as f64 / img_h as f64;
-    let img_plane_w = img_plane_dist / 2.0;
-    let img_plane_h = img_plane_w / img_ratio;
-    let img_plane_l = cam_pos.x - (img_plane_w / 2.0);
-    let img_plane_b = cam_pos.y - (img_plane_h / 2.0);
-
-    //Calculate pixel vertical and horizontal increment
-    let img_pix_inc_h = img_plane_w / img_w as f64;
-    let img_pix_inc_v = img_plane_h / img_h as f64;
-
-    //Pre-calculate values for multi sampling
-    let samplesi = settings.output.samples;
-    let samples = samplesi as f64;
-    let samples2 = (samples * samples) as f32;
-    let sample_width = img_pix_inc_h / samples;
-    let sample_offset = (img_pix_inc_h / 2.0) - (sample_width / 2.0);
-
-    let arc_settings = Arc::new(settings);
-    let arc_cam_pos = Arc::new(cam_pos);
-
-    let numcpus = num_cpus::get();
-    //let numcpus = 1;
-    println!("Number of CPUs: {}", numcpus);
+    if std::env::args().nth(1).as_ref().map(String::as_str) == Some("selftest") {
+        selftest();
+    }
 
-    let num_values = img_h * img_w * 3;
-    let mut final_buffer = vec![0.0f32; num_values as usize];
+    //Time of each phase, printed at the end
+    let mut phases = Vec::new();
 
     let mut total_watch = StopWatch::new();
     total_watch.start();
 
     let mut stop_watch = StopWatch::new();
     stop_watch.start();
+    let (settings, resume) = load_settings();
+    stop_watch.stop();
+    phases.push(("Load", stop_watch.get_millis()));
 
-    let mut last_time = time::precise_time_ns();
-    let mut lines_done = 0;
-    let mut py = img_plane_b;
-
-    let mut num_threads = 0;
-    let mut iy = 0;
-
-    let (tx, rx) = mpsc::channel();
-
-    while iy < img_h {
-        while num_threads < numcpus && iy < img_h {
-            let larc_settings = arc_settings.clone();
-            let larc_cam_pos = arc_cam_pos.clone();
-            let ltx = mpsc::Sender::clone(&tx);
-            let liy = iy;
-
-            thread::spawn(move || {
-                let mut random = Random::new();
-                let mut px = img_plane_l;
-
-                let num_values = (img_w * 3) as usize;
-                let mut colors = Vec::with_capacity(num_values);
-
-                for _ix in 0..img_w {
-                    //Create sample grid of samples * samples sub-pixels
-                    let sub_pix_l = px - sample_offset;
-                    let sub_pix_b = py - sample_offset;
-
-                    let mut pcr = 0.0;
-                    let mut pcg = 0.0;
-                    let mut pcb = 0.0;
-
-                    let steps = larc_settings.output.samples;
-                    let mut spy = sub_pix_b;
-                    for _spy in 0..steps {
-                        let mut spx = sub_pix_l;
-                        for _spx in 0..steps {
-                            let pixel = Vector4F {
-                                x: spx,
-                                y: spy,
-                                z: img_plane_dist,
-                                w: 0.0,
-                            };
-
-                            let ray_dir = (&pixel - &larc_cam_pos).normalize();
-                            let pc = trace(
-                                &larc_cam_pos,
-                                &ray_dir,
-                                &larc_settings.scene,
-                                &mut random,
-                                0,
-                            );
-
-                            pcr += pc.r;
-                            pcg += pc.g;
-                            pcb += pc.b;
-
-                            spx += sample_width;
-                        }
-                        spy += sample_width;
-                    }
-
-                    colors.push(pcb / samples2);
-                    colors.push(pcg / samples2);
-                    colors.push(pcr / samples2);
-
-                    px += img_pix_inc_h;
-                }
-
-                ltx.send((liy, colors)).unwrap();
-            });
-
-            num_threads += 1;
-            py += img_pix_inc_v;
-            iy += 1;
-        }
-
-        //Read back results from threads
-        let mut rxv = rx.try_recv();
-        while rxv.is_ok() {
-            let result = rxv.unwrap();
-            let line = result.0 as usize;
+    if settings.bake.is_some() {
+        bake(&settings);
+        return;
+    }
 
-            let stride = img_w as usize * 3;
-            let start = line * stride;
-            let end = start + stride;
+    let img_w = settings.output.width;
+    let img_h = settings.output.height;
 
-            let new = &result.1;
+    stop_watch.start();
 
-            let mut nl = 0;
-            for l in start..end {
-                final_buffer[l] += new[nl];
-                nl += 1;
-            }
+    let mut framebuffer = if settings.output.passes > 1 || resume {
+        render_progressive(&settings, resume)
+    } else {
+        xtracer::render(&settings)
+    };
 
-            num_threads -= 1;
-            lines_done += 1;
-            rxv = rx.try_recv();
-        }
+    stop_watch.stop();
+    let render_millis = stop_watch.get_millis();
+    phases.push(("Render", render_millis));
 
-        let this_time = time::precise_time_ns();
-        let diff = this_time - last_time;
-        if diff > HALF_SECOND {
-            let mut percent = (lines_done as f64 / img_h as f64) * 100.0;
-            percent = (percent * 100.0).round() / 100.0;
-            println!("{} %", percent);
-            last_time = this_time;
-        }
+    if settings.output.denoise.is_some() {
+        stop_watch.start();
+        xtracer::denoise(&mut framebuffer, &settings);
+        stop_watch.stop();
+        phases.push(("Denoise", stop_watch.get_millis()));
     }
 
-    //Read all the rest (blocking)
-    while num_threads > 0 {
-        let rxv = rx.recv();
-        let result = rxv.unwrap();
-        let line = result.0 as usize;
+    let samplesi = settings.output.samples;
+    let lens_samples = settings.camera.lens_samples;
+    let path_samples = settings.scene.path_samples * settings.scene.path_samples;
+    let traced_depth = match &settings.scene.hybrid_gi {
+        Some(hybrid) => hybrid.full_bounces.min(settings.scene.max_diffuse_depth),
+        None => settings.scene.max_diffuse_depth,
+    };
+    let spp = (samplesi * samplesi)
+        * (lens_samples * lens_samples)
+        * (path_samples.pow(traced_depth))
+        * settings.output.passes;
 
-        let stride = img_w as usize * 3;
-        let start = line * stride;
-        let end = start + stride;
+    eprintln!("=========================");
 
-        let new = &result.1;
+    if let Some(ev100) = settings.camera.ev100() {
+        eprintln!("Exposure: EV100 {:.2}", ev100);
+    }
 
-        let mut nl = 0;
-        for l in start..end {
-            final_buffer[l] += new[nl];
-            nl += 1;
+    let mut lines = Vec::new();
+    if let Some(burn_in) = &settings.output.burn_in {
+        let mut first = burn_in.scene.clone();
+        if let Some(frame) = burn_in.frame.or(settings.output.frame) {
+            first = format!("{}  FRAME {}", first, frame);
         }
 
-        num_threads -= 1;
+        let camera = &settings.camera;
+        let fov = (camera.image_plane_width() * 0.5).atan().to_degrees() * 2.0;
+        //Fields of the packed vector can't be borrowed by format!
+        let (x, y, z) = (camera.position.x, camera.position.y, camera.position.z);
+        lines = vec![
+            first,
+            format!("{} SPP  {:.2}S", spp, render_millis / 1000.0),
+            format!("CAM {:.2} {:.2} {:.2}  FOV {:.1}", x, y, z, fov),
+        ];
     }
 
-    stop_watch.stop();
-    let render_millis = stop_watch.get_millis();
-    println!("Render time: {}ms", render_millis);
-
-    println!("=========================");
+    let metadata = if settings.output.metadata {
+        format!(
+            "xtracer {}; scene {:016x}; spp {}; render {}ms",
+            env!("CARGO_PKG_VERSION"),
+            settings.hash,
+            spp,
+            render_millis.round()
+        )
+    } else {
+        String::new()
+    };
 
     stop_watch.start();
-    let mut pixels = Vec::with_capacity(((img_w * img_h) * 3) as usize);
-    let mut rand = Random::new();
-    for line in &final_buffer {
-        pixels.push(convert(*line, &mut rand));
-    }
-    stop_watch.stop();
-    println!("Convert time: {}ms", stop_watch.get_millis());
+    let filename = settings.output.filename.as_str();
+    writer::image_writer(&settings, lines).write(filename, &framebuffer, metadata.as_str());
 
-    stop_watch.start();
-    tga::write_tga(
-        arc_settings.output.filename.as_str(),
-        img_w as u16,
-        img_h as u16,
-        pixels.as_slice(),
-    );
+    for (aov, buffer) in settings.output.aovs.iter().zip(framebuffer.aovs.iter()) {
+        if settings.output.alpha && aov.name() == "alpha" {
+            //Already written as alpha channel of the image
+            continue;
+        }
+        if aov.is_float() {
+            //Data that does not fit into 0...1, like positions and light
+            let filename = aov_filename(settings.output.filename.as_str(), aov.name(), "pfm");
+            pfm::write_pfm(filename.as_str(), img_w, img_h, aov.channels(), buffer);
+            continue;
+        }
+
+        let filename = aov_filename(settings.output.filename.as_str(), aov.name(), "tga");
+        let mut aov_pixels = Vec::with_capacity(buffer.len() * 3);
+        for v in buffer {
+            //AOVs are data, so they are written without gamma correction and noise
+            let b = (v.max(0.0).min(1.0) * 255.0).round() as u8;
+            aov_pixels.push(b);
+            aov_pixels.push(b);
+            aov_pixels.push(b);
+        }
+        tga::write_tga_with_options(
+            filename.as_str(),
+            img_w as u16,
+            img_h as u16,
+            metadata.as_str(),
+            aov_pixels.as_slice(),
+            &writer::tga_options(&settings, false),
+        );
+    }
+    write_id_legend(&settings);
     stop_watch.stop();
-    println!("Write time: {}ms", stop_watch.get_millis());
+    phases.push(("Write", stop_watch.get_millis()));
 
-    println!("=========================");
+    eprintln!("=========================");
     total_watch.stop();
-    println!("TOTAL: {}ms", total_watch.get_millis());
+    let total_millis = total_watch.get_millis();
+    for (name, millis) in &phases {
+        let percent = millis / total_millis * 100.0;
+        eprintln!("{:<8}: {:.1}ms ({:.1} %)", name, millis, percent);
+    }
+    eprintln!("TOTAL   : {:.1}ms", total_millis);
 
-    let path_samples = arc_settings.scene.path_samples * arc_settings.scene.path_samples;
-    let spp = (samplesi * samplesi) * (path_samples.pow(arc_settings.scene.max_depth));
-    println!("Samples Per Pixel : {}", spp);
+    eprintln!("Samples Per Pixel : {}", spp);
 
     let samples_total = spp * img_w * img_h;
-    println!("Samples Total     : {}", samples_total);
+    eprintln!("Samples Total     : {}", samples_total);
 
     let sample_per_second = samples_total as f64 / (render_millis / 1000.0);
-    println!("Samples Per Second: {}", sample_per_second.round());
-}
-
-//Checks if the given ray (ray_org -> ray_dir) intersects any of the objects in the given vec and returns the closest point of intersection and the corresponding object.
-fn intersect<'a>(
-    ray_org: &Vector4F,
-    ray_dir: &Vector4F,
-    objects: &'a Vec<&Intersectable>,
-) -> (Option<Intersection>, Option<&'a Intersectable>) {
-    let mut closest = None;
-    let mut closest_object = None;
-    let mut min_t = std::f64::MAX;
-
-    for obj in objects {
-        let intersection = obj.intersect(ray_org, ray_dir, min_t);
-
-        if intersection.is_some() {
-            let inter = intersection.unwrap();
-
-            if inter.ray_t < min_t {
-                min_t = inter.ray_t;
-                closest = Some(inter);
-                closest_object = Some(*obj);
+    eprintln!("Samples Per Second: {}", sample_per_second.round());
+
+    if !framebuffer.failed_pixels.is_empty() {
+        let shown: Vec<String> = framebuffer
+            .failed_pixels
+            .iter()
+            .take(MAX_FAILED_PIXELS_SHOWN)
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect();
+        let more = framebuffer.failed_pixels.len() - shown.len();
+        eprintln!(
+            "Failed Pixels     : {} ({}{})",
+            framebuffer.failed_pixels.len(),
+            shown.join(" "),
+            if more > 0 {
+                format!(" and {} more", more)
+            } else {
+                String::new()
             }
-        }
+        );
     }
-
-    //println!("min_t: {}", min_t);
-
-    (closest, closest_object)
 }
 
-//Checks if the given ray (ray_org -> ray_dir) intersects any of the objects in the given vec.
-fn intersect_any(ray_org: &Vector4F, ray_dir: &Vector4F, objects: &Vec<&Intersectable>) -> bool {
-    for obj in objects {
-        if obj.intersect(ray_org, ray_dir, std::f64::MAX).is_some() {
-            return true;
-        }
+//Renders the scene built into the renderer and checks the image, to see if an installation works.
+//Exits with status 0 if it does and 1 otherwise.
+fn selftest() -> ! {
+    let problems = xtracer::selftest();
+    if problems.is_empty() {
+        eprintln!("Self test passed");
+        std::process::exit(0);
     }
 
-    false
+    eprintln!("Self test failed:");
+    for problem in problems {
+        eprintln!("  {}", problem);
+    }
+    std::process::exit(1);
 }
 
-//Traces the given ray (ray_org -> ray_dir) from the camera into the scene, shading and recursivly path tracing accordingly. Returns the color of the pixel.
-fn trace(
-    ray_org: &Vector4F,
-    ray_dir: &Vector4F,
-    scene: &Scene,
-    random: &mut Random,
-    depth: u32,
-) -> Color {
-    let mut result = Color::black();
-
-    if depth > scene.max_depth {
-        return result;
-    }
-
-    let objects = scene.objects();
-
-    let inter = intersect(ray_org, ray_dir, &objects);
-    let closest = inter.0;
-    let closest_object = inter.1;
-
-    if closest.is_some() {
-        let inter = closest.unwrap();
-        let object = closest_object.unwrap();
-        //let vdir = (ray_org - &inter.pos).normalize();
-
-        let mat_name = object.material();
-        let mut material = None;
-        for mat in &scene.materials {
-            if mat.id == mat_name {
-                material = Some(mat);
-                break;
-            }
-        }
+//Bakes the maps set in the bake settings instead of rendering the scene. Like AOVs, the maps are
+//data and written without gamma correction.
+fn bake(settings: &Settings) {
+    let bake = settings.bake.as_ref().unwrap();
 
-        if material.is_some() {
-            let mat = material.unwrap();
-            let mut lcolor = Color::black();
-
-            for light in &scene.lights {
-                let mut light_intens = 0.0;
-                let ldir = (&light.position - &inter.pos).normalize();
-
-                if let LightType::Point = light.ltype {
-                    light_intens = if intersect_any(&inter.pos, &ldir, &objects) {
-                        0.0
-                    } else {
-                        1.0
-                    };
-                } else if let LightType::Sphere = light.ltype {
-                    let mut v = 0.0;
-
-                    for _sample in 0..light.samples {
-                        let rand_pos = random.random_point_on_sphere(&light.position, light.radius);
-                        let sample_dir = &rand_pos - &inter.pos;
-
-                        if !intersect_any(&inter.pos, &sample_dir, &objects) {
-                            v = v + 1.0;
-                        }
-                    }
-
-                    light_intens = v / (light.samples as f64);
-                }
+    let mut stop_watch = StopWatch::new();
+    stop_watch.start();
+    let maps = xtracer::bake(settings);
+    stop_watch.stop();
+    eprintln!("Bake time: {}ms", stop_watch.get_millis());
 
-                //Realistic inverse-square light attenuation
-                let ldist = (&light.position - &inter.pos).len();
-                let ratio = light.radius / ldist;
-                light_intens = (ratio * ratio) * light_intens * light.intensity;
+    let to_byte = |v: f32| (v.max(0.0).min(1.0) * 255.0).round() as u8;
+    let size = maps.size as u16;
 
-                /*let diffuse = shade::shade_oren_nayar(&ldir, &inter.normal, &vdir, mat.roughness, 0.01);
-                let specular = shade::shade_cook_torrance(&ldir, &vdir, &inter.normal, mat.roughness, 0.01);
-                let shading = diffuse + specular;*/
+    if !bake.normal_map.is_empty() {
+        let mut pixels = Vec::with_capacity(maps.normals.len());
+        for n in maps.normals.chunks(3) {
+            pixels.push(to_byte(n[2]));
+            pixels.push(to_byte(n[1]));
+            pixels.push(to_byte(n[0]));
+        }
+        tga::write_tga(bake.normal_map.as_str(), size, size, pixels.as_slice());
+    }
 
-                let shading = shade::shade_lambert(&ldir, &inter.normal);
+    if !bake.ao_map.is_empty() {
+        let mut pixels = Vec::with_capacity(maps.ao.len() * 3);
+        for v in &maps.ao {
+            let b = to_byte(*v);
+            pixels.push(b);
+            pixels.push(b);
+            pixels.push(b);
+        }
+        tga::write_tga(bake.ao_map.as_str(), size, size, pixels.as_slice());
+    }
+}
 
-                let light_total = shading * light_intens;
+//Appends the AOV name to the output file name and replaces the extension,
+//"render.tga" becomes "render_ao.tga".
+fn aov_filename(filename: &str, aov_name: &str, extension: &str) -> String {
+    match filename.rfind('.') {
+        Some(dot) => format!("{}_{}.{}", &filename[..dot], aov_name, extension),
+        None => format!("{}_{}.{}", filename, aov_name, extension),
+    }
+}
 
-                lcolor.r += light.color.r * light_total as f32;
-                lcolor.g += light.color.g * light_total as f32;
-                lcolor.b += light.color.b * light_total as f32;
-            }
+//Writes the names behind the values of the object and material id AOVs to a JSON file next to
+//the output, "render.tga" gets "render_ids.json". Nothing is written without id AOVs.
+fn write_id_legend(settings: &Settings) {
+    let aovs = &settings.output.aovs;
+    let objects = aovs.iter().any(|aov| aov.name() == "object_id");
+    let materials = aovs.iter().any(|aov| aov.name() == "material_id");
+    if !objects && !materials {
+        return;
+    }
 
-            if scene.path_samples > 0 {
-                let mut path_color = Color::black();
+    let scene = &settings.scene;
+    let mut sections = Vec::new();
+    if objects {
+        let entries: Vec<String> = scene
+            .objects()
+            .iter()
+            .enumerate()
+            .map(|(i, obj)| {
+                format!(
+                    "    \"{}\": {{\"name\": {}, \"material\": {}}}",
+                    i + 1,
+                    json_string(obj.name()),
+                    json_string(obj.material_name())
+                )
+            })
+            .collect();
+        sections.push(format!(
+            "  \"object_id\": {{\n{}\n  }}",
+            entries.join(",\n")
+        ));
+    }
+    if materials {
+        let entries: Vec<String> = scene
+            .materials
+            .iter()
+            .chain(std::iter::once(&scene.default_material))
+            .enumerate()
+            .map(|(i, mat)| format!("    \"{}\": {}", i + 1, json_string(mat.id.as_str())))
+            .collect();
+        sections.push(format!(
+            "  \"material_id\": {{\n{}\n  }}",
+            entries.join(",\n")
+        ));
+    }
 
-                let sample_dirs = random.random_directions_in_hemisphere(scene.path_samples, &inter.normal);
-                for sdir in &sample_dirs {
-                    let pc = trace(&inter.pos, sdir, scene, random, depth + 1);
+    let filename = aov_filename(settings.output.filename.as_str(), "ids", "json");
+    let text = format!("{{\n{}\n}}\n", sections.join(",\n"));
+    if let Err(e) = std::fs::write(&filename, text) {
+        eprintln!("Cannot write id legend {}: {}", filename, e);
+    }
+}
 
-                    let shading = shade::shade_lambert(sdir, &inter.normal);
+//Quotes the string for JSON, escaping quotes, backslashes and control characters
+fn json_string(s: &str) -> String {
+    let mut result = String::with_capacity(s.len() + 2);
+    result.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
 
-                    path_color.r += pc.r * shading as f32;
-                    path_color.g += pc.g * shading as f32;
-                    path_color.b += pc.b * shading as f32;
+//Renders the progressive passes. The average of the finished passes is written to the output file
+//every preview interval, so the render can be stopped once it looks good enough, and the sums to
+//the checkpoint file every checkpoint interval. With resume, the render continues after the passes
+//saved in the checkpoint file.
+fn render_progressive(settings: &Settings, resume: bool) -> Framebuffer {
+    let output = &settings.output;
+    let passes = output.passes;
+    let checkpoint_file = output.checkpoint.as_ref().map(|f| f.as_str());
+
+    let mut checkpoint = Checkpoint::new(settings);
+    if resume {
+        //load_settings() made sure there is a checkpoint file
+        let filename = checkpoint_file.unwrap();
+        if Path::new(filename).exists() {
+            checkpoint = match Checkpoint::read(filename) {
+                Ok(c) => c,
+                Err(message) => {
+                    eprintln!("{}", message);
+                    std::process::exit(1);
                 }
+            };
+            if let Err(message) = checkpoint.check(settings) {
+                eprintln!("Cannot resume from {}: {}", filename, message);
+                std::process::exit(1);
+            }
+            eprintln!(
+                "Resuming after {} of {} passes from {}",
+                checkpoint.passes, passes, filename
+            );
+        } else {
+            eprintln!(
+                "No checkpoint at {}, starting with the first pass",
+                filename
+            );
+        }
+    }
 
-                /*for _ps in 0..scene.path_samples {
-                    let path_dir = random.random_point_on_hemisphere(&inter.normal);
-                    let pc = trace(&inter.pos, &path_dir, scene, random, depth + 1);
-
-                    /*let diffuse = shade::shade_oren_nayar(&path_dir, &inter.normal, &vdir, mat.roughness, 0.1);
-                    let specular = shade::shade_cook_torrance(&path_dir, &vdir, &inter.normal, mat.roughness, 0.1);
-                    let shading = diffuse + specular;*/
-
-                    let shading = shade::shade_lambert(&path_dir, &inter.normal);
-
-                    path_color.r += pc.r * shading as f32;
-                    path_color.g += pc.g * shading as f32;
-                    path_color.b += pc.b * shading as f32;
-                }*/
-
-                //let ps = 1.0 / (scene.path_samples as f32);
-                let ps = 1.0 / (sample_dirs.len() as f32);
-
-                path_color.r *= ps;
-                path_color.g *= ps;
-                path_color.b *= ps;
+    let mut preview_watch = StopWatch::new();
+    preview_watch.start();
+    let mut checkpoint_watch = StopWatch::new();
+    checkpoint_watch.start();
 
-                lcolor.r += path_color.r;
-                lcolor.g += path_color.g;
-                lcolor.b += path_color.b;
-            }
+    let cancel = AtomicBool::new(false);
+    xtracer::render_progressive(settings, &cancel, checkpoint, |framebuffer, checkpoint| {
+        let done = checkpoint.passes;
+        if done == passes {
+            return;
+        }
 
-            //Enabling this only shows GI
-            /*if depth == 0 {
-                result.r = path_color.r;
-                result.g = path_color.g;
-                result.b = path_color.b;
+        checkpoint_watch.stop();
+        if let Some(filename) = checkpoint_file {
+            if checkpoint_watch.get_millis() >= output.checkpoint_interval * 1000.0 {
+                match checkpoint.write(filename) {
+                    Ok(()) => eprintln!("Checkpoint of {} passes written to {}", done, filename),
+                    Err(message) => eprintln!("{}", message),
+                }
+                checkpoint_watch.start();
             }
-            else {*/
-            result.r = mat.color.r * lcolor.r;
-            result.g = mat.color.g * lcolor.g;
-            result.b = mat.color.b * lcolor.b;
-        //}
-        } else {
-            //If no material could be found, color is black
-            println!("Material not found: {}", mat_name);
-
-            result.r = 0.0;
-            result.g = 0.0;
-            result.b = 0.0;
         }
-    } else {
-        result.r = scene.skycolor.r;
-        result.g = scene.skycolor.g;
-        result.b = scene.skycolor.b;
-    }
 
-    result
+        preview_watch.stop();
+        if preview_watch.get_millis() >= output.preview_interval * 1000.0 {
+            let filename = output.filename.as_str();
+            writer::image_writer(settings, Vec::new()).write(filename, framebuffer, "");
+            eprintln!(
+                "Preview of {} of {} passes written to {}",
+                done, passes, filename
+            );
+            preview_watch.start();
+        }
+    })
 }
 
-fn load_settings() -> Settings {
+//Reads the settings file given on the command line, or settings.json if there is none.
+//The output settings can be overridden with command line flags:
+//  xtracer [settings.json] [--width N] [--height N] [--samples N] [--output FILE] [--frame N]
+//          [--asset-helper COMMAND] [--debug-pixel X,Y] [--preset NAME] [--progress FORMAT]
+//          [--resume]
+//The preset (draft, medium or final) sets the quality settings, flags like --samples override it.
+//With --resume a progressive render continues from its checkpoint file. Returns the settings and
+//if --resume was given.
+//The asset helper is run for every file with a custom scheme like "asset://props/chair.obj",
+//with that path as its only argument. It prints the name of the local file to use.
+fn load_settings() -> (Settings, bool) {
     let args: Vec<_> = std::env::args().collect();
     let mut filename = "settings.json";
-    if args.len() > 1 {
-        filename = args[1].as_str();
+    let mut width = None;
+    let mut height = None;
+    let mut samples = None;
+    let mut output = None;
+    let mut frame = None;
+    let mut asset_helper = None;
+    let mut debug_pixel = None;
+    let mut preset = None;
+    let mut progress = None;
+    let mut resume = false;
+
+    //Flags are checked before the scene is loaded, so typos are reported right away
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--resume" {
+            resume = true;
+            i += 1;
+        } else if arg.starts_with("--") {
+            if i + 1 >= args.len() {
+                usage_error(format!("Missing value for {}", arg).as_str());
+            }
+            let value = args[i + 1].as_str();
+            match arg {
+                "--width" => width = Some(parse_positive(arg, value)),
+                "--height" => height = Some(parse_positive(arg, value)),
+                "--samples" => samples = Some(parse_positive(arg, value)),
+                "--output" => output = Some(value.to_string()),
+                "--frame" => match value.parse() {
+                    Ok(v) => frame = Some(v),
+                    _ => usage_error(format!("--frame must be a number, got {}", value).as_str()),
+                },
+                "--asset-helper" => asset_helper = Some(value.to_string()),
+                "--debug-pixel" => debug_pixel = Some(parse_pixel(arg, value)),
+                "--preset" => preset = Some(value.to_string()),
+                "--progress" => match ProgressFormat::from_name(value) {
+                    Some(format) => progress = Some(format),
+                    None => usage_error(
+                        format!(
+                            "--progress must be percent, bar, json or quiet, got {}",
+                            value
+                        )
+                        .as_str(),
+                    ),
+                },
+                _ => usage_error(format!("Unknown option {}", arg).as_str()),
+            }
+            i += 2;
+        } else {
+            filename = arg;
+            i += 1;
+        }
     }
 
     let mut file = File::open(filename).unwrap();
     let mut json = String::new();
     file.read_to_string(&mut json).unwrap();
 
-    let json_object = json::parse_json(&json);
-    if let Some(object) = json_object {
-        return Settings::from_json(object).unwrap();
+    let json_object = match json::parse_json(&json) {
+        Ok(object) => object,
+        Err(e) => {
+            eprintln!("Error in {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+
+    let settings = match &asset_helper {
+        Some(helper) => {
+            Settings::from_json_with_resolver(json_object, &|path| run_asset_helper(helper, path))
+        }
+        None => Settings::from_json(json_object),
+    };
+    let mut settings = match settings {
+        Ok(settings) => settings,
+        Err(problems) => {
+            eprintln!("Problems in {}:", filename);
+            for problem in problems {
+                eprintln!("  {}", problem);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(name) = preset {
+        if let Err(message) = xtracer::apply_preset(&mut settings, name.as_str()) {
+            usage_error(message.as_str());
+        }
+    }
+    if let Some(w) = width {
+        settings.output.width = w;
+    }
+    if let Some(h) = height {
+        settings.output.height = h;
+    }
+    if let Some(s) = samples {
+        settings.output.samples = s;
+    }
+    if let Some(o) = output {
+        settings.output.filename = o;
+    }
+    if frame.is_some() {
+        settings.set_frame(frame);
+    }
+    if debug_pixel.is_some() {
+        settings.output.debug_pixel = debug_pixel;
+    }
+    if let Some(format) = progress {
+        settings.output.progress = format;
     }
 
-    panic!("Unable to read settings!");
+    //The size can be overridden too, so the pixel is checked against the final size
+    if let Some((x, y)) = settings.output.debug_pixel {
+        if x >= settings.output.width || y >= settings.output.height {
+            usage_error(format!("Debug pixel {},{} is outside of the image", x, y).as_str());
+        }
+    }
+    if resume && settings.output.checkpoint.is_none() {
+        usage_error("--resume needs a checkpoint file in the output settings");
+    }
+
+    //The burn-in names the render after the settings file if the scene has no name
+    if let Some(burn_in) = &mut settings.output.burn_in {
+        if burn_in.scene.is_empty() {
+            if let Some(stem) = Path::new(filename).file_stem() {
+                burn_in.scene = stem.to_string_lossy().to_string();
+            }
+        }
+    }
+
+    (settings, resume)
 }
 
-fn convert(v: f32, rand: &mut Random) -> u8 {
-    let mut result = v;
+//Runs the asset helper for the path and returns the local file it printed
+fn run_asset_helper(helper: &str, path: &str) -> Result<String, String> {
+    let output = match Command::new(helper).arg(path).output() {
+        Ok(output) => output,
+        Err(e) => return Err(format!("can't run {}: {}", helper, e)),
+    };
 
-    //Gamma correction
-    result = result.powf(1.0 / 1.8);
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} failed: {}", helper, error.trim()));
+    }
 
-    //Add some slight random noise to reduce banding
-    let r = (rand.random_f() * 2.0 - 1.0) as f32;
-    result = result + (r * (1.0 / 512.0));
+    let local = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if local.is_empty() {
+        return Err(format!("{} printed no file", helper));
+    }
+    Ok(local)
+}
 
-    if result < 0.0 {
-        result = 0.0;
-    } else if result > 1.0 {
-        result = 1.0;
+fn parse_positive(flag: &str, value: &str) -> u32 {
+    match value.parse() {
+        Ok(v) if v > 0 => v,
+        _ => usage_error(format!("{} must be a positive number, got {}", flag, value).as_str()),
     }
+}
 
-    result = result * 255.0;
+fn parse_pixel(flag: &str, value: &str) -> (u32, u32) {
+    let mut parts = value.split(',').map(|v| v.trim().parse());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(x)), Some(Ok(y)), None) => (x, y),
+        _ => usage_error(format!("{} must be X,Y, got {}", flag, value).as_str()),
+    }
+}
 
-    result.round() as u8
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!(
+        "Usage: xtracer [settings.json] [--width N] [--height N] [--samples N] [--output FILE] [--frame N] [--asset-helper COMMAND] [--debug-pixel X,Y] [--preset NAME] [--progress FORMAT] [--resume]"
+    );
+    eprintln!("       xtracer selftest");
+    std::process::exit(1);
 }
@@ -10,9 +10,20 @@ struct Vertex {
     ti: usize,
 }
 
+//Triangles of one group ("g" or "o" line) of an OBJ file that use the same material.
+//Each pair of three vertices in a row form a triangle.
+pub struct ObjGroup {
+    pub name: String,
+    //Material of the faces set with "usemtl", empty if there is none
+    pub material: String,
+    pub vertices: Vec<Vertex4F>,
+}
+
 //Loads triangles from an OBJ file. Only triangles are supported.
-//In the returned vec, each pair of three values in a row form a triangle.
-pub fn load_obj(filename: &str) -> Vec<Vertex4F> {
+//Faces before the first group statement are put into a group with an empty name.
+//A group that uses several materials is split into one ObjGroup per material, all with the name
+//of the group. Material libraries (mtllib) are not read. Groups without any faces are not returned.
+pub fn load_obj(filename: &str) -> Vec<ObjGroup> {
     let file = File::open(filename).unwrap();
     let reader = BufReader::new(file);
 
@@ -20,6 +31,15 @@ pub fn load_obj(filename: &str) -> Vec<Vertex4F> {
     let mut normals: Vec<(f64, f64, f64)> = Vec::new();
     let mut tex_coords: Vec<(f64, f64)> = Vec::new();
     let mut faces: Vec<Vec<Vertex>> = Vec::new();
+    let mut face_groups: Vec<usize> = Vec::new();
+    let mut groups = vec![ObjGroup {
+        name: String::new(),
+        material: String::new(),
+        vertices: Vec::new(),
+    }];
+    let mut group_name = String::new();
+    let mut material = String::new();
+    let mut current = 0;
 
     for line in reader.lines() {
         if line.is_ok() {
@@ -33,13 +53,18 @@ pub fn load_obj(filename: &str) -> Vec<Vertex4F> {
                 normals.push(read_normal(l));
             } else if l.starts_with("f") {
                 faces.push(read_face(l));
+                face_groups.push(current);
+            } else if l.starts_with("g ") || l.starts_with("o ") {
+                group_name = l[2..].trim().to_string();
+                current = group_index(&mut groups, &group_name, &material);
+            } else if l.starts_with("usemtl ") {
+                material = l[7..].trim().to_string();
+                current = group_index(&mut groups, &group_name, &material);
             }
         }
     }
 
-    let mut result = Vec::new();
-
-    for face in faces {
+    for (face, group) in faces.into_iter().zip(face_groups) {
         let mut has_normals = false;
         let mut verts = Vec::new();
 
@@ -61,6 +86,8 @@ pub fn load_obj(filename: &str) -> Vec<Vertex4F> {
                 let vtex = tex_coords[v.ti - 1];
                 vert.tex_u = vtex.0;
                 vert.tex_v = vtex.1;
+                vert.tex_u2 = vtex.0;
+                vert.tex_v2 = vtex.1;
             }
 
             verts.push(vert);
@@ -77,11 +104,31 @@ pub fn load_obj(filename: &str) -> Vec<Vertex4F> {
         }
 
         for v in verts {
-            result.push(v);
+            groups[group].vertices.push(v);
         }
     }
 
-    result
+    groups.retain(|g| !g.vertices.is_empty());
+    groups
+}
+
+//Index of the group with the given name and material, which is added if there is none yet.
+//Groups that are continued later in the file are merged this way.
+fn group_index(groups: &mut Vec<ObjGroup>, name: &str, material: &str) -> usize {
+    match groups
+        .iter()
+        .position(|g| g.name == name && g.material == material)
+    {
+        Some(index) => index,
+        None => {
+            groups.push(ObjGroup {
+                name: name.to_string(),
+                material: material.to_string(),
+                vertices: Vec::new(),
+            });
+            groups.len() - 1
+        }
+    }
 }
 
 fn read_vertex(line: String) -> (f64, f64, f64) {
@@ -1,7 +1,21 @@
 use linear;
+use linear::Ray;
 use linear::Vector4F;
+use linear::Vertex4F;
 use settings::Triangle;
 
+//Depth of the octree of a mesh that doesn't set "octree_depth", and the most it can set. Every
+//level can multiply the number of nodes by 8.
+pub const DEFAULT_DEPTH: u32 = 6;
+pub const MAX_DEPTH: u32 = 10;
+
+//Nodes with fewer triangles are not split any further, testing them all is faster than
+//walking more nodes
+const LEAF_TRIANGLES: usize = 8;
+
+//Maximum number of rays in a packet, one bit of the ray masks per ray
+pub const MAX_PACKET: usize = 8;
+
 pub struct OctreeNode {
     pub children: Vec<OctreeNode>,
     pub tris: Vec<usize>,
@@ -19,22 +33,18 @@ impl OctreeNode {
         }
     }
 
-    pub fn intersection_candidates(&self, rorg: &Vector4F, rdir: &Vector4F) -> Vec<usize> {
-        let mut result = Vec::new();
-        self.intersection_candidates_rec(rorg, rdir, &mut result);
-        result
+    //Fills candidates with the indexes of all triangles in leaf nodes hit by the ray within its
+    //t range. The vec is cleared first so it can be reused between rays.
+    pub fn intersection_candidates(&self, ray: &Ray, candidates: &mut Vec<usize>) {
+        candidates.clear();
+        self.intersection_candidates_rec(ray, candidates);
     }
 
-    fn intersection_candidates_rec(
-        &self,
-        rorg: &Vector4F,
-        rdir: &Vector4F,
-        candidates: &mut Vec<usize>,
-    ) {
-        if linear::ray_intersects_aabb(rorg, rdir, &self.min, &self.max) {
+    fn intersection_candidates_rec(&self, ray: &Ray, candidates: &mut Vec<usize>) {
+        if linear::ray_intersects_aabb(ray, &self.min, &self.max) {
             if self.children.len() > 0 {
                 for child in &self.children {
-                    child.intersection_candidates_rec(rorg, rdir, candidates);
+                    child.intersection_candidates_rec(ray, candidates);
                 }
             } else {
                 for tri in &self.tris {
@@ -43,6 +53,38 @@ impl OctreeNode {
             }
         }
     }
+
+    //Same as intersection_candidates() for a packet of coherent rays, which walks the nodes once
+    //for all rays. Each candidate comes with a mask of the rays that reached its leaf.
+    pub fn packet_candidates(&self, rays: &[Ray], candidates: &mut Vec<(usize, u8)>) {
+        assert!(rays.len() <= MAX_PACKET);
+        candidates.clear();
+        let mask = ((1u32 << rays.len()) - 1) as u8;
+        self.packet_candidates_rec(rays, mask, candidates);
+    }
+
+    fn packet_candidates_rec(&self, rays: &[Ray], mask: u8, candidates: &mut Vec<(usize, u8)>) {
+        let mut hits = 0;
+        for (i, ray) in rays.iter().enumerate() {
+            if mask & (1 << i) != 0 && linear::ray_intersects_aabb(ray, &self.min, &self.max) {
+                hits |= 1 << i;
+            }
+        }
+
+        if hits == 0 {
+            return;
+        }
+
+        if self.children.len() > 0 {
+            for child in &self.children {
+                child.packet_candidates_rec(rays, hits, candidates);
+            }
+        } else {
+            for tri in &self.tris {
+                candidates.push((*tri, hits));
+            }
+        }
+    }
 }
 
 /// Minimum function for four f64 values
@@ -57,10 +99,16 @@ fn qmax(v1: f64, v2: f64, v3: f64, v4: f64) -> f64 {
 
 /// Build an octree for the given triangles.
 ///
+/// - *vertices*: Vertices the triangles index into
 /// - *triangles*: Vec of trianlges
+/// - *max_depth*: Depth at which nodes are not split any more, 1 is just the root node
 ///
-/// returns: Octree with fixed depth
-pub fn build_octree(triangles: &Vec<Triangle>) -> OctreeNode {
+/// returns: Octree that is only split where there are many triangles
+pub fn build_octree(
+    vertices: &[Vertex4F],
+    triangles: &Vec<Triangle>,
+    max_depth: u32,
+) -> OctreeNode {
     let mut result = OctreeNode::new();
 
     let mut min = Vector4F {
@@ -80,13 +128,14 @@ pub fn build_octree(triangles: &Vec<Triangle>) -> OctreeNode {
     let mut indexes = Vec::with_capacity(triangles.len());
     let mut i = 0;
     for tri in triangles {
-        min.x = qmin(min.x, tri.v1.pos.x, tri.v2.pos.x, tri.v3.pos.x);
-        min.y = qmin(min.y, tri.v1.pos.y, tri.v2.pos.y, tri.v3.pos.y);
-        min.z = qmin(min.z, tri.v1.pos.z, tri.v2.pos.z, tri.v3.pos.z);
+        let (v1, v2, v3) = tri.corners(vertices);
+        min.x = qmin(min.x, v1.pos.x, v2.pos.x, v3.pos.x);
+        min.y = qmin(min.y, v1.pos.y, v2.pos.y, v3.pos.y);
+        min.z = qmin(min.z, v1.pos.z, v2.pos.z, v3.pos.z);
 
-        max.x = qmax(max.x, tri.v1.pos.x, tri.v2.pos.x, tri.v3.pos.x);
-        max.y = qmax(max.y, tri.v1.pos.y, tri.v2.pos.y, tri.v3.pos.y);
-        max.z = qmax(max.z, tri.v1.pos.z, tri.v2.pos.z, tri.v3.pos.z);
+        max.x = qmax(max.x, v1.pos.x, v2.pos.x, v3.pos.x);
+        max.y = qmax(max.y, v1.pos.y, v2.pos.y, v3.pos.y);
+        max.z = qmax(max.z, v1.pos.z, v2.pos.z, v3.pos.z);
 
         indexes.push(i);
         i += 1;
@@ -94,7 +143,7 @@ pub fn build_octree(triangles: &Vec<Triangle>) -> OctreeNode {
 
     result.min = min;
     result.max = max;
-    build_octree_rec(&mut result, triangles, &indexes, 1, 6);
+    build_octree_rec(&mut result, vertices, triangles, &indexes, 1, max_depth);
 
     result
 }
@@ -102,12 +151,14 @@ pub fn build_octree(triangles: &Vec<Triangle>) -> OctreeNode {
 ///Internal recursive octree building function.
 ///
 /// - *node*: The node to find triangles for.
+/// - *vertices*: the vertices the triangles index into.
 /// - *triangles*: the list of triangles to check.
 /// - *indexes*: list of indexes in the triangles list that are to be considered for the current node.
 /// - *depth*: current depth of the node in the tree.
 /// - *max_depth*: maximum tree depth.
 fn build_octree_rec(
     node: &mut OctreeNode,
+    vertices: &[Vertex4F],
     triangles: &Vec<Triangle>,
     indexes: &Vec<usize>,
     depth: u32,
@@ -121,8 +172,8 @@ fn build_octree_rec(
     if depth > 1 {
         tris = Vec::new();
         for t in indexes {
-            let tri = &triangles[*t];
-            if linear::triangle_aabb_overlap(&tri.v1.pos, &tri.v2.pos, &tri.v3.pos, min, max) {
+            let (v1, v2, v3) = triangles[*t].corners(vertices);
+            if linear::triangle_aabb_overlap(&v1.pos, &v2.pos, &v3.pos, min, max) {
                 tris.push(*t);
             }
         }
@@ -130,9 +181,9 @@ fn build_octree_rec(
         tris = indexes.clone();
     }
 
-    //Maximum level reached, stop recursion
+    //Maximum level reached or few enough triangles, stop recursion
     //Save intersecting tris only for leave nodes
-    if depth >= max_depth {
+    if depth >= max_depth || tris.len() < LEAF_TRIANGLES {
         node.tris = tris;
         return;
     }
@@ -153,7 +204,7 @@ fn build_octree_rec(
                 nnode.min = nmin;
                 nnode.max = nmax;
 
-                build_octree_rec(&mut nnode, triangles, &tris, depth + 1, max_depth);
+                build_octree_rec(&mut nnode, vertices, triangles, &tris, depth + 1, max_depth);
                 node.children.push(nnode);
 
                 z += half_z;
@@ -0,0 +1,28 @@
+use std::fs::File;
+use std::io::Write;
+
+//Write float image data to a Portable Float Map (PFM) file.
+//Spec taken from http://www.pauldebevec.com/Research/HDR/PFM/
+//
+//filename: The name of the file to write to, should end with ".pfm"
+//width: The width of the image in pixels
+//height: The height of the image in pixels
+//channels: 3 for RGB or 1 for greyscale images
+//values: The raw pixel data, rows from bottom to top, channels interleaved per pixel
+pub fn write_pfm(filename: &str, width: u32, height: u32, channels: usize, values: &[f32]) {
+    let mut file = File::create(filename).unwrap();
+
+    //"PF" is RGB, "Pf" is greyscale
+    let kind = if channels == 3 { "PF" } else { "Pf" };
+    //Negative scale means little endian
+    let header = format!("{}\n{} {}\n-1.0\n", kind, width, height);
+    file.write_all(header.as_bytes()).unwrap();
+
+    let mut bytes = Vec::with_capacity(values.len() * 4);
+    for v in values {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    file.write_all(&bytes).unwrap();
+    file.flush().unwrap();
+}
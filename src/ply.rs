@@ -0,0 +1,314 @@
+use linear::Vector4F;
+use linear::Vertex4F;
+use obj::ObjGroup;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(Clone, Copy)]
+enum Type {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+struct Property {
+    name: String,
+    value_type: Type,
+    //Type of the number of values for list properties, None for single values
+    count_type: Option<Type>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+//Values of the file after the header, either as text or as little endian binary data
+enum Body<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary(&'a [u8], usize),
+}
+
+impl<'a> Body<'a> {
+    fn next(&mut self, value_type: Type) -> f64 {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().expect("Unexpected end of PLY file");
+                token.parse().expect("Invalid number in PLY file")
+            }
+            Body::Binary(data, pos) => {
+                let size = type_size(value_type);
+                if *pos + size > data.len() {
+                    panic!("Unexpected end of PLY file");
+                }
+                let b = &data[*pos..(*pos + size)];
+                *pos += size;
+
+                match value_type {
+                    Type::Int8 => b[0] as i8 as f64,
+                    Type::UInt8 => b[0] as f64,
+                    Type::Int16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::UInt16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::Int32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::UInt32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::Float64 => {
+                        let mut bytes = [0; 8];
+                        bytes.copy_from_slice(b);
+                        f64::from_le_bytes(bytes)
+                    }
+                }
+            }
+        }
+    }
+}
+
+//Loads triangles from a PLY file in ASCII or binary little endian format. Vertices can have
+//positions (x, y, z), normals (nx, ny, nz), texture coordinates (u, v or s, t) and colors (red,
+//green, blue). Faces with more than three vertices are split into triangle fans. Like for OBJ
+//files, the triangles are returned with three vertices each, in a single unnamed group.
+pub fn load_ply(filename: &str) -> Vec<ObjGroup> {
+    let mut file = File::open(filename).unwrap();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+
+    let (elements, binary, body_start) = read_header(&data);
+    let mut body = if binary {
+        Body::Binary(&data, body_start)
+    } else {
+        let text = std::str::from_utf8(&data[body_start..]).expect("PLY file is not text");
+        Body::Ascii(text.split_whitespace())
+    };
+
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    let mut has_normals = false;
+    let mut has_uv2 = false;
+    let mut values = Vec::new();
+
+    for element in &elements {
+        for _ in 0..element.count {
+            if element.name == "vertex" {
+                let mut vertex = Vertex4F::new();
+                vertex.pos = Vector4F::new(0.0, 0.0, 0.0);
+
+                for p in &element.properties {
+                    read_property(&mut body, p, &mut values);
+                    if p.count_type.is_some() {
+                        continue;
+                    }
+
+                    let value = values[0];
+                    match p.name.as_str() {
+                        "x" => vertex.pos.x = value,
+                        "y" => vertex.pos.y = value,
+                        "z" => vertex.pos.z = value,
+                        "nx" => vertex.normal.x = value,
+                        "ny" => vertex.normal.y = value,
+                        "nz" => vertex.normal.z = value,
+                        "u" | "s" | "texture_u" | "texture_s" => vertex.tex_u = value,
+                        "v" | "t" | "texture_v" | "texture_t" => vertex.tex_v = value,
+                        "u2" | "s2" | "texture_u2" | "texture_s2" => {
+                            vertex.tex_u2 = value;
+                            has_uv2 = true;
+                        }
+                        "v2" | "t2" | "texture_v2" | "texture_t2" => vertex.tex_v2 = value,
+                        "red" => vertex.color.r = color_value(value, p.value_type),
+                        "green" => vertex.color.g = color_value(value, p.value_type),
+                        "blue" => vertex.color.b = color_value(value, p.value_type),
+                        _ => {}
+                    }
+                    if p.name == "nx" {
+                        has_normals = true;
+                    }
+                }
+
+                //Without a second UV set the first one is used for both
+                if !has_uv2 {
+                    vertex.tex_u2 = vertex.tex_u;
+                    vertex.tex_v2 = vertex.tex_v;
+                }
+
+                vertices.push(vertex);
+            } else {
+                for p in &element.properties {
+                    read_property(&mut body, p, &mut values);
+                    let is_indices = p.name == "vertex_indices" || p.name == "vertex_index";
+                    if element.name == "face" && is_indices {
+                        let face: Vec<usize> = values.iter().map(|i| *i as usize).collect();
+                        faces.push(face);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    for face in faces {
+        for i in 1..face.len().saturating_sub(1) {
+            let mut verts = Vec::with_capacity(3);
+            for index in &[face[0], face[i], face[i + 1]] {
+                if *index >= vertices.len() {
+                    panic!("PLY face uses vertex {} of {}", index, vertices.len());
+                }
+                verts.push(vertices[*index].clone());
+            }
+
+            if has_normals {
+                for v in &mut verts {
+                    v.normal = v.normal.normalize();
+                }
+            } else {
+                let edge1 = &verts[0].pos - &verts[1].pos;
+                let edge2 = &verts[2].pos - &verts[1].pos;
+                let cross = Vector4F::cross(&edge2, &edge1).normalize();
+
+                for v in &mut verts {
+                    v.normal = cross.clone();
+                }
+            }
+
+            result.append(&mut verts);
+        }
+    }
+
+    if result.is_empty() {
+        return Vec::new();
+    }
+
+    vec![ObjGroup {
+        name: String::new(),
+        material: String::new(),
+        vertices: result,
+    }]
+}
+
+//Reads the elements of the header. Returns them with true for binary files and the position
+//where the data after the header starts.
+fn read_header(data: &[u8]) -> (Vec<Element>, bool, usize) {
+    let end = match data.windows(10).position(|w| w == b"end_header") {
+        Some(end) => end,
+        None => panic!("PLY header has no end_header"),
+    };
+
+    //The data starts after the line break of end_header
+    let mut body_start = end + 10;
+    while body_start < data.len() && data[body_start] != b'\n' {
+        body_start += 1;
+    }
+    body_start += 1;
+
+    let header = String::from_utf8_lossy(&data[..end]);
+    let mut lines = header.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        panic!("Not a PLY file");
+    }
+
+    let mut elements: Vec<Element> = Vec::new();
+    let mut binary = false;
+
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+
+        match tokens[0] {
+            "format" => {
+                binary = match tokens.get(1) {
+                    Some(&"ascii") => false,
+                    Some(&"binary_little_endian") => true,
+                    _ => panic!("Unsupported PLY format: {}", line),
+                }
+            }
+            "element" => {
+                if tokens.len() != 3 {
+                    panic!("Invalid PLY element: {}", line);
+                }
+                elements.push(Element {
+                    name: tokens[1].to_string(),
+                    count: tokens[2].parse().unwrap(),
+                    properties: Vec::new(),
+                });
+            }
+            "property" => {
+                let element = match elements.last_mut() {
+                    Some(e) => e,
+                    None => panic!("PLY property outside of an element: {}", line),
+                };
+
+                let property = if tokens.get(1) == Some(&"list") && tokens.len() == 5 {
+                    Property {
+                        name: tokens[4].to_string(),
+                        value_type: read_type(tokens[3]),
+                        count_type: Some(read_type(tokens[2])),
+                    }
+                } else if tokens.len() == 3 {
+                    Property {
+                        name: tokens[2].to_string(),
+                        value_type: read_type(tokens[1]),
+                        count_type: None,
+                    }
+                } else {
+                    panic!("Invalid PLY property: {}", line);
+                };
+                element.properties.push(property);
+            }
+            //Comments and obj_info
+            _ => {}
+        }
+    }
+
+    (elements, binary, body_start)
+}
+
+//Reads all values of the property into values, one for single value properties
+fn read_property(body: &mut Body, property: &Property, values: &mut Vec<f64>) {
+    values.clear();
+
+    let count = match property.count_type {
+        Some(count_type) => body.next(count_type) as usize,
+        None => 1,
+    };
+    for _ in 0..count {
+        values.push(body.next(property.value_type));
+    }
+}
+
+fn read_type(name: &str) -> Type {
+    match name {
+        "char" | "int8" => Type::Int8,
+        "uchar" | "uint8" => Type::UInt8,
+        "short" | "int16" => Type::Int16,
+        "ushort" | "uint16" => Type::UInt16,
+        "int" | "int32" => Type::Int32,
+        "uint" | "uint32" => Type::UInt32,
+        "float" | "float32" => Type::Float32,
+        "double" | "float64" => Type::Float64,
+        _ => panic!("Unknown PLY type: {}", name),
+    }
+}
+
+fn type_size(value_type: Type) -> usize {
+    match value_type {
+        Type::Int8 | Type::UInt8 => 1,
+        Type::Int16 | Type::UInt16 => 2,
+        Type::Int32 | Type::UInt32 | Type::Float32 => 4,
+        Type::Float64 => 8,
+    }
+}
+
+//Integer colors use the full range of their type, float colors are already 0...1
+fn color_value(value: f64, value_type: Type) -> f32 {
+    match value_type {
+        Type::UInt8 => (value / 255.0) as f32,
+        Type::UInt16 => (value / 65535.0) as f32,
+        _ => value as f32,
+    }
+}
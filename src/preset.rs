@@ -0,0 +1,60 @@
+use settings::AdaptiveSampling;
+use settings::PixelFilter;
+use settings::Settings;
+
+//Names of the quality presets, from fastest to best
+pub const PRESETS: [&str; 3] = ["draft", "medium", "final"];
+
+//Replaces the quality settings by those of a named preset: samples, path samples, diffuse depth,
+//edge samples, pixel filter and adaptive sampling. Everything else, like the size of the image,
+//is kept.
+pub fn apply_preset(settings: &mut Settings, name: &str) -> Result<(), String> {
+    let output = &mut settings.output;
+    let scene = &mut settings.scene;
+
+    match name {
+        //Quick look at the lighting, noisy and aliased
+        "draft" => {
+            output.samples = 1;
+            output.edge_samples = 0;
+            output.filter = PixelFilter::Box;
+            output.adaptive = None;
+            scene.path_samples = 1;
+            scene.max_diffuse_depth = 1;
+        }
+        "medium" => {
+            output.samples = 2;
+            output.edge_samples = 2;
+            output.filter = PixelFilter::Tent { radius: 1.0 };
+            output.adaptive = Some(AdaptiveSampling {
+                min_samples: 0,
+                max_samples: 16,
+                threshold: 0.05,
+            });
+            scene.path_samples = 2;
+            scene.max_diffuse_depth = 2;
+        }
+        "final" => {
+            output.samples = 4;
+            output.edge_samples = 4;
+            output.filter = PixelFilter::Gaussian { radius: 1.5 };
+            output.adaptive = Some(AdaptiveSampling {
+                min_samples: 0,
+                max_samples: 64,
+                threshold: 0.02,
+            });
+            //Every bounce multiplies the paths, so more depth instead of more path samples
+            scene.path_samples = 2;
+            scene.max_diffuse_depth = 3;
+        }
+        _ => {
+            return Err(format!(
+                "Unknown preset {}, expected one of {}",
+                name,
+                PRESETS.join(", ")
+            ))
+        }
+    }
+
+    Ok(())
+}
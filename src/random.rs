@@ -1,51 +1,159 @@
 use linear::Vector4F;
-use rand::Rng;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use time;
 
 const PI: f64 = 3.1415926535897932384626433;
 
+//Generators created by Random::new(), so generators created at the same time still differ
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+//Where the samples of a stratified grid are placed in their cells, for pixel samples as well as
+//directions
+#[derive(Clone, Copy)]
+pub enum Sampler {
+    //Close to the cell centers, scattered by at most a quarter of a cell
+    Grid,
+    //Anywhere in the cells, which trades the regular pattern of the grid for noise, so edges don't
+    //show steps at low sample counts
+    Jittered,
+}
+
+//Xoshiro256++ generator. Not cryptographic, but fast and good enough for sampling. Each render
+//thread owns its own, so drawing a number is a few shifts and adds without any locking.
+#[derive(Clone)]
 pub struct Random {
+    state: [u64; 4],
+    sampler: Sampler,
 }
 
 impl Random {
+    //Creates a generator with a random seed.
     pub fn new() -> Random {
-        Random {}
+        let count = CREATED.fetch_add(1, Ordering::Relaxed) as u64;
+        Random::with_seed(time::precise_time_ns() ^ count.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    //Creates a generator that always returns the same sequence for the same seed.
+    pub fn with_seed(seed: u64) -> Random {
+        let mut random = Random {
+            state: [0; 4],
+            sampler: Sampler::Grid,
+        };
+        random.reseed(seed);
+        random
+    }
+
+    //Changes how stratified samples are placed. Reseeding keeps the sampler.
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.sampler = sampler;
+    }
+
+    //Restarts the generator with the given seed.
+    pub fn reseed(&mut self, seed: u64) {
+        //The state is filled by SplitMix64, which never makes it all zeros
+        let mut s = seed;
+        for value in self.state.iter_mut() {
+            s = s.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = s;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            *value = z ^ (z >> 31);
+        }
+    }
+
+    //Next 64 random bits
+    fn next(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = (s[0].wrapping_add(s[3])).rotate_left(23).wrapping_add(s[0]);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        result
     }
 
     //Crete random number in range 0...u32.MAX
     pub fn random(&mut self) -> u32 {
-        rand::thread_rng().gen()
+        (self.next() >> 32) as u32
     }
 
     //Create random number in range 0.0...1.0
     pub fn random_f(&mut self) -> f64 {
-        rand::thread_rng().gen()
+        //The upper 53 bits fill the mantissa, so every value is a multiple of 2^-53 below 1.0
+        (self.next() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
     }
 
-    fn random_samples(&mut self, num_samples: u32) -> Vec<(f64, f64)> {
+    //Fills result with num_samples * num_samples stratified samples in the range 0.0...1.0.
+    fn random_samples(&mut self, num_samples: u32, result: &mut Vec<(f64, f64)>) {
         let sample_width = 1.0 / num_samples as f64;
         let half_width = sample_width * 0.5;
 
-        let mut result = Vec::with_capacity((num_samples * num_samples) as usize);
+        result.clear();
+        result.reserve((num_samples * num_samples) as usize);
         for y in 0..num_samples {
             for x in 0..num_samples {
-                let scatter = half_width * (self.random_f() - 0.5);
-                let offset = half_width + scatter;
-                let vx = (x as f64 * sample_width) + offset;
-                let vy = (y as f64 * sample_width) + offset;
+                //Independent offsets in x and y, so the samples cover the whole cell
+                let (offset_x, offset_y) = match self.sampler {
+                    Sampler::Grid => (
+                        half_width + half_width * (self.random_f() - 0.5),
+                        half_width + half_width * (self.random_f() - 0.5),
+                    ),
+                    Sampler::Jittered => (
+                        sample_width * self.random_f(),
+                        sample_width * self.random_f(),
+                    ),
+                };
+                let vx = (x as f64 * sample_width) + offset_x;
+                let vy = (y as f64 * sample_width) + offset_y;
 
                 result.push((vx, vy));
             }
         }
+    }
 
-        result
+    //Fills result with num_samples * num_samples stratified points on the unit disk.
+    //Uses the concentric mapping so the stratification of the square is preserved on the disk.
+    pub fn random_points_on_disk(&mut self, num_samples: u32, result: &mut Vec<(f64, f64)>) {
+        self.random_samples(num_samples, result);
+
+        for sample in result.iter_mut() {
+            let a = 2.0 * sample.0 - 1.0;
+            let b = 2.0 * sample.1 - 1.0;
+
+            if a == 0.0 && b == 0.0 {
+                *sample = (0.0, 0.0);
+                continue;
+            }
+
+            let (r, theta) = if a.abs() > b.abs() {
+                (a, (PI / 4.0) * (b / a))
+            } else {
+                (b, (PI / 2.0) - (PI / 4.0) * (a / b))
+            };
+
+            *sample = (r * theta.cos(), r * theta.sin());
+        }
     }
 
-    pub fn random_directions_in_hemisphere(&mut self, num_samples: u32, n: &Vector4F) -> Vec<Vector4F> {
-        //TODO: Do this inline and get &mut vec from outside to fill to avoid creating millions of vecs
-        let samples = self.random_samples(num_samples);
+    //Fills result with num_samples * num_samples random directions in the hemisphere given by n.
+    //The samples buffer is used as temporary storage, both buffers are cleared first.
+    pub fn random_directions_in_hemisphere(
+        &mut self,
+        num_samples: u32,
+        n: &Vector4F,
+        samples: &mut Vec<(f64, f64)>,
+        result: &mut Vec<Vector4F>,
+    ) {
+        self.random_samples(num_samples, samples);
 
-        let mut result = Vec::new();
-        for sample in samples {
+        result.clear();
+        for sample in samples.iter() {
             let u = sample.0;
             let v = sample.1;
             let theta = 2.0 * PI * u;
@@ -67,8 +175,6 @@ impl Random {
                 result.push(dir);
             }
         }
-
-        result
     }
 
     //Creates point on unit sphere centered at (0,0,0) with radius 1.0.
@@ -103,6 +209,56 @@ impl Random {
         }
     }
 
+    //Creates a random direction inside the cone around the given axis with the given cosine of the opening half-angle.
+    //Directions are distributed uniformly over the solid angle of the cone, so the PDF is 1 / (2 * PI * (1 - cos_max)).
+    pub fn random_direction_in_cone(&mut self, axis: &Vector4F, cos_max: f64) -> Vector4F {
+        let u = self.random_f();
+        let v = self.random_f();
+
+        let cos_theta = 1.0 - u * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        let x = sin_theta * phi.cos();
+        let y = sin_theta * phi.sin();
+
+        to_world(x, y, cos_theta, axis)
+    }
+
+    //Fills result with num_samples * num_samples cosine weighted directions in the hemisphere given by n.
+    //The PDF of each direction is cos(theta) / PI, which cancels out the cosine term of diffuse shading.
+    //The samples buffer is used as temporary storage, both buffers are cleared first.
+    pub fn random_cosine_directions_in_hemisphere(
+        &mut self,
+        num_samples: u32,
+        n: &Vector4F,
+        samples: &mut Vec<(f64, f64)>,
+        result: &mut Vec<Vector4F>,
+    ) {
+        //Malley's method: project stratified points on the disk up to the hemisphere
+        self.random_points_on_disk(num_samples, samples);
+
+        result.clear();
+        for sample in samples.iter() {
+            let x = sample.0;
+            let y = sample.1;
+            let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+            result.push(to_world(x, y, z, n));
+        }
+    }
+
+    //Creates a cosine weighted random direction in the hemisphere given by n. The PDF is cos(theta) / PI.
+    pub fn random_cosine_direction(&mut self, n: &Vector4F) -> Vector4F {
+        let r = self.random_f().sqrt();
+        let phi = 2.0 * PI * self.random_f();
+        let x = r * phi.cos();
+        let y = r * phi.sin();
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+        to_world(x, y, z, n)
+    }
+
     //Create point on hemisphere centered at (0,0,0) and with radius 1.0. The direction of the top of the hemisphere is given by n.
     pub fn random_point_on_hemisphere(&mut self, n: &Vector4F) -> Vector4F {
         let usp = self.random_point_on_unit_sphere();
@@ -114,3 +270,22 @@ impl Random {
         }
     }
 }
+
+//Transforms the direction (x, y, z) from a local frame where z is the given axis into world space.
+fn to_world(x: f64, y: f64, z: f64, axis: &Vector4F) -> Vector4F {
+    //Build orthonormal basis around the axis
+    let helper = if axis.x.abs() > 0.9 {
+        Vector4F::new(0.0, 1.0, 0.0)
+    } else {
+        Vector4F::new(1.0, 0.0, 0.0)
+    };
+    let tangent = Vector4F::cross(axis, &helper).normalize();
+    let bitangent = Vector4F::cross(axis, &tangent);
+
+    Vector4F {
+        x: tangent.x * x + bitangent.x * y + axis.x * z,
+        y: tangent.y * x + bitangent.y * y + axis.y * z,
+        z: tangent.z * x + bitangent.z * y + axis.z * z,
+        w: 0.0,
+    }
+}
@@ -0,0 +1,3070 @@
+use accumulator::Accumulator;
+use accumulator::Variance;
+use checkpoint::Checkpoint;
+use dissolve::dissolve_objects;
+use dissolve::visible_objects;
+use environment::Environment;
+use framebuffer::Framebuffer;
+use linear;
+use linear::Intersection;
+use linear::Ray;
+use linear::Vector4F;
+use num_cpus;
+use random::Random;
+use random::Sampler;
+use scratch::Scratch;
+use settings::Aov;
+use settings::Camera;
+use settings::Color;
+use settings::DebugView;
+use settings::Dirt;
+use settings::HybridGi;
+use settings::Intersectable;
+use settings::Light;
+use settings::LightPaths;
+use settings::LightType;
+use settings::Material;
+use settings::Output;
+use settings::PixelFilter;
+use settings::ProgressFormat;
+use settings::Projection;
+use settings::Scene;
+use settings::SectionPlane;
+use settings::Settings;
+use settings::ShadowSprite;
+use shade;
+use std;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::io::Write;
+use std::panic;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Once;
+use std::thread;
+use stopwatch::StopWatch;
+use tiles::tiles;
+use tiles::Tile;
+use time;
+
+const HALF_SECOND: u64 = 500000000;
+//Characters of the progress bar between the brackets
+const PROGRESS_BAR_WIDTH: usize = 40;
+//Samples with NaN or infinite radiance that are traced again to print their path
+const NAN_PATHS_LOGGED: usize = 10;
+//Camera samples added at once to noisy pixels, one packet
+const ADAPTIVE_BATCH: usize = 4;
+//Brightness below which the noise of adaptive sampling is measured absolute instead of relative,
+//otherwise nearly black pixels would always take the maximum samples
+const ADAPTIVE_MIN_LUMINANCE: f64 = 0.05;
+//Wavelengths in nanometers used for the red, green and blue interference color of thin films
+const FILM_WAVELENGTHS: [f64; 3] = [650.0, 510.0, 475.0];
+//Directions around a hit that are probed for neighboring faces of rounded edges
+const EDGE_PROBES: u32 = 4;
+//Triangle tests of a camera ray that are shown as red in the triangle test debug view
+const DEBUG_MAX_TRIANGLE_TESTS: f64 = 1024.0;
+//Distance at which the Mitchell filter becomes negative
+const MITCHELL_ZERO: f64 = 8.0 / 7.0;
+//Halvings of the interval when sampling the Mitchell filter, plenty for f64
+const MITCHELL_BISECTIONS: u32 = 32;
+
+//State of a running render, passed to the progress callback.
+pub struct Progress {
+    //Finished pixels counted in lines of the image. With a tiled render order, the finished pixels
+    //are spread over many partly finished lines.
+    pub lines_done: u32,
+    pub lines_total: u32,
+    pub elapsed_millis: f64,
+    //Time the threads spent on the finished lines, added up
+    pub cost_millis: f64,
+    //Estimated time until the render is finished, based on the average cost per line so far. 0 while
+    //no pixels are finished.
+    pub eta_millis: f64,
+}
+
+//Renders the image described by the given settings using all available CPUs.
+//Returns the linear radiance values of all pixels and the AOV passes requested in the output settings.
+//Progress is printed to the console every half second. If rendering a tile panics, its pixels
+//are rendered again one by one, pixels that still panic stay black and are listed as failed.
+pub fn render(settings: &Settings) -> Framebuffer {
+    let cancel = AtomicBool::new(false);
+    render_with_progress(
+        settings,
+        &cancel,
+        console_progress(settings.output.progress),
+    )
+}
+
+//Renders the pixels that can see one of the changed boxes again and keeps all other pixels of
+//previous, a render of the same output and camera from before the scene was edited. For edited
+//objects, pass their bounds before and after the edit, so the pixels where the object was and
+//where it is now are both updated. A pixel is re-rendered if the camera ray through its center or
+//one of its corners passes through a box grown by margin before it hits anything. The margin
+//catches shadows and reflections close to the object, effects further away keep their old
+//values. Re-rendered pixels are the same as in a full render of the edited scene.
+pub fn render_dirty(
+    settings: &Settings,
+    previous: &Framebuffer,
+    changed: &[(Vector4F, Vector4F)],
+    margin: f64,
+) -> Framebuffer {
+    let output = &settings.output;
+    if previous.width != output.width || previous.height != output.height {
+        panic!(
+            "Previous render is {}x{}, but the output is {}x{}",
+            previous.width, previous.height, output.width, output.height
+        );
+    }
+
+    let dirty = dirty_pixels(settings, changed, margin);
+    eprintln!(
+        "Re-rendering {} of {} pixels",
+        dirty.iter().filter(|d| **d).count(),
+        dirty.len()
+    );
+
+    let cancel = AtomicBool::new(false);
+    let fresh = render_pixels(
+        settings,
+        &cancel,
+        console_progress(settings.output.progress),
+        Some(&dirty),
+        0,
+    );
+
+    let mut result = previous.clone();
+    for (i, _) in dirty.iter().enumerate().filter(|(_, d)| **d) {
+        result.copy_pixel(&fresh, i);
+    }
+
+    //Failed pixels of the previous render are only fixed if they were rendered again
+    let width = output.width;
+    result
+        .failed_pixels
+        .retain(|&(x, y)| !dirty[((output.height - 1 - y) * width + x) as usize]);
+    result.failed_pixels.extend_from_slice(&fresh.failed_pixels);
+    result
+}
+
+//Prints the progress to stdout in the given format every half second. The bar and the JSON
+//lines are also printed when the render is done, so their last update shows all lines done.
+fn console_progress(format: ProgressFormat) -> impl FnMut(&Progress) {
+    let mut last_time = time::precise_time_ns();
+
+    move |progress| {
+        let this_time = time::precise_time_ns();
+        let due = this_time - last_time > HALF_SECOND;
+        let finished = progress.lines_done >= progress.lines_total;
+        let fraction = progress.lines_done as f64 / progress.lines_total.max(1) as f64;
+
+        match format {
+            ProgressFormat::Percent if due => {
+                let percent = (fraction * 100.0 * 100.0).round() / 100.0;
+                println!("{} %, {:.1}s left", percent, progress.eta_millis / 1000.0);
+            }
+            ProgressFormat::Bar if due || finished => {
+                let filled = (fraction * PROGRESS_BAR_WIDTH as f64).round() as usize;
+                print!(
+                    "\r[{}{}] {:5.1} %, {:.1}s left ",
+                    "#".repeat(filled),
+                    " ".repeat(PROGRESS_BAR_WIDTH - filled),
+                    fraction * 100.0,
+                    progress.eta_millis / 1000.0
+                );
+                if finished {
+                    println!();
+                }
+                std::io::stdout().flush().ok();
+            }
+            ProgressFormat::Json if due || finished => {
+                println!(
+                    "{{\"lines_done\": {}, \"lines_total\": {}, \"elapsed_ms\": {:.0}, \"eta_ms\": {:.0}}}",
+                    progress.lines_done,
+                    progress.lines_total,
+                    progress.elapsed_millis,
+                    progress.eta_millis
+                );
+            }
+            _ => return,
+        }
+        last_time = this_time;
+    }
+}
+
+//Same as render(), but calls the progress callback every time lines are finished. Setting cancel
+//to true stops the render as soon as the lines currently being rendered are done. The returned
+//framebuffer then only contains the finished lines and is marked as cancelled.
+pub fn render_with_progress<F>(settings: &Settings, cancel: &AtomicBool, progress: F) -> Framebuffer
+where
+    F: FnMut(&Progress),
+{
+    render_pixels(settings, cancel, progress, None, 0)
+}
+
+//Renders the number of passes set in the output settings and averages them. Every pass has
+//samples * samples samples per pixel with different random numbers, so the noise goes down with
+//every pass. The render continues after the passes of the given checkpoint, use Checkpoint::new()
+//to start from the first pass. After each pass the callback gets the average of the finished
+//passes and the checkpoint, to write a preview or save the checkpoint. Setting cancel to true
+//stops the render as soon as the lines currently being rendered are done. The unfinished pass is
+//dropped and the returned framebuffer is marked as cancelled, unless no pass was finished.
+pub fn render_progressive<F>(
+    settings: &Settings,
+    cancel: &AtomicBool,
+    mut checkpoint: Checkpoint,
+    mut pass_done: F,
+) -> Framebuffer
+where
+    F: FnMut(&Framebuffer, &Checkpoint),
+{
+    let passes = settings.output.passes;
+    //Pixels that failed in any pass, they are darker than they should be in the average
+    let mut failed_pixels: Vec<(u32, u32)> = Vec::new();
+    while checkpoint.passes < passes {
+        let pass = checkpoint.passes;
+        eprintln!("Pass {} of {}", pass + 1, passes);
+        let framebuffer = render_pixels(
+            settings,
+            cancel,
+            console_progress(settings.output.progress),
+            None,
+            pass,
+        );
+        for pixel in &framebuffer.failed_pixels {
+            if !failed_pixels.contains(pixel) {
+                failed_pixels.push(*pixel);
+            }
+        }
+        if framebuffer.cancelled {
+            if pass == 0 {
+                return framebuffer;
+            }
+            let mut average = checkpoint.average(settings);
+            average.cancelled = true;
+            average.failed_pixels = failed_pixels;
+            return average;
+        }
+
+        checkpoint.add(&framebuffer);
+        pass_done(&checkpoint.average(settings), &checkpoint);
+    }
+
+    let mut average = checkpoint.average(settings);
+    average.failed_pixels = failed_pixels;
+    average
+}
+
+//Seed of a progressive pass. The first pass uses the seed of the frame, so a render with one pass
+//is the same as a regular render.
+fn pass_seed(seed: u64, pass: u32) -> u64 {
+    if pass == 0 {
+        return seed;
+    }
+
+    let mut h = seed ^ (pass as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    h = (h ^ (h >> 32)).wrapping_mul(0xD6E8_FEB8_6659_FD93);
+    h ^ (h >> 32)
+}
+
+//Renders the pixels that are true in dirty, or all pixels if it is None. Other pixels stay black.
+//With a debug pixel in the output settings only that pixel is rendered. The random numbers
+//depend on the progressive pass.
+fn render_pixels<F>(
+    settings: &Settings,
+    cancel: &AtomicBool,
+    mut progress: F,
+    dirty: Option<&[bool]>,
+    pass: u32,
+) -> Framebuffer
+where
+    F: FnMut(&Progress),
+{
+    let img_w = settings.output.width;
+    let img_h = settings.output.height;
+
+    let numcpus = num_cpus::get();
+    //let numcpus = 1;
+    eprintln!("Number of CPUs: {}", numcpus);
+
+    //Built once and shared by all threads, instead of once per ray
+    let dissolved = dissolve_objects(&settings.scene, settings.output.frame);
+    let objects = visible_objects(&dissolved);
+
+    let plane = ImagePlane::new(settings);
+    let job = TileJob {
+        settings,
+        //Centers of the pixel columns and lines on the image plane, added up pixel by pixel
+        columns: plane_positions(plane.left, plane.width / img_w as f64, img_w),
+        lines: plane_positions(plane.bottom, plane.height / img_h as f64, img_h),
+        plane,
+        objects: &objects,
+        dirty,
+        //Lines are counted from the bottom while rendering
+        debug_pixel: settings.output.debug_pixel.map(|(x, y)| (x, img_h - 1 - y)),
+        seed: pass_seed(settings.output.frame_seed(), pass),
+        //The passes of a progressive render would all hit the same spots of the sub-pixels otherwise
+        jitter: match settings.output.sampler {
+            Sampler::Grid => settings.output.passes > 1,
+            Sampler::Jittered => true,
+        },
+        aov_channels: settings.output.aovs.iter().map(|a| a.channels()).sum(),
+        nan_samples: AtomicUsize::new(0),
+    };
+
+    let mut framebuffer = Framebuffer::new(
+        img_w,
+        img_h,
+        &settings.output.aovs,
+        &settings.output.pixel_format,
+    );
+
+    let mut watch = StopWatch::new();
+    watch.start();
+
+    let mut tiles = tiles(img_w, img_h, &settings.output.render_order);
+
+    let mut tiles_done = 0;
+    let mut pixels_done = 0;
+    let mut cost_millis = 0.0;
+
+    let mut num_threads = 0;
+    let mut next_tile = 0;
+    let mut failed_tiles = Vec::new();
+
+    let (tx, rx) = mpsc::channel();
+    install_tile_panic_hook();
+
+    thread::scope(|scope| {
+        while next_tile < tiles.len() && !cancel.load(Ordering::Relaxed) {
+            while num_threads < numcpus && next_tile < tiles.len() {
+                let ltx = mpsc::Sender::clone(&tx);
+                let tile = tiles[next_tile];
+                let job = &job;
+
+                scope.spawn(move || {
+                    let mut tile_watch = StopWatch::new();
+                    tile_watch.start();
+                    let values = catch_tile_panic(|| render_tile(&tile, job));
+                    tile_watch.stop();
+                    ltx.send((tile, values, tile_watch.get_millis())).unwrap();
+                });
+
+                num_threads += 1;
+                next_tile += 1;
+            }
+
+            //Read back results from threads
+            let mut rxv = rx.try_recv();
+            while rxv.is_ok() {
+                let result = rxv.unwrap();
+                num_threads -= 1;
+                tiles_done += 1;
+                cost_millis += result.2;
+                match result.1 {
+                    Ok(values) => {
+                        store_tile(&result.0, &values, &settings.output.aovs, &mut framebuffer);
+                        pixels_done += result.0.pixels();
+                    }
+                    Err(message) => {
+                        pixels_done += retry_tile(
+                            &result.0,
+                            &message,
+                            &mut tiles,
+                            &mut failed_tiles,
+                            &mut framebuffer,
+                        );
+                    }
+                }
+                report_progress(
+                    &mut progress,
+                    &mut watch,
+                    (pixels_done, img_w * img_h),
+                    img_w,
+                    cost_millis,
+                    numcpus.min(tiles.len() - tiles_done),
+                );
+                rxv = rx.try_recv();
+            }
+        }
+
+        //Read all the rest (blocking)
+        while num_threads > 0 {
+            let rxv = rx.recv();
+            let result = rxv.unwrap();
+            num_threads -= 1;
+            tiles_done += 1;
+            cost_millis += result.2;
+            match result.1 {
+                Ok(values) => {
+                    store_tile(&result.0, &values, &settings.output.aovs, &mut framebuffer);
+                    pixels_done += result.0.pixels();
+                }
+                Err(message) => {
+                    pixels_done += retry_tile(
+                        &result.0,
+                        &message,
+                        &mut tiles,
+                        &mut failed_tiles,
+                        &mut framebuffer,
+                    );
+                }
+            }
+            report_progress(
+                &mut progress,
+                &mut watch,
+                (pixels_done, img_w * img_h),
+                img_w,
+                cost_millis,
+                numcpus.min(tiles.len() - tiles_done),
+            );
+        }
+    });
+
+    report_failed_tiles(&failed_tiles, img_h);
+    if settings.output.check_nan {
+        eprintln!(
+            "Samples with NaN or infinite radiance: {}",
+            job.nan_samples.load(Ordering::Relaxed)
+        );
+    }
+
+    framebuffer.cancelled = tiles_done < tiles.len();
+    framebuffer
+}
+
+//Everything the threads need to render the tiles of a pass, shared by all of them
+struct TileJob<'a> {
+    settings: &'a Settings,
+    plane: ImagePlane,
+    objects: &'a Vec<&'a Intersectable>,
+    //Centers of the pixel columns and lines on the image plane
+    columns: Vec<f64>,
+    lines: Vec<f64>,
+    dirty: Option<&'a [bool]>,
+    //Column and line of the pixel to debug, lines counted from the bottom
+    debug_pixel: Option<(u32, u32)>,
+    seed: u64,
+    jitter: bool,
+    aov_channels: usize,
+    //Number of samples with NaN or infinite radiance, if they are checked
+    nan_samples: AtomicUsize,
+}
+
+//Renders the pixels of the tile. Returns their colors and their AOV values, interleaved per pixel.
+fn render_tile(tile: &Tile, job: &TileJob) -> (Vec<f32>, Vec<f32>) {
+    let settings = job.settings;
+    let plane = &job.plane;
+    let objects = job.objects;
+    let (columns, lines) = (&job.columns, &job.lines);
+    let (debug_pixel, dirty, seed, jitter) = (job.debug_pixel, job.dirty, job.seed, job.jitter);
+    let aov_channels = job.aov_channels;
+    let nan_samples = &job.nan_samples;
+    let check_nan = settings.output.check_nan;
+    let img_w = settings.output.width;
+    let img_h = settings.output.height;
+
+    //Calculate pixel horizontal increment
+    let img_pix_inc_h = plane.width / img_w as f64;
+
+    //Pre-calculate values for multi sampling
+    let samples = settings.output.samples as f64;
+    let lens_samples = settings.camera.lens_samples;
+    let samples2 = (samples * samples) as f32 * (lens_samples * lens_samples) as f32;
+    let sample_width = img_pix_inc_h / samples;
+    let sample_offset = (img_pix_inc_h / 2.0) - (sample_width / 2.0);
+
+    let mut random = Random::with_seed(seed);
+    random.set_sampler(settings.output.sampler);
+    let mut scratch = Scratch::new();
+
+    let num_values = (tile.pixels() * 3) as usize;
+    let mut colors = Vec::with_capacity(num_values);
+
+    //AOV values of the tile, interleaved per pixel
+    let mut aov_values = Vec::with_capacity(tile.pixels() as usize * aov_channels);
+    let mut pixel_aovs = vec![0.0f32; aov_channels];
+
+    //Positions of the samples of a pixel on the image plane
+    let mut points = Vec::new();
+    let mut block_points = Vec::new();
+
+    //Buffers for tracing primary rays in packets
+    let mut rays = Vec::new();
+    let mut weights = Vec::new();
+    let mut hits = Vec::new();
+    let mut found = Vec::new();
+
+    let accumulation = settings.output.accumulation;
+    let mut pcr = Accumulator::new(accumulation);
+    let mut pcg = Accumulator::new(accumulation);
+    let mut pcb = Accumulator::new(accumulation);
+    let mut variance = Variance::new();
+
+    for (liy, ix) in tile_pixels(tile) {
+        let px = columns[ix as usize];
+        let lpy = lines[liy as usize];
+        let debugging = debug_pixel == Some((ix, liy));
+        if !dirty.map_or(true, |d| d[(liy * img_w + ix) as usize])
+            || (debug_pixel.is_some() && !debugging)
+        {
+            colors.extend_from_slice(&[0.0, 0.0, 0.0]);
+            aov_values.extend(std::iter::repeat(0.0).take(aov_channels));
+            continue;
+        }
+
+        //Each pixel has its own random sequence, so the result does not depend
+        //on which thread renders which line
+        random.reseed(pixel_seed(seed, ix, liy, img_w));
+
+        //Create sample grid of samples * samples sub-pixels
+        let sub_pix_l = px - sample_offset;
+        let sub_pix_b = lpy - sample_offset;
+
+        pcr.reset();
+        pcg.reset();
+        pcb.reset();
+        variance.reset();
+        let mut weights_sum = 0.0f32;
+
+        for v in pixel_aovs.iter_mut() {
+            *v = 0.0;
+        }
+
+        points.clear();
+        let steps = settings.output.samples;
+        if let PixelFilter::Box = settings.output.filter {
+            let mut spy = sub_pix_b;
+            for _spy in 0..steps {
+                let mut spx = sub_pix_l;
+                for _spx in 0..steps {
+                    if jitter {
+                        let jx = (random.random_f() - 0.5) * sample_width;
+                        let jy = (random.random_f() - 0.5) * sample_width;
+                        points.push((spx + jx, spy + jy));
+                    } else {
+                        points.push((spx, spy));
+                    }
+                    spx += sample_width;
+                }
+                spy += sample_width;
+            }
+        } else {
+            //Jittered in the sub-pixels, so the stratification survives the
+            //mapping through the filter
+            for sy in 0..steps {
+                for sx in 0..steps {
+                    let u = (sx as f64 + random.random_f()) / samples;
+                    let v = (sy as f64 + random.random_f()) / samples;
+                    let (fx, fy) = filter_offset(&settings.output.filter, u, v);
+                    points.push((px + fx * img_pix_inc_h, lpy + fy * img_pix_inc_h));
+                }
+            }
+        }
+        let grid_samples = points.len();
+        if settings.output.packets {
+            order_in_blocks(&mut points, steps as usize, &mut block_points);
+        }
+
+        let pixel = (ix, img_h - 1 - liy);
+        let mut sample = 0;
+        let mut i = 0;
+        while i < points.len() {
+            if settings.output.packets {
+                //All grid or all edge samples at once, so the lens samples are
+                //drawn before tracing
+                let end = if i < grid_samples {
+                    grid_samples
+                } else {
+                    points.len()
+                };
+                let mut lens = std::mem::take(&mut scratch.lens);
+
+                while i < end {
+                    rays.clear();
+                    weights.clear();
+                    for &(spx, spy) in &points[i..(i + 4).min(end)] {
+                        let (eye_pos, ray_dir) = plane.camera_ray(&settings.camera, spx, spy);
+                        let weight = filter_weight(
+                            &settings.output.filter,
+                            (spx - px) / img_pix_inc_h,
+                            (spy - lpy) / img_pix_inc_h,
+                        );
+                        random.random_points_on_disk(lens_samples, &mut lens);
+                        for lp in &lens {
+                            weights.push(weight);
+                            let (ray_org, lens_dir) =
+                                lens_ray(&eye_pos, &ray_dir, &settings.camera, lp);
+                            rays.push(Ray::with_max_t(ray_org, lens_dir, std::f64::MAX));
+                        }
+                    }
+                    i = (i + 4).min(end);
+
+                    //With lens samples a sample point has several rays, which
+                    //are coherent too
+                    for (packet, weights) in rays.chunks_mut(4).zip(weights.chunks(4)) {
+                        intersect_packet(
+                            packet,
+                            objects,
+                            &settings.scene.section_planes,
+                            &mut scratch,
+                            &mut hits,
+                            &mut found,
+                        );
+
+                        let packet_hits = packet.iter().zip(hits.drain(..));
+                        for ((ray, hit), weight) in packet_hits.zip(weights) {
+                            if debugging {
+                                scratch.log = Some(Vec::new());
+                            }
+                            let replay = if check_nan {
+                                Some(random.clone())
+                            } else {
+                                None
+                            };
+                            let pc = match settings.output.debug_view {
+                                Some(ref view) => debug_color(
+                                    view,
+                                    &ray.org,
+                                    &ray.dir,
+                                    settings,
+                                    objects,
+                                    &mut random,
+                                    &mut scratch,
+                                ),
+                                //Sprites are black, the shadow is their alpha
+                                None if settings.output.shadow_sprite.is_some() => Color::black(),
+                                None => trace_hit(
+                                    &ray.org,
+                                    &ray.dir,
+                                    hit,
+                                    &settings.scene,
+                                    &settings.output.light_paths,
+                                    objects,
+                                    &mut random,
+                                    &mut scratch,
+                                    0,
+                                    0,
+                                    0.0,
+                                ),
+                            };
+                            let pc = check_sample(
+                                pc,
+                                pixel,
+                                sample,
+                                &ray.org,
+                                &ray.dir,
+                                replay,
+                                settings,
+                                objects,
+                                &mut scratch,
+                                nan_samples,
+                            );
+                            let pc = scaled_color(&pc, *weight);
+                            weights_sum += *weight;
+                            variance.add(pc.luminance());
+                            sample += 1;
+
+                            pcr.add(pc.r);
+                            pcg.add(pc.g);
+                            pcb.add(pc.b);
+
+                            if aov_channels > 0 {
+                                trace_aovs(
+                                    &ray.org,
+                                    &ray.dir,
+                                    settings,
+                                    objects,
+                                    &mut random,
+                                    &mut scratch,
+                                    &mut pixel_aovs,
+                                );
+                            }
+                        }
+                    }
+                }
+
+                scratch.lens = lens;
+            } else {
+                let (spx, spy) = points[i];
+                let (eye_pos, ray_dir) = plane.camera_ray(&settings.camera, spx, spy);
+                let weight = filter_weight(
+                    &settings.output.filter,
+                    (spx - px) / img_pix_inc_h,
+                    (spy - lpy) / img_pix_inc_h,
+                );
+
+                //Each pixel sample gets its own stratified set of lens samples, so
+                //the lens is a separate sample dimension that converges with the
+                //pixel samples.
+                let camera = &settings.camera;
+                let mut lens = std::mem::take(&mut scratch.lens);
+                random.random_points_on_disk(lens_samples, &mut lens);
+
+                for lp in &lens {
+                    let (ray_org, lens_dir) = lens_ray(&eye_pos, &ray_dir, camera, lp);
+                    if debugging {
+                        scratch.log = Some(Vec::new());
+                    }
+                    let replay = if check_nan {
+                        Some(random.clone())
+                    } else {
+                        None
+                    };
+                    let pc = match settings.output.debug_view {
+                        Some(ref view) => debug_color(
+                            view,
+                            &ray_org,
+                            &lens_dir,
+                            settings,
+                            objects,
+                            &mut random,
+                            &mut scratch,
+                        ),
+                        None if settings.output.shadow_sprite.is_some() => Color::black(),
+                        None => trace(
+                            &ray_org,
+                            &lens_dir,
+                            &settings.scene,
+                            &settings.output.light_paths,
+                            objects,
+                            &mut random,
+                            &mut scratch,
+                            0,
+                            0,
+                            0.0,
+                        ),
+                    };
+                    let pc = check_sample(
+                        pc,
+                        pixel,
+                        sample,
+                        &ray_org,
+                        &lens_dir,
+                        replay,
+                        settings,
+                        objects,
+                        &mut scratch,
+                        nan_samples,
+                    );
+                    let pc = scaled_color(&pc, weight);
+                    weights_sum += weight;
+                    variance.add(pc.luminance());
+                    sample += 1;
+
+                    pcr.add(pc.r);
+                    pcg.add(pc.g);
+                    pcb.add(pc.b);
+
+                    if aov_channels > 0 {
+                        trace_aovs(
+                            &ray_org,
+                            &lens_dir,
+                            settings,
+                            objects,
+                            &mut random,
+                            &mut scratch,
+                            &mut pixel_aovs,
+                        );
+                    }
+                }
+
+                scratch.lens = lens;
+                i += 1;
+            }
+
+            //Pixels with a silhouette get more samples once the grid is done
+            let edge_samples = settings.output.edge_samples;
+            if i == grid_samples
+                && edge_samples > 0
+                && is_edge(
+                    px,
+                    lpy,
+                    img_pix_inc_h,
+                    plane,
+                    settings,
+                    objects,
+                    &mut scratch,
+                )
+            {
+                let w = img_pix_inc_h / edge_samples as f64;
+                let l = px - img_pix_inc_h / 2.0;
+                let b = lpy - img_pix_inc_h / 2.0;
+                for ey in 0..edge_samples {
+                    for ex in 0..edge_samples {
+                        let (rx, ry) = (random.random_f(), random.random_f());
+                        if let PixelFilter::Box = settings.output.filter {
+                            points.push((l + (ex as f64 + rx) * w, b + (ey as f64 + ry) * w));
+                        } else {
+                            let n = edge_samples as f64;
+                            let (fx, fy) = filter_offset(
+                                &settings.output.filter,
+                                (ex as f64 + rx) / n,
+                                (ey as f64 + ry) / n,
+                            );
+                            points.push((px + fx * img_pix_inc_h, lpy + fy * img_pix_inc_h));
+                        }
+                    }
+                }
+            }
+
+            //Noisy pixels get more samples once all others are traced
+            if let Some(adaptive) = &settings.output.adaptive {
+                let count = points.len() as u32;
+                if i == points.len()
+                    && count < adaptive.max_samples
+                    && (count < adaptive.min_samples || is_noisy(&variance, adaptive.threshold))
+                {
+                    let left = (adaptive.max_samples - count) as usize;
+                    for _ in 0..ADAPTIVE_BATCH.min(left) {
+                        let (u, v) = (random.random_f(), random.random_f());
+                        let (fx, fy) = match settings.output.filter {
+                            PixelFilter::Box => (u - 0.5, v - 0.5),
+                            ref filter => filter_offset(filter, u, v),
+                        };
+                        points.push((px + fx * img_pix_inc_h, lpy + fy * img_pix_inc_h));
+                    }
+                }
+            }
+        }
+
+        let num_samples = if points.len() == grid_samples {
+            samples2
+        } else {
+            points.len() as f32 * (lens_samples * lens_samples) as f32
+        };
+        //With negative weights, the few samples in the lobes would make flat
+        //areas noisy if only their number was divided out
+        let color_samples = match settings.output.filter {
+            PixelFilter::Mitchell { .. } if weights_sum > 0.0 => weights_sum,
+            _ => num_samples,
+        };
+
+        colors.push(pcr.value() / color_samples);
+        colors.push(pcg.value() / color_samples);
+        colors.push(pcb.value() / color_samples);
+
+        if debugging {
+            eprintln!(
+                "Pixel {}, {}: [{},{},{}] from {} samples",
+                pixel.0,
+                pixel.1,
+                pcr.value() / color_samples,
+                pcg.value() / color_samples,
+                pcb.value() / color_samples,
+                sample
+            );
+        }
+
+        for v in &pixel_aovs {
+            aov_values.push(v / num_samples);
+        }
+    }
+
+    (colors, aov_values)
+}
+
+thread_local! {
+    //True while the thread renders a tile
+    static IN_TILE: Cell<bool> = const { Cell::new(false) };
+    //Message of the last panic of a tile on the thread
+    static TILE_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+//Replaces the panic hook, so a panic while rendering a tile is not printed by the default hook but
+//reported once with its tile when the render is done. Panics outside of tiles still go to the previous hook, so it only
+//needs to be installed once.
+fn install_tile_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if IN_TILE.with(|t| t.get()) {
+                let message = info.to_string().replace('\n', " ");
+                TILE_PANIC.with(|p| *p.borrow_mut() = Some(message));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+//Calls f, which renders a tile. A panic, like an assert on broken geometry, only loses this tile
+//instead of the whole render. Its message is returned instead.
+fn catch_tile_panic<F, T>(f: F) -> Result<T, String>
+where
+    F: FnOnce() -> T,
+{
+    IN_TILE.with(|t| t.set(true));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    IN_TILE.with(|t| t.set(false));
+
+    result.map_err(|_| {
+        TILE_PANIC
+            .with(|p| p.borrow_mut().take())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+//Positions of count pixel centers on the image plane, starting at first. The positions are added
+//up one pixel after the other, so they are the same whatever order the pixels are rendered in.
+fn plane_positions(first: f64, increment: f64, count: u32) -> Vec<f64> {
+    let mut result = Vec::with_capacity(count as usize);
+    let mut pos = first;
+    for _ in 0..count {
+        result.push(pos);
+        pos += increment;
+    }
+    result
+}
+
+//Line and column of every pixel of the tile, line by line
+fn tile_pixels(tile: &Tile) -> impl Iterator<Item = (u32, u32)> {
+    let tile = *tile;
+    (tile.y..(tile.y + tile.height))
+        .flat_map(move |y| (tile.x..(tile.x + tile.width)).map(move |x| (y, x)))
+}
+
+//Checks the radiance of a sample traced from the camera. The path of a sample of the debug pixel
+//is printed. With replay, samples with NaN or infinite radiance are counted and count as black.
+//The first few of them are traced again from the random state they started with, to print their
+//path.
+fn check_sample(
+    pc: Color,
+    pixel: (u32, u32),
+    sample: u32,
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    replay: Option<Random>,
+    settings: &Settings,
+    objects: &Vec<&Intersectable>,
+    scratch: &mut Scratch,
+    nan_samples: &AtomicUsize,
+) -> Color {
+    if let Some(log) = scratch.log.take() {
+        eprintln!(
+            "Pixel {}, {}, sample {}: {}\n{}",
+            pixel.0,
+            pixel.1,
+            sample,
+            pc,
+            log.join("\n")
+        );
+    }
+
+    if pc.r.is_finite() && pc.g.is_finite() && pc.b.is_finite() {
+        return pc;
+    }
+
+    match replay {
+        Some(mut random) => {
+            if nan_samples.fetch_add(1, Ordering::Relaxed) < NAN_PATHS_LOGGED {
+                scratch.log = Some(Vec::new());
+                trace(
+                    ray_org,
+                    ray_dir,
+                    &settings.scene,
+                    &settings.output.light_paths,
+                    objects,
+                    &mut random,
+                    scratch,
+                    0,
+                    0,
+                    0.0,
+                );
+                let log = scratch.log.take().unwrap_or_default();
+                eprintln!(
+                    "NaN/Inf radiance at pixel {}, {}, sample {}: {}\n{}",
+                    pixel.0,
+                    pixel.1,
+                    sample,
+                    pc,
+                    log.join("\n")
+                );
+            }
+            Color::black()
+        }
+        None => pc,
+    }
+}
+
+//Adds a line to the path log of the sample being debugged, indented by the depth of the ray. The
+//line is only formatted while a log is collected.
+fn log_path<F>(scratch: &mut Scratch, depth: u32, transmissions: u32, line: F)
+where
+    F: FnOnce() -> String,
+{
+    if let Some(ref mut log) = scratch.log {
+        let indent = "  ".repeat((depth + transmissions + 1) as usize);
+        log.push(format!("{}{}", indent, line()));
+    }
+}
+
+//The ETA is based on the cost of the finished pixels, split over the threads that are left for the
+//remaining tiles. The elapsed time would also count the tiles that are still being rendered, and
+//overestimate the remaining time until many tiles are done. Pixels are given as (done, total) and
+//reported as lines of the given width.
+fn report_progress<F>(
+    progress: &mut F,
+    watch: &mut StopWatch,
+    pixels: (u32, u32),
+    width: u32,
+    cost_millis: f64,
+    threads: usize,
+) where
+    F: FnMut(&Progress),
+{
+    watch.stop();
+    let elapsed_millis = watch.get_millis();
+    let (pixels_done, pixels_total) = pixels;
+    let pixels_left = pixels_total - pixels_done;
+    let threads = threads.max(1);
+    //Nothing is known about the cost before the first pixels are done, like when the first tile
+    //failed and was queued again
+    let eta_millis = if pixels_done == 0 {
+        0.0
+    } else {
+        cost_millis / pixels_done as f64 * pixels_left as f64 / threads as f64
+    };
+
+    progress(&Progress {
+        lines_done: pixels_done / width,
+        lines_total: pixels_total / width,
+        elapsed_millis,
+        cost_millis,
+        eta_millis,
+    });
+}
+
+//Adds the colors and AOV values of a tile that was rendered by a thread to the final buffers.
+fn store_tile(
+    tile: &Tile,
+    values: &(Vec<f32>, Vec<f32>),
+    aovs: &[Aov],
+    framebuffer: &mut Framebuffer,
+) {
+    let img_w = framebuffer.width as usize;
+    let tile_w = tile.width as usize;
+
+    for (row, line) in values.0.chunks(tile_w * 3).enumerate() {
+        let start = ((tile.y as usize + row) * img_w + tile.x as usize) * 3;
+        framebuffer.pixels.add_line(start, line);
+    }
+
+    //AOV values of all channels are interleaved per pixel
+    let mut offset = 0;
+    for (y, x) in tile_pixels(tile) {
+        for (a, aov) in aovs.iter().enumerate() {
+            let channels = aov.channels();
+            let start = (y as usize * img_w + x as usize) * channels;
+            for c in 0..channels {
+                framebuffer.aovs[a][start + c] += values.1[offset];
+                offset += 1;
+            }
+        }
+    }
+}
+
+//Tile whose thread panicked, reported once when the render is done
+struct FailedTile {
+    tile: Tile,
+    //Message of the first panic in the tile
+    message: String,
+    //Pixels of the tile that panicked again when they were rendered one by one
+    failed_pixels: u32,
+}
+
+//Handles a tile whose thread panicked with the given message. Its pixels are queued again one by
+//one, so only the pixels that panic again are lost. A single pixel that failed is marked as
+//failed and counted for its tile. Returns the number of pixels that are done with the tile.
+fn retry_tile(
+    tile: &Tile,
+    message: &str,
+    tiles: &mut Vec<Tile>,
+    failed_tiles: &mut Vec<FailedTile>,
+    framebuffer: &mut Framebuffer,
+) -> u32 {
+    let img_h = framebuffer.height;
+    if tile.pixels() > 1 {
+        failed_tiles.push(FailedTile {
+            tile: *tile,
+            message: message.to_string(),
+            failed_pixels: 0,
+        });
+        for (y, x) in tile_pixels(tile) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: 1,
+                height: 1,
+            });
+        }
+        return 0;
+    }
+
+    let pixel = (tile.x, img_h - 1 - tile.y);
+    framebuffer.failed_pixels.push(pixel);
+    match failed_tiles
+        .iter_mut()
+        .find(|failed| failed.tile.contains(tile.x, tile.y))
+    {
+        Some(failed) => failed.failed_pixels += 1,
+        //Tiles of a single pixel are not retried
+        None => failed_tiles.push(FailedTile {
+            tile: *tile,
+            message: message.to_string(),
+            failed_pixels: 1,
+        }),
+    }
+    1
+}
+
+//Prints one line per tile that panicked, with the number of its pixels that stay black
+fn report_failed_tiles(failed_tiles: &[FailedTile], img_h: u32) {
+    if failed_tiles.is_empty() {
+        return;
+    }
+
+    eprintln!("Rendering failed in {} tiles:", failed_tiles.len());
+    for failed in failed_tiles {
+        let tile = &failed.tile;
+        eprintln!(
+            "  {}x{} tile at {}, {}: {} of {} pixels stay black ({})",
+            tile.width,
+            tile.height,
+            tile.x,
+            img_h - tile.y - tile.height,
+            failed.failed_pixels,
+            tile.pixels(),
+            failed.message
+        );
+    }
+}
+
+//Reorders a row-major grid of steps * steps points into blocks of 2x2, so each packet of four
+//consecutive points covers a square of sub-pixels instead of a line.
+fn order_in_blocks(points: &mut Vec<(f64, f64)>, steps: usize, temp: &mut Vec<(f64, f64)>) {
+    temp.clear();
+    for by in (0..steps).step_by(2) {
+        for bx in (0..steps).step_by(2) {
+            for y in by..(by + 2).min(steps) {
+                for x in bx..(bx + 2).min(steps) {
+                    temp.push(points[y * steps + x]);
+                }
+            }
+        }
+    }
+    std::mem::swap(points, temp);
+}
+
+//Maps u, v in [0, 1) to an offset from the pixel center in pixels, distributed like the filter.
+fn filter_offset(filter: &PixelFilter, u: f64, v: f64) -> (f64, f64) {
+    match filter {
+        PixelFilter::Box => (u - 0.5, v - 0.5),
+        PixelFilter::Tent { radius } => (tent_sample(u) * radius, tent_sample(v) * radius),
+        PixelFilter::Gaussian { radius } => {
+            //Polar form of the Box-Muller transform, with the radius limited by scaling u
+            let sigma = radius / 3.0;
+            let cutoff = 1.0 - (-(radius * radius) / (2.0 * sigma * sigma)).exp();
+            let r = sigma * (-2.0 * (1.0 - u * cutoff).ln()).sqrt();
+            let angle = 2.0 * PI * v;
+            (r * angle.cos(), r * angle.sin())
+        }
+        PixelFilter::Mitchell { radius } => {
+            (mitchell_sample(u) * radius, mitchell_sample(v) * radius)
+        }
+    }
+}
+
+//Weight of a sample at the given offset from the pixel center in pixels, drawn by filter_offset().
+//Only Mitchell samples are weighted: they are distributed like the absolute value of the filter,
+//so samples in the negative lobes count negative.
+fn filter_weight(filter: &PixelFilter, x: f64, y: f64) -> f32 {
+    match filter {
+        PixelFilter::Mitchell { radius } => {
+            //The distribution covers more than the filter, by the area of the lobes
+            let scale = 2.0 * mitchell_abs_integral(2.0);
+            let sign = |v: f64| mitchell(2.0 * v.abs() / radius).signum();
+            (sign(x) * sign(y) * scale * scale) as f32
+        }
+        _ => 1.0,
+    }
+}
+
+//Mitchell-Netravali filter with B = C = 1/3 at the distance x in 0...2. It integrates to 1 over
+//-2...2 and is negative between MITCHELL_ZERO and 2.
+fn mitchell(x: f64) -> f64 {
+    if x < 1.0 {
+        (7.0 * x * x * x - 12.0 * x * x + 16.0 / 3.0) / 6.0
+    } else if x < 2.0 {
+        (-7.0 / 3.0 * x * x * x + 12.0 * x * x - 20.0 * x + 32.0 / 3.0) / 6.0
+    } else {
+        0.0
+    }
+}
+
+//Integral of mitchell() from 0 to x
+fn mitchell_integral(x: f64) -> f64 {
+    let inner = |x: f64| (7.0 / 4.0 * x.powi(4) - 4.0 * x.powi(3) + 16.0 / 3.0 * x) / 6.0;
+    let outer =
+        |x: f64| (-7.0 / 12.0 * x.powi(4) + 4.0 * x.powi(3) - 10.0 * x * x + 32.0 / 3.0 * x) / 6.0;
+    let x = x.min(2.0);
+    if x < 1.0 {
+        inner(x)
+    } else {
+        inner(1.0) + outer(x) - outer(1.0)
+    }
+}
+
+//Integral of the absolute value of mitchell() from 0 to x
+fn mitchell_abs_integral(x: f64) -> f64 {
+    if x <= MITCHELL_ZERO {
+        mitchell_integral(x)
+    } else {
+        2.0 * mitchell_integral(MITCHELL_ZERO) - mitchell_integral(x)
+    }
+}
+
+//Maps u in [0, 1) to an offset in -1...1 distributed like the absolute value of a Mitchell filter
+//with a radius of 1, by bisecting its integral
+fn mitchell_sample(u: f64) -> f64 {
+    let side = if u < 0.5 { -1.0 } else { 1.0 };
+    let target = (2.0 * u - 1.0).abs() * mitchell_abs_integral(2.0);
+
+    let (mut lo, mut hi) = (0.0, 2.0);
+    for _ in 0..MITCHELL_BISECTIONS {
+        let mid = (lo + hi) * 0.5;
+        if mitchell_abs_integral(mid) < target {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    side * (lo + hi) * 0.25
+}
+
+//Inverse of the CDF of a tent filter with a radius of 1.
+fn tent_sample(u: f64) -> f64 {
+    if u < 0.5 {
+        (2.0 * u).sqrt() - 1.0
+    } else {
+        1.0 - (2.0 - 2.0 * u).sqrt()
+    }
+}
+
+//True if the standard error of the brightness of a pixel is above the threshold, relative to the
+//brightness
+fn is_noisy(variance: &Variance, threshold: f64) -> bool {
+    variance.standard_error() > threshold * variance.mean().max(ADAPTIVE_MIN_LUMINANCE)
+}
+
+//Seed of the random sequence used for the pixel at x, y.
+pub fn pixel_seed(seed: u64, x: u32, y: u32, width: u32) -> u64 {
+    let index = y as u64 * width as u64 + x as u64;
+    seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ index
+}
+
+//True if the camera rays through the center and the corners of the pixel do not all hit the same
+//object at about the same distance, so there is a silhouette in the pixel. The corners are shared
+//with the neighboring pixels, so both sides of an edge are found.
+fn is_edge(
+    px: f64,
+    py: f64,
+    pixel_size: f64,
+    plane: &ImagePlane,
+    settings: &Settings,
+    objects: &Vec<&Intersectable>,
+    scratch: &mut Scratch,
+) -> bool {
+    let half = pixel_size / 2.0;
+    let offsets = [
+        (0.0, 0.0),
+        (-half, -half),
+        (half, -half),
+        (-half, half),
+        (half, half),
+    ];
+
+    let mut center = None;
+    for (i, (ox, oy)) in offsets.iter().enumerate() {
+        let (eye_pos, ray_dir) = plane.camera_ray(&settings.camera, px + ox, py + oy);
+        let hit = intersect(
+            &eye_pos,
+            &ray_dir,
+            objects,
+            &settings.scene.section_planes,
+            scratch,
+        )
+        .map(|hit| (hit.object, hit.inter.ray_t));
+
+        if i == 0 {
+            center = hit;
+            continue;
+        }
+
+        let threshold = settings.output.edge_depth_threshold;
+        let differs = match (center, hit) {
+            (Some(a), Some(b)) => a.0 != b.0 || (a.1 - b.1).abs() > threshold * a.1.min(b.1),
+            (None, None) => false,
+            _ => true,
+        };
+        if differs {
+            return true;
+        }
+    }
+
+    false
+}
+
+//True for every pixel, in the order of the framebuffer, that is re-rendered by render_dirty()
+fn dirty_pixels(settings: &Settings, changed: &[(Vector4F, Vector4F)], margin: f64) -> Vec<bool> {
+    let img_w = settings.output.width;
+    let img_h = settings.output.height;
+    let plane = ImagePlane::new(settings);
+    let pixel_size = plane.width / img_w as f64;
+    let half = pixel_size / 2.0;
+    let offsets = [
+        (0.0, 0.0),
+        (-half, -half),
+        (half, -half),
+        (-half, half),
+        (half, half),
+    ];
+    let dissolved = dissolve_objects(&settings.scene, settings.output.frame);
+    let objects = visible_objects(&dissolved);
+
+    let grow = Vector4F::new(margin, margin, margin);
+    let boxes: Vec<(Vector4F, Vector4F)> = changed
+        .iter()
+        .map(|(min, max)| (min - &grow, max + &grow))
+        .collect();
+
+    let mut dirty = vec![false; (img_w * img_h) as usize];
+
+    let rows_per_thread = (img_h as usize).div_ceil(num_cpus::get()).max(1);
+    thread::scope(|scope| {
+        for (i, chunk) in dirty
+            .chunks_mut(rows_per_thread * img_w as usize)
+            .enumerate()
+        {
+            let first = i * rows_per_thread * img_w as usize;
+            let plane = &plane;
+            let objects = &objects;
+            let boxes = &boxes;
+            let offsets = &offsets;
+
+            scope.spawn(move || {
+                let mut scratch = Scratch::new();
+                for (j, pixel) in chunk.iter_mut().enumerate() {
+                    let ix = (first + j) as u32 % img_w;
+                    let iy = (first + j) as u32 / img_w;
+                    //Same pixel positions as the render
+                    let px = plane.left + ix as f64 * pixel_size;
+                    let py = plane.bottom + iy as f64 * plane.height / img_h as f64;
+
+                    *pixel = offsets.iter().any(|(ox, oy)| {
+                        let (org, dir) = plane.camera_ray(&settings.camera, px + ox, py + oy);
+                        let hit = intersect(
+                            &org,
+                            &dir,
+                            objects,
+                            &settings.scene.section_planes,
+                            &mut scratch,
+                        );
+                        let max_t = hit.map_or(std::f64::INFINITY, |hit| hit.inter.ray_t);
+                        boxes
+                            .iter()
+                            .any(|(min, max)| segment_touches_box(&org, &dir, max_t, min, max))
+                    });
+                }
+            });
+        }
+    });
+
+    dirty
+}
+
+//True if the ray from org to org + dir * max_t passes through or ends in the box
+fn segment_touches_box(
+    org: &Vector4F,
+    dir: &Vector4F,
+    max_t: f64,
+    min: &Vector4F,
+    max: &Vector4F,
+) -> bool {
+    let mut t0: f64 = 0.0;
+    let mut t1 = max_t;
+
+    for (o, d, lo, hi) in &[
+        (org.x, dir.x, min.x, max.x),
+        (org.y, dir.y, min.y, max.y),
+        (org.z, dir.z, min.z, max.z),
+    ] {
+        if *d == 0.0 {
+            if o < lo || o > hi {
+                return false;
+            }
+            continue;
+        }
+
+        let (a, b) = ((lo - o) / d, (hi - o) / d);
+        t0 = t0.max(a.min(b));
+        t1 = t1.min(a.max(b));
+        if t0 > t1 {
+            return false;
+        }
+    }
+
+    true
+}
+
+//Image plane of the camera at a distance of 1.0, in world units
+struct ImagePlane {
+    left: f64,
+    bottom: f64,
+    width: f64,
+    height: f64,
+}
+
+impl ImagePlane {
+    fn new(settings: &Settings) -> ImagePlane {
+        let cam_pos = &settings.camera.position;
+        let width = settings.camera.image_plane_width();
+        let height = width / (settings.output.width as f64 / settings.output.height as f64);
+
+        ImagePlane {
+            left: cam_pos.x - (width / 2.0),
+            bottom: cam_pos.y - (height / 2.0),
+            width,
+            height,
+        }
+    }
+
+    //Pinhole ray of the camera through the point on the image plane, as origin and direction
+    fn camera_ray(&self, camera: &Camera, x: f64, y: f64) -> (Vector4F, Vector4F) {
+        let cam_pos = &camera.position;
+        match camera.projection {
+            Projection::Perspective => {
+                let pixel = Vector4F {
+                    x,
+                    y,
+                    z: cam_pos.z + 1.0,
+                    w: 0.0,
+                };
+                (cam_pos.clone(), (&pixel - cam_pos).normalize())
+            }
+            Projection::Ods => {
+                let u = (x - self.left) / self.width;
+                let v = (y - self.bottom) / self.height;
+                ods_ray(camera, u, v)
+            }
+        }
+    }
+}
+
+//Ray of the ODS projection for the point of the image at u, v, which go from 0.0 to 1.0 starting
+//at the bottom left. Both panoramas are equirectangular with forward (+Z) in the middle. The eyes
+//are on a circle with the diameter eye_separation, always at a right angle to the direction.
+fn ods_ray(camera: &Camera, u: f64, v: f64) -> (Vector4F, Vector4F) {
+    //The left eye is on top and looks from the left of the direction
+    let (side, v) = if v >= 0.5 {
+        (-1.0, v * 2.0 - 1.0)
+    } else {
+        (1.0, v * 2.0)
+    };
+
+    let theta = (u - 0.5) * 2.0 * PI;
+    let phi = (v - 0.5) * PI;
+    let dir = Vector4F::new(theta.sin() * phi.cos(), phi.sin(), theta.cos() * phi.cos());
+
+    //Right of the direction is +X when looking forward
+    let offset = side * camera.eye_separation * 0.5;
+    let p = &camera.position;
+    let org = Vector4F::new(p.x + theta.cos() * offset, p.y, p.z - theta.sin() * offset);
+
+    (org, dir)
+}
+
+//Creates a camera ray through the given point on the unit lens disk using the thin lens model.
+//All rays through the same pixel converge at the focus distance, so only objects there are sharp.
+fn lens_ray(
+    cam_pos: &Vector4F,
+    pinhole_dir: &Vector4F,
+    camera: &Camera,
+    lens_point: &(f64, f64),
+) -> (Vector4F, Vector4F) {
+    if camera.aperture <= 0.0 {
+        return (cam_pos.clone(), pinhole_dir.clone());
+    }
+
+    let ft = camera.focus_distance / pinhole_dir.z;
+    let focus_point = Vector4F::new(
+        cam_pos.x + pinhole_dir.x * ft,
+        cam_pos.y + pinhole_dir.y * ft,
+        cam_pos.z + pinhole_dir.z * ft,
+    );
+
+    let ray_org = Vector4F::new(
+        cam_pos.x + lens_point.0 * camera.aperture,
+        cam_pos.y + lens_point.1 * camera.aperture,
+        cam_pos.z,
+    );
+    let ray_dir = (&focus_point - &ray_org).normalize();
+
+    (ray_org, ray_dir)
+}
+
+//Clips the given ray (ray_org -> ray_dir) against the section planes. Returns the part of the ray
+//that is not clipped as (t_enter, t_exit, index of the plane the ray enters through), or None if
+//all of it is clipped. Without section planes the whole ray is returned.
+fn clip_ray(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    planes: &[SectionPlane],
+) -> Option<(f64, f64, Option<usize>)> {
+    let mut t_enter = 0.0;
+    let mut t_exit = std::f64::MAX;
+    let mut entering = None;
+
+    for (i, plane) in planes.iter().enumerate() {
+        let dist = plane.distance(ray_org);
+        let speed = Vector4F::dot(ray_dir, &plane.normal);
+
+        if speed == 0.0 {
+            //Parallel to the plane, either completely clipped or not at all
+            if dist > 0.0 {
+                return None;
+            }
+            continue;
+        }
+
+        let t = -dist / speed;
+        if speed < 0.0 {
+            if t > t_enter {
+                t_enter = t;
+                entering = Some(i);
+            }
+        } else if t < t_exit {
+            t_exit = t;
+        }
+    }
+
+    if t_enter >= t_exit {
+        return None;
+    }
+
+    Some((t_enter, t_exit, entering))
+}
+
+//Returns the plane the ray enters through at the given point if the cut has to be capped there,
+//which is the case if the point is inside of any object. The index of that object is returned too.
+fn cap_plane<'a>(
+    pos: &Vector4F,
+    entering: Option<usize>,
+    planes: &'a [SectionPlane],
+    objects: &Vec<&Intersectable>,
+    scratch: &mut Scratch,
+) -> Option<(&'a SectionPlane, usize)> {
+    let plane = &planes[entering?];
+    if plane.cap_material.is_empty() {
+        return None;
+    }
+
+    objects
+        .iter()
+        .position(|obj| obj.contains(pos, scratch))
+        .map(|object| (plane, object))
+}
+
+fn point_on_ray(ray_org: &Vector4F, ray_dir: &Vector4F, t: f64) -> Vector4F {
+    Vector4F::new(
+        ray_org.x + ray_dir.x * t,
+        ray_org.y + ray_dir.y * t,
+        ray_org.z + ray_dir.z * t,
+    )
+}
+
+//Closest hit of a ray with the objects of the scene
+pub struct Hit {
+    pub inter: Intersection,
+    //Index of the material in the scene
+    pub material: usize,
+    //Index of the object in the object list. There is no instancing, so this is also the instance id.
+    pub object: usize,
+}
+
+//Checks if the given ray (ray_org -> ray_dir) intersects any of the objects in the given vec and returns the closest point of intersection, with the material and object there.
+//Geometry clipped by section planes is ignored, and where the ray enters through a cut the cap is hit instead.
+pub fn intersect(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    scratch: &mut Scratch,
+) -> Option<Hit> {
+    let (t_enter, t_exit, entering) = clip_ray(ray_org, ray_dir, planes)?;
+
+    //Start at the first point that is not clipped, so clipped hits do not hide the ones behind them
+    let org = point_on_ray(ray_org, ray_dir, t_enter);
+
+    if let Some((plane, object)) = cap_plane(&org, entering, planes, objects, scratch) {
+        let inter = Intersection {
+            pos: org,
+            normal: plane.normal.clone(),
+            tex_u: 0.0,
+            tex_v: 0.0,
+            tex_u2: 0.0,
+            tex_v2: 0.0,
+            barycentric: Vector4F::null(),
+            ray_t: t_enter,
+        };
+        return Some(Hit {
+            inter,
+            material: plane.cap_material_index,
+            object,
+        });
+    }
+
+    let mut closest = None;
+    let mut min_t = t_exit - t_enter;
+
+    for (i, obj) in objects.iter().enumerate() {
+        let intersection = obj.intersect(&org, ray_dir, min_t, scratch);
+
+        if intersection.is_some() {
+            let inter = intersection.unwrap();
+
+            if inter.ray_t < min_t {
+                min_t = inter.ray_t;
+                closest = Some(Hit {
+                    inter,
+                    material: obj.material(),
+                    object: i,
+                });
+            }
+        }
+    }
+
+    //println!("min_t: {}", min_t);
+
+    closest.map(|mut hit| {
+        hit.inter.ray_t += t_enter;
+        hit
+    })
+}
+
+//Same as intersect() for a packet of coherent rays, hits[i] is the closest hit of rays[i].
+//Section planes clip every ray differently, so with section planes the rays are intersected
+//one by one.
+fn intersect_packet(
+    rays: &mut [Ray],
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    scratch: &mut Scratch,
+    hits: &mut Vec<Option<Hit>>,
+    found: &mut Vec<Option<Intersection>>,
+) {
+    hits.clear();
+
+    if !planes.is_empty() {
+        for ray in rays.iter() {
+            hits.push(intersect(&ray.org, &ray.dir, objects, planes, scratch));
+        }
+        return;
+    }
+
+    for _ in 0..rays.len() {
+        hits.push(None);
+    }
+    found.clear();
+    for _ in 0..rays.len() {
+        found.push(None);
+    }
+
+    for (i, obj) in objects.iter().enumerate() {
+        obj.intersect_packet(rays, found, scratch);
+
+        for (r, inter) in found.iter_mut().enumerate() {
+            if let Some(inter) = inter.take() {
+                if inter.ray_t < rays[r].max_t {
+                    rays[r].max_t = inter.ray_t;
+                    hits[r] = Some(Hit {
+                        inter,
+                        material: obj.material(),
+                        object: i,
+                    });
+                }
+            }
+        }
+    }
+}
+
+//Checks if the given ray (ray_org -> ray_dir) intersects any of the objects in the given vec closer than max_t.
+//Used for shadow rays, where max_t is the distance to the light so objects behind the light don't cast shadows.
+fn intersect_any(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    max_t: f64,
+    scratch: &mut Scratch,
+) -> bool {
+    let (t_enter, t_exit, entering) = match clip_ray(ray_org, ray_dir, planes) {
+        Some(clipped) => clipped,
+        None => return false,
+    };
+    if t_enter >= max_t {
+        return false;
+    }
+
+    let org = point_on_ray(ray_org, ray_dir, t_enter);
+    if cap_plane(&org, entering, planes, objects, scratch).is_some() {
+        return true;
+    }
+
+    let max_t = max_t.min(t_exit) - t_enter;
+    for obj in objects {
+        if let Some(inter) = obj.intersect(&org, ray_dir, max_t, scratch) {
+            if inter.ray_t < max_t {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+//Returns the fraction of light that passes along the given ray (ray_org -> ray_dir) up to max_t.
+//Opaque objects block all light, translucent ones let light through tinted by their color,
+//so shadows of stained glass are colored. Scenes without translucent materials only need to
+//know if anything is hit at all, which is a lot cheaper than finding every hit along the ray.
+fn transmittance(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    scene: &Scene,
+    objects: &Vec<&Intersectable>,
+    max_t: f64,
+    scratch: &mut Scratch,
+) -> Color {
+    let blocked = blocker_transmittance(ray_org, ray_dir, scene, max_t);
+    if is_black(&blocked) {
+        return blocked;
+    }
+
+    if !scene.has_translucent_materials() {
+        return if intersect_any(
+            ray_org,
+            ray_dir,
+            objects,
+            &scene.section_planes,
+            max_t,
+            scratch,
+        ) {
+            Color::black()
+        } else {
+            blocked
+        };
+    }
+
+    let mut result = blocked;
+    let mut org = ray_org.clone();
+    let mut remaining = max_t;
+    let mut layers = 0;
+    let mut thin_layers = 0;
+
+    loop {
+        let hit = match intersect(&org, ray_dir, objects, &scene.section_planes, scratch) {
+            Some(hit) if hit.inter.ray_t < remaining => hit,
+            _ => return result,
+        };
+        let inter = hit.inter;
+
+        //Same as for camera rays, too many translucent layers are treated as opaque. Thin
+        //surfaces have their own limit.
+        let mat = scene.material(hit.material);
+        let (crossed, max_crossed) = if mat.thin {
+            (&mut thin_layers, scene.max_thin_layers)
+        } else {
+            (&mut layers, scene.max_transmission_depth)
+        };
+        if mat.opacity >= 1.0 || *crossed >= max_crossed {
+            return Color::black();
+        }
+        *crossed += 1;
+
+        let t = (1.0 - mat.opacity) as f32;
+        let color = mat.instance_color(hit.object);
+        result.r *= color.r * t;
+        result.g *= color.g * t;
+        result.b *= color.b * t;
+
+        //Nothing noticeable gets through anymore, no need to look for more hits
+        if result.r.max(result.g).max(result.b) < 0.0001 {
+            return Color::black();
+        }
+
+        //Continue behind the hit, slightly offset so the same surface is not hit again
+        remaining -= inter.ray_t;
+        org = offset_origin(&inter.pos, ray_dir);
+    }
+}
+
+//Returns the fraction of light that passes all blockers of the scene along the given ray up to
+//max_t. Blockers are only seen by rays carrying light to a surface, not by camera rays.
+fn blocker_transmittance(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    scene: &Scene,
+    max_t: f64,
+) -> Color {
+    let mut result = Color::white();
+    for blocker in &scene.blockers {
+        let t = blocker.transmittance(ray_org, ray_dir, max_t, &scene.textures);
+        result.r *= t.r;
+        result.g *= t.g;
+        result.b *= t.b;
+    }
+    result
+}
+
+fn is_black(color: &Color) -> bool {
+    color.r <= 0.0 && color.g <= 0.0 && color.b <= 0.0
+}
+
+//Power heuristic (beta = 2) for multiple importance sampling. Returns the weight for a sample taken
+//with strategy f, given nf samples with PDF fpdf and ng samples with PDF gpdf for the other strategy.
+fn power_heuristic(nf: u32, fpdf: f64, ng: u32, gpdf: f64) -> f64 {
+    let f = nf as f64 * fpdf;
+    let g = ng as f64 * gpdf;
+
+    if f == 0.0 {
+        return 0.0;
+    }
+
+    (f * f) / (f * f + g * g)
+}
+
+//PDF of sampling the given direction for a diffuse bounce at a surface with the given normal.
+//Diffuse bounces are cosine weighted.
+fn diffuse_pdf(dir: &Vector4F, normal: &Vector4F) -> f64 {
+    f64::max(0.0, Vector4F::dot(dir, normal)) / PI
+}
+
+//Returns the number of BSDF samples traced from a surface hit at the given depth.
+fn bsdf_samples(scene: &Scene, depth: u32) -> u32 {
+    if depth < scene.max_diffuse_depth && hybrid_fallback(scene, depth).is_none() {
+        scene.path_samples * scene.path_samples
+    } else {
+        0
+    }
+}
+
+//The hybrid GI settings if the indirect light at a hit at the given depth is approximated
+fn hybrid_fallback(scene: &Scene, depth: u32) -> Option<&HybridGi> {
+    scene
+        .hybrid_gi
+        .as_ref()
+        .filter(|hybrid| depth >= hybrid.full_bounces)
+}
+
+//Checks if a ray hits any of the sphere lights before max_t. Rays sampled from a BSDF (depth > 0)
+//hit all sphere lights, and the emitted light is weighted against explicit light sampling from
+//ray_org. Camera rays only see the visible lights, at full weight, as nothing else samples them.
+//bsdf_pdf is the PDF of the ray direction multiplied by the number of BSDF samples. Returns the
+//emitted light and the distance along the ray to the light.
+fn hit_light(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    scene: &Scene,
+    max_t: f64,
+    bsdf_pdf: f64,
+    depth: u32,
+) -> Option<(Color, f64)> {
+    let mut closest = None;
+    let mut ray = Ray::with_max_t(ray_org.clone(), ray_dir.clone(), max_t);
+
+    for light in &scene.lights {
+        if depth == 0 && !light.visible {
+            continue;
+        }
+        if let LightType::Sphere = light.ltype {
+            let inter = linear::intersect_ray_sphere(&ray, &light.position, light.radius);
+            if let Some(inter) = inter {
+                ray.max_t = inter.ray_t;
+                closest = Some(light);
+            }
+        }
+    }
+
+    closest.map(|light| {
+        if depth == 0 {
+            //Camera rays see the full radiance, there is no light sample to weight against
+            let radiance = (light.intensity / PI) as f32;
+            return (scaled_color(&light.color, radiance), ray.max_t);
+        }
+
+        let ldist = (&light.position - ray_org).len();
+        let mut weight = 0.0;
+
+        //Explicit light sampling does not sample directions when inside of a light
+        if ldist > light.radius {
+            let sin_max2 = (light.radius * light.radius) / (ldist * ldist);
+            let cos_max = (1.0 - sin_max2).max(0.0).sqrt();
+            let light_pdf = 1.0 / (2.0 * PI * (1.0 - cos_max));
+            weight = power_heuristic(1, bsdf_pdf, light.samples, light_pdf);
+        }
+
+        let radiance = (light.intensity / PI * weight) as f32;
+        let blocked = blocker_transmittance(ray_org, ray_dir, scene, ray.max_t);
+        let emitted = Color::new(
+            light.color.r * radiance * blocked.r,
+            light.color.g * radiance * blocked.g,
+            light.color.b * radiance * blocked.b,
+        );
+        (emitted, ray.max_t)
+    })
+}
+
+//Dims the radiance arriving along the ray from distance t by the fog of the scene and adds the
+//light scattered in by the fog, if in_scatter is set
+fn apply_fog(
+    radiance: Color,
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    t: f64,
+    scene: &Scene,
+    in_scatter: bool,
+) -> Color {
+    match scene.fog {
+        Some(ref fog) => {
+            let transmittance = fog.transmittance(ray_org, ray_dir, t) as f32;
+            let fogged = if in_scatter { 1.0 - transmittance } else { 0.0 };
+            Color::new(
+                radiance.r * transmittance + fog.color.r * fogged,
+                radiance.g * transmittance + fog.color.g * fogged,
+                radiance.b * transmittance + fog.color.b * fogged,
+            )
+        }
+        None => radiance,
+    }
+}
+
+//Returns an upper bound of the irradiance the given light can cause at the given point,
+//ignoring occlusion and the orientation of the surface.
+fn max_irradiance(light: &Light, pos: &Vector4F) -> f64 {
+    let color = light.color.r.max(light.color.g).max(light.color.b) as f64;
+
+    let bound = match light.ltype {
+        LightType::Directional => light.intensity,
+        LightType::Point => light.irradiance((&light.position - pos).len()),
+        //A sphere light never causes more irradiance than a point light of the same radius
+        //and intensity, except for points inside of the sphere
+        LightType::Sphere => {
+            let ldist = (&light.position - pos).len();
+            if ldist <= light.radius {
+                light.intensity
+            } else {
+                let ratio = light.radius / ldist;
+                (ratio * ratio) * light.intensity
+            }
+        }
+    };
+
+    bound * color
+}
+
+//Samples the direct light arriving at the given surface point from the given light.
+//Returns the irradiance at the point, which is the incoming radiance weighted by the cosine term
+//and divided by the sampling PDF, averaged over all light samples. Occluded samples contribute nothing,
+//samples passing through translucent objects are tinted by them.
+//bsdf_samples is the number of BSDF samples taken at the same point, used to weight area lights
+//against BSDF sampling with multiple importance sampling.
+//For surfaces with sheen, the same samples are also weighted with the sheen BRDF, which is
+//returned as second color.
+fn sample_light(
+    light: &Light,
+    pos: &Vector4F,
+    normal: &Vector4F,
+    sheen: Option<&Sheen>,
+    scene: &Scene,
+    objects: &Vec<&Intersectable>,
+    random: &mut Random,
+    scratch: &mut Scratch,
+    bsdf_samples: u32,
+) -> (Color, Color) {
+    let to_light = &light.position - pos;
+    let ldist = to_light.len();
+    let mut irradiance = Color::black();
+    let mut sheen_light = Color::black();
+
+    if let LightType::Point = light.ltype {
+        //Point lights are a delta distribution, there is exactly one direction to sample
+        let ldir = to_light.normalize();
+        let cos = shade::shade_lambert(&ldir, normal);
+
+        if cos > 0.0 {
+            let t = transmittance(pos, &ldir, scene, objects, ldist, scratch);
+            let scale = light.irradiance(ldist) * cos;
+            add_scaled(&mut irradiance, &t, scale);
+            add_sheen(&mut sheen_light, sheen, &ldir, normal, &t, scale);
+        }
+    } else if let LightType::Sphere = light.ltype {
+        //Radiance of the sphere surface, chosen so that the sphere emits the same
+        //amount of light as a point light with the same radius and intensity.
+        let radiance = light.intensity / PI;
+
+        if ldist <= light.radius {
+            //Inside of the light, light arrives from all directions. Sheen is left out, it would
+            //need the integral over the hemisphere.
+            add_scaled(&mut irradiance, &Color::white(), radiance * PI);
+        } else {
+            //Sample directions uniformly inside the cone subtended by the sphere
+            let sin_max2 = (light.radius * light.radius) / (ldist * ldist);
+            let cos_max = (1.0 - sin_max2).max(0.0).sqrt();
+            let inv_pdf = 2.0 * PI * (1.0 - cos_max);
+            let axis = to_light.normalize();
+
+            for _sample in 0..light.samples {
+                let sample_dir = random.random_direction_in_cone(&axis, cos_max);
+                let cos = shade::shade_lambert(&sample_dir, normal);
+
+                if cos > 0.0 {
+                    //Only objects between the point and the surface of the light can block it
+                    let ray = Ray::new(pos.clone(), sample_dir.clone());
+                    let max_t = linear::intersect_ray_sphere(&ray, &light.position, light.radius)
+                        .map_or(ldist, |i| i.ray_t);
+
+                    let weight = power_heuristic(
+                        light.samples,
+                        1.0 / inv_pdf,
+                        bsdf_samples,
+                        diffuse_pdf(&sample_dir, normal),
+                    );
+                    let t = transmittance(pos, &sample_dir, scene, objects, max_t, scratch);
+                    let scale = radiance * cos * inv_pdf * weight;
+                    add_scaled(&mut irradiance, &t, scale);
+                    add_sheen(&mut sheen_light, sheen, &sample_dir, normal, &t, scale);
+                }
+            }
+
+            let ns = 1.0 / (light.samples.max(1) as f32);
+            irradiance.r *= ns;
+            irradiance.g *= ns;
+            irradiance.b *= ns;
+            sheen_light = scaled_color(&sheen_light, ns);
+        }
+    } else if let LightType::Directional = light.ltype {
+        //Infinitely far away disk, like the sun. Intensity is the irradiance at normal incidence.
+        //Directions are sampled uniformly inside the cone of the disk, which gives soft shadows
+        //depending on the angular radius. Radiance / PDF is constant, so only the cosine remains.
+        let axis = light.direction.invert().normalize();
+        let cos_max = ((light.angular_radius / 180.0) * PI).cos();
+
+        for _sample in 0..light.samples {
+            let sample_dir = if light.angular_radius > 0.0 {
+                random.random_direction_in_cone(&axis, cos_max)
+            } else {
+                axis.clone()
+            };
+            let cos = shade::shade_lambert(&sample_dir, normal);
+
+            if cos > 0.0 {
+                let t = transmittance(pos, &sample_dir, scene, objects, std::f64::MAX, scratch);
+                add_scaled(&mut irradiance, &t, light.intensity * cos);
+                add_sheen(
+                    &mut sheen_light,
+                    sheen,
+                    &sample_dir,
+                    normal,
+                    &t,
+                    light.intensity * cos,
+                );
+            }
+        }
+
+        let ns = 1.0 / (light.samples.max(1) as f32);
+        irradiance.r *= ns;
+        irradiance.g *= ns;
+        irradiance.b *= ns;
+        sheen_light = scaled_color(&sheen_light, ns);
+    }
+
+    (
+        Color {
+            r: light.color.r * irradiance.r,
+            g: light.color.g * irradiance.g,
+            b: light.color.b * irradiance.b,
+        },
+        Color {
+            r: light.color.r * sheen_light.r,
+            g: light.color.g * sheen_light.g,
+            b: light.color.b * sheen_light.b,
+        },
+    )
+}
+
+//Adds color * scale to result.
+fn add_scaled(result: &mut Color, color: &Color, scale: f64) {
+    let scale = scale as f32;
+    result.r += color.r * scale;
+    result.g += color.g * scale;
+    result.b += color.b * scale;
+}
+
+//Sheen lobe of a surface that is lit, evaluated for each direction light arrives from
+struct Sheen {
+    //Direction from the surface to the viewer
+    view: Vector4F,
+    roughness: f64,
+}
+
+//Adds color * scale weighted with the sheen BRDF for light arriving from the given direction.
+//Scale already contains the cosine term.
+fn add_sheen(
+    result: &mut Color,
+    sheen: Option<&Sheen>,
+    dir: &Vector4F,
+    normal: &Vector4F,
+    color: &Color,
+    scale: f64,
+) {
+    if let Some(sheen) = sheen {
+        let brdf = shade::shade_sheen(dir, &sheen.view, normal, sheen.roughness);
+        add_scaled(result, color, scale * brdf);
+    }
+}
+
+//Sheen of the material seen at the angle with the given cosine, tinted by the interference color
+//of its thin film. The tint only changes the hue, the brightness of the sheen is kept.
+fn sheen_color(mat: &Material, cos_view: f64) -> Color {
+    if mat.film_thickness <= 0.0 {
+        return mat.sheen.clone();
+    }
+
+    let film = |wavelength| {
+        shade::thin_film_reflectance(
+            cos_view,
+            mat.film_thickness,
+            mat.film_ior,
+            mat.ior,
+            wavelength,
+        )
+    };
+    let (r, g, b) = (
+        film(FILM_WAVELENGTHS[0]),
+        film(FILM_WAVELENGTHS[1]),
+        film(FILM_WAVELENGTHS[2]),
+    );
+    let mean = (r + g + b) / 3.0;
+    if mean < 1e-6 {
+        return mat.sheen.clone();
+    }
+
+    Color {
+        r: mat.sheen.r * (r / mean) as f32,
+        g: mat.sheen.g * (g / mean) as f32,
+        b: mat.sheen.b * (b / mean) as f32,
+    }
+}
+
+fn scaled_color(color: &Color, scale: f32) -> Color {
+    Color {
+        r: color.r * scale,
+        g: color.g * scale,
+        b: color.b * scale,
+    }
+}
+
+//Samples the light arriving at the given surface point from the environment map, using
+//the precomputed luminance CDF to pick directions. Returns the irradiance at the point, and the
+//light weighted with the sheen BRDF like sample_light().
+//Samples are weighted against BSDF sampling with multiple importance sampling.
+fn sample_environment(
+    env: &Environment,
+    pos: &Vector4F,
+    normal: &Vector4F,
+    sheen: Option<&Sheen>,
+    scene: &Scene,
+    objects: &Vec<&Intersectable>,
+    random: &mut Random,
+    scratch: &mut Scratch,
+    bsdf_samples: u32,
+) -> (Color, Color) {
+    let mut result = Color::black();
+    let mut sheen_light = Color::black();
+    if !env.can_sample() || env.samples == 0 {
+        return (result, sheen_light);
+    }
+
+    for _sample in 0..env.samples {
+        let (sample_dir, pdf) = env.sample(random);
+        let cos = shade::shade_lambert(&sample_dir, normal);
+
+        if cos > 0.0 && pdf > 0.0 {
+            let t = transmittance(pos, &sample_dir, scene, objects, std::f64::MAX, scratch);
+            let radiance = env.lookup(&sample_dir);
+            let mis = power_heuristic(env.samples, pdf, bsdf_samples, diffuse_pdf(&sample_dir, normal));
+            let weight = (cos / pdf * mis) as f32;
+
+            result.r += radiance.r * t.r * weight;
+            result.g += radiance.g * t.g * weight;
+            result.b += radiance.b * t.b * weight;
+
+            let light = Color {
+                r: radiance.r * t.r,
+                g: radiance.g * t.g,
+                b: radiance.b * t.b,
+            };
+            add_sheen(
+                &mut sheen_light,
+                sheen,
+                &sample_dir,
+                normal,
+                &light,
+                weight as f64,
+            );
+        }
+    }
+
+    let ns = 1.0 / env.samples as f32;
+    result.r *= ns;
+    result.g *= ns;
+    result.b *= ns;
+
+    (result, scaled_color(&sheen_light, ns))
+}
+
+//Color of a camera ray in the given debug view. The ray is intersected on its own, also when
+//rendering packets, so the triangle tests are those of a single ray.
+fn debug_color(
+    view: &DebugView,
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    settings: &Settings,
+    objects: &Vec<&Intersectable>,
+    random: &mut Random,
+    scratch: &mut Scratch,
+) -> Color {
+    let scene = &settings.scene;
+    let planes = &scene.section_planes;
+    scratch.triangle_tests = 0;
+    let hit = intersect(ray_org, ray_dir, objects, planes, scratch);
+
+    match (view, hit) {
+        (DebugView::TriangleTests, _) => {
+            let tests = scratch.triangle_tests as f64;
+            heat_color((tests + 1.0).log2() / (DEBUG_MAX_TRIANGLE_TESTS + 1.0).log2())
+        }
+        (_, None) => Color::black(),
+        (DebugView::Normals, Some(hit)) => {
+            //The normal that is shaded, with rounded edges
+            let mat = scene.material(hit.material);
+            let n = if mat.edge_radius > 0.0 {
+                rounded_normal(
+                    &hit.inter,
+                    hit.object,
+                    mat.edge_radius,
+                    objects,
+                    planes,
+                    random,
+                    scratch,
+                )
+            } else {
+                hit.inter.normal
+            };
+            Color::new(
+                (n.x * 0.5 + 0.5) as f32,
+                (n.y * 0.5 + 0.5) as f32,
+                (n.z * 0.5 + 0.5) as f32,
+            )
+        }
+        (DebugView::Depth { max_distance }, Some(hit)) => {
+            let distance = hit.inter.ray_t * ray_dir.len();
+            let v = (1.0 - distance / max_distance).max(0.0) as f32;
+            Color::new(v, v, v)
+        }
+        (DebugView::Uvs, Some(hit)) => {
+            let (u, v) = hit.inter.uv(1);
+            Color::new((u - u.floor()) as f32, (v - v.floor()) as f32, 0.0)
+        }
+    }
+}
+
+//Maps 0...1 to a color going from blue over green to red
+fn heat_color(t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0) as f32;
+    if t < 0.5 {
+        Color::new(0.0, t * 2.0, 1.0 - t * 2.0)
+    } else {
+        Color::new(t * 2.0 - 1.0, 2.0 - t * 2.0, 0.0)
+    }
+}
+
+//Casts the given camera ray and adds the values of all AOVs at the first hit to values.
+fn trace_aovs(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    settings: &Settings,
+    objects: &Vec<&Intersectable>,
+    random: &mut Random,
+    scratch: &mut Scratch,
+    values: &mut Vec<f32>,
+) {
+    let output = &settings.output;
+    let scene = &settings.scene;
+    let planes = &scene.section_planes;
+    let hit = intersect(ray_org, ray_dir, objects, planes, scratch);
+    let closest = hit.as_ref().map(|hit| &hit.inter);
+
+    let mut i = 0;
+    for aov in &output.aovs {
+        match aov {
+            Aov::AmbientOcclusion => {
+                values[i] += match closest {
+                    Some(ref inter) => {
+                        ambient_occlusion(inter, objects, planes, output, random, scratch)
+                    }
+                    None => 1.0,
+                };
+            }
+            Aov::Curvature => {
+                values[i] += match closest {
+                    Some(ref inter) => curvature(inter, objects, planes, output, scratch),
+                    None => 0.5,
+                };
+            }
+            Aov::Position => {
+                //Rays that miss everything keep the origin as position
+                if let Some(ref inter) = closest {
+                    values[i] += inter.pos.x as f32;
+                    values[i + 1] += inter.pos.y as f32;
+                    values[i + 2] += inter.pos.z as f32;
+                }
+            }
+            Aov::Depth => {
+                if let Some(ref inter) = closest {
+                    values[i] += (inter.ray_t * ray_dir.len()) as f32;
+                }
+            }
+            Aov::Normal => {
+                if let Some(ref inter) = closest {
+                    values[i] += inter.normal.x as f32;
+                    values[i + 1] += inter.normal.y as f32;
+                    values[i + 2] += inter.normal.z as f32;
+                }
+            }
+            Aov::Albedo => {
+                if let Some(ref hit) = hit {
+                    let mat = scene.material(hit.material);
+                    let mut albedo = mat.instance_color(hit.object);
+                    for decal in &scene.decals {
+                        decal.apply(&hit.inter, &scene.textures, &mut albedo);
+                    }
+                    if let Some(ref dirt) = mat.dirt {
+                        apply_dirt(
+                            dirt,
+                            dirt.samples,
+                            &hit.inter,
+                            objects,
+                            planes,
+                            random,
+                            scratch,
+                            &mut albedo,
+                        );
+                    }
+                    values[i] += albedo.r;
+                    values[i + 1] += albedo.g;
+                    values[i + 2] += albedo.b;
+                }
+            }
+            Aov::Alpha => match output.shadow_sprite {
+                Some(ref sprite) => {
+                    values[i] +=
+                        shadow_opacity(sprite, ray_org, ray_dir, objects, planes, random, scratch);
+                }
+                None => {
+                    if hit.is_some() {
+                        values[i] += 1.0;
+                    }
+                }
+            },
+            Aov::ObjectId => {
+                if let Some(ref hit) = hit {
+                    values[i] += (hit.object + 1) as f32;
+                }
+            }
+            Aov::MaterialId => {
+                if let Some(ref hit) = hit {
+                    //Indexes out of range all use the default material
+                    values[i] += (hit.material.min(scene.materials.len()) + 1) as f32;
+                }
+            }
+            Aov::Direct | Aov::Indirect => {
+                //Traced like a light path pass, limited to the light paths of the image
+                let (min_bounces, max_bounces) = match aov {
+                    Aov::Direct => (1, 1),
+                    _ => (2, std::u32::MAX),
+                };
+                let paths = LightPaths {
+                    min_bounces: min_bounces.max(output.light_paths.min_bounces),
+                    max_bounces: max_bounces.min(output.light_paths.max_bounces),
+                    transmission: output.light_paths.transmission,
+                };
+                let light = trace(
+                    ray_org, ray_dir, scene, &paths, objects, random, scratch, 0, 0, 0.0,
+                );
+                values[i] += light.r;
+                values[i + 1] += light.g;
+                values[i + 2] += light.b;
+            }
+        }
+
+        i += aov.channels();
+    }
+}
+
+//Returns the fraction of cosine weighted directions above the hit that are not occluded within ao_distance.
+pub fn ambient_occlusion(
+    inter: &Intersection,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    output: &Output,
+    random: &mut Random,
+    scratch: &mut Scratch,
+) -> f32 {
+    occlusion(
+        inter,
+        objects,
+        planes,
+        output.ao_samples,
+        output.ao_distance,
+        random,
+        scratch,
+    )
+}
+
+//Same as ambient_occlusion(), with samples * samples directions that look up to distance
+fn occlusion(
+    inter: &Intersection,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    samples: u32,
+    distance: f64,
+    random: &mut Random,
+    scratch: &mut Scratch,
+) -> f32 {
+    let org = offset_origin(&inter.pos, &inter.normal);
+
+    let mut dirs = scratch.take_directions();
+    random.random_cosine_directions_in_hemisphere(
+        samples,
+        &inter.normal,
+        &mut scratch.samples,
+        &mut dirs,
+    );
+
+    let mut open = 0;
+    for dir in &dirs {
+        if !intersect_any(&org, dir, objects, planes, distance, scratch) {
+            open += 1;
+        }
+    }
+
+    let result = open as f32 / dirs.len().max(1) as f32;
+    scratch.give_directions(dirs);
+    result
+}
+
+//Blends the albedo towards the color of the dirt by how much of the surface is occluded within the
+//distance of the dirt, estimated with the given number of rays
+fn apply_dirt(
+    dirt: &Dirt,
+    samples: u32,
+    inter: &Intersection,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    random: &mut Random,
+    scratch: &mut Scratch,
+    albedo: &mut Color,
+) {
+    let open = occlusion(
+        inter,
+        objects,
+        planes,
+        samples,
+        dirt.distance,
+        random,
+        scratch,
+    );
+    let amount = (1.0 - open) * dirt.strength as f32;
+    albedo.r += (dirt.color.r - albedo.r) * amount;
+    albedo.g += (dirt.color.g - albedo.g) * amount;
+    albedo.b += (dirt.color.b - albedo.b) * amount;
+}
+
+//Opacity of the shadow sprite where the camera ray hits the ground, looking through the objects.
+//Rays that miss the ground are transparent.
+fn shadow_opacity(
+    sprite: &ShadowSprite,
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    random: &mut Random,
+    scratch: &mut Scratch,
+) -> f32 {
+    let ground = match sprite.ground {
+        Some(ground) => ground,
+        None => return 0.0,
+    };
+    let ray_t = (ground - ray_org.y) / ray_dir.y;
+    if !ray_t.is_finite() || ray_t <= 0.0 {
+        return 0.0;
+    }
+
+    let inter = Intersection {
+        pos: Vector4F::new(
+            ray_org.x + ray_dir.x * ray_t,
+            ground,
+            ray_org.z + ray_dir.z * ray_t,
+        ),
+        normal: Vector4F::new(0.0, 1.0, 0.0),
+        tex_u: 0.0,
+        tex_v: 0.0,
+        tex_u2: 0.0,
+        tex_v2: 0.0,
+        barycentric: Vector4F::null(),
+        ray_t,
+    };
+    1.0 - occlusion(
+        &inter,
+        objects,
+        planes,
+        sprite.samples,
+        sprite.distance,
+        random,
+        scratch,
+    )
+}
+
+//Estimates the curvature at the hit by probing the surface normals at curvature_radius around it.
+//Returns 0.5 for flat surfaces, 1.0 for a convex sphere with radius curvature_radius and 0.0 for
+//a concave one.
+fn curvature(
+    inter: &Intersection,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    output: &Output,
+    scratch: &mut Scratch,
+) -> f32 {
+    let n = &inter.normal;
+    let r = output.curvature_radius;
+
+    let (tangent, bitangent) = tangent_frame(n);
+    let offsets = [
+        tangent.clone(),
+        tangent.invert(),
+        bitangent.clone(),
+        bitangent.invert(),
+    ];
+
+    let probe_dir = n.invert();
+    let mut sum = 0.0;
+    let mut hits = 0;
+
+    for offset in &offsets {
+        //Shoot a ray down onto the surface from above the neighboring point
+        let probe_org = Vector4F::new(
+            inter.pos.x + (n.x + offset.x) * r,
+            inter.pos.y + (n.y + offset.y) * r,
+            inter.pos.z + (n.z + offset.z) * r,
+        );
+
+        if let Some(Hit { inter: probe, .. }) =
+            intersect(&probe_org, &probe_dir, objects, planes, scratch)
+        {
+            if probe.ray_t <= 2.0 * r {
+                let dp = &probe.pos - &inter.pos;
+                let dn = &probe.normal - n;
+                let len2 = dp.sqr_len();
+                if len2 > 0.0 {
+                    sum += Vector4F::dot(&dn, &dp) / len2;
+                    hits += 1;
+                }
+            }
+        }
+    }
+
+    if hits == 0 {
+        return 0.5;
+    }
+
+    let k = (sum / hits as f64) * r;
+    (0.5 + 0.5 * k.clamp(-1.0, 1.0)) as f32
+}
+
+//Two directions perpendicular to the normal and to each other
+fn tangent_frame(n: &Vector4F) -> (Vector4F, Vector4F) {
+    let helper = if n.x.abs() > 0.9 {
+        Vector4F::new(0.0, 1.0, 0.0)
+    } else {
+        Vector4F::new(1.0, 0.0, 0.0)
+    };
+    let tangent = Vector4F::cross(n, &helper).normalize();
+    let bitangent = Vector4F::cross(n, &tangent);
+    (tangent, bitangent)
+}
+
+//Shading normal of a hit on the given object with its edges rounded by radius. Normals of faces of
+//the same object closer than radius are blended in, the closer the more. Faces are found with
+//probes along the surface in EDGE_PROBES directions: a ray from slightly above the hit finds
+//concave edges, a ray coming back from beyond radius slightly below the surface finds convex ones.
+fn rounded_normal(
+    inter: &Intersection,
+    object: usize,
+    radius: f64,
+    objects: &Vec<&Intersectable>,
+    planes: &[SectionPlane],
+    random: &mut Random,
+    scratch: &mut Scratch,
+) -> Vector4F {
+    let n = &inter.normal;
+    let (tangent, bitangent) = tangent_frame(n);
+    let height = radius * 0.1;
+    //The directions are rotated randomly, so the rounding has no visible steps
+    let step = 2.0 * PI / EDGE_PROBES as f64;
+    let rotation = random.random_f() * step;
+
+    let mut result = n.clone();
+    for i in 0..EDGE_PROBES {
+        let angle = rotation + step * i as f64;
+        let (sin, cos) = angle.sin_cos();
+        let dir = Vector4F::new(
+            tangent.x * cos + bitangent.x * sin,
+            tangent.y * cos + bitangent.y * sin,
+            tangent.z * cos + bitangent.z * sin,
+        );
+
+        let above = Vector4F::new(
+            inter.pos.x + n.x * height,
+            inter.pos.y + n.y * height,
+            inter.pos.z + n.z * height,
+        );
+        let beyond = Vector4F::new(
+            inter.pos.x + dir.x * radius - n.x * height,
+            inter.pos.y + dir.y * radius - n.y * height,
+            inter.pos.z + dir.z * radius - n.z * height,
+        );
+        let probes = [(above, dir.clone(), false), (beyond, dir.invert(), true)];
+
+        for (org, probe_dir, from_beyond) in &probes {
+            let hit = match intersect(org, probe_dir, objects, planes, scratch) {
+                Some(hit) if hit.object == object && hit.inter.ray_t < radius => hit,
+                _ => continue,
+            };
+            let distance = if *from_beyond {
+                radius - hit.inter.ray_t
+            } else {
+                hit.inter.ray_t
+            };
+            let w = 1.0 - distance / radius;
+            let pn = &hit.inter.normal;
+            result = Vector4F::new(
+                result.x + pn.x * w,
+                result.y + pn.y * w,
+                result.z + pn.z * w,
+            );
+        }
+    }
+
+    result.normalize()
+}
+
+//Moves the origin of a secondary ray slightly along the normal to avoid hitting the surface it starts on.
+fn offset_origin(pos: &Vector4F, normal: &Vector4F) -> Vector4F {
+    const EPSILON: f64 = 0.00001;
+
+    Vector4F::new(
+        pos.x + normal.x * EPSILON,
+        pos.y + normal.y * EPSILON,
+        pos.z + normal.z * EPSILON,
+    )
+}
+
+//Traces the given ray (ray_org -> ray_dir) from the camera into the scene, shading and recursivly path tracing accordingly. Returns the color of the pixel.
+//Only light arriving along the given light paths is included.
+fn trace(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    scene: &Scene,
+    paths: &LightPaths,
+    objects: &Vec<&Intersectable>,
+    random: &mut Random,
+    scratch: &mut Scratch,
+    depth: u32,
+    transmissions: u32,
+    bsdf_pdf: f64,
+) -> Color {
+    if depth > scene.max_diffuse_depth {
+        return Color::black();
+    }
+
+    let closest = intersect(ray_org, ray_dir, objects, &scene.section_planes, scratch);
+    trace_hit(
+        ray_org,
+        ray_dir,
+        closest,
+        scene,
+        paths,
+        objects,
+        random,
+        scratch,
+        depth,
+        transmissions,
+        bsdf_pdf,
+    )
+}
+
+//Same as trace(), for a ray whose closest hit has already been found.
+fn trace_hit(
+    ray_org: &Vector4F,
+    ray_dir: &Vector4F,
+    closest: Option<Hit>,
+    scene: &Scene,
+    paths: &LightPaths,
+    objects: &Vec<&Intersectable>,
+    random: &mut Random,
+    scratch: &mut Scratch,
+    depth: u32,
+    transmissions: u32,
+    bsdf_pdf: f64,
+) -> Color {
+    let mut result = Color::black();
+
+    let distance = closest
+        .as_ref()
+        .map_or(std::f64::INFINITY, |hit| hit.inter.ray_t);
+    let missed = closest.is_none();
+
+    //Paths sampled from a BSDF can hit any sphere light, camera rays only the visible ones
+    let max_t = closest
+        .as_ref()
+        .map_or(std::f64::MAX, |hit| hit.inter.ray_t);
+    if let Some((emitted, light_t)) = hit_light(ray_org, ray_dir, scene, max_t, bsdf_pdf, depth) {
+        if !paths.accepts(depth, transmissions) {
+            log_path(scratch, depth, transmissions, || {
+                "light, not part of the light paths".to_string()
+            });
+            return result;
+        }
+        log_path(scratch, depth, transmissions, || {
+            format!("light, emitted {}", emitted)
+        });
+        return apply_fog(emitted, ray_org, ray_dir, light_t, scene, true);
+    }
+
+    if closest.is_some() {
+        let hit = closest.unwrap();
+        let mut inter = hit.inter;
+        //let vdir = (ray_org - &inter.pos).normalize();
+
+        //Objects with unknown materials are shaded with the default material of the scene
+        let mat = scene.material(hit.material);
+        if mat.edge_radius > 0.0 {
+            inter.normal = rounded_normal(
+                &inter,
+                hit.object,
+                mat.edge_radius,
+                objects,
+                &scene.section_planes,
+                random,
+                scratch,
+            );
+        }
+        //Objects sharing a material can vary in color
+        let base_color = mat.instance_color(hit.object);
+        let name = objects[hit.object].name();
+        log_path(scratch, depth, transmissions, || {
+            format!(
+                "hit '{}' with material '{}' at t {}, position {}, normal {}",
+                name, mat.id, inter.ray_t, inter.pos, inter.normal
+            )
+        });
+
+        let mut lcolor = Color::black();
+        //Light reflected by the sheen lobe, which is added on top of the diffuse light
+        let mut sheen_light = Color::black();
+        let sheen = if is_black(&mat.sheen) {
+            None
+        } else {
+            Some(Sheen {
+                view: ray_dir.invert().normalize(),
+                roughness: mat.sheen_roughness,
+            })
+        };
+
+        //Direct lighting, explicitly sampled at every bounce (next event estimation).
+        //The material is lambertian, so the BRDF is albedo / PI, plus the sheen lobe.
+        let bsdf_samples = bsdf_samples(scene, depth);
+        if paths.accepts(depth + 1, transmissions) {
+            for light in &scene.lights {
+                //Skip lights that are too far away or too dim to matter, saves the shadow rays
+                if max_irradiance(light, &inter.pos) < scene.light_threshold {
+                    continue;
+                }
+
+                let (irradiance, sheen_irradiance) = sample_light(
+                    light,
+                    &inter.pos,
+                    &inter.normal,
+                    sheen.as_ref(),
+                    scene,
+                    objects,
+                    random,
+                    scratch,
+                    bsdf_samples,
+                );
+
+                lcolor.r += irradiance.r / PI as f32;
+                lcolor.g += irradiance.g / PI as f32;
+                lcolor.b += irradiance.b / PI as f32;
+                add_scaled(&mut sheen_light, &sheen_irradiance, 1.0);
+            }
+
+            if let Some(ref env) = scene.environment {
+                let (irradiance, sheen_irradiance) = sample_environment(
+                    env,
+                    &inter.pos,
+                    &inter.normal,
+                    sheen.as_ref(),
+                    scene,
+                    objects,
+                    random,
+                    scratch,
+                    bsdf_samples,
+                );
+
+                lcolor.r += irradiance.r / PI as f32;
+                lcolor.g += irradiance.g / PI as f32;
+                lcolor.b += irradiance.b / PI as f32;
+                add_scaled(&mut sheen_light, &sheen_irradiance, 1.0);
+            }
+
+            log_path(scratch, depth, transmissions, || {
+                format!("direct light {}", lcolor)
+            });
+        }
+
+        //Indirect light has at least one more bounce than direct light
+        let indirect = scene.path_samples > 0 && depth < paths.max_bounces;
+        if let Some(hybrid) = hybrid_fallback(scene, depth) {
+            if indirect
+                && depth < scene.max_diffuse_depth
+                && paths.accepts(depth + 1, transmissions)
+            {
+                let ambient = hybrid.ambient.as_ref().unwrap_or(&scene.skycolor);
+                let ao = occlusion(
+                    &inter,
+                    objects,
+                    &scene.section_planes,
+                    hybrid.ao_samples,
+                    hybrid.ao_distance,
+                    random,
+                    scratch,
+                );
+
+                lcolor.r += ambient.r * ao;
+                lcolor.g += ambient.g * ao;
+                lcolor.b += ambient.b * ao;
+                log_path(scratch, depth, transmissions, || {
+                    format!("ambient occlusion {}", ao)
+                });
+            }
+        } else if indirect {
+            let mut path_color = Color::black();
+            let mut path_sheen = Color::black();
+
+            let mut sample_dirs = scratch.take_directions();
+            random.random_cosine_directions_in_hemisphere(
+                scene.path_samples,
+                &inter.normal,
+                &mut scratch.samples,
+                &mut sample_dirs,
+            );
+            for sdir in &sample_dirs {
+                let pdf = diffuse_pdf(sdir, &inter.normal) * bsdf_samples as f64;
+                let pc = trace(
+                    &inter.pos,
+                    sdir,
+                    scene,
+                    paths,
+                    objects,
+                    random,
+                    scratch,
+                    depth + 1,
+                    transmissions,
+                    pdf,
+                );
+
+                //The cosine term is already part of the sample distribution
+                path_color.r += pc.r;
+                path_color.g += pc.g;
+                path_color.b += pc.b;
+
+                //The PDF is cos / PI, so BRDF * cos / PDF = BRDF * PI
+                add_sheen(
+                    &mut path_sheen,
+                    sheen.as_ref(),
+                    sdir,
+                    &inter.normal,
+                    &pc,
+                    PI,
+                );
+            }
+
+            /*for _ps in 0..scene.path_samples {
+                let path_dir = random.random_point_on_hemisphere(&inter.normal);
+                let pc = trace(&inter.pos, &path_dir, scene, random, depth + 1);
+
+                /*let diffuse = shade::shade_oren_nayar(&path_dir, &inter.normal, &vdir, mat.roughness, 0.1);
+                let specular = shade::shade_cook_torrance(&path_dir, &vdir, &inter.normal, mat.roughness, 0.1);
+                let shading = diffuse + specular;*/
+
+                let shading = shade::shade_lambert(&path_dir, &inter.normal);
+
+                path_color.r += pc.r * shading as f32;
+                path_color.g += pc.g * shading as f32;
+                path_color.b += pc.b * shading as f32;
+            }*/
+
+            //Directions are cosine weighted, so the PDF is cos / PI.
+            //BRDF * cos / PDF = (albedo / PI) * cos * (PI / cos) = albedo, albedo is applied below.
+            let ps = 1.0 / (sample_dirs.len() as f32);
+            scratch.give_directions(sample_dirs);
+
+            path_color.r *= ps;
+            path_color.g *= ps;
+            path_color.b *= ps;
+            add_scaled(&mut sheen_light, &path_sheen, ps as f64);
+            log_path(scratch, depth, transmissions, || {
+                format!("indirect light {}", path_color)
+            });
+
+            lcolor.r += path_color.r;
+            lcolor.g += path_color.g;
+            lcolor.b += path_color.b;
+        }
+
+        //Enabling this only shows GI
+        /*if depth == 0 {
+            result.r = path_color.r;
+            result.g = path_color.g;
+            result.b = path_color.b;
+        }
+        else {*/
+        let mut albedo = base_color.clone();
+        for decal in &scene.decals {
+            decal.apply(&inter, &scene.textures, &mut albedo);
+        }
+        if let Some(ref dirt) = mat.dirt {
+            //The dirt is linear in the occlusion, so a single ray per bounce averages out over the
+            //paths, only the first hit needs all samples
+            let samples = if depth == 0 { dirt.samples } else { 1 };
+            apply_dirt(
+                dirt,
+                samples,
+                &inter,
+                objects,
+                &scene.section_planes,
+                random,
+                scratch,
+                &mut albedo,
+            );
+        }
+
+        let sheen = match sheen {
+            Some(ref sheen) => sheen_color(mat, Vector4F::dot(&inter.normal, &sheen.view)),
+            None => Color::black(),
+        };
+        result.r = albedo.r * lcolor.r + sheen.r * sheen_light.r;
+        result.g = albedo.g * lcolor.g + sheen.g * sheen_light.g;
+        result.b = albedo.b * lcolor.b + sheen.b * sheen_light.b;
+        //}
+
+        //Translucent surfaces blend with whatever is behind them, tinted like in transmittance()
+        //Past the transmission depth they are treated as opaque, thin ones past max_thin_layers.
+        let passes = if mat.thin {
+            scratch.thin_layers < scene.max_thin_layers
+        } else {
+            transmissions < scene.max_transmission_depth
+        };
+        if mat.opacity < 1.0 && passes {
+            let org = offset_origin(&inter.pos, ray_dir);
+            let passed = if mat.thin {
+                scratch.thin_layers += 1;
+                transmissions
+            } else {
+                transmissions + 1
+            };
+            let behind = trace(
+                &org, ray_dir, scene, paths, objects, random, scratch, depth, passed, bsdf_pdf,
+            );
+            if mat.thin {
+                scratch.thin_layers -= 1;
+            }
+            let opacity = mat.opacity as f32;
+            let t = 1.0 - opacity;
+            log_path(scratch, depth, transmissions, || {
+                format!("seen through opacity {}: {}", opacity, behind)
+            });
+
+            result.r = result.r * opacity + behind.r * base_color.r * t;
+            result.g = result.g * opacity + behind.g * base_color.g * t;
+            result.b = result.b * opacity + behind.b * base_color.b * t;
+        }
+    } else if !paths.accepts(depth, transmissions) {
+        //The background is not part of the pass
+    } else if let Some(ref env) = scene.environment {
+        result = env.lookup(ray_dir);
+
+        //Light from the environment is also sampled explicitly at every surface hit,
+        //so weight paths sampled from a BSDF against that.
+        if depth > 0 {
+            let weight = power_heuristic(1, bsdf_pdf, env.samples, env.pdf(ray_dir)) as f32;
+            result.r *= weight;
+            result.g *= weight;
+            result.b *= weight;
+        }
+    } else if let Some(ref backdrop) = scene.backdrop {
+        result = backdrop.color(ray_dir);
+    } else {
+        result.r = scene.skycolor.r;
+        result.g = scene.skycolor.g;
+        result.b = scene.skycolor.b;
+    }
+    if missed {
+        log_path(scratch, depth, transmissions, || {
+            format!("missed, background {}", result)
+        });
+    }
+
+    //Light reaching a surface along a path is blocked, what the camera sees directly is not
+    if depth > 0 && !scene.blockers.is_empty() {
+        let blocked = blocker_transmittance(ray_org, ray_dir, scene, distance);
+        result.r *= blocked.r;
+        result.g *= blocked.g;
+        result.b *= blocked.b;
+    }
+
+    //Every segment of a path is fogged, so indirect light is dimmed too. The fog color is
+    //only added where the background would be part of the pass.
+    let accepted = paths.accepts(depth, transmissions);
+    let result = apply_fog(result, ray_org, ray_dir, distance, scene, accepted);
+
+    log_path(scratch, depth, transmissions, || {
+        format!("radiance {}", result)
+    });
+    result
+}
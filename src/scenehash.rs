@@ -0,0 +1,83 @@
+use json::JsonValue;
+use std::fs::File;
+use std::io::Read;
+use validate;
+
+//Content hash of a scene: the settings and the contents of all files they reference. Any edit
+//to the settings or to one of the assets changes the hash, so it tells if something derived from
+//the scene is still up to date. Command line overrides of the output are not part of it.
+pub fn hash_scene(json: &JsonValue) -> u64 {
+    let mut hasher = Hasher::new();
+    hash_json(json, &mut hasher);
+
+    for (_what, file) in validate::referenced_files(json) {
+        hasher.write(file.as_bytes());
+
+        //Missing files are reported by the validation, they just don't add to the hash
+        let mut content = Vec::new();
+        if let Ok(mut f) = File::open(&file) {
+            if f.read_to_end(&mut content).is_ok() {
+                hasher.write(&content);
+            }
+        }
+    }
+
+    hasher.hash
+}
+
+//Every value starts with a tag and strings and lists with their length, so different structures
+//can't produce the same stream of bytes.
+fn hash_json(value: &JsonValue, hasher: &mut Hasher) {
+    match value {
+        JsonValue::Null => hasher.write(&[0]),
+        JsonValue::Number(num) => {
+            hasher.write(&[1]);
+            hasher.write(&num.to_bits().to_le_bytes());
+        }
+        JsonValue::Boolean(b) => hasher.write(&[2, *b as u8]),
+        JsonValue::String(st) => {
+            hasher.write(&[3]);
+            hash_str(st, hasher);
+        }
+        JsonValue::Array(values) => {
+            hasher.write(&[4]);
+            hasher.write(&(values.len() as u64).to_le_bytes());
+            for v in values {
+                hash_json(v, hasher);
+            }
+        }
+        JsonValue::Object(fields) => {
+            hasher.write(&[5]);
+            hasher.write(&(fields.len() as u64).to_le_bytes());
+            for f in fields {
+                hash_str(&f.0, hasher);
+                hash_json(&f.1, hasher);
+            }
+        }
+    }
+}
+
+fn hash_str(st: &str, hasher: &mut Hasher) {
+    hasher.write(&(st.len() as u64).to_le_bytes());
+    hasher.write(st.as_bytes());
+}
+
+//64 bit FNV-1a
+struct Hasher {
+    hash: u64,
+}
+
+impl Hasher {
+    fn new() -> Hasher {
+        Hasher {
+            hash: 0xcbf29ce484222325,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.hash ^= *b as u64;
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use linear::Ray;
+use linear::Vector4F;
+
+//Per-thread scratch memory for temporaries that are needed while tracing a ray.
+//One instance is created per worker and reused for all rays it traces, so after
+//the first few rays no more allocations happen for candidate lists, sample
+//buffers and direction lists.
+pub struct Scratch {
+    //Triangle indexes returned by the octree for the current mesh intersection
+    pub candidates: Vec<usize>,
+    //Triangle indexes with the mask of the rays to test for the current packet intersection
+    pub packet_candidates: Vec<(usize, u8)>,
+    //Rays of the current packet in object space of the mesh
+    pub packet_rays: Vec<Ray>,
+    //Stratified 2D samples used to generate sample directions
+    pub samples: Vec<(f64, f64)>,
+    //Stratified points on the unit disk used to sample the camera lens
+    pub lens: Vec<(f64, f64)>,
+    //Lines describing the path of the ray being traced, only collected while debugging a sample
+    pub log: Option<Vec<String>>,
+    //Ray triangle tests done by meshes, for the triangle test debug view
+    pub triangle_tests: u64,
+    //Thin surfaces the current ray has passed through on its way from the camera
+    pub thin_layers: u32,
+    //Pool of direction buffers. Path tracing recurses, so each depth needs its
+    //own buffer. Buffers are taken from the pool and handed back when done.
+    directions: Vec<Vec<Vector4F>>,
+}
+
+impl Scratch {
+    pub fn new() -> Scratch {
+        Scratch {
+            candidates: Vec::new(),
+            packet_candidates: Vec::new(),
+            packet_rays: Vec::new(),
+            samples: Vec::new(),
+            lens: Vec::new(),
+            log: None,
+            triangle_tests: 0,
+            thin_layers: 0,
+            directions: Vec::new(),
+        }
+    }
+
+    //Takes an empty direction buffer from the pool, or creates a new one if the pool is empty.
+    pub fn take_directions(&mut self) -> Vec<Vector4F> {
+        self.directions.pop().unwrap_or_default()
+    }
+
+    //Hands a direction buffer back to the pool so it can be reused by the next ray.
+    pub fn give_directions(&mut self, mut dirs: Vec<Vector4F>) {
+        dirs.clear();
+        self.directions.push(dirs);
+    }
+}
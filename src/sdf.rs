@@ -0,0 +1,320 @@
+use linear::Ray;
+use linear::Vector4F;
+use vox::VoxelObject;
+
+//Distance in voxels around the surface that is stored exactly, further away it is clamped
+const BAND: f64 = 3.0;
+//Distance to the surface at which a marching ray counts as hit, in voxels
+const HIT_DISTANCE: f64 = 0.001;
+//Marched distance is scaled by this, as the interpolated field can overestimate the distance
+const STEP_SCALE: f64 = 0.8;
+//Smallest step, so rays grazing the surface still make progress
+const MIN_STEP: f64 = 0.0005;
+//Marching steps after which a ray counts as a miss
+const MAX_STEPS: u32 = 1024;
+//Halvings of the last step when a ray overshot the surface
+const BISECTIONS: u32 = 16;
+//Squared distance of samples without any sample of the other kind, larger than any grid
+const FAR: f64 = 1e20;
+
+//Signed distance to the surface of a voxel object, sampled at the voxel centers and smoothed, so
+//its zero level is a rounded, "melted" version of the voxels. Negative inside. Distances are in
+//voxels, which are unit cubes in object space like for blocky voxels.
+pub struct DistanceField {
+    //Samples per axis: the voxel grid with a margin of empty voxels on every side
+    size: [usize; 3],
+    margin: usize,
+    values: Vec<f32>,
+}
+
+impl DistanceField {
+    //Converts the voxels to a distance field and blurs it with a gaussian of the given radius in
+    //voxels. Larger radii round off more of the edges and fill small gaps.
+    pub fn from_voxels(voxels: &VoxelObject, radius: f64) -> DistanceField {
+        let margin = (BAND.max(radius) as usize) + 2;
+        let size = [
+            voxels.width as usize + 2 * margin,
+            voxels.height as usize + 2 * margin,
+            voxels.depth as usize + 2 * margin,
+        ];
+        let count = size[0] * size[1] * size[2];
+
+        let mut inside = vec![false; count];
+        for z in 0..voxels.depth {
+            for y in 0..voxels.height {
+                for x in 0..voxels.width {
+                    if voxels.get(x, y, z).is_some() {
+                        let (sx, sy, sz) = (x as usize + margin, y as usize + margin, z as usize);
+                        inside[((sz + margin) * size[1] + sy) * size[0] + sx] = true;
+                    }
+                }
+            }
+        }
+
+        //Squared distances from every sample to the nearest inside and outside sample
+        let mut to_inside: Vec<f64> = inside.iter().map(|i| if *i { 0.0 } else { FAR }).collect();
+        let mut to_outside: Vec<f64> = inside.iter().map(|i| if *i { FAR } else { 0.0 }).collect();
+        for axis in 0..3 {
+            for_each_line(size, axis, &mut to_inside, distance_transform);
+            for_each_line(size, axis, &mut to_outside, distance_transform);
+        }
+
+        //The surface is halfway between the centers of an inside and an outside voxel
+        let mut values: Vec<f32> = (0..count)
+            .map(|i| {
+                let d = if inside[i] {
+                    0.5 - to_outside[i].sqrt()
+                } else {
+                    to_inside[i].sqrt() - 0.5
+                };
+                d.clamp(-BAND, BAND) as f32
+            })
+            .collect();
+
+        if radius > 0.0 {
+            let kernel = gaussian_kernel(radius);
+            for axis in 0..3 {
+                for_each_line(size, axis, &mut values, |line| blur(line, &kernel));
+            }
+        }
+
+        DistanceField {
+            size,
+            margin,
+            values,
+        }
+    }
+
+    //Object space box that contains the zero level of the field
+    pub fn bounds(&self) -> (Vector4F, Vector4F) {
+        let m = self.margin as f64;
+        (
+            Vector4F::new(-m, -m, -m),
+            Vector4F::new(
+                self.size[0] as f64 - m,
+                self.size[1] as f64 - m,
+                self.size[2] as f64 - m,
+            ),
+        )
+    }
+
+    //Trilinearly interpolated distance at the object space position
+    pub fn distance(&self, pos: &Vector4F) -> f64 {
+        let m = self.margin as f64 - 0.5;
+        let g = [pos.x + m, pos.y + m, pos.z + m];
+
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for a in 0..3 {
+            let max = (self.size[a] - 1) as f64;
+            let c = g[a].max(0.0).min(max);
+            base[a] = (c.floor() as usize).min(self.size[a] - 2);
+            frac[a] = c - base[a] as f64;
+        }
+
+        let mut result = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for a in 0..3 {
+                let upper = corner & (1 << a) != 0;
+                index[a] = base[a] + upper as usize;
+                weight *= if upper { frac[a] } else { 1.0 - frac[a] };
+            }
+            result += weight * self.value(index) as f64;
+        }
+        result
+    }
+
+    //Direction in which the distance grows fastest, the normal of the surface
+    pub fn normal(&self, pos: &Vector4F) -> Vector4F {
+        let h = 0.25;
+        let dx = Vector4F::new(h, 0.0, 0.0);
+        let dy = Vector4F::new(0.0, h, 0.0);
+        let dz = Vector4F::new(0.0, 0.0, h);
+        Vector4F::new(
+            self.distance(&(pos + &dx)) - self.distance(&(pos - &dx)),
+            self.distance(&(pos + &dy)) - self.distance(&(pos - &dy)),
+            self.distance(&(pos + &dz)) - self.distance(&(pos - &dz)),
+        )
+        .normalize()
+    }
+
+    //Marches the object space ray through the field and returns the ray_t of the first point where
+    //it enters the surface, within the t range of the ray. Like the faces of meshes, the surface is
+    //only hit from the outside, so rays starting on or inside of it leave it first.
+    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (min, max) = self.bounds();
+        let (t0, t1) = clip(ray, &min, &max)?;
+        let len = ray.dir.len();
+        let end = t1.min(ray.max_t);
+
+        let mut t = t0.max(0.0);
+        let mut outside = false;
+        let mut last_t = t;
+        for _ in 0..MAX_STEPS {
+            if t > end {
+                return None;
+            }
+
+            let d = self.distance(&point(ray, t));
+            if !outside {
+                outside = d > 2.0 * HIT_DISTANCE;
+            } else if d < HIT_DISTANCE {
+                if d >= 0.0 {
+                    return Some(t);
+                }
+                //Overshot the surface, it is between the last two steps
+                let (mut lo, mut hi) = (last_t, t);
+                for _ in 0..BISECTIONS {
+                    let mid = (lo + hi) * 0.5;
+                    if self.distance(&point(ray, mid)) > 0.0 {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                return Some(hi);
+            }
+
+            last_t = t;
+            t += (d.abs() * STEP_SCALE).max(MIN_STEP) / len;
+        }
+
+        None
+    }
+
+    fn value(&self, index: [usize; 3]) -> f32 {
+        self.values[(index[2] * self.size[1] + index[1]) * self.size[0] + index[0]]
+    }
+}
+
+fn point(ray: &Ray, t: f64) -> Vector4F {
+    Vector4F::new(
+        ray.org.x + ray.dir.x * t,
+        ray.org.y + ray.dir.y * t,
+        ray.org.z + ray.dir.z * t,
+    )
+}
+
+//Range of t in which the ray is inside of the box, None if it misses it
+fn clip(ray: &Ray, min: &Vector4F, max: &Vector4F) -> Option<(f64, f64)> {
+    let org = [ray.org.x, ray.org.y, ray.org.z];
+    let inv = [ray.inv_dir.x, ray.inv_dir.y, ray.inv_dir.z];
+    let lo = [min.x, min.y, min.z];
+    let hi = [max.x, max.y, max.z];
+
+    let mut t0 = std::f64::NEG_INFINITY;
+    let mut t1 = std::f64::INFINITY;
+    for a in 0..3 {
+        let ta = (lo[a] - org[a]) * inv[a];
+        let tb = (hi[a] - org[a]) * inv[a];
+        t0 = t0.max(ta.min(tb));
+        t1 = t1.min(ta.max(tb));
+    }
+
+    if t0 > t1 || t1 < 0.0 {
+        None
+    } else {
+        Some((t0, t1))
+    }
+}
+
+//Calls f for every line of values along the axis, with the values copied into a buffer and
+//written back afterwards
+fn for_each_line<T, F>(size: [usize; 3], axis: usize, values: &mut [T], mut f: F)
+where
+    T: Copy,
+    F: FnMut(&mut [T]),
+{
+    let stride = [1, size[0], size[0] * size[1]][axis];
+    let (u, v) = [(1, 2), (0, 2), (0, 1)][axis];
+    let strides = [1, size[0], size[0] * size[1]];
+
+    let mut line = Vec::with_capacity(size[axis]);
+    for j in 0..size[v] {
+        for i in 0..size[u] {
+            let start = i * strides[u] + j * strides[v];
+            line.clear();
+            line.extend((0..size[axis]).map(|k| values[start + k * stride]));
+            f(&mut line);
+            for (k, value) in line.iter().enumerate() {
+                values[start + k * stride] = *value;
+            }
+        }
+    }
+}
+
+//Replaces the squared distances of a line by the smallest (i - j)^2 + f[j] over all j, the lower
+//envelope of parabolas by Felzenszwalb and Huttenlocher. Applied along every axis, it turns the
+//squared distances to the samples of one kind into the exact squared euclidean distances.
+fn distance_transform(f: &mut [f64]) {
+    let n = f.len();
+    let mut hull = vec![0usize; n];
+    let mut starts = vec![0.0f64; n + 1];
+    let mut k = 0;
+    starts[0] = std::f64::NEG_INFINITY;
+    starts[1] = std::f64::INFINITY;
+
+    for q in 1..n {
+        let fq = f[q] + (q * q) as f64;
+        let mut s;
+        loop {
+            let p = hull[k];
+            s = (fq - (f[p] + (p * p) as f64)) / (2.0 * (q as f64 - p as f64));
+            if s <= starts[k] && k > 0 {
+                k -= 1;
+            } else {
+                break;
+            }
+        }
+        if s <= starts[k] {
+            //Only possible for k = 0, the new parabola is below the first everywhere
+            hull[0] = q;
+            starts[1] = std::f64::INFINITY;
+            continue;
+        }
+        k += 1;
+        hull[k] = q;
+        starts[k] = s;
+        starts[k + 1] = std::f64::INFINITY;
+    }
+
+    let input = f.to_vec();
+    k = 0;
+    for (q, value) in f.iter_mut().enumerate() {
+        while starts[k + 1] < q as f64 {
+            k += 1;
+        }
+        let d = q as f64 - hull[k] as f64;
+        *value = d * d + input[hull[k]];
+    }
+}
+
+//Normalized gaussian weights with a standard deviation of half the radius, cut off at the radius
+fn gaussian_kernel(radius: f64) -> Vec<f32> {
+    let r = radius.ceil() as i64;
+    let sigma = (radius * 0.5).max(0.1);
+    let weights: Vec<f64> = (-r..=r)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| (w / sum) as f32).collect()
+}
+
+//Convolves the line with the kernel, repeating the values at the ends
+fn blur(line: &mut [f32], kernel: &[f32]) {
+    let r = (kernel.len() / 2) as i64;
+    let n = line.len() as i64;
+    let input = line.to_vec();
+    for (i, value) in line.iter_mut().enumerate() {
+        *value = kernel
+            .iter()
+            .enumerate()
+            .map(|(k, w)| {
+                let j = (i as i64 + k as i64 - r).max(0).min(n - 1);
+                w * input[j as usize]
+            })
+            .sum();
+    }
+}
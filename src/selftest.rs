@@ -0,0 +1,91 @@
+use json;
+use render::render_with_progress;
+use settings::Settings;
+use std::sync::atomic::AtomicBool;
+
+//Built-in scene of the self test, a "white furnace": a grey sphere under a white sky without
+//lights. Every path ends in the sky, so the sphere reflects its albedo times the sky wherever it
+//is seen, and nothing can be brighter than the sky.
+const SCENE: &str = r#"{
+    "scene": {
+        "skycolor": [1.0, 1.0, 1.0],
+        "max_trace_depth": 2,
+        "path_samples": 2,
+        "materials": [
+            { "id": "grey", "color": [0.5, 0.5, 0.5] }
+        ],
+        "spheres": [
+            { "center": [0.0, 0.0, 5.0], "radius": 1.0, "material": "grey" }
+        ]
+    },
+    "output": {
+        "file": "selftest.tga",
+        "width": 32,
+        "height": 32,
+        "samples": 2
+    }
+}"#;
+
+//Radiance of the sky and albedo of the sphere in the scene
+const SKY: f32 = 1.0;
+const ALBEDO: f32 = 0.5;
+//Allowed difference to the expected radiance, for noise and rounding
+const TOLERANCE: f32 = 0.05;
+
+//Renders the built-in scene and checks the image against what the scene must look like. Nothing
+//is read from or written to files. Returns the problems found, which is empty if all is well.
+pub fn selftest() -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let settings = json::parse_json(SCENE)
+        .map_err(|e| vec![format!("the built-in scene does not parse: {}", e)])
+        .and_then(Settings::from_json);
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(errors) => return errors,
+    };
+
+    let cancel = AtomicBool::new(false);
+    let framebuffer = render_with_progress(&settings, &cancel, |_| ());
+    if framebuffer.cancelled {
+        problems.push("the render did not finish".to_string());
+    }
+    if !framebuffer.failed_pixels.is_empty() {
+        problems.push(format!(
+            "{} pixels failed to render",
+            framebuffer.failed_pixels.len()
+        ));
+    }
+
+    let num_values = (framebuffer.width * framebuffer.height * 3) as usize;
+    let values: Vec<f32> = (0..num_values).map(|i| framebuffer.pixels.get(i)).collect();
+    if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        problems.push("the image has negative, NaN or infinite radiance".to_string());
+    }
+
+    //The sphere fills the center of the image
+    let center =
+        ((framebuffer.height / 2 * framebuffer.width + framebuffer.width / 2) * 3) as usize;
+    let sphere = &values[center..(center + 3)];
+    if sphere.iter().all(|v| *v <= 0.0) {
+        problems.push("the sphere is black".to_string());
+    } else if sphere.iter().any(|v| (v - ALBEDO * SKY).abs() > TOLERANCE) {
+        problems.push(format!(
+            "the sphere has radiance {:?}, expected {} under a sky of {}",
+            sphere,
+            ALBEDO * SKY,
+            SKY
+        ));
+    }
+
+    //Surfaces can't reflect more light than they get
+    let max = values.iter().cloned().fold(0.0f32, f32::max);
+    if max > SKY + TOLERANCE {
+        problems.push(format!(
+            "the brightest pixel has radiance {}, more than the sky of {}",
+            max, SKY
+        ));
+    }
+
+    problems
+}
@@ -1,19 +1,47 @@
+use animation::Flicker;
+use animation::LightAnimation;
+use assets;
+use assets::AssetResolver;
+use decimate;
+use degenerate;
+use environment::Environment;
 use json::JsonValue;
 use linear;
 use linear::Intersection;
+use linear::Matrix4;
+use linear::Ray;
+use linear::Transform;
+use linear::TrianglePlane;
 use linear::Vector4F;
 use linear::Vertex4F;
 use obj;
 use octree;
 use octree::OctreeNode;
+use ply;
+use random::Sampler;
+use scenehash;
+use scratch::Scratch;
+use sdf::DistanceField;
+use shade;
+use sky::Sky;
+use smooth;
 use vox;
 use std::clone::Clone;
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fmt::Result;
+use std::path::Path;
 use stopwatch::StopWatch;
+use studio;
+use texture::Texture;
+use texture::TextureCache;
+use validate;
 use vox::VoxelObject;
 
+//Material of the placeholders for objects with missing files, created if the scene has none
+const PLACEHOLDER_MATERIAL: &str = "placeholder";
+
 pub struct Color {
     pub r: f32,
     pub g: f32,
@@ -48,6 +76,11 @@ impl Color {
             b: self.b,
         }
     }
+
+    //Brightness as perceived, with the Rec. 709 weights
+    pub fn luminance(&self) -> f64 {
+        (0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b) as f64
+    }
 }
 
 impl Display for Color {
@@ -72,895 +105,4196 @@ pub struct Material {
     pub reflect: f64,
     pub refract: f64,
     pub ior: f64,
+    //Squared to get the width of the microfacet distribution. Materials of older formats can give a
+    //Phong "specular_exponent" instead, which is converted to roughness when reading them. Only
+    //stored for now, there is no glossy lobe that uses it.
     pub roughness: f64,
+    //1.0 is fully opaque. Light passing through the surface is tinted by the color of the material.
+    pub opacity: f64,
+    //Translucent surface without an inside, like a window pane, soap bubble or leaf. Light passes
+    //it in one step, so it does not count against max_transmission_depth but max_thin_layers. It
+    //also does not count as a transmission for light paths.
+    pub thin: bool,
+    //Soft highlight at grazing angles on top of the diffuse color, like on cloth and velvet.
+    //Black has no sheen.
+    pub sheen: Color,
+    pub sheen_roughness: f64,
+    //Thin film on the surface in nanometers, like soap or oil, 0 is none. Interference in the film
+    //tints the sheen depending on the view angle. The film has its own IOR, the surface below it
+    //has the IOR of the material.
+    pub film_thickness: f64,
+    pub film_ior: f64,
+    //Edges of objects are shaded as if they were rounded with this radius, 0 keeps them sharp.
+    //Only changes the shading normals, the geometry stays the same.
+    pub edge_radius: f64,
+    //Maximum random change of hue in degrees and of value as a fraction, per object using the
+    //material. Keeps scattered copies of an object from looking identical.
+    pub hue_jitter: f64,
+    pub value_jitter: f64,
+    //Changes the random variation of all objects
+    pub variation_seed: u64,
+    //Grime in crevices and corners, None for a clean surface
+    pub dirt: Option<Dirt>,
+}
+
+//Procedural dirt: the color of the material is blended towards the dirt color where other surfaces
+//are close, found with a few occlusion rays at every hit. Cheap grime without baked maps, but noisy
+//with few samples.
+pub struct Dirt {
+    pub color: Color,
+    //Surfaces further away than this do not cause dirt
+    pub distance: f64,
+    pub samples: u32,
+    //Blend towards the dirt color where the surface is fully occluded, 1.0 is the dirt color
+    pub strength: f64,
+}
+
+impl Material {
+    //Returns the color of the material for the object with the given index, with the hue and value
+    //jitter applied. The same object always gets the same color.
+    pub fn instance_color(&self, instance: usize) -> Color {
+        if self.hue_jitter == 0.0 && self.value_jitter == 0.0 {
+            return self.color.clone();
+        }
+
+        let hash = hash_instance(instance as u64, self.variation_seed);
+        //Two independent numbers in -1...1 from the two halves of the hash
+        let r1 = (hash >> 32) as f64 / std::u32::MAX as f64 * 2.0 - 1.0;
+        let r2 = (hash & 0xFFFF_FFFF) as f64 / std::u32::MAX as f64 * 2.0 - 1.0;
+
+        let (h, s, v) = rgb_to_hsv(&self.color);
+        let h = (h + r1 * self.hue_jitter).rem_euclid(360.0);
+        let v = (v * (1.0 + r2 * self.value_jitter)).max(0.0);
+        hsv_to_rgb(h, s, v)
+    }
+}
+
+//SplitMix64 finalizer, gives well distributed bits for consecutive instance ids
+fn hash_instance(instance: u64, seed: u64) -> u64 {
+    let mut z = instance
+        .wrapping_add(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+//Returns hue in degrees, saturation and value
+fn rgb_to_hsv(color: &Color) -> (f64, f64, f64) {
+    let r = color.r as f64;
+    let g = color.g as f64;
+    let b = color.b as f64;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    (h, s, max)
+}
+
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> Color {
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = v - c;
+
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    Color::new((r + m) as f32, (g + m) as f32, (b + m) as f32)
+}
+
+//Sync, so lists of objects can be shared between render threads
+pub trait Intersectable: Sync {
+    fn intersect(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        min_t: f64,
+        scratch: &mut Scratch,
+    ) -> Option<Intersection>;
+    //Index of the material in the materials of the scene. Indexes that are out of range
+    //use the default material.
+    fn material(&self) -> usize;
+    //Material id as given in the scene file
+    fn material_name(&self) -> &str;
+    //True if the given point is inside of the object. Used to cap objects cut open by section planes.
+    fn contains(&self, pos: &Vector4F, scratch: &mut Scratch) -> bool;
+    //Name of the object as given in the scene file, can be empty
+    fn name(&self) -> &str;
+    //Axis aligned bounding box as (min, max), or None if the object is infinite
+    fn bounds(&self) -> Option<(Vector4F, Vector4F)>;
+    //Intersects a packet of coherent rays, hits[i] is set if rays[i] hits the object closer than
+    //its max_t. Objects that can share work between the rays override this.
+    fn intersect_packet(
+        &self,
+        rays: &[Ray],
+        hits: &mut [Option<Intersection>],
+        scratch: &mut Scratch,
+    ) {
+        for (ray, hit) in rays.iter().zip(hits.iter_mut()) {
+            *hit = self
+                .intersect(&ray.org, &ray.dir, ray.max_t, scratch)
+                .filter(|inter| inter.ray_t < ray.max_t);
+        }
+    }
+}
+
+//Axis aligned bounding box that grows to include all points added to it
+pub struct Bounds {
+    min: Vector4F,
+    max: Vector4F,
 }
 
-pub trait Intersectable {
-    fn intersect(&self, rorg: &Vector4F, rdir: &Vector4F, min_t: f64) -> Option<Intersection>;
-    fn material(&self) -> String;
+impl Bounds {
+    pub fn new() -> Bounds {
+        Bounds {
+            min: Vector4F::new(std::f64::MAX, std::f64::MAX, std::f64::MAX),
+            max: Vector4F::new(std::f64::MIN, std::f64::MIN, std::f64::MIN),
+        }
+    }
+
+    pub fn add(&mut self, p: &Vector4F) {
+        self.min = Vector4F::new(
+            self.min.x.min(p.x),
+            self.min.y.min(p.y),
+            self.min.z.min(p.z),
+        );
+        self.max = Vector4F::new(
+            self.max.x.max(p.x),
+            self.max.y.max(p.y),
+            self.max.z.max(p.z),
+        );
+    }
+
+    //Returns (min, max), or None if nothing was added
+    pub fn get(&self) -> Option<(Vector4F, Vector4F)> {
+        if self.min.x > self.max.x {
+            return None;
+        }
+
+        Some((self.min.clone(), self.max.clone()))
+    }
 }
 
 pub struct Sphere {
+    pub name: String,
     pub center: Vector4F,
     pub radius: f64,
+    //Set for spheres with a rotation or scale, which makes them ellipsoids. In object space the
+    //sphere is at the origin, the transform includes the translation to the center.
+    pub transform: Option<Transform>,
     pub material: String,
+    pub material_index: usize,
 }
 
 impl Intersectable for Sphere {
-    fn intersect(&self, rorg: &Vector4F, rdir: &Vector4F, min_t: f64) -> Option<Intersection> {
-        linear::intersect_ray_sphere(rorg, rdir, &self.center, self.radius, min_t)
+    fn intersect(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        min_t: f64,
+        _scratch: &mut Scratch,
+    ) -> Option<Intersection> {
+        let transform = match &self.transform {
+            Some(t) => t,
+            None => {
+                let ray = Ray::with_max_t(rorg.clone(), rdir.clone(), min_t);
+                return linear::intersect_ray_sphere(&ray, &self.center, self.radius);
+            }
+        };
+
+        //The sphere intersection normalizes the direction, so ray_t is scaled by the length the
+        //direction has in object space
+        let org = transform.world_to_object.transform_point(rorg);
+        let dir = transform.world_to_object.transform_vector(rdir);
+        let scale = dir.len() / rdir.len();
+
+        let origin = Vector4F::new(0.0, 0.0, 0.0);
+        let ray = Ray::with_max_t(org, dir, min_t * scale);
+        let mut inter = linear::intersect_ray_sphere(&ray, &origin, self.radius)?;
+        inter.pos = transform.object_to_world.transform_point(&inter.pos);
+        inter.normal = transform.transform_normal(&inter.normal);
+        inter.ray_t /= scale;
+        Some(inter)
+    }
+
+    fn material(&self) -> usize {
+        self.material_index
+    }
+
+    fn material_name(&self) -> &str {
+        self.material.as_str()
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn bounds(&self) -> Option<(Vector4F, Vector4F)> {
+        let r = match &self.transform {
+            //The extent of an ellipsoid along an axis is the length of that row of the matrix
+            Some(t) => {
+                let m = &t.object_to_world.m;
+                let extent = |i: usize| {
+                    (m[i][0] * m[i][0] + m[i][1] * m[i][1] + m[i][2] * m[i][2]).sqrt() * self.radius
+                };
+                Vector4F::new(extent(0), extent(1), extent(2))
+            }
+            None => Vector4F::new(self.radius, self.radius, self.radius),
+        };
+        Some((&self.center - &r, &self.center + &r))
+    }
+
+    fn contains(&self, pos: &Vector4F, _scratch: &mut Scratch) -> bool {
+        match &self.transform {
+            Some(t) => t.world_to_object.transform_point(pos).len() < self.radius,
+            None => (pos - &self.center).len() < self.radius,
+        }
+    }
+}
+
+//Flat surface through point that faces the side the normal points to. It is infinite if width and
+//height are 0.0, otherwise it is a rectangle of that size centered at point.
+pub struct Plane {
+    pub name: String,
+    pub point: Vector4F,
+    pub normal: Vector4F,
+    //Axes of the rectangle, derived from the normal
+    pub tangent: Vector4F,
+    pub bitangent: Vector4F,
+    pub width: f64,
+    pub height: f64,
+    pub material: String,
+    pub material_index: usize,
+}
+
+impl Plane {
+    pub fn new(
+        name: String,
+        point: Vector4F,
+        normal: Vector4F,
+        width: f64,
+        height: f64,
+        material: String,
+    ) -> Plane {
+        //Zero normals are reported by validation
+        let normal = if normal.len() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        };
+        //For planes facing along an axis, the rectangle is aligned to the other axes
+        let helper = if normal.x.abs() > 0.9 {
+            Vector4F::new(0.0, 1.0, 0.0)
+        } else {
+            Vector4F::new(1.0, 0.0, 0.0)
+        };
+        let tangent = Vector4F::cross(&normal, &helper).normalize();
+        let bitangent = Vector4F::cross(&normal, &tangent);
+
+        Plane {
+            name,
+            point,
+            normal,
+            tangent,
+            bitangent,
+            width,
+            height,
+            material,
+            material_index: 0,
+        }
+    }
+
+    fn is_infinite(&self) -> bool {
+        self.width <= 0.0 && self.height <= 0.0
+    }
+}
+
+impl Intersectable for Plane {
+    fn intersect(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        min_t: f64,
+        _scratch: &mut Scratch,
+    ) -> Option<Intersection> {
+        let dir = rdir.normalize();
+
+        //Only the front side can be hit, like triangles
+        let denom = Vector4F::dot(&self.normal, &dir);
+        if denom >= 0.0 {
+            return None;
+        }
+
+        let t = Vector4F::dot(&(&self.point - rorg), &self.normal) / denom;
+        if t <= 0.0 || t > min_t {
+            return None;
+        }
+
+        let pos = Vector4F::new(rorg.x + dir.x * t, rorg.y + dir.y * t, rorg.z + dir.z * t);
+        let local = &pos - &self.point;
+        let mut u = Vector4F::dot(&local, &self.tangent);
+        let mut v = Vector4F::dot(&local, &self.bitangent);
+
+        if !self.is_infinite() {
+            if u.abs() > self.width * 0.5 || v.abs() > self.height * 0.5 {
+                return None;
+            }
+            u = u / self.width + 0.5;
+            v = v / self.height + 0.5;
+        }
+
+        Some(Intersection {
+            pos,
+            normal: self.normal.clone(),
+            tex_u: u,
+            tex_v: v,
+            tex_u2: u,
+            tex_v2: v,
+            barycentric: Vector4F::null(),
+            ray_t: t,
+        })
+    }
+
+    fn material(&self) -> usize {
+        self.material_index
+    }
+
+    fn material_name(&self) -> &str {
+        self.material.as_str()
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn bounds(&self) -> Option<(Vector4F, Vector4F)> {
+        if self.is_infinite() {
+            return None;
+        }
+
+        let mut bounds = Bounds::new();
+        for (su, sv) in &[(-0.5, -0.5), (-0.5, 0.5), (0.5, -0.5), (0.5, 0.5)] {
+            let u = su * self.width;
+            let v = sv * self.height;
+            bounds.add(&Vector4F::new(
+                self.point.x + self.tangent.x * u + self.bitangent.x * v,
+                self.point.y + self.tangent.y * u + self.bitangent.y * v,
+                self.point.z + self.tangent.z * u + self.bitangent.z * v,
+            ));
+        }
+        bounds.get()
     }
 
-    fn material(&self) -> String {
-        self.material.clone()
+    //An infinite plane is the surface of the half space behind it, rectangles have no inside
+    fn contains(&self, pos: &Vector4F, _scratch: &mut Scratch) -> bool {
+        self.is_infinite() && Vector4F::dot(&(pos - &self.point), &self.normal) < 0.0
     }
 }
 
+//Indexes of the corners in the vertices of the mesh
 pub struct Triangle {
-    pub v1: Vertex4F,
-    pub v2: Vertex4F,
-    pub v3: Vertex4F,
+    pub v1: u32,
+    pub v2: u32,
+    pub v3: u32,
+}
+
+impl Triangle {
+    pub fn corners<'a>(
+        &self,
+        vertices: &'a [Vertex4F],
+    ) -> (&'a Vertex4F, &'a Vertex4F, &'a Vertex4F) {
+        (
+            &vertices[self.v1 as usize],
+            &vertices[self.v2 as usize],
+            &vertices[self.v3 as usize],
+        )
+    }
 }
 
 pub struct Mesh {
+    pub name: String,
+    //Name of the OBJ group the triangles belong to, empty if the file has no groups
+    pub group: String,
+    //Vertices and octree are in object space, rays are transformed into it. Triangles that share
+    //a corner with the same position, normal, texture coordinates and color share the vertex.
+    pub vertices: Vec<Vertex4F>,
     pub triangles: Vec<Triangle>,
-    pub translation: Vector4F,
-    pub rotation: Vector4F,
-    pub scale: Vector4F,
+    pub transform: Transform,
     pub material: String,
+    //Material set with "usemtl" in the OBJ file, empty if there is none
+    pub obj_material: String,
+    pub material_index: usize,
     pub octree: OctreeNode,
 }
 
 impl Intersectable for Mesh {
-    fn intersect(&self, rorg: &Vector4F, rdir: &Vector4F, min_t: f64) -> Option<Intersection> {
-        let candidates = self.octree.intersection_candidates(rorg, &rdir.normalize());
+    fn intersect(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        min_t: f64,
+        scratch: &mut Scratch,
+    ) -> Option<Intersection> {
+        //The direction is not normalized, so ray_t is the same in object and world space
+        let mut ray = Ray::with_max_t(
+            self.transform.world_to_object.transform_point(rorg),
+            self.transform.world_to_object.transform_vector(rdir),
+            min_t,
+        );
+
+        self.octree
+            .intersection_candidates(&ray, &mut scratch.candidates);
+        scratch.triangle_tests += scratch.candidates.len() as u64;
 
         let mut closest = None;
-        let mut lmin_t = min_t;
 
-        for t in candidates {
-            let tri = &self.triangles[t];
+        for t in &scratch.candidates {
+            let (v1, v2, v3) = self.triangles[*t].corners(&self.vertices);
 
-            let intersection =
-                linear::intersect_ray_triangle(&rorg, &rdir, &tri.v1, &tri.v2, &tri.v3, lmin_t);
+            let intersection = linear::intersect_ray_triangle(&ray, v1, v2, v3);
 
             if intersection.is_some() {
                 let inter = intersection.unwrap();
-                if inter.ray_t < lmin_t {
-                    lmin_t = inter.ray_t;
+                if inter.ray_t < ray.max_t {
+                    ray.max_t = inter.ray_t;
                     closest = Some(inter);
                 }
             }
         }
 
-        closest
+        closest.map(|mut inter| {
+            inter.pos = self.transform.object_to_world.transform_point(&inter.pos);
+            inter.normal = self.transform.transform_normal(&inter.normal);
+            inter
+        })
+    }
+
+    //Each node of the octree and each candidate triangle is fetched once for all rays
+    fn intersect_packet(
+        &self,
+        rays: &[Ray],
+        hits: &mut [Option<Intersection>],
+        scratch: &mut Scratch,
+    ) {
+        let mut local = std::mem::take(&mut scratch.packet_rays);
+        local.clear();
+        for ray in rays {
+            local.push(Ray::with_max_t(
+                self.transform.world_to_object.transform_point(&ray.org),
+                self.transform.world_to_object.transform_vector(&ray.dir),
+                ray.max_t,
+            ));
+        }
+
+        self.octree
+            .packet_candidates(&local, &mut scratch.packet_candidates);
+
+        for hit in hits.iter_mut() {
+            *hit = None;
+        }
+
+        for (t, mask) in &scratch.packet_candidates {
+            let (v1, v2, v3) = self.triangles[*t].corners(&self.vertices);
+            let plane = TrianglePlane::new(&v1.pos, &v2.pos, &v3.pos);
+
+            for (i, ray) in local.iter_mut().enumerate() {
+                if mask & (1 << i) == 0 {
+                    continue;
+                }
+
+                if let Some(inter) = linear::intersect_ray_triangle_plane(ray, v1, v2, v3, &plane) {
+                    if inter.ray_t < ray.max_t {
+                        ray.max_t = inter.ray_t;
+                        hits[i] = Some(inter);
+                    }
+                }
+            }
+        }
+
+        for hit in hits.iter_mut() {
+            if let Some(ref mut inter) = *hit {
+                inter.pos = self.transform.object_to_world.transform_point(&inter.pos);
+                inter.normal = self.transform.transform_normal(&inter.normal);
+            }
+        }
+
+        scratch.packet_rays = local;
+    }
+
+    fn material(&self) -> usize {
+        self.material_index
     }
 
-    fn material(&self) -> String {
-        self.material.clone()
+    fn material_name(&self) -> &str {
+        self.material.as_str()
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn bounds(&self) -> Option<(Vector4F, Vector4F)> {
+        let to_world = &self.transform.object_to_world;
+        let mut bounds = Bounds::new();
+        for v in &self.vertices {
+            bounds.add(&to_world.transform_point(&v.pos));
+        }
+        bounds.get()
+    }
+
+    //Counts the triangles crossed by a ray leaving the point, an odd count means inside.
+    //Only works for closed meshes. The direction is skewed to not run along edges of
+    //axis aligned geometry.
+    fn contains(&self, pos: &Vector4F, scratch: &mut Scratch) -> bool {
+        let ray = Ray::new(
+            self.transform.world_to_object.transform_point(pos),
+            Vector4F::new(0.5773, 0.5779, 0.5769).normalize(),
+        );
+        self.octree
+            .intersection_candidates(&ray, &mut scratch.candidates);
+
+        let mut crossings = 0;
+        for t in &scratch.candidates {
+            let (v1, v2, v3) = self.triangles[*t].corners(&self.vertices);
+            if linear::ray_crosses_triangle(&ray, &v1.pos, &v2.pos, &v3.pos) {
+                crossings += 1;
+            }
+        }
+
+        crossings % 2 == 1
     }
 }
 
+#[derive(PartialEq)]
 pub enum LightType {
     Point,
     Sphere,
+    Directional,
+}
+
+//How the irradiance of a point light falls off with the distance. Only inverse square is physical,
+//the others are for lighting by hand, they are equal to it at the reference distance of the light.
+#[derive(PartialEq)]
+pub enum Falloff {
+    None,
+    Linear,
+    InverseSquare,
 }
 
 pub struct Light {
     pub ltype: LightType,
     pub position: Vector4F,
+    //Direction the light travels in, only used by directional lights
+    pub direction: Vector4F,
+    //Angular radius in degrees of the disk of a directional light. Controls shadow softness.
+    pub angular_radius: f64,
     pub color: Color,
+    //Seen by camera rays as an emissive sphere. Only used by sphere lights, other lights have no
+    //surface to see.
     pub visible: bool,
     pub radius: f64,
     pub samples: u32,
+    //Irradiance at the reference distance of point and sphere lights, at normal incidence. For
+    //directional lights it is the irradiance everywhere.
     pub intensity: f64,
+    //Distance at which point and sphere lights cause the intensity, the radius unless the intensity
+    //was given in units. For sphere lights it is always the radius.
+    pub reference: f64,
+    pub falloff: Falloff,
+    //Changes of the color and intensity over the frames, applied by Scene::animate_lights()
+    pub animation: Option<LightAnimation>,
 }
 
-pub struct Scene {
-    pub materials: Vec<Material>,
-    pub spheres: Vec<Sphere>,
-    pub meshes: Vec<Mesh>,
-    pub voxels: Vec<Voxels>,
-    pub lights: Vec<Light>,
-    pub skycolor: Color,
-    pub max_depth: u32,
-    pub path_samples: u32,
+impl Light {
+    //Irradiance at normal incidence caused by a point light at the given distance
+    pub fn irradiance(&self, dist: f64) -> f64 {
+        let ratio = self.reference / dist;
+        match self.falloff {
+            Falloff::None => self.intensity,
+            Falloff::Linear => ratio * self.intensity,
+            Falloff::InverseSquare => (ratio * ratio) * self.intensity,
+        }
+    }
 }
 
-impl Scene {
-    pub fn objects<'a>(&'a self) -> Vec<&'a Intersectable> {
-        let mut result = Vec::with_capacity(self.spheres.len() + self.meshes.len());
-        for sp in &self.spheres {
-            result.push(sp as &Intersectable);
-        }
-        for mesh in &self.meshes {
-            result.push(mesh as &Intersectable);
-        }
-        for vox in &self.voxels {
-            result.push(vox as &Intersectable);
-        }
+//Vertical color gradient seen by rays that hit nothing, used instead of the sky color. Above the
+//horizon it goes from the horizon color to the top color straight up, below it to the bottom color
+//straight down.
+pub struct Backdrop {
+    pub top: Color,
+    pub horizon: Color,
+    pub bottom: Color,
+}
 
-        result
+impl Backdrop {
+    pub fn color(&self, dir: &Vector4F) -> Color {
+        let y = dir.normalize().y;
+        let (end, t) = if y >= 0.0 {
+            (&self.top, y as f32)
+        } else {
+            (&self.bottom, -y as f32)
+        };
+        Color::new(
+            self.horizon.r + (end.r - self.horizon.r) * t,
+            self.horizon.g + (end.g - self.horizon.g) * t,
+            self.horizon.b + (end.b - self.horizon.b) * t,
+        )
     }
 }
 
-pub struct Output {
-    pub filename: String,
-    pub width: u32,
-    pub height: u32,
+//Light from all directions with the sky color above and the ground color below the horizon, or
+//from a texture. Only turned into an environment map if nothing replaces it.
+pub struct Dome {
+    pub color: Color,
+    pub ground: Color,
+    pub file: Option<String>,
+    pub intensity: f64,
+    pub rotation: f64,
     pub samples: u32,
 }
 
-pub struct Settings {
-    pub scene: Scene,
-    pub output: Output,
+impl Dome {
+    pub fn environment(&self) -> Environment {
+        let texture = self.file.as_ref().map(|file| Texture::load(file.as_str()));
+        Environment::dome(
+            &self.color,
+            &self.ground,
+            texture.as_ref(),
+            self.intensity,
+            self.rotation,
+            self.samples,
+        )
+    }
 }
 
-impl Settings {
-    pub fn from_json(json: JsonValue) -> Option<Settings> {
-        if let JsonValue::Object(nodes) = json {
-            let mut scene = None;
-            let mut output = None;
+//Exponential height fog. The density is given at the fog height, it grows below and falls off
+//above it, so low areas fill with fog while high ones stay clear. Only the visible rays are fogged,
+//shadow rays are not, which is much cheaper than a volume.
+pub struct Fog {
+    pub color: Color,
+    //Extinction per unit of distance at the fog height
+    pub density: f64,
+    pub height: f64,
+    //How fast the density changes with the height, 0.0 for the same density everywhere
+    pub falloff: f64,
+}
 
-            for node in nodes {
-                if node.0 == "scene" {
-                    scene = read_scene(node.1);
-                } else if node.0 == "output" {
-                    output = read_output(node.1);
-                }
-            }
+//Cheap approximation of global illumination for previews. The first full_bounces diffuse bounces
+//are path traced as usual, past them the indirect light at a hit is the ambient color times the
+//ambient occlusion there instead of more path samples. Direct light is still sampled at every hit.
+pub struct HybridGi {
+    pub full_bounces: u32,
+    //Uses the sky color if not set
+    pub ambient: Option<Color>,
+    pub ao_samples: u32,
+    pub ao_distance: f64,
+}
 
-            return Some(Settings {
-                scene: scene.unwrap(),
-                output: output.unwrap(),
-            });
+impl Fog {
+    //Fraction of the light that passes the fog along the ray up to distance t, which can be infinite
+    pub fn transmittance(&self, org: &Vector4F, dir: &Vector4F, t: f64) -> f64 {
+        let start = self.density * (-self.falloff * (org.y - self.height)).exp();
+        if start <= 0.0 {
+            return 1.0;
         }
 
-        None
+        //Integral of the density along the ray, horizontal rays see a constant density
+        let k = self.falloff * dir.normalize().y;
+        let depth = if k.abs() < 1e-9 {
+            start * t
+        } else {
+            start * (1.0 - (-k * t).exp()) / k
+        };
+
+        (-depth).exp()
     }
 }
 
-pub struct Voxels {
+//Removes all geometry on the side of the plane the normal points to, for cutaway renders.
+//Where the plane cuts through an object, the cut is closed with the cap material.
+pub struct SectionPlane {
+    pub point: Vector4F,
+    pub normal: Vector4F,
+    //Empty if the cut should stay open
+    pub cap_material: String,
+    pub cap_material_index: usize,
+}
+
+impl SectionPlane {
+    //Signed distance of the point to the plane, positive on the clipped side
+    pub fn distance(&self, pos: &Vector4F) -> f64 {
+        Vector4F::dot(&(pos - &self.point), &self.normal)
+    }
+}
+
+//Projects a texture onto all surfaces inside of a box volume, modifying their albedo.
+//The texture is projected along the local Z axis of the box, the box spans -0.5...0.5 in
+//object space and is placed with translation, rotation (degrees) and scale.
+pub struct Decal {
     pub translation: Vector4F,
     pub rotation: Vector4F,
     pub scale: Vector4F,
-    pub material: String,
-    pub voxels: VoxelObject,
+    pub opacity: f64,
+    //Id of the texture in the texture cache of the scene
+    pub texture: usize,
+    //UV set (1 or 2) of the surface to map the texture with instead of projecting it. The box
+    //still limits where the decal is applied.
+    pub uv_set: Option<u32>,
 }
 
-impl Intersectable for Voxels {
-    fn intersect(&self, rorg: &Vector4F, rdir: &Vector4F, _min_t: f64) -> Option<Intersection> {
-        //Create inverted transforms to be able to go from world space to object space
-        let inv_trans = &self.translation.invert();
-        let inv_rot = &self.rotation.invert();
-        let inv_scale = &Vector4F {
-            x: 1.0 / self.scale.x,
-            y: 1.0 / self.scale.y,
-            z: 1.0 / self.scale.z,
-            w: 1.0,
+impl Decal {
+    //Blends the decal texture over the given albedo if the point is inside of the decal volume
+    //and the surface faces the projection.
+    pub fn apply(&self, inter: &Intersection, textures: &TextureCache, albedo: &mut Color) {
+        //Transform into object space of the decal box
+        let local = (&inter.pos - &self.translation)
+            .rotate_z(-self.rotation.z)
+            .rotate_y(-self.rotation.y)
+            .rotate_x(-self.rotation.x);
+        let x = local.x / self.scale.x;
+        let y = local.y / self.scale.y;
+        let z = local.z / self.scale.z;
+
+        if x.abs() > 0.5 || y.abs() > 0.5 || z.abs() > 0.5 {
+            return;
+        }
+
+        let (u, v) = match self.uv_set {
+            Some(set) => inter.uv(set),
+            None => {
+                //Only surfaces facing against the projection direction receive the decal
+                let local_normal = inter
+                    .normal
+                    .rotate_z(-self.rotation.z)
+                    .rotate_y(-self.rotation.y)
+                    .rotate_x(-self.rotation.x);
+                if local_normal.z >= 0.0 {
+                    return;
+                }
+                (x + 0.5, y + 0.5)
+            }
         };
 
-        //Transform ray origin and direction into object space
-        let rorg_obj_space = &(rorg + inv_trans) * inv_scale;
-        let rdir_obj_space = rdir
-            .rotate_x(inv_rot.x)
-            .rotate_y(inv_rot.y)
-            .rotate_z(inv_rot.z);
+        let (color, alpha) = textures.get(self.texture).sample(u, v);
+        let a = alpha * self.opacity as f32;
 
-        //let mut x: i32;
-        //let mut y: i32;
-        //let mut z: i32;
+        albedo.r = albedo.r * (1.0 - a) + color.r * a;
+        albedo.g = albedo.g * (1.0 - a) + color.g * a;
+        albedo.b = albedo.b * (1.0 - a) + color.b * a;
+    }
+}
 
-        let min = Vector4F::null();
-        let max = Vector4F::new(
-            self.voxels.width as f64,
-            self.voxels.height as f64,
-            self.voxels.depth as f64,
-        );
+//Rectangle that is invisible to the camera but blocks light, like a flag or gobo in a studio.
+//It spans -0.5...0.5 on the local X and Y axes and is placed with translation, rotation (degrees)
+//and scale like a decal. Without a texture it blocks all light, a texture is used as the pattern
+//of a gobo: white lets the light through, black blocks it and colors tint it like a gel.
+//Transparent parts of the texture let all light through.
+pub struct Blocker {
+    pub translation: Vector4F,
+    pub rotation: Vector4F,
+    pub scale: Vector4F,
+    //Id of the texture in the texture cache of the scene
+    pub texture: Option<usize>,
+}
 
-        let intersection = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
-        let mut closest_intersection = None;
-        let mut min_t = std::f64::MAX;
+impl Blocker {
+    //Returns the fraction of light passing the blocker along the given ray up to max_t.
+    pub fn transmittance(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        max_t: f64,
+        textures: &TextureCache,
+    ) -> Color {
+        //Transform into object space of the blocker, which keeps the ray parameter t
+        let org = (rorg - &self.translation)
+            .rotate_z(-self.rotation.z)
+            .rotate_y(-self.rotation.y)
+            .rotate_x(-self.rotation.x);
+        let dir = rdir
+            .rotate_z(-self.rotation.z)
+            .rotate_y(-self.rotation.y)
+            .rotate_x(-self.rotation.x);
 
-        if intersection.is_some() {
-            for z in 0..self.voxels.depth {
-                for y in 0..self.voxels.height {
-                    for x in 0..self.voxels.width {
-                        let voxel = self.voxels.get(x, y, z);
-                        if voxel.is_some() {
-                            //println!("some: {};{};{}", x, y, z);
+        //The scale does not move the plane at z = 0
+        let t = -org.z / dir.z;
+        if !(t > 0.0 && t < max_t) {
+            return Color::white();
+        }
 
-                            let min = Vector4F::new(x as f64, y as f64, z as f64);
-                            let max = Vector4F::new(x as f64 + 1.0, y as f64 + 1.0, z as f64 + 1.0);
+        let x = (org.x + dir.x * t) / self.scale.x;
+        let y = (org.y + dir.y * t) / self.scale.y;
+        if x.abs() > 0.5 || y.abs() > 0.5 {
+            return Color::white();
+        }
 
-                            let intersection = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
-                            if intersection.is_some() {
-                                //println!("inter: {};{};{}", x, y, z);
-                                let inter = intersection.unwrap();
-                                let ray_t = inter.ray_t;
-                                if ray_t < min_t {
-                                    closest_intersection = Some(inter);
-                                    min_t = ray_t;
-                                }
-                            }
-                        }
-                    }
-                }
+        match self.texture {
+            Some(texture) => {
+                let (color, alpha) = textures.get(texture).sample(x + 0.5, y + 0.5);
+                let t = 1.0 - alpha;
+                Color::new(
+                    color.r * alpha + t,
+                    color.g * alpha + t,
+                    color.b * alpha + t,
+                )
             }
+            None => Color::black(),
         }
-
+    }
+}
+
+//Animation of the visibility of all objects with the given name over the frames, for objects that
+//appear or disappear in a sequence. Between the keys the visibility is interpolated linearly,
+//before the first and after the last key it stays the same. 1 is fully visible, 0 is invisible.
+pub struct VisibilityAnimation {
+    pub object: String,
+    //(frame, visibility), sorted by frame
+    pub keys: Vec<(f64, f64)>,
+}
+
+impl VisibilityAnimation {
+    pub fn at(&self, frame: f64) -> f64 {
+        let value = match self.keys.iter().position(|k| k.0 > frame) {
+            None => self.keys.last().map_or(1.0, |k| k.1),
+            Some(0) => self.keys[0].1,
+            Some(i) => {
+                let (f0, v0) = self.keys[i - 1];
+                let (f1, v1) = self.keys[i];
+                v0 + (v1 - v0) * (frame - f0) / (f1 - f0)
+            }
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+pub struct Scene {
+    pub materials: Vec<Material>,
+    pub spheres: Vec<Sphere>,
+    pub planes: Vec<Plane>,
+    pub meshes: Vec<Mesh>,
+    pub voxels: Vec<Voxels>,
+    pub lights: Vec<Light>,
+    pub decals: Vec<Decal>,
+    pub blockers: Vec<Blocker>,
+    pub visibility: Vec<VisibilityAnimation>,
+    pub section_planes: Vec<SectionPlane>,
+    pub skycolor: Color,
+    //Replaces the sky color if set, from the sky gradient of the scene or the studio
+    pub backdrop: Option<Backdrop>,
+    pub environment: Option<Environment>,
+    pub fog: Option<Fog>,
+    pub hybrid_gi: Option<HybridGi>,
+    //Maximum number of diffuse bounces of a path
+    pub max_diffuse_depth: u32,
+    //Maximum number of translucent surfaces a ray passes through
+    pub max_transmission_depth: u32,
+    //Maximum number of thin surfaces a ray passes through. They don't count as transmissions, but
+    //dense foliage seen at grazing angles would otherwise recurse without limit.
+    pub max_thin_layers: u32,
+    pub path_samples: u32,
+    //Lights that can cause less irradiance than this at a shading point are skipped there.
+    //0.0 means all lights are always sampled.
+    pub light_threshold: f64,
+    //Used for objects that reference a material id that does not exist
+    pub default_material: Material,
+    //If true, objects with unknown materials are an error instead of using the default material
+    pub strict_materials: bool,
+    pub textures: TextureCache,
+}
+
+impl Scene {
+    //Sets the color and intensity of the animated lights to their values at the given frame.
+    //Renders without a frame use frame 0.
+    pub fn animate_lights(&mut self, frame: Option<u32>) {
+        let frame = frame.unwrap_or(0) as f64;
+        for light in &mut self.lights {
+            if let Some(animation) = &light.animation {
+                let (color, intensity) = animation.at(frame);
+                light.color = color;
+                light.intensity = intensity;
+            }
+        }
+    }
+
+    //Returns the material with the given index, or the default material if the index is out of range.
+    pub fn material(&self, index: usize) -> &Material {
+        match self.materials.get(index) {
+            Some(mat) => mat,
+            None => &self.default_material,
+        }
+    }
+
+    //True if any material lets light through, so shadow rays have to look past the first hit.
+    pub fn has_translucent_materials(&self) -> bool {
+        self.default_material.opacity < 1.0 || self.materials.iter().any(|m| m.opacity < 1.0)
+    }
+
+    //Returns the bounding box of all objects with the given name, or of all objects for "all".
+    //Infinite objects are ignored. None if there is nothing with bounds.
+    pub fn bounds_of(&self, target: &str) -> Option<(Vector4F, Vector4F)> {
+        let mut bounds = Bounds::new();
+
+        for obj in self.objects() {
+            if target != "all" && obj.name() != target {
+                continue;
+            }
+            if let Some((min, max)) = obj.bounds() {
+                bounds.add(&min);
+                bounds.add(&max);
+            }
+        }
+
+        bounds.get()
+    }
+
+    pub fn objects<'a>(&'a self) -> Vec<&'a Intersectable> {
+        let mut result = Vec::with_capacity(self.spheres.len() + self.meshes.len());
+        for sp in &self.spheres {
+            result.push(sp as &Intersectable);
+        }
+        for plane in &self.planes {
+            result.push(plane as &Intersectable);
+        }
+        for mesh in &self.meshes {
+            result.push(mesh as &Intersectable);
+        }
+        for vox in &self.voxels {
+            result.push(vox as &Intersectable);
+        }
+
+        result
+    }
+
+    //Visibility of every object at the given frame, in the order of objects(). Renders without a
+    //frame use frame 0.
+    pub fn object_visibility(&self, frame: Option<u32>) -> Vec<f64> {
+        let frame = frame.unwrap_or(0) as f64;
+        self.objects()
+            .iter()
+            .map(|obj| {
+                self.visibility
+                    .iter()
+                    .filter(|a| a.object == obj.name())
+                    .fold(1.0, |v, a| v * a.at(frame))
+            })
+            .collect()
+    }
+}
+
+//Additional per-pixel passes that are rendered next to the beauty image and written to separate files.
+pub enum Aov {
+    //Fraction of the hemisphere above the first hit that is not occluded within ao_distance
+    AmbientOcclusion,
+    //Signed surface curvature at the first hit, 0.5 is flat, brighter is convex, darker is concave
+    Curvature,
+    //World space XYZ position of the first hit, written as float image
+    Position,
+    //Distance from the camera to the first hit along the ray, 0 where nothing is hit
+    Depth,
+    //World space normal of the first hit, 0 where nothing is hit
+    Normal,
+    //Color of the surface at the first hit with decals applied, without any lighting
+    Albedo,
+    //Light that reached the camera after one bounce, like the light paths "bounces": [1, 1]
+    Direct,
+    //Light that reached the camera after two or more bounces. Together with direct light and
+    //the background it adds up to the image.
+    Indirect,
+    //Fraction of the camera rays that hit an object, 0 where only the sky is seen
+    Alpha,
+    //Index of the object at the first hit plus one, 0 where nothing is hit. Pixels on the edge
+    //between objects average their ids. The names are written to a legend next to the image.
+    ObjectId,
+    //Index of the material at the first hit plus one like the object id, the default material
+    //comes after the materials of the scene
+    MaterialId,
+}
+
+impl Aov {
+    //Suffix appended to the output file name for this pass
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::AmbientOcclusion => "ao",
+            Aov::Curvature => "curvature",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Alpha => "alpha",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+
+    //Number of values per pixel
+    pub fn channels(&self) -> usize {
+        match self {
+            Aov::AmbientOcclusion => 1,
+            Aov::Curvature => 1,
+            Aov::Position => 3,
+            Aov::Depth => 1,
+            Aov::Normal => 3,
+            Aov::Albedo => 3,
+            Aov::Direct => 3,
+            Aov::Indirect => 3,
+            Aov::Alpha => 1,
+            Aov::ObjectId => 1,
+            Aov::MaterialId => 1,
+        }
+    }
+
+    //True for AOVs with values that don't fit into 0...1, they are written as float images
+    pub fn is_float(&self) -> bool {
+        match self {
+            Aov::AmbientOcclusion | Aov::Curvature | Aov::Alpha => false,
+            _ => true,
+        }
+    }
+}
+
+//Storage format of the pixels in the framebuffer.
+pub enum PixelFormat {
+    F32,
+    //Half float, needs half the memory of F32 for very large images. Has about 3 significant
+    //digits and values above 65504 are clamped.
+    F16,
+}
+
+//Order of the color channels in the written images. TGA viewers expect BGR, RGB is for tools that
+//read the pixel data of the files directly.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChannelOrder {
+    Bgr,
+    Rgb,
+}
+
+//Order in which the threads pick the parts of the image to render. It changes which parts are
+//seen first in the progress of a render or a cancelled render, not the image.
+#[derive(Clone, Copy)]
+pub enum RenderOrder {
+    //Line by line, starting at the bottom of the image
+    Scanline,
+    //Square tiles along a Hilbert curve, so the finished tiles always form a compact area
+    Hilbert,
+    //Square tiles from the center of the image outwards, where the subject usually is
+    Spiral,
+}
+
+//How the progress of a render is printed to the console. Only the progress goes to stdout, all
+//other messages go to stderr, so stdout can be parsed line by line.
+#[derive(Clone, Copy)]
+pub enum ProgressFormat {
+    //Percentage and remaining time every half second
+    Percent,
+    //Progress bar that is redrawn in place, for terminals
+    Bar,
+    //One JSON object per line with lines done, elapsed and remaining time, for render managers
+    Json,
+    //No progress at all
+    Quiet,
+}
+
+impl ProgressFormat {
+    //Format with the given name, as used in the settings and on the command line
+    pub fn from_name(name: &str) -> Option<ProgressFormat> {
+        match name {
+            "percent" => Some(ProgressFormat::Percent),
+            "bar" => Some(ProgressFormat::Bar),
+            "json" => Some(ProgressFormat::Json),
+            "quiet" => Some(ProgressFormat::Quiet),
+            _ => None,
+        }
+    }
+}
+
+//Precision used to sum up the samples of a pixel.
+#[derive(Clone, Copy)]
+pub enum Accumulation {
+    F32,
+    //f32 with compensation of rounding errors
+    Compensated,
+    F64,
+}
+
+//Reconstruction filter of the pixels. Instead of weighting the samples, their positions are
+//distributed according to the filter, so all samples have the same weight and filters with a
+//radius above half a pixel smooth thin highlights without ringing. The radius is in pixels.
+pub enum PixelFilter {
+    //Regular grid of samples inside of the pixel
+    Box,
+    Tent { radius: f64 },
+    //Gaussian with a standard deviation of a third of the radius, cut off at the radius
+    Gaussian { radius: f64 },
+    //Mitchell-Netravali with B = C = 1/3. Its small negative lobes keep edges sharper than the
+    //others. Samples in the lobes count negative, so it is the only filter that can ring a little.
+    Mitchell { radius: f64 },
+}
+
+//Diagnostic view rendered instead of the lit image, to find problems with the geometry without
+//logging from the tracer. Rays that miss everything are black.
+#[derive(Clone, Copy)]
+pub enum DebugView {
+    //Shading normal at the first hit, mapped from -1...1 to 0...1
+    Normals,
+    //Distance to the first hit, white at the camera fading to black at max_distance
+    Depth { max_distance: f64 },
+    //Texture coordinates of the first UV set as red and green, repeating outside of 0...1
+    Uvs,
+    //Triangles tested for the camera ray as a heat map from blue over green to red, log scaled
+    TriangleTests,
+}
+
+//Whether light paths through translucent surfaces are part of a pass
+#[derive(Clone, Copy)]
+pub enum PathTransmission {
+    Any,
+    Only,
+    Excluded,
+}
+
+//Selects which light paths contribute to the image, a simplified form of light path expressions.
+//Bounces are the diffuse surface interactions between camera and light, so direct light has one
+//bounce and the background seen by the camera has none. Transmission refers to translucent
+//surfaces the path from the camera passed through.
+#[derive(Clone, Copy)]
+pub struct LightPaths {
+    pub min_bounces: u32,
+    pub max_bounces: u32,
+    pub transmission: PathTransmission,
+}
+
+impl LightPaths {
+    pub fn all() -> LightPaths {
+        LightPaths {
+            min_bounces: 0,
+            max_bounces: std::u32::MAX,
+            transmission: PathTransmission::Any,
+        }
+    }
+
+    //True if light arriving after the given number of bounces and transmissions is part of the pass
+    pub fn accepts(&self, bounces: u32, transmissions: u32) -> bool {
+        let transmission = match self.transmission {
+            PathTransmission::Any => true,
+            PathTransmission::Only => transmissions > 0,
+            PathTransmission::Excluded => transmissions == 0,
+        };
+
+        transmission && bounces >= self.min_bounces && bounces <= self.max_bounces
+    }
+}
+
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+//Information stamped into a corner of the output image, for review tools that need to identify
+//renders. Samples, render time and camera are always part of it.
+pub struct BurnIn {
+    //Name of the scene or shot. Empty uses the name of the settings file.
+    pub scene: String,
+    pub frame: Option<u32>,
+    pub corner: Corner,
+}
+
+//Adds samples to noisy pixels until the standard error of their brightness is below threshold
+//relative to the brightness. Pixels get at least min_samples and at most max_samples camera
+//samples, including the grid of samples * samples.
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64,
+}
+
+//Edge preserving filter applied to the radiance after rendering. Each pixel becomes the average of
+//the pixels within radius, weighted by their distance and by how similar their color is. If the
+//normal and albedo AOVs are rendered, they are compared too, so edges and textures stay sharp.
+//The sigmas are the differences at which the weight falls to about 60%.
+pub struct Denoise {
+    pub radius: u32,
+    pub color_sigma: f64,
+    pub normal_sigma: f64,
+    pub albedo_sigma: f64,
+}
+
+//Renders only the soft shadow the objects cast onto the ground below them, black with the shadow
+//as alpha on a transparent background, for drop shadow sprites. The objects themselves are looked
+//through. The shadow is the ambient occlusion of the ground, so it is darkest where the objects
+//touch it and fades out with the distance to them.
+pub struct ShadowSprite {
+    //Height of the ground. Without it, the ground is at the lowest point of the objects.
+    pub ground: Option<f64>,
+    //Number of occlusion samples per axis for each pixel sample
+    pub samples: u32,
+    //Distance up to which the objects cast a shadow
+    pub distance: f64,
+}
+
+pub struct Output {
+    pub filename: String,
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub aovs: Vec<Aov>,
+    //Number of AO samples per axis for each pixel sample
+    pub ao_samples: u32,
+    //Maximum distance at which geometry occludes
+    pub ao_distance: f64,
+    //Distance from the hit point at which neighboring normals are probed for curvature
+    pub curvature_radius: f64,
+    pub pixel_format: PixelFormat,
+    pub accumulation: Accumulation,
+    pub render_order: RenderOrder,
+    pub sampler: Sampler,
+    pub progress: ProgressFormat,
+    pub filter: PixelFilter,
+    pub debug_view: Option<DebugView>,
+    //Trace the primary rays of a pixel in packets of 2x2 samples, which walk the octrees of the
+    //meshes together. Only pays off with several samples or lens samples per pixel. The noise
+    //differs from tracing single rays, as the lens samples are drawn in a different order.
+    pub packets: bool,
+    //Base seed of the random numbers. The same seed always produces the same image.
+    pub seed: u64,
+    //Frame number when rendering an animation, which gives every frame its own noise
+    pub frame: Option<u32>,
+    //Additional jittered samples per axis for pixels with a silhouette, where the objects or
+    //distances seen through the pixel corners differ. 0 turns this off.
+    pub edge_samples: u32,
+    //Relative difference of the distances through two pixel corners that counts as an edge
+    pub edge_depth_threshold: f64,
+    pub light_paths: LightPaths,
+    pub burn_in: Option<BurnIn>,
+    //Store renderer version, settings hash, samples and render time in the written TGA files.
+    //Off by default, as the render time makes the files differ between runs.
+    pub metadata: bool,
+    //Write the image as 32 bit TGA with the alpha AOV as alpha channel, so it can be composited
+    //over other backgrounds. The alpha AOV is added if it is not requested, it is not written to
+    //its own file then.
+    pub alpha: bool,
+    //Run length encode the written TGA files
+    pub rle: bool,
+    //Store the top row of the TGA files first
+    pub top_down: bool,
+    pub channel_order: ChannelOrder,
+    //Bits per color channel of the written images, 8 or 5. With 5 bits, pixels are stored as 16
+    //bit with one bit of alpha.
+    pub bits_per_channel: u32,
+    //Look for samples with NaN or infinite radiance, which are logged with their path and count
+    //as black
+    pub check_nan: bool,
+    //Only render this pixel, with 0, 0 at the top left, and log the path of every sample
+    pub debug_pixel: Option<(u32, u32)>,
+    //Number of progressive passes. Every pass renders all pixels with samples * samples samples
+    //and different random numbers, the image is the average of all passes.
+    pub passes: u32,
+    //Seconds between preview images written during a progressive render
+    pub preview_interval: f64,
+    //File the finished passes of a progressive render are saved to, so it can be resumed
+    pub checkpoint: Option<String>,
+    //Seconds between checkpoints
+    pub checkpoint_interval: f64,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub denoise: Option<Denoise>,
+    pub shadow_sprite: Option<ShadowSprite>,
+}
+
+impl Output {
+    //Seed used for rendering. Without a frame it is the base seed, otherwise it is mixed with the
+    //frame number, so neighboring frames get unrelated noise that is the same on every run.
+    pub fn frame_seed(&self) -> u64 {
+        match self.frame {
+            Some(frame) => {
+                let mut h = self.seed ^ (frame as u64).wrapping_mul(0xD6E8_FEB8_6659_FD93);
+                h = (h ^ (h >> 32)).wrapping_mul(0xD6E8_FEB8_6659_FD93);
+                h ^ (h >> 32)
+            }
+            None => self.seed,
+        }
+    }
+}
+
+pub enum Projection {
+    //Regular camera looking through an image plane
+    Perspective,
+    //Omni-directional stereo for VR video. The top half of the image is the 360 degree panorama of
+    //the left eye, the bottom half the one of the right eye, so the image should be as high as it
+    //is wide. Each column is seen from where the eye is when the head turns to look at it.
+    Ods,
+}
+
+pub struct Camera {
+    //The camera always looks along +Z with +Y up
+    pub position: Vector4F,
+    pub projection: Projection,
+    //Distance between the eyes for the ODS projection
+    pub eye_separation: f64,
+    //Horizontal field of view in degrees. None uses an image plane that is half as wide as it is
+    //away from the camera, which is about 28 degrees.
+    pub fov: Option<f64>,
+    //Name of the object to frame, or "all" for all objects. Moves the camera so the bounding box
+    //of the target fills the image.
+    pub frame: Option<String>,
+    //Radius of the lens. 0.0 means pinhole camera without depth of field.
+    pub aperture: f64,
+    //Distance from the camera to the plane that is in perfect focus
+    pub focus_distance: f64,
+    //Number of lens samples per axis for each pixel sample, so each pixel sample
+    //is multiplied by lens_samples * lens_samples rays.
+    pub lens_samples: u32,
+    //Photographic exposure settings. If none of them is set, the rendered values are used
+    //as they are. Otherwise missing values use the defaults of the "sunny 16" rule.
+    pub iso: Option<f64>,
+    //Shutter time in seconds
+    pub shutter: Option<f64>,
+    pub f_number: Option<f64>,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            position: Vector4F::null(),
+            projection: Projection::Perspective,
+            eye_separation: 0.064,
+            fov: None,
+            frame: None,
+            aperture: 0.0,
+            focus_distance: 1.0,
+            lens_samples: 1,
+            iso: None,
+            shutter: None,
+            f_number: None,
+        }
+    }
+
+    //Width of the image plane at a distance of 1.0 from the camera
+    pub fn image_plane_width(&self) -> f64 {
+        match self.fov {
+            Some(fov) => 2.0 * (fov / 360.0 * std::f64::consts::PI).tan(),
+            None => 0.5,
+        }
+    }
+
+    //Moves the camera back along -Z until the bounding sphere of the box fits into the image with
+    //the given aspect ratio (width / height), centered on the box.
+    pub fn frame_box(&mut self, min: &Vector4F, max: &Vector4F, aspect: f64) {
+        let center = Vector4F::new(
+            (min.x + max.x) * 0.5,
+            (min.y + max.y) * 0.5,
+            (min.z + max.z) * 0.5,
+        );
+        let radius = (max - min).len() * 0.5;
+
+        //Half of the smaller field of view decides, with a small margin around the object
+        let half_w = self.image_plane_width() * 0.5;
+        let tan_half = half_w.min(half_w / aspect);
+        let sin_half = tan_half / (1.0 + tan_half * tan_half).sqrt();
+        let dist = radius / sin_half * 1.1;
+
+        self.position = Vector4F::new(center.x, center.y, center.z - dist);
+    }
+
+    //Returns the exposure value for ISO 100 of the photographic exposure settings,
+    //or None if the camera has no exposure settings.
+    pub fn ev100(&self) -> Option<f64> {
+        if self.iso.is_none() && self.shutter.is_none() && self.f_number.is_none() {
+            return None;
+        }
+
+        let iso = self.iso.unwrap_or(100.0);
+        let shutter = self.shutter.unwrap_or(1.0 / 100.0);
+        let f_number = self.f_number.unwrap_or(16.0);
+
+        Some(((f_number * f_number) / shutter * (100.0 / iso)).log2())
+    }
+
+    //Returns the factor that rendered radiance values are multiplied with before tone mapping.
+    //Uses the saturation based sensitivity model, so a radiance of 1.0 / exposure just saturates the sensor.
+    pub fn exposure(&self) -> f64 {
+        match self.ev100() {
+            Some(ev100) => 1.0 / (1.2 * 2.0f64.powf(ev100)),
+            None => 1.0,
+        }
+    }
+}
+
+//Bakes maps of a detailed mesh into the texture space of a simpler one, instead of rendering.
+//Rays start outside of each texel on the low poly surface and look for the high poly surface
+//inwards along the normal.
+pub struct Bake {
+    //Names of the meshes, all OBJ groups of a mesh are used
+    pub low: String,
+    pub high: String,
+    //Width and height of the maps in pixels
+    pub size: u32,
+    //Distance outside of the low poly surface where rays start. Rays search twice as far inwards,
+    //so it should be the largest distance between the two surfaces.
+    pub distance: f64,
+    //Tangent space normal map written to this file, not written if empty
+    pub normal_map: String,
+    //Ambient occlusion of the high poly mesh written to this file, not written if empty. Uses
+    //ao_samples and ao_distance of the output.
+    pub ao_map: String,
+    //UV set (1 or 2) of the low poly mesh the maps are laid out in
+    pub uv_set: u32,
+}
+
+pub struct Settings {
+    pub scene: Scene,
+    pub output: Output,
+    pub camera: Camera,
+    pub bake: Option<Bake>,
+    //Content hash of the settings and all files they reference
+    pub hash: u64,
+}
+
+impl Settings {
+    //Renders the given frame of an animation, with the lights as they are at that frame
+    pub fn set_frame(&mut self, frame: Option<u32>) {
+        self.output.frame = frame;
+        self.scene.animate_lights(frame);
+    }
+
+    //Reads the settings and validates them. If anything is wrong, all problems that were
+    //found are returned.
+    pub fn from_json(json: JsonValue) -> std::result::Result<Settings, Vec<String>> {
+        Settings::read(json, None)
+    }
+
+    //Like from_json(), but files with a custom scheme like "asset://" are turned into local files
+    //by the resolver before anything is loaded
+    pub fn from_json_with_resolver(
+        json: JsonValue,
+        resolver: &AssetResolver,
+    ) -> std::result::Result<Settings, Vec<String>> {
+        Settings::read(json, Some(resolver))
+    }
+
+    fn read(
+        mut json: JsonValue,
+        resolver: Option<&AssetResolver>,
+    ) -> std::result::Result<Settings, Vec<String>> {
+        //In lenient mode, missing files are replaced by placeholders so the render still happens
+        let mut problems = assets::resolve_assets(&mut json, resolver);
+        problems.append(&mut validate::check_files(&json));
+        if !problems.is_empty() {
+            if !validate::is_lenient(&json) {
+                return Err(problems);
+            }
+            for problem in problems {
+                eprintln!("Warning: {}, using a placeholder", problem);
+            }
+        }
+
+        let hash = scenehash::hash_scene(&json);
+
+        if let JsonValue::Object(nodes) = json {
+            let mut scene = None;
+            let mut output = None;
+            let mut camera = Camera::new();
+            let mut bake = None;
+
+            for node in nodes {
+                if node.0 == "scene" {
+                    scene = read_scene(node.1);
+                } else if node.0 == "output" {
+                    output = read_output(node.1);
+                } else if node.0 == "camera" {
+                    camera = read_camera(node.1).unwrap();
+                } else if node.0 == "bake" {
+                    bake = read_bake(node.1);
+                }
+            }
+
+            let mut problems = Vec::new();
+            if scene.is_none() {
+                problems.push("scene is missing".to_string());
+            }
+            if output.is_none() {
+                problems.push("output is missing".to_string());
+            }
+            if !problems.is_empty() {
+                return Err(problems);
+            }
+
+            let mut settings = Settings {
+                scene: scene.unwrap(),
+                output: output.unwrap(),
+                camera,
+                bake,
+                hash,
+            };
+
+            if let Some(target) = settings.camera.frame.clone() {
+                match settings.scene.bounds_of(&target) {
+                    Some((min, max)) => {
+                        let aspect = settings.output.width as f64 / settings.output.height as f64;
+                        settings.camera.frame_box(&min, &max, aspect);
+                    }
+                    None => return Err(vec![format!("camera: nothing to frame: {}", target)]),
+                }
+            }
+
+            let frame = settings.output.frame;
+            settings.scene.animate_lights(frame);
+
+            if let Some(sprite) = &mut settings.output.shadow_sprite {
+                if sprite.ground.is_none() {
+                    sprite.ground = settings.scene.bounds_of("all").map(|(min, _)| min.y);
+                }
+            }
+
+            let problems = validate::check_settings(&settings);
+            if !problems.is_empty() {
+                return Err(problems);
+            }
+
+            return Ok(settings);
+        }
+
+        Err(vec!["Settings must be a JSON object".to_string()])
+    }
+}
+
+pub struct Voxels {
+    pub name: String,
+    //In object space each voxel is a unit cube, the grid starts at the origin
+    pub transform: Transform,
+    pub material: String,
+    pub material_index: usize,
+    pub voxels: VoxelObject,
+    //Smoothed distance field of the voxels, rendered instead of the blocks if set
+    pub smooth: Option<DistanceField>,
+}
+
+impl Intersectable for Voxels {
+    fn intersect(
+        &self,
+        rorg: &Vector4F,
+        rdir: &Vector4F,
+        min_t: f64,
+        _scratch: &mut Scratch,
+    ) -> Option<Intersection> {
+        //Transform ray origin and direction into object space. The direction is not normalized,
+        //so ray_t is the same in object and world space.
+        let rorg_obj_space = self.transform.world_to_object.transform_point(rorg);
+        let rdir_obj_space = self.transform.world_to_object.transform_vector(rdir);
+
+        if let Some(field) = &self.smooth {
+            let ray = Ray::with_max_t(rorg_obj_space, rdir_obj_space, min_t);
+            let ray_t = field.intersect(&ray)?;
+            let pos = Vector4F::new(
+                ray.org.x + ray.dir.x * ray_t,
+                ray.org.y + ray.dir.y * ray_t,
+                ray.org.z + ray.dir.z * ray_t,
+            );
+
+            return Some(Intersection {
+                pos: self.transform.object_to_world.transform_point(&pos),
+                normal: self.transform.transform_normal(&field.normal(&pos)),
+                tex_u: 0.0,
+                tex_v: 0.0,
+                tex_u2: 0.0,
+                tex_v2: 0.0,
+                barycentric: Vector4F::null(),
+                ray_t,
+            });
+        }
+
+        let ray = Ray::new(rorg_obj_space.clone(), rdir_obj_space.clone());
+
+        //let mut x: i32;
+        //let mut y: i32;
+        //let mut z: i32;
+
+        let min = Vector4F::null();
+        let max = Vector4F::new(
+            self.voxels.width as f64,
+            self.voxels.height as f64,
+            self.voxels.depth as f64,
+        );
+
+        let intersection = linear::intersect_ray_aabb2(&ray, &min, &max);
+        let mut closest_intersection = None;
+        let mut min_t = std::f64::MAX;
+
+        if intersection.is_some() {
+            for z in 0..self.voxels.depth {
+                for y in 0..self.voxels.height {
+                    for x in 0..self.voxels.width {
+                        let voxel = self.voxels.get(x, y, z);
+                        if voxel.is_some() {
+                            //println!("some: {};{};{}", x, y, z);
+
+                            let min = Vector4F::new(x as f64, y as f64, z as f64);
+                            let max = Vector4F::new(x as f64 + 1.0, y as f64 + 1.0, z as f64 + 1.0);
+
+                            let intersection = linear::intersect_ray_aabb2(&ray, &min, &max);
+                            if intersection.is_some() {
+                                //println!("inter: {};{};{}", x, y, z);
+                                let inter = intersection.unwrap();
+                                let ray_t = inter.ray_t;
+                                if ray_t < min_t {
+                                    closest_intersection = Some(inter);
+                                    min_t = ray_t;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
         if closest_intersection.is_some() {
             let inter = closest_intersection.unwrap();
 
-            let world_pos = &(&inter.pos * &self.scale) + &self.translation;
-            let world_normal = inter.normal.rotate_x(self.rotation.x).rotate_y(self.rotation.y).rotate_z(self.rotation.z);
-            //Need to recalc t with world coordinates
-            let world_t = (world_pos.x - rorg.x) / rdir.x;
+            let world_pos = self.transform.object_to_world.transform_point(&inter.pos);
+            let world_normal = self.transform.transform_normal(&inter.normal);
+
+            //println!("ipos: {}", inter.pos);
+            //println!("wpos: {}", world_pos);
+
+            return Some(Intersection {
+                pos: world_pos,
+                normal: world_normal,
+                tex_u: 0.0,
+                tex_v: 0.0,
+                tex_u2: 0.0,
+                tex_v2: 0.0,
+                barycentric: Vector4F::null(),
+                ray_t: inter.ray_t,
+            });
+        }
+
+        /*
+        //For tracing only the AABB
+        let intersection = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
+        if intersection.is_some() {
+            let inter = intersection.unwrap();
+
+            let world_pos = &(&inter.pos * &self.scale) + &self.translation;
+            let world_normal = inter.normal.rotate_x(self.rotation.x).rotate_y(self.rotation.y).rotate_z(self.rotation.z);
+            //Need to recalc t with world coordinates
+            let world_t = (world_pos.x - rorg.x) / rdir.x;
+
+            //println!("ipos: {}", inter.pos);
+            //println!("wpos: {}", world_pos);
+            //println!("t: {}", world_t);
+
+            return Some(Intersection {
+                pos: world_pos,
+                normal: world_normal,
+                tex_u: 0.0,
+                tex_v: 0.0,
+                barycentric: Vector4F::null(),
+                ray_t: world_t,
+            });
+        }
+        else {
+            return None;
+        }
+        */
+
+        /*
+        if linear::point_in_aabb(&rorg_obj_space, &min, &max) {
+            //If ray origin is inside voxel grid, just truncate coordinates to get starting voxel
+            x = rorg_obj_space.x as i32;
+            y = rorg_obj_space.y as i32;
+            z = rorg_obj_space.z as i32;
+        } else {
+            //Find voxel where ray enters
+            let inter = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
+            if inter.is_some() {
+                let intersection = inter.unwrap();
+                x = intersection.pos.x as i32;
+                y = intersection.pos.y as i32;
+                z = intersection.pos.z as i32;
+
+                if x == self.voxels.width as i32 {
+                    //println!("x '{}' is out", x);
+                    x = (self.voxels.width - 1) as i32;
+                }
+                if y == self.voxels.height as i32 {
+                    //println!("y '{}' is out", y);
+                    y = (self.voxels.height - 1) as i32;
+                }
+                if z == self.voxels.depth as i32 {
+                    //println!("z '{}' is out", z);
+                    z = (self.voxels.depth - 1) as i32;
+                }
+            } else {
+                return None;
+            }
+        }
+
+        let step_x = rdir_obj_space.x.signum() as i32;
+        let step_y = rdir_obj_space.y.signum() as i32;
+        let step_z = rdir_obj_space.z.signum() as i32;
+
+        let out_x: i32 = if step_x > 0 {self.voxels.width as i32} else {-1};
+        let out_y: i32 = if step_y > 0 {self.voxels.height as i32} else {-1};
+        let out_z: i32 = if step_z > 0 {self.voxels.depth as i32} else {-1};
+
+        let mut t_max_x = get_max_element(rorg_obj_space.x, rdir_obj_space.x, self.voxels.width as f64);
+        let mut t_max_y = get_max_element(rorg_obj_space.y, rdir_obj_space.y, self.voxels.height as f64);
+        let mut t_max_z = get_max_element(rorg_obj_space.z, rdir_obj_space.z, self.voxels.depth as f64);
+
+        let t_delta_x = 1.0 / rdir_obj_space.x;
+        let t_delta_y = 1.0 / rdir_obj_space.y;
+        let t_delta_z = 1.0 / rdir_obj_space.z;
+
+        let mut intersects = false;
+        //println!("---------------");
+        //println!("F x,y,z: {};{};{}", x, y, z);
+        //dbg!(step_x);
+        //dbg!(out_z);
+        
+        loop {
+            if t_max_x < t_max_y {
+                if t_max_x < t_max_z {
+                    x = x + step_x;
+                    if x == out_x {
+                        break;
+                    }
+                    t_max_x = t_max_x + t_delta_x;
+                } else {
+                    z = z + step_z;
+                    if z == out_z {
+                        break;
+                    }
+                    t_max_z = t_max_z + t_delta_z;
+                }
+            } else {
+                if t_max_y < t_max_z {
+                    y = y + step_y;
+                    if y == out_y {
+                        break;
+                    }
+                    t_max_y = t_max_y + t_delta_y;
+                } else {
+                    z = z + step_z;
+                    if z == out_z {
+                        break;
+                    }
+                    t_max_z = t_max_z + t_delta_z;
+                }
+            }
+
+            //println!("x,y,z: {};{};{}", x, y, z);
+            let voxel = self.voxels.get(x as u32, y as u32, z as u32);
+            if voxel.is_some() {
+                intersects = true;
+                break;
+            }
+        }
+
+        if intersects {
+            let min = Vector4F::new(x as f64, y as f64, z as f64);
+            let max = Vector4F::new((x + 1) as f64, (y + 1) as f64, (z + 1) as f64);
+            let intersection = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
+
+            if intersection.is_some() {
+                let inter = intersection.unwrap();
+
+                let world_pos = &(&inter.pos * &self.scale) + &self.translation;
+                let world_normal = inter.normal.rotate_x(self.rotation.x).rotate_y(self.rotation.y).rotate_z(self.rotation.z);
+                //Need to recalc t with oroginal ray in world space
+                let world_t = (world_pos.x - rorg.x) / rdir.x;
+
+                //println!("ipos: {}", inter.pos);
+                //println!("wpos: {}", world_pos);
+                //println!("t: {}", world_t);
+
+                return Some(Intersection {
+                    pos: world_pos,
+                    normal: world_normal,
+                    tex_u: 0.0,
+                    tex_v: 0.0,
+                    barycentric: Vector4F::null(),
+                    ray_t: world_t,
+                });
+            }
+            else {
+                return None;
+            }
+        }
+        */
+
+        None
+    }
+
+    fn material(&self) -> usize {
+        self.material_index
+    }
+
+    fn material_name(&self) -> &str {
+        self.material.as_str()
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    //Bounds of the transformed corners of the voxel grid, or of the distance field that can reach
+    //a bit past the grid when smoothed
+    fn bounds(&self) -> Option<(Vector4F, Vector4F)> {
+        let (min, max) = match &self.smooth {
+            Some(field) => field.bounds(),
+            None => (
+                Vector4F::null(),
+                Vector4F::new(
+                    self.voxels.width as f64,
+                    self.voxels.height as f64,
+                    self.voxels.depth as f64,
+                ),
+            ),
+        };
+        let mut bounds = Bounds::new();
+        for i in 0..8 {
+            let corner = Vector4F::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            bounds.add(&self.transform.object_to_world.transform_point(&corner));
+        }
+        bounds.get()
+    }
+
+    fn contains(&self, pos: &Vector4F, _scratch: &mut Scratch) -> bool {
+        let obj_pos = self.transform.world_to_object.transform_point(pos);
+
+        if let Some(field) = &self.smooth {
+            return field.distance(&obj_pos) < 0.0;
+        }
+
+        if obj_pos.x < 0.0 || obj_pos.y < 0.0 || obj_pos.z < 0.0 {
+            return false;
+        }
+
+        let x = obj_pos.x as u32;
+        let y = obj_pos.y as u32;
+        let z = obj_pos.z as u32;
+        if x >= self.voxels.width || y >= self.voxels.height || z >= self.voxels.depth {
+            return false;
+        }
+
+        self.voxels.get(x, y, z).is_some()
+    }
+}
+
+fn _get_max_element(org: f64, dir: f64, out: f64) -> f64 {
+    let mut max = if dir > 0.0 {std::f64::INFINITY} else {std::f64::NEG_INFINITY};
+
+    if dir > 0.0 {
+        if org < 0.0 {
+            max = dir / -org;
+        } else if org >= 0.0 && org <= out {
+            max = dir / (org.trunc() + 1.0);
+        } else {
+            panic!("Ray starts outside of voxel model, this should not happen!");
+        }
+    } else if dir < 0.0 {
+        if org > out {
+            max = -dir / (org - out);
+        } else if org >= 0.0 && org <= out {
+            max = -dir / (org.trunc());
+        } else {
+            panic!("Ray starts outside of voxel model, this should not happen!");
+        }
+    }
+
+    max
+}
+
+fn read_scene(scene: JsonValue) -> Option<Scene> {
+    if let JsonValue::Object(fields) = scene {
+        let mut materials = Vec::new();
+        let mut spheres = Vec::new();
+        let mut planes = Vec::new();
+        let mut meshes = Vec::new();
+        let mut voxels = Vec::new();
+        let mut lights = Vec::new();
+        let mut decals = Vec::new();
+        let mut blockers = Vec::new();
+        let mut visibility = Vec::new();
+        let mut section_planes = Vec::new();
+        let mut placeholders = Vec::new();
+        let mut skycolor = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+        };
+        let mut environment = None;
+        let mut dome = None;
+        let mut sky = None;
+        let mut backdrop = None;
+        let mut fog = None;
+        let mut hybrid_gi = None;
+        let mut max_depth = 5;
+        let mut max_diffuse_depth = None;
+        let mut max_transmission_depth = None;
+        let mut max_thin_layers = 64;
+        let mut path_samples = 1;
+        let mut light_threshold = 0.0;
+        //Bright magenta, so missing materials are easy to spot
+        let mut default_material = Material {
+            id: "default".to_string(),
+            color: Color::new(1.0, 0.0, 1.0),
+            reflect: 0.0,
+            refract: 0.0,
+            ior: 1.0,
+            roughness: 0.001,
+            opacity: 1.0,
+            thin: false,
+            sheen: Color::black(),
+            sheen_roughness: 0.3,
+            film_thickness: 0.0,
+            film_ior: 1.33,
+            edge_radius: 0.0,
+            hue_jitter: 0.0,
+            value_jitter: 0.0,
+            variation_seed: 0,
+            dirt: None,
+        };
+        let mut strict_materials = false;
+        let mut studio = false;
+        let mut overrides = Vec::new();
+        let mut textures = TextureCache::new(0);
+        let mut texture_cache_mb = 1024.0;
+
+        for f in fields {
+            if f.0 == "skycolor" {
+                let v = read_number_triplet(&f.1).unwrap();
+                skycolor.r = v.0 as f32;
+                skycolor.g = v.1 as f32;
+                skycolor.b = v.2 as f32;
+            } else if f.0 == "max_trace_depth" {
+                if let JsonValue::Number(md) = f.1 {
+                    max_depth = md as u32;
+                }
+            } else if f.0 == "max_diffuse_depth" {
+                if let JsonValue::Number(md) = f.1 {
+                    max_diffuse_depth = Some(md as u32);
+                }
+            } else if f.0 == "max_transmission_depth" {
+                if let JsonValue::Number(md) = f.1 {
+                    max_transmission_depth = Some(md as u32);
+                }
+            } else if f.0 == "max_thin_layers" {
+                if let JsonValue::Number(md) = f.1 {
+                    max_thin_layers = md as u32;
+                }
+            } else if f.0 == "path_samples" {
+                if let JsonValue::Number(ps) = f.1 {
+                    path_samples = ps as u32;
+                }
+            } else if f.0 == "light_threshold" {
+                if let JsonValue::Number(lt) = f.1 {
+                    light_threshold = lt;
+                }
+            } else if f.0 == "environment" {
+                environment = read_environment(f.1);
+            } else if f.0 == "dome" {
+                dome = read_dome(f.1);
+            } else if f.0 == "sky" {
+                sky = read_sky(f.1);
+            } else if f.0 == "sky_gradient" {
+                backdrop = read_sky_gradient(f.1);
+            } else if f.0 == "fog" {
+                fog = read_fog(f.1);
+            } else if f.0 == "hybrid_gi" {
+                hybrid_gi = read_hybrid_gi(f.1);
+            } else if f.0 == "default_material" {
+                if let JsonValue::Object(mat_fields) = f.1 {
+                    default_material = read_material(mat_fields, "default");
+                }
+            } else if f.0 == "texture_cache_mb" {
+                if let JsonValue::Number(mb) = f.1 {
+                    texture_cache_mb = mb;
+                }
+            } else if f.0 == "strict_materials" {
+                if let JsonValue::Boolean(strict) = f.1 {
+                    strict_materials = strict;
+                }
+            } else if f.0 == "studio" {
+                if let JsonValue::Boolean(st) = f.1 {
+                    studio = st;
+                }
+            } else if let JsonValue::Array(values) = f.1 {
+                if f.0 == "materials" {
+                    materials = read_materials(values);
+                } else if f.0 == "spheres" {
+                    spheres = read_spheres(values);
+                } else if f.0 == "planes" {
+                    planes = read_planes(values);
+                } else if f.0 == "meshes" {
+                    meshes = read_meshes(values, &mut placeholders);
+                } else if f.0 == "lights" {
+                    lights = read_lights(values);
+                } else if f.0 == "voxels" {
+                    voxels = read_voxels(values, &mut placeholders);
+                } else if f.0 == "decals" {
+                    decals = read_decals(values, &mut textures);
+                } else if f.0 == "blockers" {
+                    blockers = read_blockers(values, &mut textures);
+                } else if f.0 == "visibility" {
+                    visibility = read_visibility(values);
+                } else if f.0 == "section_planes" {
+                    section_planes = read_section_planes(values);
+                } else if f.0 == "material_overrides" {
+                    overrides = read_material_overrides(values);
+                }
+            }
+        }
+
+        //max_trace_depth is the default for the depth of all kinds of bounces
+        let max_diffuse_depth = max_diffuse_depth.unwrap_or(max_depth);
+        let max_transmission_depth = max_transmission_depth.unwrap_or(max_depth);
+
+        if max_diffuse_depth == 0 {
+            path_samples = 0;
+        }
+
+        textures.memory_budget = (texture_cache_mb * 1024.0 * 1024.0) as usize;
+
+        //Placeholders are added after the overrides, so they always stand out
+        if !placeholders.is_empty() {
+            if find_material(&materials, PLACEHOLDER_MATERIAL) == materials.len() {
+                materials.push(Material {
+                    id: PLACEHOLDER_MATERIAL.to_string(),
+                    color: Color::new(1.0, 0.0, 1.0),
+                    reflect: 0.0,
+                    refract: 0.0,
+                    ior: 1.0,
+                    roughness: 0.8,
+                    opacity: 1.0,
+                    thin: false,
+                    sheen: Color::black(),
+                    sheen_roughness: 0.3,
+                    film_thickness: 0.0,
+                    film_ior: 1.33,
+                    edge_radius: 0.0,
+                    hue_jitter: 0.0,
+                    value_jitter: 0.0,
+                    variation_seed: 0,
+                    dirt: None,
+                });
+            }
+        }
+
+        //OBJ materials replace the material of the mesh if the scene has them, so models whose
+        //material names are not in the scene still use the material of the mesh
+        for mesh in &mut meshes {
+            if find_material(&materials, &mesh.obj_material) < materials.len() {
+                mesh.material = mesh.obj_material.clone();
+            }
+        }
+
+        //Overrides are applied in order, so later entries win
+        for (object, material) in overrides {
+            apply_material_override(
+                &object,
+                &material,
+                &mut spheres,
+                &mut planes,
+                &mut meshes,
+                &mut voxels,
+            );
+        }
+        spheres.append(&mut placeholders);
+
+        //Resolve material ids once, so no strings are compared while rendering
+        for sp in &mut spheres {
+            sp.material_index = find_material(&materials, &sp.material);
+        }
+        for plane in &mut planes {
+            plane.material_index = find_material(&materials, &plane.material);
+        }
+        for mesh in &mut meshes {
+            mesh.material_index = find_material(&materials, &mesh.material);
+        }
+        for vox in &mut voxels {
+            vox.material_index = find_material(&materials, &vox.material);
+        }
+        for plane in &mut section_planes {
+            plane.cap_material_index = find_material(&materials, &plane.cap_material);
+        }
+
+        //An environment map replaces the dome, which replaces the sky. The sky follows the sun,
+        //so it needs the lights.
+        let environment = environment
+            .or_else(|| dome.map(|dome| dome.environment()))
+            .or_else(|| sky.map(|sky| sky.environment(&lights)));
+
+        let mut scene = Scene {
+            materials,
+            spheres,
+            planes,
+            meshes,
+            voxels,
+            lights,
+            decals,
+            blockers,
+            visibility,
+            section_planes,
+            skycolor,
+            backdrop,
+            environment,
+            fog,
+            hybrid_gi,
+            max_diffuse_depth,
+            max_transmission_depth,
+            max_thin_layers,
+            path_samples,
+            light_threshold,
+            default_material,
+            strict_materials,
+            textures,
+        };
+
+        //Studio mode adds ground, backdrop and lights around the objects of the scene
+        if studio {
+            studio::setup(&mut scene);
+        }
+
+        return Some(scene);
+    }
+
+    None
+}
+
+fn read_materials(materials: Vec<JsonValue>) -> Vec<Material> {
+    let mut result = Vec::new();
+
+    for mat in materials {
+        if let JsonValue::Object(fields) = mat {
+            let material = read_material(fields, "");
+            if material.id.is_empty() {
+                panic!("Material without id found");
+            }
+            result.push(material);
+        }
+    }
+
+    result
+}
+
+fn read_material(fields: Vec<(String, JsonValue)>, default_id: &str) -> Material {
+    let mut id = default_id.to_string();
+    let mut color = Color::black();
+    let mut reflect = 0.0;
+    let mut refract = 0.0;
+    let mut ior = 1.0;
+    let mut roughness = None;
+    let mut specular_exponent = None;
+    let mut opacity = 1.0;
+    let mut thin = false;
+    let mut sheen = Color::black();
+    let mut sheen_roughness = 0.3;
+    let mut film_thickness = 0.0;
+    let mut film_ior = 1.33;
+    let mut edge_radius = 0.0;
+    let mut hue_jitter = 0.0;
+    let mut value_jitter = 0.0;
+    let mut variation_seed = 0;
+    let mut dirt = None;
+
+    for f in fields {
+        if f.0 == "id" {
+            if let JsonValue::String(idstr) = f.1 {
+                id = idstr;
+            }
+        } else if f.0 == "color" {
+            let values = read_number_triplet(&f.1).unwrap();
+            color = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+        } else if f.0 == "refract" {
+            if let JsonValue::Number(refr) = f.1 {
+                refract = refr;
+            }
+        } else if f.0 == "reflect" {
+            if let JsonValue::Number(refl) = f.1 {
+                reflect = refl;
+            }
+        } else if f.0 == "ior" {
+            if let JsonValue::Number(iorv) = f.1 {
+                ior = iorv;
+            }
+        } else if f.0 == "roughness" {
+            if let JsonValue::Number(rgv) = f.1 {
+                roughness = Some(rgv);
+            }
+        } else if f.0 == "specular_exponent" {
+            if let JsonValue::Number(se) = f.1 {
+                specular_exponent = Some(se);
+            }
+        } else if f.0 == "opacity" {
+            if let JsonValue::Number(op) = f.1 {
+                opacity = op;
+            }
+        } else if f.0 == "thin" {
+            if let JsonValue::Boolean(b) = f.1 {
+                thin = b;
+            }
+        } else if f.0 == "sheen" {
+            let values = read_number_triplet(&f.1).unwrap();
+            sheen = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+        } else if f.0 == "sheen_roughness" {
+            if let JsonValue::Number(sr) = f.1 {
+                sheen_roughness = sr;
+            }
+        } else if f.0 == "film_thickness" {
+            if let JsonValue::Number(ft) = f.1 {
+                film_thickness = ft;
+            }
+        } else if f.0 == "film_ior" {
+            if let JsonValue::Number(fi) = f.1 {
+                film_ior = fi;
+            }
+        } else if f.0 == "edge_radius" {
+            if let JsonValue::Number(er) = f.1 {
+                edge_radius = er;
+            }
+        } else if f.0 == "hue_jitter" {
+            if let JsonValue::Number(hj) = f.1 {
+                hue_jitter = hj;
+            }
+        } else if f.0 == "value_jitter" {
+            if let JsonValue::Number(vj) = f.1 {
+                value_jitter = vj;
+            }
+        } else if f.0 == "variation_seed" {
+            if let JsonValue::Number(vs) = f.1 {
+                variation_seed = vs as u64;
+            }
+        } else if f.0 == "dirt" {
+            dirt = read_dirt(f.1);
+        }
+    }
+
+    //Roughness given directly wins over the exponent
+    let roughness = match (roughness, specular_exponent) {
+        (Some(roughness), _) => roughness,
+        (None, Some(exponent)) => shade::phong_roughness(exponent),
+        (None, None) => 0.001,
+    };
+
+    Material {
+        id,
+        color,
+        reflect,
+        refract,
+        ior,
+        roughness,
+        opacity,
+        thin,
+        sheen,
+        sheen_roughness,
+        film_thickness,
+        film_ior,
+        edge_radius,
+        hue_jitter,
+        value_jitter,
+        variation_seed,
+        dirt,
+    }
+}
+
+//Reads either a boolean to enable dirt with default values, or an object with color, distance,
+//samples and strength
+fn read_dirt(value: JsonValue) -> Option<Dirt> {
+    let mut result = Dirt {
+        color: Color::new(0.05, 0.04, 0.03),
+        distance: 0.2,
+        samples: 8,
+        strength: 1.0,
+    };
+
+    match value {
+        JsonValue::Boolean(enabled) => {
+            if !enabled {
+                return None;
+            }
+        }
+        JsonValue::Object(fields) => {
+            for f in fields {
+                if f.0 == "color" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    result.color = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+                } else if f.0 == "distance" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.distance = num;
+                    }
+                } else if f.0 == "samples" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.samples = num as u32;
+                    }
+                } else if f.0 == "strength" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.strength = num;
+                    }
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    Some(result)
+}
+
+//Returns the index of the material with the given id, or the number of materials if there is none.
+pub fn find_material(materials: &[Material], id: &str) -> usize {
+    match materials.iter().position(|m| m.id == id) {
+        Some(index) => index,
+        None => materials.len(),
+    }
+}
+
+//Reads the list of material overrides as (object name, material id) pairs.
+fn read_material_overrides(overrides: Vec<JsonValue>) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+
+    for ov in overrides {
+        if let JsonValue::Object(fields) = ov {
+            let mut object = None;
+            let mut material = None;
+
+            for f in fields {
+                if f.0 == "object" {
+                    if let JsonValue::String(s) = f.1 {
+                        object = Some(s);
+                    }
+                } else if f.0 == "material" {
+                    if let JsonValue::String(s) = f.1 {
+                        material = Some(s);
+                    }
+                }
+            }
+
+            result.push((object.unwrap(), material.unwrap()));
+        }
+    }
+
+    result
+}
+
+//Sets the material of all objects with the given name. Groups of OBJ meshes
+//are addressed with "mesh name/group name".
+fn apply_material_override(
+    object: &str,
+    material: &str,
+    spheres: &mut Vec<Sphere>,
+    planes: &mut Vec<Plane>,
+    meshes: &mut Vec<Mesh>,
+    voxels: &mut Vec<Voxels>,
+) {
+    let mut found = false;
+
+    for sp in spheres.iter_mut().filter(|sp| sp.name == object) {
+        sp.material = material.to_string();
+        found = true;
+    }
+    for plane in planes.iter_mut().filter(|plane| plane.name == object) {
+        plane.material = material.to_string();
+        found = true;
+    }
+    for mesh in meshes.iter_mut() {
+        let group_name = format!("{}/{}", mesh.name, mesh.group);
+        if mesh.name == object || group_name == object {
+            mesh.material = material.to_string();
+            found = true;
+        }
+    }
+    for vox in voxels.iter_mut().filter(|vox| vox.name == object) {
+        vox.material = material.to_string();
+        found = true;
+    }
+
+    if !found {
+        eprintln!("Material override: object not found: {}", object);
+    }
+}
+
+fn read_planes(planes: Vec<JsonValue>) -> Vec<Plane> {
+    let mut result = Vec::new();
+
+    for plane in planes {
+        if let JsonValue::Object(fields) = plane {
+            let mut point = Vector4F::null();
+            let mut normal = Vector4F::new(0.0, 1.0, 0.0);
+            let mut width = 0.0;
+            let mut height = 0.0;
+            let mut mat_id = String::from("_default");
+            let mut name = String::new();
+
+            for f in fields {
+                if f.0 == "name" {
+                    if let JsonValue::String(s) = f.1 {
+                        name = s;
+                    }
+                } else if f.0 == "point" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    point = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "normal" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    normal = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "size" {
+                    if let JsonValue::Array(values) = f.1 {
+                        if let (Some(JsonValue::Number(w)), Some(JsonValue::Number(h))) =
+                            (values.get(0), values.get(1))
+                        {
+                            width = *w;
+                            height = *h;
+                        }
+                    }
+                } else if f.0 == "material" {
+                    if let JsonValue::String(matid) = f.1 {
+                        mat_id = matid;
+                    }
+                }
+            }
+
+            result.push(Plane::new(name, point, normal, width, height, mat_id));
+        }
+    }
+
+    result
+}
+
+fn read_spheres(spheres: Vec<JsonValue>) -> Vec<Sphere> {
+    let mut result = Vec::new();
+
+    for sp in spheres {
+        if let JsonValue::Object(fields) = sp {
+            let mut center = Vector4F {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 0.0,
+            };
+            let mut radius = 1.0;
+            let mut rotation = None;
+            let mut scale = None;
+            let mut mat_id = String::from("_default");
+            let mut name = String::new();
+
+            for f in fields {
+                if f.0 == "name" {
+                    if let JsonValue::String(s) = f.1 {
+                        name = s;
+                    }
+                } else if f.0 == "center" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    center = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "radius" {
+                    if let JsonValue::Number(rad) = f.1 {
+                        radius = rad;
+                    }
+                } else if f.0 == "rotation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    rotation = Some(Vector4F::new(values.0, values.1, values.2));
+                } else if f.0 == "scale" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    scale = Some(Vector4F::new(values.0, values.1, values.2));
+                } else if f.0 == "material" {
+                    if let JsonValue::String(matid) = f.1 {
+                        mat_id = matid;
+                    }
+                }
+            }
+
+            //Plain spheres are intersected directly, without transforming the rays
+            let mut transform = None;
+            if rotation.is_some() || scale.is_some() {
+                let rotation = rotation.unwrap_or(Vector4F::null());
+                let scale = scale.unwrap_or(Vector4F::new(1.0, 1.0, 1.0));
+                let matrix = Matrix4::from_trs(&center, &rotation, &scale);
+                transform = match Transform::new(matrix) {
+                    Some(t) => Some(t),
+                    None => panic!("Sphere '{}' has a scale of 0", name),
+                };
+            }
+
+            result.push(Sphere {
+                name,
+                center: center,
+                radius: radius,
+                transform,
+                material: mat_id,
+                material_index: 0,
+            });
+        }
+    }
+
+    result
+}
+
+//Stands in for an object whose file is missing when loading leniently. The magenta sphere sits at
+//the position of the object and is sized by its scale.
+fn placeholder(name: &str, translation: &Vector4F, scale: &Vector4F) -> Sphere {
+    Sphere {
+        name: name.to_string(),
+        center: translation.clone(),
+        radius: 0.5 * scale.x.abs().max(scale.y.abs()).max(scale.z.abs()),
+        transform: None,
+        material: PLACEHOLDER_MATERIAL.to_string(),
+        material_index: 0,
+    }
+}
+
+fn read_meshes(meshes: Vec<JsonValue>, placeholders: &mut Vec<Sphere>) -> Vec<Mesh> {
+    let mut result = Vec::new();
+
+    for mesh in meshes {
+        if let JsonValue::Object(fields) = mesh {
+            let mut groups = Vec::new();
+            let mut missing = false;
+            let mut max_triangles = None;
+            let mut smooth_angle = None;
+            let mut octree_depth = octree::DEFAULT_DEPTH;
+            let mut name = String::new();
+            let mut translation = Vector4F::null();
+            let mut rotation = Vector4F::null();
+            let mut scale = Vector4F::new(1.0, 1.0, 1.0);
+            let mut material = String::new();
+
+            for f in fields {
+                if f.0 == "file" {
+                    if let JsonValue::String(s) = f.1 {
+                        if !Path::new(&s).is_file() {
+                            missing = true;
+                            continue;
+                        }
+
+                        eprintln!("Loading mesh: '{}'", s);
+                        groups = if s.to_lowercase().ends_with(".ply") {
+                            ply::load_ply(s.as_str())
+                        } else {
+                            obj::load_obj(s.as_str())
+                        };
+                        let num_vertices: usize = groups.iter().map(|g| g.vertices.len()).sum();
+                        eprintln!(
+                            "Loaded {} vertices, {} triangles, {} groups",
+                            num_vertices,
+                            num_vertices / 3,
+                            groups.len()
+                        );
+                    }
+                } else if f.0 == "name" {
+                    if let JsonValue::String(s) = f.1 {
+                        name = s;
+                    }
+                } else if f.0 == "translation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    translation = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "scale" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    scale = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "rotation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    rotation = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "material" {
+                    if let JsonValue::String(s) = f.1 {
+                        material = s;
+                    }
+                } else if f.0 == "max_triangles" {
+                    if let JsonValue::Number(num) = f.1 {
+                        max_triangles = Some(num.max(1.0) as usize);
+                    }
+                } else if f.0 == "smooth_angle" {
+                    if let JsonValue::Number(num) = f.1 {
+                        smooth_angle = Some(num);
+                    }
+                } else if f.0 == "octree_depth" {
+                    if let JsonValue::Number(num) = f.1 {
+                        octree_depth = num.max(1.0).min(octree::MAX_DEPTH as f64) as u32;
+                    }
+                }
+            }
+
+            if missing {
+                placeholders.push(placeholder(&name, &translation, &scale));
+                continue;
+            }
+
+            let total_triangles: usize = groups.iter().map(|g| g.vertices.len() / 3).sum();
+
+            //Vertices stay in object space, rays are transformed instead
+            let matrix = Matrix4::from_trs(&translation, &rotation, &scale);
+
+            //Each group of the OBJ file becomes its own object, so it can have its own material
+            for group in groups {
+                let mut vertices = group.vertices;
+                let mut stopwatch = StopWatch::new();
+
+                //Before decimating and smoothing, which need the face normals too
+                let repairs = degenerate::remove_degenerate(&mut vertices);
+                if repairs.any() {
+                    eprintln!(
+                        "Warning: mesh '{}' group '{}': skipped {} triangles with invalid \
+                         positions and {} without area, replaced {} invalid normals",
+                        name, group.name, repairs.invalid, repairs.zero_area, repairs.normals
+                    );
+                }
+                if vertices.is_empty() {
+                    continue;
+                }
+
+                let transform = match Transform::new(matrix.clone()) {
+                    Some(t) => t,
+                    None => panic!("Mesh '{}' has a scale of 0", name),
+                };
+
+                //The triangle budget is shared by the groups in relation to their size
+                if let Some(max) = max_triangles {
+                    if total_triangles > max {
+                        let group_max = (max * (vertices.len() / 3) / total_triangles).max(1);
+
+                        stopwatch.start();
+                        vertices = decimate::decimate(vertices, group_max);
+                        stopwatch.stop();
+                        eprintln!(
+                            "Decimating to {} triangles took {}ms",
+                            vertices.len() / 3,
+                            stopwatch.get_millis()
+                        );
+                    }
+                }
+
+                //Done after decimating, which moves the vertices
+                if let Some(angle) = smooth_angle {
+                    smooth::smooth_normals(&mut vertices, angle);
+                }
+
+                stopwatch.start();
+                let num_corners = vertices.len();
+                let (vertices, triangles) = create_triangles(vertices);
+                stopwatch.stop();
+                eprintln!(
+                    "Creating triangles took {}ms, {} of {} vertices are unique",
+                    stopwatch.get_millis(),
+                    vertices.len(),
+                    num_corners
+                );
+
+                stopwatch.start();
+                let octree = octree::build_octree(&vertices, &triangles, octree_depth);
+                stopwatch.stop();
+                eprintln!("Building octree took {}ms", stopwatch.get_millis());
+
+                let m = Mesh {
+                    name: name.clone(),
+                    group: group.name,
+                    vertices,
+                    triangles,
+                    transform,
+                    material: material.clone(),
+                    obj_material: group.material,
+                    material_index: 0,
+                    octree,
+                };
+
+                result.push(m);
+            }
+        }
+    }
+
+    result
+}
+
+//Welds the corners, three per triangle as loaded from OBJ files, into a vertex buffer with every
+//distinct vertex once and triangles that index into it. Only corners that are exactly equal are
+//welded, so creases and texture seams stay intact.
+fn create_triangles(verts: Vec<Vertex4F>) -> (Vec<Vertex4F>, Vec<Triangle>) {
+    let num_tris = verts.len() / 3;
+    let mut vertices = Vec::new();
+    let mut indexes = Vec::with_capacity(num_tris * 3);
+    let mut unique = HashMap::new();
+
+    for v in verts.into_iter().take(num_tris * 3) {
+        let key = [
+            v.pos.x.to_bits(),
+            v.pos.y.to_bits(),
+            v.pos.z.to_bits(),
+            v.normal.x.to_bits(),
+            v.normal.y.to_bits(),
+            v.normal.z.to_bits(),
+            v.tex_u.to_bits(),
+            v.tex_v.to_bits(),
+            v.tex_u2.to_bits(),
+            v.tex_v2.to_bits(),
+            v.color.r.to_bits() as u64,
+            v.color.g.to_bits() as u64,
+            v.color.b.to_bits() as u64,
+        ];
+
+        let index = *unique.entry(key).or_insert_with(|| {
+            vertices.push(v);
+            (vertices.len() - 1) as u32
+        });
+        indexes.push(index);
+    }
+    vertices.shrink_to_fit();
+
+    let triangles = indexes
+        .chunks(3)
+        .map(|c| Triangle {
+            v1: c[0],
+            v2: c[1],
+            v3: c[2],
+        })
+        .collect();
+
+    (vertices, triangles)
+}
+
+fn read_voxels(voxels: Vec<JsonValue>, placeholders: &mut Vec<Sphere>) -> Vec<Voxels> {
+    let mut result = Vec::new();
+
+    for vox in voxels {
+        if let JsonValue::Object(fields) = vox {
+            let mut voxels = None;
+            let mut missing = false;
+            let mut translation = Vector4F::null();
+            let mut rotation = Vector4F::null();
+            let mut scale = Vector4F::new(1.0, 1.0, 1.0);
+            let mut material = String::new();
+            let mut name = String::new();
+            let mut smooth = 0.0;
+
+            for f in fields {
+                if f.0 == "name" {
+                    if let JsonValue::String(s) = f.1 {
+                        name = s;
+                    }
+                } else if f.0 == "smooth" {
+                    if let JsonValue::Number(n) = f.1 {
+                        smooth = n;
+                    }
+                } else if f.0 == "file" {
+                    if let JsonValue::String(s) = f.1 {
+                        if !Path::new(&s).is_file() {
+                            missing = true;
+                            continue;
+                        }
+
+                        eprintln!("Loading voxel mesh: '{}'", s);
+                        voxels = vox::read_voxels(s.as_str());
+                    }
+                } else if f.0 == "translation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    translation = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "scale" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    scale = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "rotation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    rotation = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "material" {
+                    if let JsonValue::String(s) = f.1 {
+                        material = s;
+                    }
+                }
+            }
+
+            if missing {
+                placeholders.push(placeholder(&name, &translation, &scale));
+                continue;
+            }
+
+            let voxels = voxels.unwrap();
+            eprintln!("Loaded {} voxels", voxels.data.len());
+
+            let matrix = Matrix4::from_trs(&translation, &rotation, &scale);
+            let transform = match Transform::new(matrix) {
+                Some(t) => t,
+                None => panic!("Voxels '{}' have a scale of 0", name),
+            };
+
+            //Radius of the smoothing in voxels, 0 renders the blocks
+            let smooth = if smooth > 0.0 {
+                Some(DistanceField::from_voxels(&voxels, smooth))
+            } else {
+                None
+            };
+
+            let v = Voxels {
+                name,
+                transform,
+                material,
+                material_index: 0,
+                voxels,
+                smooth
+            };
+
+            result.push(v);
+        }
+    }
+
+    result
+}
+
+fn read_decals(decals: Vec<JsonValue>, textures: &mut TextureCache) -> Vec<Decal> {
+    let mut result = Vec::new();
+
+    for decal in decals {
+        if let JsonValue::Object(fields) = decal {
+            let mut texture = None;
+            let mut translation = Vector4F::null();
+            let mut rotation = Vector4F::null();
+            let mut scale = Vector4F::new(1.0, 1.0, 1.0);
+            let mut opacity = 1.0;
+            let mut uv_set = None;
+
+            for f in fields {
+                if f.0 == "texture" {
+                    if let JsonValue::String(s) = f.1 {
+                        texture = Some(textures.add(s.as_str()));
+                    }
+                } else if f.0 == "translation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    translation = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "rotation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    rotation = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "scale" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    scale = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "opacity" {
+                    if let JsonValue::Number(num) = f.1 {
+                        opacity = num;
+                    }
+                } else if f.0 == "uv_set" {
+                    if let JsonValue::Number(num) = f.1 {
+                        uv_set = Some(num as u32);
+                    }
+                }
+            }
+
+            result.push(Decal {
+                translation,
+                rotation,
+                scale,
+                opacity,
+                //Without a texture, lenient loading uses the placeholder of a missing file
+                texture: texture.unwrap_or_else(|| textures.add("")),
+                uv_set,
+            });
+        }
+    }
+
+    result
+}
+
+fn read_blockers(blockers: Vec<JsonValue>, textures: &mut TextureCache) -> Vec<Blocker> {
+    let mut result = Vec::new();
+
+    for blocker in blockers {
+        if let JsonValue::Object(fields) = blocker {
+            let mut texture = None;
+            let mut translation = Vector4F::null();
+            let mut rotation = Vector4F::null();
+            let mut scale = Vector4F::new(1.0, 1.0, 1.0);
+
+            for f in fields {
+                if f.0 == "texture" {
+                    if let JsonValue::String(s) = f.1 {
+                        texture = Some(textures.add(s.as_str()));
+                    }
+                } else if f.0 == "translation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    translation = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "rotation" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    rotation = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "scale" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    scale = Vector4F::new(values.0, values.1, values.2);
+                }
+            }
+
+            result.push(Blocker {
+                translation,
+                rotation,
+                scale,
+                texture,
+            });
+        }
+    }
+
+    result
+}
+
+fn read_visibility(animations: Vec<JsonValue>) -> Vec<VisibilityAnimation> {
+    let mut result = Vec::new();
+
+    for animation in animations {
+        if let JsonValue::Object(fields) = animation {
+            let mut object = String::new();
+            let mut keys = Vec::new();
+
+            for f in fields {
+                if f.0 == "object" {
+                    if let JsonValue::String(s) = f.1 {
+                        object = s;
+                    }
+                } else if f.0 == "keys" {
+                    if let JsonValue::Array(values) = f.1 {
+                        for key in values {
+                            if let JsonValue::Array(key) = key {
+                                if let [JsonValue::Number(frame), JsonValue::Number(v)] =
+                                    key.as_slice()
+                                {
+                                    keys.push((*frame, *v));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            result.push(VisibilityAnimation { object, keys });
+        }
+    }
+
+    result
+}
+
+fn read_section_planes(planes: Vec<JsonValue>) -> Vec<SectionPlane> {
+    let mut result = Vec::new();
+
+    for plane in planes {
+        if let JsonValue::Object(fields) = plane {
+            let mut point = Vector4F::null();
+            let mut normal = Vector4F::new(0.0, 0.0, -1.0);
+            let mut cap_material = String::new();
+
+            for f in fields {
+                if f.0 == "point" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    point = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "normal" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    normal = Vector4F::new(values.0, values.1, values.2);
+                } else if f.0 == "cap_material" {
+                    if let JsonValue::String(s) = f.1 {
+                        cap_material = s;
+                    }
+                }
+            }
+
+            result.push(SectionPlane {
+                point,
+                //Zero normals are reported by validation
+                normal: if normal.len() > 0.0 {
+                    normal.normalize()
+                } else {
+                    normal
+                },
+                cap_material,
+                cap_material_index: 0,
+            });
+        }
+    }
+
+    result
+}
+
+fn read_lights(lights: Vec<JsonValue>) -> Vec<Light> {
+    let mut result = Vec::new();
+
+    for light in lights {
+        if let JsonValue::Object(fields) = light {
+            let mut ltype = LightType::Point;
+            let mut position = Vector4F {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            };
+            let mut color = Color {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+            };
+            let mut direction = Vector4F::new(0.0, -1.0, 0.0);
+            //The real sun is about 0.53 degrees across
+            let mut angular_radius = 0.265;
+            let mut radius = 1.0;
+            let mut visible = false;
+            let mut samples = 1;
+            let mut intensity = 1.0;
+            let mut falloff = Falloff::InverseSquare;
+            let mut animation = None;
+            //Radiant intensity per unit of intensity, or None if the intensity is the irradiance
+            //at the radius
+            let mut units = None;
+
+            for f in fields {
+                if f.0 == "type" {
+                    if let JsonValue::String(t) = f.1 {
+                        let ts = t.trim().to_lowercase();
+                        if ts == "point" {
+                            ltype = LightType::Point;
+                        } else if ts == "sphere" {
+                            ltype = LightType::Sphere;
+                        } else if ts == "directional" || ts == "sun" {
+                            ltype = LightType::Directional;
+                        } else {
+                            let mut message = String::new();
+                            message.push_str("Unknown light type: ");
+                            message.push_str(ts.as_str());
+                            panic!(message);
+                        }
+                    }
+                } else if f.0 == "position" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    position = Vector4F {
+                        x: values.0,
+                        y: values.1,
+                        z: values.2,
+                        w: 1.0,
+                    };
+                } else if f.0 == "direction" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    direction = Vector4F::new(values.0, values.1, values.2).normalize();
+                } else if f.0 == "angular_radius" {
+                    if let JsonValue::Number(ar) = f.1 {
+                        angular_radius = ar;
+                    }
+                } else if f.0 == "color" {
+                    let values = read_number_triplet(&f.1).unwrap();
+                    color = Color {
+                        r: values.0 as f32,
+                        g: values.1 as f32,
+                        b: values.2 as f32,
+                    }
+                } else if f.0 == "radius" {
+                    if let JsonValue::Number(rad) = f.1 {
+                        radius = rad;
+                    }
+                } else if f.0 == "samples" {
+                    if let JsonValue::Number(sm) = f.1 {
+                        samples = sm as u32;
+                    }
+                } else if f.0 == "visible" {
+                    if let JsonValue::Boolean(b) = f.1 {
+                        visible = b;
+                    }
+                } else if f.0 == "intensity" {
+                    if let JsonValue::Number(int) = f.1 {
+                        intensity = int;
+                    }
+                } else if f.0 == "falloff" {
+                    if let JsonValue::String(s) = f.1 {
+                        let st = s.trim().to_lowercase();
+                        falloff = match st.as_str() {
+                            "none" => Falloff::None,
+                            "linear" => Falloff::Linear,
+                            "inverse_square" => Falloff::InverseSquare,
+                            _ => panic!("Unknown light falloff: {}", st),
+                        };
+                    }
+                } else if f.0 == "units" {
+                    if let JsonValue::String(s) = f.1 {
+                        let st = s.trim().to_lowercase();
+                        units = match st.as_str() {
+                            //Radiant power, spread evenly over all directions
+                            "watts" => Some(1.0 / (4.0 * std::f64::consts::PI)),
+                            //Radiant intensity, the irradiance at a distance of 1
+                            "candela" => Some(1.0),
+                            _ => panic!("Unknown light units: {}", st),
+                        };
+                    }
+                } else if f.0 == "animation" {
+                    if let JsonValue::Object(fields) = f.1 {
+                        animation = Some(read_light_animation(fields));
+                    }
+                }
+            }
+
+            //Intensities in units make the brightness independent of the radius. Sphere lights
+            //need the irradiance at their surface, point lights keep a reference distance of 1.
+            let mut reference = radius;
+            let mut scale = 1.0;
+            if let Some(units) = units {
+                match ltype {
+                    LightType::Sphere => scale = units / (radius * radius),
+                    LightType::Point => {
+                        scale = units;
+                        reference = 1.0;
+                    }
+                    //Directional lights are far away, their intensity is already the irradiance
+                    LightType::Directional => {}
+                }
+            }
+            intensity *= scale;
 
-            //println!("ipos: {}", inter.pos);
-            //println!("wpos: {}", world_pos);
-            //println!("t: {}", world_t);
+            if let Some(animation) = &mut animation {
+                animation.color = color.clone();
+                animation.intensity = intensity;
+                for key in &mut animation.intensity_keys {
+                    key.1 *= scale;
+                }
+            }
 
-            return Some(Intersection {
-                pos: world_pos,
-                normal: world_normal,
-                tex_u: 0.0,
-                tex_v: 0.0,
-                barycentric: Vector4F::null(),
-                ray_t: world_t,
+            result.push(Light {
+                ltype,
+                position,
+                direction,
+                angular_radius,
+                color,
+                visible,
+                radius,
+                samples,
+                intensity,
+                reference,
+                falloff,
+                animation,
             });
         }
+    }
 
-        /*
-        //For tracing only the AABB
-        let intersection = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
-        if intersection.is_some() {
-            let inter = intersection.unwrap();
+    result
+}
 
-            let world_pos = &(&inter.pos * &self.scale) + &self.translation;
-            let world_normal = inter.normal.rotate_x(self.rotation.x).rotate_y(self.rotation.y).rotate_z(self.rotation.z);
-            //Need to recalc t with world coordinates
-            let world_t = (world_pos.x - rorg.x) / rdir.x;
+//Reads the keys and flicker of a light. The color and intensity without keys are set by the light.
+fn read_light_animation(fields: Vec<(String, JsonValue)>) -> LightAnimation {
+    let mut result = LightAnimation {
+        color: Color::white(),
+        intensity: 1.0,
+        intensity_keys: Vec::new(),
+        color_keys: Vec::new(),
+        temperature_keys: Vec::new(),
+        flicker: None,
+    };
 
-            //println!("ipos: {}", inter.pos);
-            //println!("wpos: {}", world_pos);
-            //println!("t: {}", world_t);
+    for f in fields {
+        if f.0 == "intensity" {
+            result.intensity_keys = read_keys(&f.1, 1)
+                .into_iter()
+                .map(|(frame, v)| (frame, v[0]))
+                .collect();
+        } else if f.0 == "color" {
+            result.color_keys = read_keys(&f.1, 3)
+                .into_iter()
+                .map(|(frame, v)| (frame, Color::new(v[0] as f32, v[1] as f32, v[2] as f32)))
+                .collect();
+        } else if f.0 == "temperature" {
+            result.temperature_keys = read_keys(&f.1, 1)
+                .into_iter()
+                .map(|(frame, v)| (frame, v[0]))
+                .collect();
+        } else if f.0 == "flicker" {
+            result.flicker = read_flicker(f.1);
+        }
+    }
 
-            return Some(Intersection {
-                pos: world_pos,
-                normal: world_normal,
-                tex_u: 0.0,
-                tex_v: 0.0,
-                barycentric: Vector4F::null(),
-                ray_t: world_t,
-            });
+    result
+}
+
+//Reads keys like [frame, value...] with the given number of values, sorted by frame. Keys with
+//another number of values are skipped.
+fn read_keys(value: &JsonValue, values: usize) -> Vec<(f64, Vec<f64>)> {
+    let mut keys = Vec::new();
+    if let JsonValue::Array(entries) = value {
+        for key in entries {
+            if let JsonValue::Array(key) = key {
+                let numbers: Vec<f64> = key
+                    .iter()
+                    .filter_map(|v| match v {
+                        JsonValue::Number(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                if numbers.len() == values + 1 {
+                    keys.push((numbers[0], numbers[1..].to_vec()));
+                }
+            }
         }
-        else {
-            return None;
+    }
+    keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    keys
+}
+
+//Reads either a boolean to enable flicker with default values, or an object with amount, speed
+//and seed
+fn read_flicker(value: JsonValue) -> Option<Flicker> {
+    let mut result = Flicker {
+        amount: 0.2,
+        speed: 0.5,
+        seed: 0,
+    };
+
+    match value {
+        JsonValue::Boolean(enabled) => {
+            if !enabled {
+                return None;
+            }
         }
-        */
+        JsonValue::Object(fields) => {
+            for f in fields {
+                if f.0 == "amount" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.amount = num;
+                    }
+                } else if f.0 == "speed" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.speed = num;
+                    }
+                } else if f.0 == "seed" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.seed = num as u64;
+                    }
+                }
+            }
+        }
+        _ => return None,
+    }
 
-        /*
-        if linear::point_in_aabb(&rorg_obj_space, &min, &max) {
-            //If ray origin is inside voxel grid, just truncate coordinates to get starting voxel
-            x = rorg_obj_space.x as i32;
-            y = rorg_obj_space.y as i32;
-            z = rorg_obj_space.z as i32;
-        } else {
-            //Find voxel where ray enters
-            let inter = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
-            if inter.is_some() {
-                let intersection = inter.unwrap();
-                x = intersection.pos.x as i32;
-                y = intersection.pos.y as i32;
-                z = intersection.pos.z as i32;
+    Some(result)
+}
 
-                if x == self.voxels.width as i32 {
-                    //println!("x '{}' is out", x);
-                    x = (self.voxels.width - 1) as i32;
+fn read_fog(fog: JsonValue) -> Option<Fog> {
+    if let JsonValue::Object(fields) = fog {
+        let mut result = Fog {
+            color: Color::new(0.8, 0.8, 0.8),
+            density: 0.1,
+            height: 0.0,
+            falloff: 1.0,
+        };
+
+        for f in fields {
+            if f.0 == "color" {
+                let v = read_number_triplet(&f.1).unwrap();
+                result.color = Color::new(v.0 as f32, v.1 as f32, v.2 as f32);
+            } else if f.0 == "density" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.density = num;
                 }
-                if y == self.voxels.height as i32 {
-                    //println!("y '{}' is out", y);
-                    y = (self.voxels.height - 1) as i32;
+            } else if f.0 == "height" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.height = num;
                 }
-                if z == self.voxels.depth as i32 {
-                    //println!("z '{}' is out", z);
-                    z = (self.voxels.depth - 1) as i32;
+            } else if f.0 == "falloff" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.falloff = num;
                 }
-            } else {
-                return None;
             }
         }
 
-        let step_x = rdir_obj_space.x.signum() as i32;
-        let step_y = rdir_obj_space.y.signum() as i32;
-        let step_z = rdir_obj_space.z.signum() as i32;
-
-        let out_x: i32 = if step_x > 0 {self.voxels.width as i32} else {-1};
-        let out_y: i32 = if step_y > 0 {self.voxels.height as i32} else {-1};
-        let out_z: i32 = if step_z > 0 {self.voxels.depth as i32} else {-1};
+        return Some(result);
+    }
 
-        let mut t_max_x = get_max_element(rorg_obj_space.x, rdir_obj_space.x, self.voxels.width as f64);
-        let mut t_max_y = get_max_element(rorg_obj_space.y, rdir_obj_space.y, self.voxels.height as f64);
-        let mut t_max_z = get_max_element(rorg_obj_space.z, rdir_obj_space.z, self.voxels.depth as f64);
+    None
+}
 
-        let t_delta_x = 1.0 / rdir_obj_space.x;
-        let t_delta_y = 1.0 / rdir_obj_space.y;
-        let t_delta_z = 1.0 / rdir_obj_space.z;
+fn read_hybrid_gi(hybrid: JsonValue) -> Option<HybridGi> {
+    if let JsonValue::Object(fields) = hybrid {
+        let mut result = HybridGi {
+            full_bounces: 1,
+            ambient: None,
+            ao_samples: 2,
+            ao_distance: 1.0,
+        };
 
-        let mut intersects = false;
-        //println!("---------------");
-        //println!("F x,y,z: {};{};{}", x, y, z);
-        //dbg!(step_x);
-        //dbg!(out_z);
-        
-        loop {
-            if t_max_x < t_max_y {
-                if t_max_x < t_max_z {
-                    x = x + step_x;
-                    if x == out_x {
-                        break;
-                    }
-                    t_max_x = t_max_x + t_delta_x;
-                } else {
-                    z = z + step_z;
-                    if z == out_z {
-                        break;
-                    }
-                    t_max_z = t_max_z + t_delta_z;
+        for f in fields {
+            if f.0 == "full_bounces" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.full_bounces = num as u32;
                 }
-            } else {
-                if t_max_y < t_max_z {
-                    y = y + step_y;
-                    if y == out_y {
-                        break;
-                    }
-                    t_max_y = t_max_y + t_delta_y;
-                } else {
-                    z = z + step_z;
-                    if z == out_z {
-                        break;
-                    }
-                    t_max_z = t_max_z + t_delta_z;
+            } else if f.0 == "ambient" {
+                let v = read_number_triplet(&f.1).unwrap();
+                result.ambient = Some(Color::new(v.0 as f32, v.1 as f32, v.2 as f32));
+            } else if f.0 == "ao_samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.ao_samples = num as u32;
+                }
+            } else if f.0 == "ao_distance" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.ao_distance = num;
                 }
-            }
-
-            //println!("x,y,z: {};{};{}", x, y, z);
-            let voxel = self.voxels.get(x as u32, y as u32, z as u32);
-            if voxel.is_some() {
-                intersects = true;
-                break;
             }
         }
 
-        if intersects {
-            let min = Vector4F::new(x as f64, y as f64, z as f64);
-            let max = Vector4F::new((x + 1) as f64, (y + 1) as f64, (z + 1) as f64);
-            let intersection = linear::intersect_ray_aabb2(&rorg_obj_space, &rdir_obj_space, &min, &max);
-
-            if intersection.is_some() {
-                let inter = intersection.unwrap();
+        return Some(result);
+    }
 
-                let world_pos = &(&inter.pos * &self.scale) + &self.translation;
-                let world_normal = inter.normal.rotate_x(self.rotation.x).rotate_y(self.rotation.y).rotate_z(self.rotation.z);
-                //Need to recalc t with oroginal ray in world space
-                let world_t = (world_pos.x - rorg.x) / rdir.x;
+    None
+}
 
-                //println!("ipos: {}", inter.pos);
-                //println!("wpos: {}", world_pos);
-                //println!("t: {}", world_t);
+fn read_environment(environment: JsonValue) -> Option<Environment> {
+    if let JsonValue::Object(fields) = environment {
+        let mut file = None;
+        let mut intensity = 1.0;
+        let mut rotation = 0.0;
+        let mut samples = 1;
 
-                return Some(Intersection {
-                    pos: world_pos,
-                    normal: world_normal,
-                    tex_u: 0.0,
-                    tex_v: 0.0,
-                    barycentric: Vector4F::null(),
-                    ray_t: world_t,
-                });
-            }
-            else {
-                return None;
+        for f in fields {
+            if f.0 == "file" {
+                if let JsonValue::String(s) = f.1 {
+                    file = Some(s);
+                }
+            } else if f.0 == "intensity" {
+                if let JsonValue::Number(num) = f.1 {
+                    intensity = num;
+                }
+            } else if f.0 == "rotation" {
+                if let JsonValue::Number(num) = f.1 {
+                    rotation = num;
+                }
+            } else if f.0 == "samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    samples = num as u32;
+                }
             }
         }
-        */
 
-        None
-    }
+        //Without the map, lenient loading falls back to the sky color
+        let file = match file {
+            Some(file) if Path::new(&file).is_file() => file,
+            _ => return None,
+        };
 
-    fn material(&self) -> String {
-        self.material.clone()
-    }
-}
+        eprintln!("Loading environment map: '{}'", file);
 
-fn _get_max_element(org: f64, dir: f64, out: f64) -> f64 {
-    let mut max = if dir > 0.0 {std::f64::INFINITY} else {std::f64::NEG_INFINITY};
+        let mut stopwatch = StopWatch::new();
+        stopwatch.start();
+        let env = Environment::load(file.as_str(), intensity, rotation, samples);
+        stopwatch.stop();
+        eprintln!(
+            "Loading environment and building CDF took {}ms",
+            stopwatch.get_millis()
+        );
 
-    if dir > 0.0 {
-        if org < 0.0 {
-            max = dir / -org;
-        } else if org >= 0.0 && org <= out {
-            max = dir / (org.trunc() + 1.0);
-        } else {
-            panic!("Ray starts outside of voxel model, this should not happen!");
-        }
-    } else if dir < 0.0 {
-        if org > out {
-            max = -dir / (org - out);
-        } else if org >= 0.0 && org <= out {
-            max = -dir / (org.trunc());
-        } else {
-            panic!("Ray starts outside of voxel model, this should not happen!");
-        }
+        return Some(env);
     }
 
-    max
+    None
 }
 
-fn read_scene(scene: JsonValue) -> Option<Scene> {
-    if let JsonValue::Object(fields) = scene {
-        let mut materials = Vec::new();
-        let mut spheres = Vec::new();
-        let mut meshes = Vec::new();
-        let mut voxels = Vec::new();
-        let mut lights = Vec::new();
-        let mut skycolor = Color {
-            r: 0.0,
-            g: 0.0,
-            b: 0.0,
-        };
-        let mut max_depth = 5;
-        let mut path_samples = 1;
+fn read_dome(dome: JsonValue) -> Option<Dome> {
+    if let JsonValue::Object(fields) = dome {
+        let mut color = Color::white();
+        let mut ground = Color::black();
+        let mut file = None;
+        let mut intensity = 1.0;
+        let mut rotation = 0.0;
+        let mut samples = 1;
 
         for f in fields {
-            if f.0 == "skycolor" {
-                let v = read_number_triplet(&f.1).unwrap();
-                skycolor.r = v.0 as f32;
-                skycolor.g = v.1 as f32;
-                skycolor.b = v.2 as f32;
-            } else if f.0 == "max_trace_depth" {
-                if let JsonValue::Number(md) = f.1 {
-                    max_depth = md as u32;
+            if f.0 == "color" {
+                let values = read_number_triplet(&f.1).unwrap();
+                color = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+            } else if f.0 == "ground" {
+                let values = read_number_triplet(&f.1).unwrap();
+                ground = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+            } else if f.0 == "texture" {
+                if let JsonValue::String(s) = f.1 {
+                    file = Some(s);
                 }
-            } else if f.0 == "path_samples" {
-                if let JsonValue::Number(ps) = f.1 {
-                    path_samples = ps as u32;
+            } else if f.0 == "intensity" {
+                if let JsonValue::Number(num) = f.1 {
+                    intensity = num;
                 }
-            } else if let JsonValue::Array(values) = f.1 {
-                if f.0 == "materials" {
-                    materials = read_materials(values);
-                } else if f.0 == "spheres" {
-                    spheres = read_spheres(values);
-                } else if f.0 == "meshes" {
-                    meshes = read_meshes(values);
-                } else if f.0 == "lights" {
-                    lights = read_lights(values);
-                } else if f.0 == "voxels" {
-                    voxels = read_voxels(values);
+            } else if f.0 == "rotation" {
+                if let JsonValue::Number(num) = f.1 {
+                    rotation = num;
+                }
+            } else if f.0 == "samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    samples = num as u32;
                 }
             }
         }
 
-        if max_depth <= 0 {
-            path_samples = 0;
-        }
-
-        return Some(Scene {
-            materials,
-            spheres,
-            meshes,
-            voxels,
-            lights,
-            skycolor,
-            max_depth,
-            path_samples,
+        return Some(Dome {
+            color,
+            ground,
+            file,
+            intensity,
+            rotation,
+            samples,
         });
     }
 
     None
 }
 
-fn read_materials(materials: Vec<JsonValue>) -> Vec<Material> {
-    let mut result = Vec::new();
-
-    for mat in materials {
-        if let JsonValue::Object(fields) = mat {
-            let mut id: Option<String> = None;
-            let mut color = Color::black();
-            let mut reflect = 0.0;
-            let mut refract = 0.0;
-            let mut ior = 1.0;
-            let mut roughness = 0.001;
+//Reads the zenith, horizon and ground colors of a backdrop gradient, missing colors are a light
+//blue sky and a brown ground
+fn read_sky_gradient(gradient: JsonValue) -> Option<Backdrop> {
+    if let JsonValue::Object(fields) = gradient {
+        let mut result = Backdrop {
+            top: Color::new(0.3, 0.5, 0.9),
+            horizon: Color::new(0.8, 0.85, 0.9),
+            bottom: Color::new(0.3, 0.25, 0.2),
+        };
 
-            for f in fields {
-                if f.0 == "id" {
-                    if let JsonValue::String(idstr) = f.1 {
-                        id = Some(idstr);
-                    }
-                } else if f.0 == "color" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    color = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
-                } else if f.0 == "refract" {
-                    if let JsonValue::Number(refr) = f.1 {
-                        refract = refr;
-                    }
-                } else if f.0 == "reflect" {
-                    if let JsonValue::Number(refl) = f.1 {
-                        reflect = refl;
-                    }
-                } else if f.0 == "ior" {
-                    if let JsonValue::Number(iorv) = f.1 {
-                        ior = iorv;
-                    }
-                } else if f.0 == "roughness" {
-                    if let JsonValue::Number(rgv) = f.1 {
-                        roughness = rgv;
-                    }
-                }
+        for f in fields {
+            if f.0 == "zenith" {
+                let values = read_number_triplet(&f.1).unwrap();
+                result.top = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+            } else if f.0 == "horizon" {
+                let values = read_number_triplet(&f.1).unwrap();
+                result.horizon = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+            } else if f.0 == "ground" {
+                let values = read_number_triplet(&f.1).unwrap();
+                result.bottom = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
             }
-
-            result.push(Material {
-                id: id.unwrap(),
-                color,
-                reflect,
-                refract,
-                ior,
-                roughness,
-            });
         }
+
+        return Some(result);
     }
 
-    result
+    None
 }
 
-fn read_spheres(spheres: Vec<JsonValue>) -> Vec<Sphere> {
-    let mut result = Vec::new();
-
-    for sp in spheres {
-        if let JsonValue::Object(fields) = sp {
-            let mut center = Vector4F {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 0.0,
-            };
-            let mut radius = 1.0;
-            let mut mat_id = String::from("_default");
+fn read_sky(sky: JsonValue) -> Option<Sky> {
+    if let JsonValue::Object(fields) = sky {
+        let mut result = Sky {
+            turbidity: 3.0,
+            sun: None,
+            ground: Color::black(),
+            intensity: 1.0,
+            samples: 1,
+        };
 
-            for f in fields {
-                if f.0 == "center" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    center = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
-                    };
-                } else if f.0 == "radius" {
-                    if let JsonValue::Number(rad) = f.1 {
-                        radius = rad;
-                    }
-                } else if f.0 == "material" {
-                    if let JsonValue::String(matid) = f.1 {
-                        mat_id = matid;
-                    }
+        for f in fields {
+            if f.0 == "turbidity" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.turbidity = num;
+                }
+            } else if f.0 == "sun_direction" {
+                let values = read_number_triplet(&f.1).unwrap();
+                result.sun = Some(Vector4F::new(values.0, values.1, values.2));
+            } else if f.0 == "ground" {
+                let values = read_number_triplet(&f.1).unwrap();
+                result.ground = Color::new(values.0 as f32, values.1 as f32, values.2 as f32);
+            } else if f.0 == "intensity" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.intensity = num;
+                }
+            } else if f.0 == "samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.samples = num as u32;
                 }
             }
-
-            result.push(Sphere {
-                center: center,
-                radius: radius,
-                material: mat_id,
-            });
         }
+
+        return Some(result);
     }
 
-    result
+    None
 }
 
-fn read_meshes(meshes: Vec<JsonValue>) -> Vec<Mesh> {
-    let mut result = Vec::new();
-
-    for mesh in meshes {
-        if let JsonValue::Object(fields) = mesh {
-            let mut vertices = Vec::new();
-            let mut translation = Vector4F::null();
-            let mut rotation = Vector4F::null();
-            let mut scale = Vector4F::new(1.0, 1.0, 1.0);
-            let mut material = String::new();
+fn read_output(output: JsonValue) -> Option<Output> {
+    if let JsonValue::Object(fields) = output {
+        let mut filename = String::from("render.tga");
+        let mut width = 1920;
+        let mut height = 1080;
+        let mut samples = 1;
+        let mut aovs = Vec::new();
+        let mut ao_samples = 2;
+        let mut ao_distance = 1.0;
+        let mut curvature_radius = 0.01;
+        let mut pixel_format = PixelFormat::F32;
+        let mut accumulation = Accumulation::F32;
+        let mut render_order = RenderOrder::Scanline;
+        let mut sampler = Sampler::Grid;
+        let mut progress = ProgressFormat::Percent;
+        let mut filter = String::from("box");
+        let mut filter_radius = None;
+        let mut debug_view = None;
+        let mut debug_max_distance = 10.0;
+        let mut packets = false;
+        let mut seed = 0;
+        let mut frame = None;
+        let mut edge_samples = 0;
+        let mut edge_depth_threshold = 0.1;
+        let mut light_paths = LightPaths::all();
+        let mut burn_in = None;
+        let mut metadata = false;
+        let mut alpha = false;
+        let mut rle = false;
+        let mut top_down = false;
+        let mut channel_order = ChannelOrder::Bgr;
+        let mut bits_per_channel = 8;
+        let mut check_nan = false;
+        let mut debug_pixel = None;
+        let mut passes = 1;
+        let mut preview_interval = 10.0;
+        let mut checkpoint = None;
+        let mut checkpoint_interval = 600.0;
+        let mut adaptive = None;
+        let mut denoise = None;
+        let mut shadow_sprite = None;
 
-            for f in fields {
-                if f.0 == "file" {
-                    if let JsonValue::String(s) = f.1 {
-                        println!("Loading mesh: '{}'", s);
-                        vertices = obj::load_obj(s.as_str());
-                        println!(
-                            "Loaded {} vertices, {} triangles",
-                            vertices.len(),
-                            vertices.len() / 3
-                        );
-                    }
-                } else if f.0 == "translation" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    translation = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
+        for f in fields {
+            if f.0 == "file" {
+                if let JsonValue::String(st) = f.1 {
+                    filename = st;
+                }
+            } else if f.0 == "width" {
+                if let JsonValue::Number(num) = f.1 {
+                    width = num as u32;
+                }
+            } else if f.0 == "height" {
+                if let JsonValue::Number(num) = f.1 {
+                    height = num as u32;
+                }
+            } else if f.0 == "samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    samples = num as u32;
+                }
+            } else if f.0 == "aovs" {
+                if let JsonValue::Array(values) = f.1 {
+                    aovs = read_aovs(values);
+                }
+            } else if f.0 == "ao_samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    ao_samples = num as u32;
+                }
+            } else if f.0 == "ao_distance" {
+                if let JsonValue::Number(num) = f.1 {
+                    ao_distance = num;
+                }
+            } else if f.0 == "curvature_radius" {
+                if let JsonValue::Number(num) = f.1 {
+                    curvature_radius = num;
+                }
+            } else if f.0 == "pixel_format" {
+                if let JsonValue::String(st) = f.1 {
+                    pixel_format = match st.as_str() {
+                        "f32" => PixelFormat::F32,
+                        "f16" => PixelFormat::F16,
+                        _ => panic!("Unknown pixel format: {}", st),
                     };
-                } else if f.0 == "scale" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    scale = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
+                }
+            } else if f.0 == "accumulation" {
+                if let JsonValue::String(st) = f.1 {
+                    accumulation = match st.as_str() {
+                        "f32" => Accumulation::F32,
+                        "compensated" => Accumulation::Compensated,
+                        "f64" => Accumulation::F64,
+                        _ => panic!("Unknown accumulation: {}", st),
                     };
-                } else if f.0 == "rotation" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    rotation = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
+                }
+            } else if f.0 == "render_order" {
+                if let JsonValue::String(st) = f.1 {
+                    render_order = match st.as_str() {
+                        "scanline" => RenderOrder::Scanline,
+                        "hilbert" => RenderOrder::Hilbert,
+                        "spiral" => RenderOrder::Spiral,
+                        _ => panic!("Unknown render order: {}", st),
                     };
-                } else if f.0 == "material" {
-                    if let JsonValue::String(s) = f.1 {
-                        material = s;
+                }
+            } else if f.0 == "sampler" {
+                if let JsonValue::String(st) = f.1 {
+                    sampler = match st.as_str() {
+                        "grid" => Sampler::Grid,
+                        "jittered" => Sampler::Jittered,
+                        _ => panic!("Unknown sampler: {}", st),
+                    };
+                }
+            } else if f.0 == "progress" {
+                if let JsonValue::String(st) = f.1 {
+                    progress = match ProgressFormat::from_name(st.as_str()) {
+                        Some(format) => format,
+                        None => panic!("Unknown progress format: {}", st),
+                    };
+                }
+            } else if f.0 == "filter" {
+                if let JsonValue::String(st) = f.1 {
+                    filter = st;
+                }
+            } else if f.0 == "filter_radius" {
+                if let JsonValue::Number(num) = f.1 {
+                    filter_radius = Some(num);
+                }
+            } else if f.0 == "debug_view" {
+                if let JsonValue::String(st) = f.1 {
+                    debug_view = Some(st);
+                }
+            } else if f.0 == "debug_max_distance" {
+                if let JsonValue::Number(num) = f.1 {
+                    debug_max_distance = num;
+                }
+            } else if f.0 == "packets" {
+                if let JsonValue::Boolean(b) = f.1 {
+                    packets = b;
+                }
+            } else if f.0 == "seed" {
+                if let JsonValue::Number(num) = f.1 {
+                    seed = num as u64;
+                }
+            } else if f.0 == "frame" {
+                if let JsonValue::Number(num) = f.1 {
+                    frame = Some(num as u32);
+                }
+            } else if f.0 == "edge_samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    edge_samples = num as u32;
+                }
+            } else if f.0 == "edge_depth_threshold" {
+                if let JsonValue::Number(num) = f.1 {
+                    edge_depth_threshold = num;
+                }
+            } else if f.0 == "light_paths" {
+                light_paths = read_light_paths(f.1);
+            } else if f.0 == "burn_in" {
+                burn_in = read_burn_in(f.1);
+            } else if f.0 == "metadata" {
+                if let JsonValue::Boolean(b) = f.1 {
+                    metadata = b;
+                }
+            } else if f.0 == "alpha" {
+                if let JsonValue::Boolean(b) = f.1 {
+                    alpha = b;
+                }
+            } else if f.0 == "rle" {
+                if let JsonValue::Boolean(b) = f.1 {
+                    rle = b;
+                }
+            } else if f.0 == "top_down" {
+                if let JsonValue::Boolean(b) = f.1 {
+                    top_down = b;
+                }
+            } else if f.0 == "channel_order" {
+                if let JsonValue::String(st) = f.1 {
+                    channel_order = match st.as_str() {
+                        "bgr" => ChannelOrder::Bgr,
+                        "rgb" => ChannelOrder::Rgb,
+                        _ => panic!("Unknown channel order: {}", st),
+                    };
+                }
+            } else if f.0 == "bits_per_channel" {
+                if let JsonValue::Number(num) = f.1 {
+                    bits_per_channel = num as u32;
+                }
+            } else if f.0 == "check_nan" {
+                if let JsonValue::Boolean(b) = f.1 {
+                    check_nan = b;
+                }
+            } else if f.0 == "debug_pixel" {
+                if let JsonValue::Array(values) = f.1 {
+                    if let [JsonValue::Number(x), JsonValue::Number(y)] = values.as_slice() {
+                        debug_pixel = Some((*x as u32, *y as u32));
                     }
                 }
+            } else if f.0 == "passes" {
+                if let JsonValue::Number(num) = f.1 {
+                    passes = num as u32;
+                }
+            } else if f.0 == "preview_interval" {
+                if let JsonValue::Number(num) = f.1 {
+                    preview_interval = num;
+                }
+            } else if f.0 == "checkpoint" {
+                if let JsonValue::String(st) = f.1 {
+                    checkpoint = Some(st);
+                }
+            } else if f.0 == "checkpoint_interval" {
+                if let JsonValue::Number(num) = f.1 {
+                    checkpoint_interval = num;
+                }
+            } else if f.0 == "adaptive" {
+                adaptive = read_adaptive(f.1);
+            } else if f.0 == "denoise" {
+                denoise = read_denoise(f.1);
+            } else if f.0 == "shadow_sprite" {
+                shadow_sprite = read_shadow_sprite(f.1);
             }
+        }
 
-            let mut stopwatch = StopWatch::new();
+        //Shadow sprites are nothing but their alpha
+        if shadow_sprite.is_some() {
+            alpha = true;
+        }
+        if alpha && !aovs.iter().any(|aov| aov.name() == "alpha") {
+            aovs.push(Aov::Alpha);
+        }
 
-            //Apply transform to position AND normals
-            stopwatch.start();
-            for vert in &mut vertices {
-                let new_pos = vert
-                    .pos
-                    .rotate_x(rotation.x)
-                    .rotate_y(rotation.y)
-                    .rotate_z(rotation.z);
-                vert.pos = &(&new_pos * &scale) + &translation;
+        let filter = match filter.as_str() {
+            "box" => PixelFilter::Box,
+            "tent" => PixelFilter::Tent {
+                radius: filter_radius.unwrap_or(1.0),
+            },
+            "gaussian" => PixelFilter::Gaussian {
+                radius: filter_radius.unwrap_or(1.5),
+            },
+            "mitchell" => PixelFilter::Mitchell {
+                radius: filter_radius.unwrap_or(2.0),
+            },
+            _ => panic!("Unknown filter: {}", filter),
+        };
 
-                let new_norm = vert
-                    .normal
-                    .rotate_x(rotation.x)
-                    .rotate_y(rotation.y)
-                    .rotate_z(rotation.z);
-                vert.normal = new_norm;
-            }
-            stopwatch.stop();
-            println!("Transforming vertices took {}ms", stopwatch.get_millis());
-
-            stopwatch.start();
-            let triangles = create_triangles(&mut vertices);
-            stopwatch.stop();
-            println!("Creating triangles took {}ms", stopwatch.get_millis());
-
-            stopwatch.start();
-            let octree = octree::build_octree(&triangles);
-            stopwatch.stop();
-            println!("Building octree took {}ms", stopwatch.get_millis());
-
-            let m = Mesh {
-                triangles,
-                translation,
-                rotation,
-                scale,
-                material,
-                octree,
-            };
+        let debug_view = debug_view.map(|view| match view.as_str() {
+            "normals" => DebugView::Normals,
+            "depth" => DebugView::Depth {
+                max_distance: debug_max_distance,
+            },
+            "uvs" => DebugView::Uvs,
+            "triangle_tests" => DebugView::TriangleTests,
+            _ => panic!("Unknown debug view: {}", view),
+        });
 
-            result.push(m);
-        }
+        return Some(Output {
+            filename,
+            width,
+            height,
+            samples,
+            aovs,
+            ao_samples,
+            ao_distance,
+            curvature_radius,
+            pixel_format,
+            accumulation,
+            render_order,
+            sampler,
+            progress,
+            filter,
+            debug_view,
+            packets,
+            seed,
+            frame,
+            edge_samples,
+            edge_depth_threshold,
+            light_paths,
+            burn_in,
+            metadata,
+            alpha,
+            rle,
+            top_down,
+            channel_order,
+            bits_per_channel,
+            check_nan,
+            debug_pixel,
+            passes,
+            preview_interval,
+            checkpoint,
+            checkpoint_interval,
+            adaptive,
+            denoise,
+            shadow_sprite,
+        });
     }
 
-    result
+    None
 }
 
-fn create_triangles(verts: &mut Vec<Vertex4F>) -> Vec<Triangle> {
-    let num_tris = verts.len() / 3;
-    let mut result = Vec::with_capacity(num_tris);
-
-    for i in 0..num_tris {
-        let i1 = i * 3;
+//Reads either a boolean to enable the burn-in with default values, or an object with scene, frame
+//and corner.
+fn read_burn_in(value: JsonValue) -> Option<BurnIn> {
+    let mut result = BurnIn {
+        scene: String::new(),
+        frame: None,
+        corner: Corner::BottomLeft,
+    };
 
-        result.push(Triangle {
-            v1: verts[i1].clone(),
-            v2: verts[i1 + 1].clone(),
-            v3: verts[i1 + 2].clone(),
-        });
+    match value {
+        JsonValue::Boolean(enabled) => {
+            if !enabled {
+                return None;
+            }
+        }
+        JsonValue::Object(fields) => {
+            for f in fields {
+                if f.0 == "scene" {
+                    if let JsonValue::String(st) = f.1 {
+                        result.scene = st;
+                    }
+                } else if f.0 == "frame" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.frame = Some(num as u32);
+                    }
+                } else if f.0 == "corner" {
+                    if let JsonValue::String(st) = f.1 {
+                        result.corner = match st.as_str() {
+                            "top_left" => Corner::TopLeft,
+                            "top_right" => Corner::TopRight,
+                            "bottom_left" => Corner::BottomLeft,
+                            "bottom_right" => Corner::BottomRight,
+                            _ => panic!("Unknown burn-in corner: {}", st),
+                        };
+                    }
+                }
+            }
+        }
+        _ => return None,
     }
 
-    result
+    Some(result)
 }
 
-fn read_voxels(voxels: Vec<JsonValue>) -> Vec<Voxels> {
-    let mut result = Vec::new();
-
-    for vox in voxels {
-        if let JsonValue::Object(fields) = vox {
-            let mut voxels = None;
-            let mut translation = Vector4F::null();
-            let mut rotation = Vector4F::null();
-            let mut scale = Vector4F::new(1.0, 1.0, 1.0);
-            let mut material = String::new();
+fn read_adaptive(value: JsonValue) -> Option<AdaptiveSampling> {
+    let mut result = AdaptiveSampling {
+        min_samples: 0,
+        max_samples: 64,
+        threshold: 0.02,
+    };
 
+    match value {
+        JsonValue::Boolean(enabled) => {
+            if !enabled {
+                return None;
+            }
+        }
+        JsonValue::Object(fields) => {
             for f in fields {
-                if f.0 == "file" {
-                    if let JsonValue::String(s) = f.1 {
-                        println!("Loading voxel mesh: '{}'", s);
-                        voxels = vox::read_voxels(s.as_str());
+                if f.0 == "min_samples" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.min_samples = num as u32;
                     }
-                } else if f.0 == "translation" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    translation = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
-                    };
-                } else if f.0 == "scale" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    scale = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
-                    };
-                } else if f.0 == "rotation" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    rotation = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
-                    };
-                } else if f.0 == "material" {
-                    if let JsonValue::String(s) = f.1 {
-                        material = s;
+                } else if f.0 == "max_samples" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.max_samples = num as u32;
+                    }
+                } else if f.0 == "threshold" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.threshold = num;
                     }
                 }
             }
+        }
+        _ => return None,
+    }
 
-            let voxels = voxels.unwrap();
-            println!("Loaded {} voxels", voxels.data.len());
+    Some(result)
+}
 
-            let v = Voxels {
-                translation,
-                rotation,
-                scale,
-                material,
-                voxels
-            };
+fn read_denoise(value: JsonValue) -> Option<Denoise> {
+    let mut result = Denoise {
+        radius: 3,
+        color_sigma: 0.2,
+        normal_sigma: 0.3,
+        albedo_sigma: 0.1,
+    };
 
-            result.push(v);
+    match value {
+        JsonValue::Boolean(enabled) => {
+            if !enabled {
+                return None;
+            }
+        }
+        JsonValue::Object(fields) => {
+            for f in fields {
+                if f.0 == "radius" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.radius = num as u32;
+                    }
+                } else if f.0 == "color_sigma" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.color_sigma = num;
+                    }
+                } else if f.0 == "normal_sigma" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.normal_sigma = num;
+                    }
+                } else if f.0 == "albedo_sigma" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.albedo_sigma = num;
+                    }
+                }
+            }
         }
+        _ => return None,
     }
 
-    result
+    Some(result)
 }
 
-fn read_lights(lights: Vec<JsonValue>) -> Vec<Light> {
-    let mut result = Vec::new();
-
-    for light in lights {
-        if let JsonValue::Object(fields) = light {
-            let mut ltype = LightType::Point;
-            let mut position = Vector4F {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 1.0,
-            };
-            let mut color = Color {
-                r: 1.0,
-                g: 1.0,
-                b: 1.0,
-            };
-            let mut radius = 1.0;
-            let mut visible = false;
-            let mut samples = 1;
-            let mut intensity = 1.0;
+//Reads either a boolean to enable shadow sprites with default values, or an object with ground,
+//samples and distance.
+fn read_shadow_sprite(value: JsonValue) -> Option<ShadowSprite> {
+    let mut result = ShadowSprite {
+        ground: None,
+        samples: 4,
+        distance: 1.0,
+    };
 
+    match value {
+        JsonValue::Boolean(enabled) => {
+            if !enabled {
+                return None;
+            }
+        }
+        JsonValue::Object(fields) => {
             for f in fields {
-                if f.0 == "type" {
-                    if let JsonValue::String(t) = f.1 {
-                        let ts = t.trim().to_lowercase();
-                        if ts == "point" {
-                            ltype = LightType::Point;
-                        } else if ts == "sphere" {
-                            ltype = LightType::Sphere;
-                        } else {
-                            let mut message = String::new();
-                            message.push_str("Unknown light type: ");
-                            message.push_str(ts.as_str());
-                            panic!(message);
-                        }
+                if f.0 == "ground" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.ground = Some(num);
                     }
-                } else if f.0 == "position" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    position = Vector4F {
-                        x: values.0,
-                        y: values.1,
-                        z: values.2,
-                        w: 1.0,
-                    };
-                } else if f.0 == "color" {
-                    let values = read_number_triplet(&f.1).unwrap();
-                    color = Color {
-                        r: values.0 as f32,
-                        g: values.1 as f32,
-                        b: values.2 as f32,
+                } else if f.0 == "samples" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.samples = num as u32;
                     }
-                } else if f.0 == "radius" {
-                    if let JsonValue::Number(rad) = f.1 {
-                        radius = rad;
+                } else if f.0 == "distance" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.distance = num;
                     }
-                } else if f.0 == "samples" {
-                    if let JsonValue::Number(sm) = f.1 {
-                        samples = sm as u32;
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    Some(result)
+}
+
+//Reads either the name of a preset or an object with min_bounces, max_bounces and transmission.
+fn read_light_paths(value: JsonValue) -> LightPaths {
+    let mut result = LightPaths::all();
+
+    match value {
+        JsonValue::String(preset) => match preset.as_str() {
+            "all" => {}
+            "background" => result.max_bounces = 0,
+            "direct" => {
+                result.min_bounces = 1;
+                result.max_bounces = 1;
+            }
+            "indirect" => result.min_bounces = 2,
+            "first_bounce" => {
+                result.min_bounces = 2;
+                result.max_bounces = 2;
+            }
+            "transmission" => result.transmission = PathTransmission::Only,
+            _ => panic!("Unknown light paths: {}", preset),
+        },
+        JsonValue::Object(fields) => {
+            for f in fields {
+                if f.0 == "min_bounces" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.min_bounces = num as u32;
                     }
-                } else if f.0 == "visible" {
-                    if let JsonValue::Boolean(b) = f.1 {
-                        visible = b;
+                } else if f.0 == "max_bounces" {
+                    if let JsonValue::Number(num) = f.1 {
+                        result.max_bounces = num as u32;
                     }
-                } else if f.0 == "intensity" {
-                    if let JsonValue::Number(int) = f.1 {
-                        intensity = int;
+                } else if f.0 == "transmission" {
+                    if let JsonValue::String(st) = f.1 {
+                        result.transmission = match st.as_str() {
+                            "any" => PathTransmission::Any,
+                            "only" => PathTransmission::Only,
+                            "excluded" => PathTransmission::Excluded,
+                            _ => panic!("Unknown transmission: {}", st),
+                        };
                     }
                 }
             }
+        }
+        _ => {}
+    }
 
-            result.push(Light {
-                ltype,
-                position,
-                color,
-                visible,
-                radius,
-                samples,
-                intensity,
-            });
+    result
+}
+
+fn read_aovs(values: Vec<JsonValue>) -> Vec<Aov> {
+    let mut result = Vec::new();
+
+    for value in values {
+        if let JsonValue::String(name) = value {
+            let ns = name.trim().to_lowercase();
+            if ns == "ao" {
+                result.push(Aov::AmbientOcclusion);
+            } else if ns == "curvature" {
+                result.push(Aov::Curvature);
+            } else if ns == "position" {
+                result.push(Aov::Position);
+            } else if ns == "depth" {
+                result.push(Aov::Depth);
+            } else if ns == "normal" {
+                result.push(Aov::Normal);
+            } else if ns == "albedo" {
+                result.push(Aov::Albedo);
+            } else if ns == "direct" {
+                result.push(Aov::Direct);
+            } else if ns == "indirect" {
+                result.push(Aov::Indirect);
+            } else if ns == "alpha" {
+                result.push(Aov::Alpha);
+            } else if ns == "object_id" {
+                result.push(Aov::ObjectId);
+            } else if ns == "material_id" {
+                result.push(Aov::MaterialId);
+            } else {
+                panic!("Unknown AOV: {}", ns);
+            }
         }
     }
 
     result
 }
 
-fn read_output(output: JsonValue) -> Option<Output> {
-    if let JsonValue::Object(fields) = output {
-        let mut filename = String::from("render.tga");
-        let mut width = 1920;
-        let mut height = 1080;
-        let mut samples = 1;
+fn read_bake(bake: JsonValue) -> Option<Bake> {
+    if let JsonValue::Object(fields) = bake {
+        let mut result = Bake {
+            low: String::new(),
+            high: String::new(),
+            size: 1024,
+            distance: 0.1,
+            normal_map: String::new(),
+            ao_map: String::new(),
+            uv_set: 1,
+        };
 
         for f in fields {
-            if f.0 == "file" {
+            if f.0 == "low" {
+                if let JsonValue::String(s) = f.1 {
+                    result.low = s;
+                }
+            } else if f.0 == "high" {
+                if let JsonValue::String(s) = f.1 {
+                    result.high = s;
+                }
+            } else if f.0 == "size" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.size = num as u32;
+                }
+            } else if f.0 == "distance" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.distance = num;
+                }
+            } else if f.0 == "normal_map" {
+                if let JsonValue::String(s) = f.1 {
+                    result.normal_map = s;
+                }
+            } else if f.0 == "ao_map" {
+                if let JsonValue::String(s) = f.1 {
+                    result.ao_map = s;
+                }
+            } else if f.0 == "uv_set" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.uv_set = num as u32;
+                }
+            }
+        }
+
+        return Some(result);
+    }
+
+    None
+}
+
+fn read_camera(camera: JsonValue) -> Option<Camera> {
+    if let JsonValue::Object(fields) = camera {
+        let mut result = Camera::new();
+
+        for f in fields {
+            if f.0 == "position" {
+                let values = read_number_triplet(&f.1).unwrap();
+                result.position = Vector4F::new(values.0, values.1, values.2);
+            } else if f.0 == "projection" {
                 if let JsonValue::String(st) = f.1 {
-                    filename = st;
+                    result.projection = match st.as_str() {
+                        "perspective" => Projection::Perspective,
+                        "ods" => Projection::Ods,
+                        _ => panic!("Unknown projection: {}", st),
+                    };
                 }
-            } else if f.0 == "width" {
+            } else if f.0 == "eye_separation" {
                 if let JsonValue::Number(num) = f.1 {
-                    width = num as u32;
+                    result.eye_separation = num;
                 }
-            } else if f.0 == "height" {
+            } else if f.0 == "fov" {
                 if let JsonValue::Number(num) = f.1 {
-                    height = num as u32;
+                    result.fov = Some(num);
                 }
-            } else if f.0 == "samples" {
+            } else if f.0 == "frame" {
+                if let JsonValue::String(target) = f.1 {
+                    result.frame = Some(target);
+                }
+            } else if f.0 == "aperture" {
                 if let JsonValue::Number(num) = f.1 {
-                    samples = num as u32;
+                    result.aperture = num;
+                }
+            } else if f.0 == "focus_distance" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.focus_distance = num;
+                }
+            } else if f.0 == "lens_samples" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.lens_samples = num as u32;
+                }
+            } else if f.0 == "iso" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.iso = Some(num);
+                }
+            } else if f.0 == "shutter" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.shutter = Some(num);
+                }
+            } else if f.0 == "f_number" {
+                if let JsonValue::Number(num) = f.1 {
+                    result.f_number = Some(num);
                 }
             }
         }
 
-        return Some(Output {
-            filename,
-            width,
-            height,
-            samples,
-        });
+        //Without aperture all lens samples would be identical
+        if result.aperture <= 0.0 {
+            result.lens_samples = 1;
+        }
+
+        return Some(result);
     }
 
     None
@@ -94,3 +94,68 @@ pub fn shade_cook_torrance(
 
     g * f * d / f64::max(PI * vdotn * ldotn, 0.000001)
 }
+
+//"Charlie" sheen distribution by Estevez and Kulla with the visibility term of Neubelt and
+//Pettineo, for cloth. Unlike the other functions this returns the BRDF without the cosine term.
+pub fn shade_sheen(l: &Vector4F, v: &Vector4F, n: &Vector4F, rough: f64) -> f64 {
+    let ndotl = Vector4F::dot(n, l);
+    let ndotv = Vector4F::dot(n, v);
+    if ndotl <= 0.0 || ndotv <= 0.0 {
+        return 0.0;
+    }
+
+    let h = Vector4F::half(l, v);
+    let ndoth = saturate(Vector4F::dot(n, &h));
+    let inv_alpha = 1.0 / f64::max(rough * rough, 0.0001);
+    let sin2 = 1.0 - ndoth * ndoth;
+    let d = (2.0 + inv_alpha) * sin2.powf(inv_alpha * 0.5) / (2.0 * PI);
+
+    let vis = 1.0 / (4.0 * (ndotl + ndotv - ndotl * ndotv));
+
+    d * vis
+}
+
+//Roughness of a microfacet lobe that matches a Phong lobe with the given specular exponent, like
+//"Ns" of MTL files. The OBJ loader does not read MTL files, the exponent comes from the scene.
+//Roughness is squared to get the width of the distribution (alpha), and a Phong exponent n has
+//about the width sqrt(2 / (n + 2)) (Walter et al.). Exponents below 0 count as 0, which is the
+//roughest surface.
+pub fn phong_roughness(exponent: f64) -> f64 {
+    let alpha = (2.0 / (exponent.max(0.0) + 2.0)).sqrt();
+    alpha.sqrt()
+}
+
+//Reflectance of a thin film of the given thickness (nanometers) and IOR on a surface with IOR
+//n_base, seen from air at the angle with the given cosine. Light reflected at the top and the
+//bottom of the film interferes, which depends on the wavelength (nanometers). Returns the
+//average of s and p polarized light.
+pub fn thin_film_reflectance(
+    cos_view: f64,
+    thickness: f64,
+    n_film: f64,
+    n_base: f64,
+    wavelength: f64,
+) -> f64 {
+    let cos1 = saturate(cos_view);
+    let sin1_2 = 1.0 - cos1 * cos1;
+    let cos2 = (1.0 - sin1_2 / (n_film * n_film)).max(0.0).sqrt();
+    let sin3_2 = sin1_2 / (n_base * n_base);
+    if sin3_2 >= 1.0 {
+        //Total internal reflection at the bottom of the film
+        return 1.0;
+    }
+    let cos3 = (1.0 - sin3_2).sqrt();
+
+    let phase = 4.0 * PI * n_film * thickness * cos2 / wavelength;
+    let airy = |r12: f64, r23: f64| {
+        let c = 2.0 * r12 * r23 * phase.cos();
+        (r12 * r12 + r23 * r23 + c) / (1.0 + r12 * r12 * r23 * r23 + c)
+    };
+
+    let r12s = (cos1 - n_film * cos2) / (cos1 + n_film * cos2);
+    let r23s = (n_film * cos2 - n_base * cos3) / (n_film * cos2 + n_base * cos3);
+    let r12p = (n_film * cos1 - cos2) / (n_film * cos1 + cos2);
+    let r23p = (n_base * cos2 - n_film * cos3) / (n_base * cos2 + n_film * cos3);
+
+    (airy(r12s, r23s) + airy(r12p, r23p)) * 0.5
+}
@@ -0,0 +1,142 @@
+use environment::Environment;
+use linear::Vector4F;
+use settings::Color;
+use settings::Light;
+use settings::LightType;
+use std::f64::consts::PI;
+
+//Size of the environment map the sky is rendered into. The sky changes slowly except around the
+//sun, which is a directional light and not part of the map.
+const SKY_WIDTH: u32 = 256;
+const SKY_HEIGHT: u32 = 128;
+//Converts the luminance of the model in kcd/m^2 to radiance, so a clear sky at noon has a zenith
+//radiance of about 0.5
+const LUMINANCE_SCALE: f64 = 0.05;
+
+//Clear sky by the analytic model of Preetham, Shirley and Smits, "A Practical Analytic Model for
+//Daylight". The sky gets brighter and warmer towards the sun and the horizon. Below the horizon
+//is the ground color.
+pub struct Sky {
+    //Haziness of the air, 2 is very clear, 10 is hazy
+    pub turbidity: f64,
+    //Direction to the sun, None to use the first directional light of the scene
+    pub sun: Option<Vector4F>,
+    pub ground: Color,
+    pub intensity: f64,
+    pub samples: u32,
+}
+
+impl Sky {
+    //Renders the sky into an environment map. Without a sun direction and directional light, the
+    //sun is at 45 degrees above the horizon in front.
+    pub fn environment(&self, lights: &[Light]) -> Environment {
+        let sun = self
+            .sun
+            .as_ref()
+            .map(|sun| sun.clone())
+            .or_else(|| {
+                lights
+                    .iter()
+                    .find(|l| l.ltype == LightType::Directional)
+                    .map(|l| l.direction.invert())
+            })
+            .unwrap_or_else(|| Vector4F::new(0.0, 1.0, 1.0))
+            .normalize();
+
+        //Theta is measured from the zenith, the model is not defined for a sun below the horizon
+        let theta_sun = sun.y.clamp(-1.0, 1.0).acos().min(PI * 0.5 - 0.01);
+        let t = self.turbidity;
+        let perez_y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_yc = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+        let (zenith_y, zenith_x, zenith_yc) = zenith(t, theta_sun);
+
+        Environment::procedural(
+            SKY_WIDTH,
+            SKY_HEIGHT,
+            self.intensity,
+            0.0,
+            self.samples,
+            |dir| {
+                if dir.y <= 0.0 {
+                    return self.ground.clone();
+                }
+                let theta = dir.y.min(1.0).acos();
+                let cos_gamma = Vector4F::dot(dir, &sun).clamp(-1.0, 1.0);
+                let gamma = cos_gamma.acos();
+
+                let luminance = zenith_y * perez(&perez_y, theta, gamma, theta_sun);
+                let x = zenith_x * perez(&perez_x, theta, gamma, theta_sun);
+                let y = zenith_yc * perez(&perez_yc, theta, gamma, theta_sun);
+                xyy_to_rgb(x, y, luminance * LUMINANCE_SCALE)
+            },
+        )
+    }
+}
+
+//Luminance in kcd/m^2 and chromaticity x and y of the zenith for the sun at theta_sun
+fn zenith(t: f64, theta_sun: f64) -> (f64, f64, f64) {
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+    let luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+
+    let (s1, s2, s3) = (
+        theta_sun,
+        theta_sun * theta_sun,
+        theta_sun * theta_sun * theta_sun,
+    );
+    let t2 = t * t;
+    let x = t2 * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+        + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+        + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+    let y = t2 * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+        + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+        + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+    (luminance, x, y)
+}
+
+//Perez distribution at view angle theta from the zenith and gamma from the sun, relative to the
+//zenith
+fn perez(c: &[f64; 5], theta: f64, gamma: f64, theta_sun: f64) -> f64 {
+    let f = |theta: f64, gamma: f64| {
+        //Directions at the horizon would divide by zero
+        let cos_theta = theta.cos().max(0.01);
+        let cos_gamma = gamma.cos();
+        (1.0 + c[0] * (c[1] / cos_theta).exp())
+            * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+    };
+    f(theta, gamma) / f(0.0, theta_sun)
+}
+
+//Converts chromaticity and luminance to linear sRGB
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::black();
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    let cy = luminance;
+    Color::new(
+        (3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0) as f32,
+        (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0) as f32,
+        (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0) as f32,
+    )
+}
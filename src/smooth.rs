@@ -0,0 +1,50 @@
+use linear::Vector4F;
+use linear::Vertex4F;
+use std::collections::HashMap;
+
+//Replaces the normals of the triangles, three vertices per triangle as loaded from OBJ files, with
+//smooth normals. The normal of a corner is the area weighted average of the face normals of all
+//triangles at the same position that are bent less than max_angle (degrees) against its own
+//triangle. Sharper edges stay creased.
+pub fn smooth_normals(vertices: &mut [Vertex4F], max_angle: f64) {
+    let num_tris = vertices.len() / 3;
+    let min_cos = max_angle.to_radians().cos();
+
+    //Not normalized, the length is twice the area of the triangle. Same orientation as the face
+    //normals of the OBJ loader.
+    let mut faces = Vec::with_capacity(num_tris);
+    for t in 0..num_tris {
+        let edge1 = &vertices[t * 3].pos - &vertices[t * 3 + 1].pos;
+        let edge2 = &vertices[t * 3 + 2].pos - &vertices[t * 3 + 1].pos;
+        faces.push(Vector4F::cross(&edge2, &edge1));
+    }
+
+    //Triangles at each position
+    let mut shared = HashMap::new();
+    for (i, v) in vertices.iter().enumerate() {
+        let key = (v.pos.x.to_bits(), v.pos.y.to_bits(), v.pos.z.to_bits());
+        shared.entry(key).or_insert_with(Vec::new).push(i / 3);
+    }
+
+    for (i, v) in vertices.iter_mut().enumerate() {
+        let own = &faces[i / 3];
+        if own.len() <= 0.0 {
+            continue;
+        }
+        let own_dir = own.normalize();
+
+        let key = (v.pos.x.to_bits(), v.pos.y.to_bits(), v.pos.z.to_bits());
+        let mut sum = Vector4F::null();
+        for t in &shared[&key] {
+            let face = &faces[*t];
+            if face.len() <= 0.0 {
+                continue;
+            }
+            if *t == i / 3 || Vector4F::dot(&own_dir, &face.normalize()) >= min_cos {
+                sum = &sum + face;
+            }
+        }
+
+        v.normal = sum.normalize();
+    }
+}
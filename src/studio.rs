@@ -0,0 +1,101 @@
+use linear::Vector4F;
+use settings::find_material;
+use settings::Backdrop;
+use settings::Color;
+use settings::Falloff;
+use settings::Light;
+use settings::LightType;
+use settings::Material;
+use settings::Plane;
+use settings::Scene;
+
+const GROUND_MATERIAL: &str = "studio_ground";
+
+//Sets up a photo studio around the objects of the scene: a ground below them, a gradient backdrop
+//and key, fill and rim lights. The camera is at the origin looking along +Z, the lights are placed
+//relative to that, scaled to the size of the objects.
+pub fn setup(scene: &mut Scene) {
+    //Scenes without objects get a unit box in front of the camera
+    let (min, max) = scene
+        .bounds_of("all")
+        .unwrap_or((Vector4F::new(-0.5, -0.5, 4.5), Vector4F::new(0.5, 0.5, 5.5)));
+    let center = Vector4F::new(
+        (min.x + max.x) * 0.5,
+        (min.y + max.y) * 0.5,
+        (min.z + max.z) * 0.5,
+    );
+    let size = ((&max - &min).len() * 0.5).max(0.001);
+
+    //Scenes can bring their own ground material, otherwise it is a light grey
+    if find_material(&scene.materials, GROUND_MATERIAL) == scene.materials.len() {
+        scene.materials.push(Material {
+            id: GROUND_MATERIAL.to_string(),
+            color: Color::new(0.8, 0.8, 0.8),
+            reflect: 0.0,
+            refract: 0.0,
+            ior: 1.0,
+            roughness: 0.8,
+            opacity: 1.0,
+            thin: false,
+            sheen: Color::black(),
+            sheen_roughness: 0.3,
+            film_thickness: 0.0,
+            film_ior: 1.33,
+            edge_radius: 0.0,
+            hue_jitter: 0.0,
+            value_jitter: 0.0,
+            variation_seed: 0,
+            dirt: None,
+        });
+    }
+
+    let mut ground = Plane::new(
+        GROUND_MATERIAL.to_string(),
+        Vector4F::new(center.x, min.y, center.z),
+        Vector4F::new(0.0, 1.0, 0.0),
+        0.0,
+        0.0,
+        GROUND_MATERIAL.to_string(),
+    );
+    ground.material_index = find_material(&scene.materials, GROUND_MATERIAL);
+    scene.planes.push(ground);
+
+    //Key light in front to the left, a dimmer fill light in front to the right and a rim light
+    //behind the objects. Values are the irradiance caused at the center of the objects.
+    let lights = [
+        (Vector4F::new(-0.7, 0.6, -0.7), 1.0),
+        (Vector4F::new(0.8, 0.2, -0.6), 0.4),
+        (Vector4F::new(0.3, 0.8, 1.0), 0.7),
+    ];
+    for (dir, irradiance) in &lights {
+        let dist = size * 3.0;
+        let radius = size * 0.5;
+        let dir = dir.normalize();
+
+        scene.lights.push(Light {
+            ltype: LightType::Sphere,
+            position: Vector4F::new(
+                center.x + dir.x * dist,
+                center.y + dir.y * dist,
+                center.z + dir.z * dist,
+            ),
+            direction: Vector4F::null(),
+            angular_radius: 0.0,
+            color: Color::white(),
+            visible: false,
+            radius,
+            samples: 4,
+            //Irradiance falls off with the squared distance in relation to the radius
+            intensity: irradiance * (dist / radius) * (dist / radius),
+            reference: radius,
+            falloff: Falloff::InverseSquare,
+            animation: None,
+        });
+    }
+
+    scene.backdrop = Some(Backdrop {
+        top: Color::new(0.35, 0.35, 0.4),
+        horizon: Color::new(0.625, 0.625, 0.65),
+        bottom: Color::new(0.9, 0.9, 0.9),
+    });
+}
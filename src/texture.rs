@@ -0,0 +1,177 @@
+use settings::Color;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use tga;
+
+//RGBA image that can be sampled with texture coordinates.
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+    pub alpha: Vec<f32>,
+}
+
+impl Texture {
+    //Loads a texture from a TGA file. A missing file, which only gets here when loading
+    //leniently, gives a magenta checker.
+    pub fn load(file_name: &str) -> Texture {
+        if !Path::new(file_name).is_file() {
+            return Texture::checker();
+        }
+
+        let (width, height, pixels, alpha) = tga::read_tga(file_name);
+        eprintln!("Loaded texture '{}': {}x{}", file_name, width, height);
+
+        Texture {
+            width,
+            height,
+            pixels,
+            alpha,
+        }
+    }
+
+    //8x8 magenta and black checker board
+    pub fn checker() -> Texture {
+        let mut pixels = Vec::with_capacity(64);
+        for y in 0..8 {
+            for x in 0..8 {
+                if (x + y) % 2 == 0 {
+                    pixels.push(Color::new(1.0, 0.0, 1.0));
+                } else {
+                    pixels.push(Color::black());
+                }
+            }
+        }
+
+        Texture {
+            width: 8,
+            height: 8,
+            pixels,
+            alpha: vec![1.0; 64],
+        }
+    }
+
+    //Returns the number of bytes used by the pixels of the texture.
+    pub fn memory_size(&self) -> usize {
+        self.pixels.len() * mem::size_of::<Color>() + self.alpha.len() * mem::size_of::<f32>()
+    }
+
+    //Returns color and alpha at the given texture coordinates. (0, 0) is the bottom left corner
+    //of the image. Coordinates outside of 0.0...1.0 wrap around.
+    pub fn sample(&self, u: f64, v: f64) -> (Color, f32) {
+        let fu = u - u.floor();
+        let fv = v - v.floor();
+
+        let x = ((fu * self.width as f64) as u32).min(self.width - 1);
+        let y = ((fv * self.height as f64) as u32).min(self.height - 1);
+
+        //Pixel rows are stored from top to bottom
+        let index = ((self.height - 1 - y) * self.width + x) as usize;
+        (self.pixels[index].clone(), self.alpha[index])
+    }
+}
+
+//Holds all image textures of a scene. Textures are loaded when they are sampled for the
+//first time. If the loaded textures need more memory than the budget, the least recently
+//used ones are dropped and loaded again when needed.
+pub struct TextureCache {
+    //Maximum memory used by loaded textures in bytes
+    pub memory_budget: usize,
+    files: Vec<String>,
+    //One slot per file, indexed like files
+    slots: Vec<Slot>,
+    //Counts the lookups, to know which texture was used least recently
+    clock: AtomicU64,
+    //Bytes used by the loaded textures. Locked while a texture is added or dropped.
+    memory_used: Mutex<usize>,
+}
+
+struct Slot {
+    //None if not loaded (yet or anymore). Only locked for writing when the texture is added or
+    //dropped, so lookups of loaded textures don't wait for each other.
+    texture: RwLock<Option<Arc<Texture>>>,
+    //Value of the clock when the texture was last used
+    last_used: AtomicU64,
+    //Held while the texture is loaded, so it is never loaded twice when several threads need it
+    //at the same time. Other textures can still be used and loaded meanwhile.
+    loading: Mutex<()>,
+}
+
+impl TextureCache {
+    pub fn new(memory_budget: usize) -> TextureCache {
+        TextureCache {
+            memory_budget,
+            files: Vec::new(),
+            slots: Vec::new(),
+            clock: AtomicU64::new(0),
+            memory_used: Mutex::new(0),
+        }
+    }
+
+    //Registers a texture file without loading it. Returns the id used to get the texture.
+    //Adding the same file twice returns the same id.
+    pub fn add(&mut self, file_name: &str) -> usize {
+        if let Some(id) = self.files.iter().position(|f| f == file_name) {
+            return id;
+        }
+
+        self.slots.push(Slot {
+            texture: RwLock::new(None),
+            last_used: AtomicU64::new(0),
+            loading: Mutex::new(()),
+        });
+        self.files.push(file_name.to_string());
+        self.files.len() - 1
+    }
+
+    //Returns the texture with the given id, loading it if needed.
+    pub fn get(&self, id: usize) -> Arc<Texture> {
+        let slot = &self.slots[id];
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        slot.last_used.store(now, Ordering::Relaxed);
+
+        if let Some(ref texture) = *slot.texture.read().unwrap() {
+            return texture.clone();
+        }
+
+        //Another thread may have loaded the texture while this one waited for the lock
+        let _loading = slot.loading.lock().unwrap();
+        if let Some(ref texture) = *slot.texture.read().unwrap() {
+            return texture.clone();
+        }
+
+        let texture = Arc::new(Texture::load(self.files[id].as_str()));
+        let size = texture.memory_size();
+
+        //Drop least recently used textures until the new one fits. Threads that still
+        //use a dropped texture keep it alive until they are done.
+        let mut memory_used = self.memory_used.lock().unwrap();
+        while *memory_used + size > self.memory_budget {
+            let lru = self
+                .slots
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.texture.read().unwrap().is_some())
+                .min_by_key(|(_, s)| s.last_used.load(Ordering::Relaxed))
+                .map(|(i, _)| i);
+
+            match lru {
+                Some(l) => {
+                    if let Some(dropped) = self.slots[l].texture.write().unwrap().take() {
+                        *memory_used -= dropped.memory_size();
+                    }
+                }
+                None => break,
+            }
+        }
+
+        *memory_used += size;
+        *slot.texture.write().unwrap() = Some(texture.clone());
+        texture
+    }
+}
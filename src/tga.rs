@@ -1,6 +1,40 @@
+use settings::ChannelOrder;
+use settings::Color;
 use std::fs::File;
+use std::io::Read;
 use std::io::Write;
 
+//Largest number of pixels in one run length encoded packet
+const MAX_PACKET: usize = 128;
+
+//How write_tga_with_options() stores the pixels
+#[derive(Clone, Copy)]
+pub struct TgaOptions {
+    //Pixels with an alpha channel, passed in the order BGRABGRA... instead of BGR
+    pub alpha: bool,
+    //Run length encoded (image type 10), much smaller for images with flat areas
+    pub rle: bool,
+    //Stores the top row first and marks it in the descriptor. Pixels are always passed with the
+    //bottom row first. Some viewers ignore the descriptor and show bottom up images flipped.
+    pub top_down: bool,
+    //Order of the color channels in the file
+    pub order: ChannelOrder,
+    //8 bits per channel for 24 or 32 bit pixels, or 5 for 16 bit pixels with a single alpha bit
+    pub bits_per_channel: u32,
+}
+
+impl Default for TgaOptions {
+    fn default() -> TgaOptions {
+        TgaOptions {
+            alpha: false,
+            rle: false,
+            top_down: false,
+            order: ChannelOrder::Bgr,
+            bits_per_channel: 8,
+        }
+    }
+}
+
 //Write image data to simple TGA file with RGB pixels.
 //Spec taken from http://paulbourke.net/dataformats/tga/
 //
@@ -9,14 +43,44 @@ use std::io::Write;
 //height: The height of the image in pixels
 //pixels: The raw pixel data, the pixel value must be us order BGRBGRBGRBGR...
 pub fn write_tga(filename: &str, width: u16, height: u16, pixels: &[u8]) {
+    write_tga_with_id(filename, width, height, "", pixels);
+}
+
+//Same as write_tga, but also stores a text in the image ID field of the file. Image viewers
+//ignore the ID, so it can hold metadata. The text is cut off after 255 bytes.
+pub fn write_tga_with_id(filename: &str, width: u16, height: u16, id: &str, pixels: &[u8]) {
+    let options = TgaOptions::default();
+    write_tga_with_options(filename, width, height, id, pixels, &options);
+}
+
+//Same as write_tga_with_id, with the pixel format, compression and row order set by the options.
+//Pixels are passed as 8 bit BGR(A) like for write_tga and converted to the format of the options.
+pub fn write_tga_with_options(
+    filename: &str,
+    width: u16,
+    height: u16,
+    id: &str,
+    pixels: &[u8],
+    options: &TgaOptions,
+) {
     let mut file = File::create(filename).unwrap();
+    let id = &id.as_bytes()[..id.len().min(255)];
+    let (bits, alpha_bits) = match (options.bits_per_channel, options.alpha) {
+        (5, true) => (16, 1),
+        (5, false) => (16, 0),
+        (_, true) => (32, 8),
+        (_, false) => (24, 0),
+    };
+    let pixels = pack_pixels(pixels, options);
+    //Bit 5 of the descriptor is set if the first row is the top row
+    let origin = if options.top_down { 0x20 } else { 0 };
 
     //Size of image ID field. 0 means no ID.
-    file.write_all(&[0 as u8]).unwrap();
+    file.write_all(&[id.len() as u8]).unwrap();
     //Color map type. 0 means to color map
     file.write_all(&[0 as u8]).unwrap();
-    //Image type code, 2 means raw RGB
-    file.write_all(&[2 as u8]).unwrap();
+    //Image type code, 2 means raw RGB, 10 run length encoded RGB
+    file.write_all(&[if options.rle { 10 } else { 2 }]).unwrap();
 
     //Color map origin, not used
     file.write_all(&u16_to_bytes(0 as u16)).unwrap();
@@ -34,15 +98,191 @@ pub fn write_tga(filename: &str, width: u16, height: u16, pixels: &[u8]) {
     //Height of image
     file.write_all(&u16_to_bytes(height)).unwrap();
     //Bits per pixel
-    file.write_all(&[24 as u8]).unwrap();
-    //Image descriptor byte, always 0
-    file.write_all(&[0 as u8]).unwrap();
+    file.write_all(&[bits as u8]).unwrap();
+    //Image descriptor byte, the lower bits are the number of alpha bits per pixel
+    file.write_all(&[(alpha_bits | origin) as u8]).unwrap();
+
+    //Image ID, directly follows the header
+    file.write_all(id).unwrap();
 
-    //Write pixel data
-    file.write_all(pixels).unwrap();
+    //Write pixel data, packets of compressed images don't cross rows
+    let row_bytes = width as usize * (bits / 8);
+    let rows: Vec<&[u8]> = if options.top_down {
+        pixels.chunks(row_bytes).rev().collect()
+    } else {
+        pixels.chunks(row_bytes).collect()
+    };
+    let mut data = Vec::with_capacity(pixels.len());
+    for row in rows {
+        if options.rle {
+            encode_rle(row, bits / 8, &mut data);
+        } else {
+            data.extend_from_slice(row);
+        }
+    }
+    file.write_all(&data).unwrap();
     file.flush().unwrap();
 }
 
+//Converts 8 bit BGR(A) pixels to the channel order and bits per channel of the options. 16 bit
+//pixels hold blue, green and red in 5 bits each from the lowest bit up, and alpha in the highest
+//bit.
+fn pack_pixels(pixels: &[u8], options: &TgaOptions) -> Vec<u8> {
+    if options.order == ChannelOrder::Bgr && options.bits_per_channel == 8 {
+        return pixels.to_vec();
+    }
+
+    let channels = if options.alpha { 4 } else { 3 };
+    let mut result = Vec::with_capacity(pixels.len());
+    for pixel in pixels.chunks(channels) {
+        let (first, third) = match options.order {
+            ChannelOrder::Bgr => (pixel[0], pixel[2]),
+            ChannelOrder::Rgb => (pixel[2], pixel[0]),
+        };
+
+        if options.bits_per_channel == 5 {
+            //The values are dithered for 5 bits already, rounding keeps the dithering
+            let five = |v: u8| ((v as u32 * 31 + 127) / 255) as u16;
+            let mut packed = five(first) | five(pixel[1]) << 5 | five(third) << 10;
+            if options.alpha && pixel[3] >= 128 {
+                packed |= 0x8000;
+            }
+            result.extend_from_slice(&u16_to_bytes(packed));
+        } else {
+            result.extend_from_slice(&[first, pixel[1], third]);
+            if options.alpha {
+                result.push(pixel[3]);
+            }
+        }
+    }
+    result
+}
+
+//Appends the run length encoded pixels to data. Runs of at least two equal pixels become a run
+//packet, everything between them raw packets.
+fn encode_rle(pixels: &[u8], bpp: usize, data: &mut Vec<u8>) {
+    let count = pixels.len() / bpp;
+    let pixel = |i: usize| &pixels[(i * bpp)..((i + 1) * bpp)];
+    let run_length = |start: usize| {
+        let mut end = start + 1;
+        while end < count && end - start < MAX_PACKET && pixel(end) == pixel(start) {
+            end += 1;
+        }
+        end - start
+    };
+
+    let mut i = 0;
+    while i < count {
+        let run = run_length(i);
+        if run >= 2 {
+            data.push(0x80 | (run - 1) as u8);
+            data.extend_from_slice(pixel(i));
+            i += run;
+            continue;
+        }
+
+        let start = i;
+        while i < count && i - start < MAX_PACKET && run_length(i) < 2 {
+            i += 1;
+        }
+        data.push((i - start - 1) as u8);
+        data.extend_from_slice(&pixels[(start * bpp)..(i * bpp)]);
+    }
+}
+
+//Appends count pixels of bpp bytes each, run length encoded in data, to pixels
+fn decode_rle(data: &[u8], bpp: usize, count: usize, pixels: &mut Vec<u8>) {
+    let end = pixels.len() + count * bpp;
+    let mut pos = 0;
+    while pixels.len() < end {
+        let header = data[pos] as usize;
+        pos += 1;
+        let count = (header & 0x7f) + 1;
+
+        if header & 0x80 != 0 {
+            for _i in 0..count {
+                pixels.extend_from_slice(&data[pos..pos + bpp]);
+            }
+            pos += bpp;
+        } else {
+            pixels.extend_from_slice(&data[pos..pos + count * bpp]);
+            pos += count * bpp;
+        }
+    }
+}
+
+//Read image data from a TGA file with 24 or 32 bits per pixel, uncompressed or run length encoded.
+//Pixel values are converted from gamma space to linear colors.
+//
+//filename: The name of the file to read from
+//
+//Returns width, height, colors and alpha values of the pixels in rows from top to bottom.
+pub fn read_tga(filename: &str) -> (u32, u32, Vec<Color>, Vec<f32>) {
+    let mut file = File::open(filename).unwrap();
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+
+    let id_length = data[0] as usize;
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let width = bytes_to_u16(data[12], data[13]) as u32;
+    let height = bytes_to_u16(data[14], data[15]) as u32;
+    let bits_per_pixel = data[16];
+    let descriptor = data[17];
+
+    if color_map_type != 0 || (image_type != 2 && image_type != 10) {
+        panic!("Unsupported TGA image type: {}", image_type);
+    }
+    if bits_per_pixel != 24 && bits_per_pixel != 32 {
+        panic!("Unsupported TGA bits per pixel: {}", bits_per_pixel);
+    }
+
+    let bpp = (bits_per_pixel / 8) as usize;
+    let num_pixels = (width * height) as usize;
+    let pos = 18 + id_length;
+
+    //Decode into raw BGR(A) values in file order
+    let mut raw = Vec::with_capacity(num_pixels * bpp);
+    if image_type == 2 {
+        raw.extend_from_slice(&data[pos..pos + num_pixels * bpp]);
+    } else {
+        decode_rle(&data[pos..], bpp, num_pixels, &mut raw);
+    }
+
+    //Bit 5 of the descriptor is set if the first row is the top row
+    let top_down = descriptor & 0x20 != 0;
+
+    let mut colors = Vec::with_capacity(num_pixels);
+    let mut alpha = Vec::with_capacity(num_pixels);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        for x in 0..width {
+            let i = ((row * width + x) as usize) * bpp;
+            colors.push(Color::new(
+                to_linear(raw[i + 2]),
+                to_linear(raw[i + 1]),
+                to_linear(raw[i]),
+            ));
+            alpha.push(if bpp == 4 {
+                raw[i + 3] as f32 / 255.0
+            } else {
+                1.0
+            });
+        }
+    }
+
+    (width, height, colors, alpha)
+}
+
+//Inverse of the gamma correction applied when writing images.
+fn to_linear(v: u8) -> f32 {
+    (v as f32 / 255.0).powf(1.8)
+}
+
+fn bytes_to_u16(low: u8, high: u8) -> u16 {
+    (low as u16) | ((high as u16) << 8)
+}
+
 fn u16_to_bytes(v: u16) -> [u8; 2] {
     let mut result: [u8; 2] = [0; 2];
 
@@ -51,3 +291,80 @@ fn u16_to_bytes(v: u16) -> [u8; 2] {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(pixels: &[u8], bpp: usize) {
+        let mut data = Vec::new();
+        encode_rle(pixels, bpp, &mut data);
+        let mut decoded = Vec::new();
+        decode_rle(&data, bpp, pixels.len() / bpp, &mut decoded);
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn runs_and_raw_pixels_round_trip() {
+        let mut pixels = Vec::new();
+        for i in 0..20u8 {
+            pixels.extend_from_slice(&[i, i, i]);
+        }
+        for _ in 0..20 {
+            pixels.extend_from_slice(&[7, 8, 9]);
+        }
+        pixels.extend_from_slice(&[1, 2, 3, 1, 2, 3, 4, 5, 6]);
+        round_trip(&pixels, 3);
+        round_trip(&[10, 20, 30, 40], 4);
+    }
+
+    #[test]
+    fn long_runs_are_split_into_packets() {
+        let pixels = vec![5u8; 300 * 3];
+        let mut data = Vec::new();
+        encode_rle(&pixels, 3, &mut data);
+        //128 + 128 + 44 pixels, one header and one pixel each
+        assert_eq!(data.len(), 3 * 4);
+        round_trip(&pixels, 3);
+    }
+
+    #[test]
+    fn long_raw_sequences_are_split_into_packets() {
+        let pixels: Vec<u8> = (0..300u32)
+            .flat_map(|i| vec![i as u8, (i >> 8) as u8])
+            .collect();
+        round_trip(&pixels, 2);
+    }
+
+    #[test]
+    fn rle_files_read_like_uncompressed_ones() {
+        let (width, height) = (5u16, 3u16);
+        let pixels: Vec<u8> = (0..(width * height) as u32)
+            .flat_map(|i| vec![(i / 4) as u8 * 40, 100, 200, 255])
+            .collect();
+        let dir = std::env::temp_dir();
+        let file = |name: &str| {
+            let path = dir.join(format!("xtracer_{}_{}.tga", name, std::process::id()));
+            path.to_str().unwrap().to_string()
+        };
+        let (raw_file, rle_file) = (file("raw"), file("rle"));
+
+        let mut options = TgaOptions {
+            alpha: true,
+            ..TgaOptions::default()
+        };
+        write_tga_with_options(&raw_file, width, height, "", &pixels, &options);
+        options.rle = true;
+        write_tga_with_options(&rle_file, width, height, "", &pixels, &options);
+        let raw = read_tga(&raw_file);
+        let rle = read_tga(&rle_file);
+        std::fs::remove_file(&raw_file).ok();
+        std::fs::remove_file(&rle_file).ok();
+
+        assert_eq!((rle.0, rle.1), (raw.0, raw.1));
+        assert_eq!(rle.3, raw.3);
+        for (a, b) in rle.2.iter().zip(&raw.2) {
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use settings::RenderOrder;
+
+//Width and height of the tiles of the Hilbert and spiral orders, in pixels
+const TILE_SIZE: u32 = 32;
+
+//Rectangle of pixels that a thread renders at once. Lines are counted from the bottom, like while
+//rendering.
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> u32 {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+//Splits an image of the given size into tiles, in the order they are rendered. The scanline
+//order renders every line as its own tile, the others square tiles that are cut off at the right
+//and top of the image.
+pub fn tiles(width: u32, height: u32, order: &RenderOrder) -> Vec<Tile> {
+    if let RenderOrder::Scanline = order {
+        return (0..height)
+            .map(|y| Tile {
+                x: 0,
+                y,
+                width,
+                height: 1,
+            })
+            .collect();
+    }
+
+    let columns = width.div_ceil(TILE_SIZE);
+    let rows = height.div_ceil(TILE_SIZE);
+    let mut result = Vec::with_capacity((columns * rows) as usize);
+    for ty in 0..rows {
+        for tx in 0..columns {
+            let (x, y) = (tx * TILE_SIZE, ty * TILE_SIZE);
+            result.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+
+    match order {
+        RenderOrder::Scanline => {}
+        RenderOrder::Hilbert => {
+            //The curve covers a square grid with a power of two side, tiles outside are skipped
+            let side = columns.max(rows).next_power_of_two();
+            result.sort_by_key(|t| hilbert_index(side, t.x / TILE_SIZE, t.y / TILE_SIZE));
+        }
+        RenderOrder::Spiral => {
+            //Rings of tiles around the center, each ring going around counterclockwise
+            let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+            let key = |t: &Tile| {
+                let dx = (t.x as f64 + t.width as f64 / 2.0) - cx;
+                let dy = (t.y as f64 + t.height as f64 / 2.0) - cy;
+                let ring = ((dx * dx + dy * dy).sqrt() / TILE_SIZE as f64).round();
+                (ring, dy.atan2(dx))
+            };
+            result.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+    }
+
+    result
+}
+
+//Distance along the Hilbert curve through a grid of side * side cells to the cell x, y. Side must
+//be a power of two.
+fn hilbert_index(side: u32, x: u32, y: u32) -> u64 {
+    let (mut x, mut y) = (x, y);
+    let mut result = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as u32;
+        let ry = ((y & s) > 0) as u32;
+        result += (s as u64) * (s as u64) * ((3 * rx) ^ ry) as u64;
+
+        //Rotate the quadrant, so the curve inside of it starts and ends at the right corners
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    result
+}
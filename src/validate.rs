@@ -0,0 +1,511 @@
+use assets;
+use json::JsonValue;
+use settings::DebugView;
+use settings::Falloff;
+use settings::LightType;
+use settings::PixelFilter;
+use settings::Projection;
+use settings::Settings;
+use std::path::Path;
+
+//Checks that all files referenced by the settings are set and exist. This runs on the raw JSON
+//before anything is loaded, so a typo in a file name is reported before big meshes are read.
+pub fn check_files(json: &JsonValue) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let scene = field(json, "scene");
+    if let Some(env) = scene.and_then(|scene| field(scene, "environment")) {
+        if !has_string(env, "file") {
+            problems.push("environment: file must be set".to_string());
+        }
+    }
+    if let Some(JsonValue::Array(decals)) = scene.and_then(|scene| field(scene, "decals")) {
+        for (i, decal) in decals.iter().enumerate() {
+            if !has_string(decal, "texture") {
+                problems.push(format!("decals[{}]: texture must be set", i));
+            }
+        }
+    }
+
+    for (what, file) in referenced_files(json) {
+        //Asset paths that are left could not be resolved, which is already a problem
+        if assets::is_asset_uri(&file) {
+            continue;
+        }
+        if !Path::new(&file).is_file() {
+            problems.push(format!("{}: file not found: {}", what, file));
+        }
+    }
+
+    problems
+}
+
+//True if the scene sets "lenient_loading", so missing files are replaced by placeholders
+//instead of being an error
+pub fn is_lenient(json: &JsonValue) -> bool {
+    match field(json, "scene").and_then(|scene| field(scene, "lenient_loading")) {
+        Some(JsonValue::Boolean(lenient)) => *lenient,
+        _ => false,
+    }
+}
+
+//Lists of objects in the scene and the field with the file each of them loads. The environment
+//map and the texture of the dome are the only files outside of these lists.
+const FILE_LISTS: &[(&str, &str)] = &[
+    ("meshes", "file"),
+    ("voxels", "file"),
+    ("decals", "texture"),
+    ("blockers", "texture"),
+];
+
+//Lists the files the scene loads, with the object referencing them like "meshes[2]"
+pub fn referenced_files(json: &JsonValue) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+
+    let scene = match field(json, "scene") {
+        Some(scene) => scene,
+        None => return result,
+    };
+
+    for (list, key) in FILE_LISTS {
+        if let Some(JsonValue::Array(values)) = field(scene, list) {
+            for (i, value) in values.iter().enumerate() {
+                if let Some(JsonValue::String(file)) = field(value, key) {
+                    result.push((format!("{}[{}]", list, i), file.clone()));
+                }
+            }
+        }
+    }
+
+    if let Some(env) = field(scene, "environment") {
+        if let Some(JsonValue::String(file)) = field(env, "file") {
+            result.push(("environment".to_string(), file.clone()));
+        }
+    }
+    if let Some(dome) = field(scene, "dome") {
+        if let Some(JsonValue::String(file)) = field(dome, "texture") {
+            result.push(("dome".to_string(), file.clone()));
+        }
+    }
+
+    result
+}
+
+//Calls f for every file the scene loads, in the same order as referenced_files(), so the file
+//names can be changed before the settings are read
+pub fn for_each_file(json: &mut JsonValue, f: &mut FnMut(&str, &mut String)) {
+    let scene = match field_mut(json, "scene") {
+        Some(scene) => scene,
+        None => return,
+    };
+
+    for (list, key) in FILE_LISTS {
+        if let Some(JsonValue::Array(values)) = field_mut(scene, list) {
+            for (i, value) in values.iter_mut().enumerate() {
+                if let Some(JsonValue::String(file)) = field_mut(value, key) {
+                    f(&format!("{}[{}]", list, i), file);
+                }
+            }
+        }
+    }
+
+    if let Some(env) = field_mut(scene, "environment") {
+        if let Some(JsonValue::String(file)) = field_mut(env, "file") {
+            f("environment", file);
+        }
+    }
+    if let Some(dome) = field_mut(scene, "dome") {
+        if let Some(JsonValue::String(file)) = field_mut(dome, "texture") {
+            f("dome", file);
+        }
+    }
+}
+
+//Checks the loaded settings for values that would make the render fail or produce garbage.
+//Objects with unknown materials are only reported as problems in strict mode, otherwise a
+//warning is printed and the default material is used.
+pub fn check_settings(settings: &Settings) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let output = &settings.output;
+    if output.filename.is_empty() {
+        problems.push("output: file must be set".to_string());
+    }
+    if output.width == 0 || output.height == 0 {
+        problems.push(format!(
+            "output: width and height must be positive, got {}x{}",
+            output.width, output.height
+        ));
+    }
+    if output.samples == 0 {
+        problems.push("output: samples must be positive".to_string());
+    }
+    if output.passes == 0 {
+        problems.push("output: passes must be positive".to_string());
+    }
+    if output.preview_interval < 0.0 {
+        problems.push("output: preview_interval must not be negative".to_string());
+    }
+    if output.checkpoint_interval < 0.0 {
+        problems.push("output: checkpoint_interval must not be negative".to_string());
+    }
+    if let Some(adaptive) = &output.adaptive {
+        if adaptive.max_samples < adaptive.min_samples {
+            problems
+                .push("output: adaptive max_samples must not be less than min_samples".to_string());
+        }
+        if adaptive.threshold <= 0.0 {
+            problems.push("output: adaptive threshold must be positive".to_string());
+        }
+    }
+    if let Some(denoise) = &output.denoise {
+        if denoise.radius == 0 {
+            problems.push("output: denoise radius must be positive".to_string());
+        }
+        if denoise.color_sigma <= 0.0 || denoise.normal_sigma <= 0.0 || denoise.albedo_sigma <= 0.0
+        {
+            problems.push("output: denoise sigmas must be positive".to_string());
+        }
+    }
+    if let Some(sprite) = &output.shadow_sprite {
+        if sprite.samples == 0 {
+            problems.push("output: shadow_sprite samples must be positive".to_string());
+        }
+        if sprite.distance <= 0.0 {
+            problems.push("output: shadow_sprite distance must be positive".to_string());
+        }
+    }
+    if output.bits_per_channel != 8 && output.bits_per_channel != 5 {
+        problems.push("output: bits_per_channel must be 8 or 5".to_string());
+    }
+    if output.ao_samples == 0 {
+        problems.push("output: ao_samples must be positive".to_string());
+    }
+    if output.ao_distance <= 0.0 {
+        problems.push("output: ao_distance must be positive".to_string());
+    }
+    if output.curvature_radius <= 0.0 {
+        problems.push("output: curvature_radius must be positive".to_string());
+    }
+    if output.edge_depth_threshold <= 0.0 {
+        problems.push("output: edge_depth_threshold must be positive".to_string());
+    }
+    if let Some((x, y)) = output.debug_pixel {
+        if x >= output.width || y >= output.height {
+            problems.push(format!(
+                "output: debug_pixel {}, {} is outside of the image",
+                x, y
+            ));
+        }
+    }
+    match output.filter {
+        PixelFilter::Tent { radius }
+        | PixelFilter::Gaussian { radius }
+        | PixelFilter::Mitchell { radius }
+            if radius <= 0.0 =>
+        {
+            problems.push("output: filter_radius must be positive".to_string());
+        }
+        _ => (),
+    }
+    if let Some(DebugView::Depth { max_distance }) = output.debug_view {
+        if max_distance <= 0.0 {
+            problems.push("output: debug_max_distance must be positive".to_string());
+        }
+    }
+
+    let camera = &settings.camera;
+    if camera.aperture < 0.0 {
+        problems.push("camera: aperture must not be negative".to_string());
+    }
+    if camera.aperture > 0.0 && camera.focus_distance <= 0.0 {
+        problems.push("camera: focus_distance must be positive".to_string());
+    }
+    if let Some(fov) = camera.fov {
+        if fov <= 0.0 || fov >= 180.0 {
+            problems.push("camera: fov must be between 0 and 180 degrees".to_string());
+        }
+    }
+    if let Projection::Ods = camera.projection {
+        if camera.aperture > 0.0 {
+            problems.push("camera: aperture is not supported by the ods projection".to_string());
+        }
+        if camera.eye_separation < 0.0 {
+            problems.push("camera: eye_separation must not be negative".to_string());
+        }
+    }
+    if camera.lens_samples == 0 {
+        problems.push("camera: lens_samples must be positive".to_string());
+    }
+    let exposure = [
+        ("iso", camera.iso),
+        ("shutter", camera.shutter),
+        ("f_number", camera.f_number),
+    ];
+    for (name, value) in &exposure {
+        if let Some(v) = value {
+            if *v <= 0.0 {
+                problems.push(format!("camera: {} must be positive", name));
+            }
+        }
+    }
+
+    let scene = &settings.scene;
+    for (i, mat) in scene.materials.iter().enumerate() {
+        if scene.materials[..i].iter().any(|m| m.id == mat.id) {
+            problems.push(format!("materials: duplicate material id {}", mat.id));
+        }
+        if mat.opacity < 0.0 || mat.opacity > 1.0 {
+            problems.push(format!("materials[{}]: opacity must be between 0 and 1", i));
+        }
+        if mat.hue_jitter < 0.0 || mat.value_jitter < 0.0 {
+            problems.push(format!("materials[{}]: jitter must not be negative", i));
+        }
+        if mat.sheen_roughness <= 0.0 || mat.sheen_roughness > 1.0 {
+            problems.push(format!(
+                "materials[{}]: sheen_roughness must be in (0, 1]",
+                i
+            ));
+        }
+        if let Some(dirt) = &mat.dirt {
+            if dirt.distance <= 0.0 || dirt.samples == 0 {
+                problems.push(format!(
+                    "materials[{}]: dirt distance and samples must be positive",
+                    i
+                ));
+            }
+            if dirt.strength < 0.0 || dirt.strength > 1.0 {
+                problems.push(format!(
+                    "materials[{}]: dirt strength must be between 0 and 1",
+                    i
+                ));
+            }
+        }
+        if mat.edge_radius < 0.0 {
+            problems.push(format!(
+                "materials[{}]: edge_radius must not be negative",
+                i
+            ));
+        }
+        if mat.film_thickness < 0.0 {
+            problems.push(format!(
+                "materials[{}]: film_thickness must not be negative",
+                i
+            ));
+        }
+        if mat.film_thickness > 0.0 && (mat.film_ior < 1.0 || mat.ior < 1.0) {
+            problems.push(format!(
+                "materials[{}]: film_ior and ior must be at least 1",
+                i
+            ));
+        }
+    }
+
+    if let Some(fog) = &scene.fog {
+        if fog.density < 0.0 {
+            problems.push("fog: density must not be negative".to_string());
+        }
+        if fog.falloff < 0.0 {
+            problems.push("fog: falloff must not be negative".to_string());
+        }
+    }
+
+    if let Some(hybrid) = &scene.hybrid_gi {
+        if hybrid.ao_samples == 0 {
+            problems.push("hybrid_gi: ao_samples must be positive".to_string());
+        }
+        if hybrid.ao_distance <= 0.0 {
+            problems.push("hybrid_gi: ao_distance must be positive".to_string());
+        }
+    }
+
+    if scene.light_threshold < 0.0 {
+        problems.push("scene: light_threshold must not be negative".to_string());
+    }
+
+    for (i, sp) in scene.spheres.iter().enumerate() {
+        if sp.radius <= 0.0 {
+            problems.push(format!("spheres[{}]: radius must be positive", i));
+        }
+    }
+
+    for (i, plane) in scene.planes.iter().enumerate() {
+        if plane.normal.len() <= 0.0 {
+            problems.push(format!("planes[{}]: normal must not be zero", i));
+        }
+        if plane.width < 0.0 || plane.height < 0.0 {
+            problems.push(format!("planes[{}]: size must not be negative", i));
+        }
+    }
+
+    for (i, animation) in scene.visibility.iter().enumerate() {
+        if animation.keys.is_empty() {
+            problems.push(format!("visibility[{}]: keys must not be empty", i));
+        }
+        if !scene.objects().iter().any(|o| o.name() == animation.object) {
+            problems.push(format!(
+                "visibility[{}]: object not found: {}",
+                i, animation.object
+            ));
+        }
+    }
+
+    let mut missing = Vec::new();
+    for obj in scene.objects() {
+        if obj.material() >= scene.materials.len() {
+            missing.push(obj.material_name().to_string());
+        }
+    }
+    for plane in &scene.section_planes {
+        if !plane.cap_material.is_empty() && plane.cap_material_index >= scene.materials.len() {
+            missing.push(plane.cap_material.clone());
+        }
+    }
+    missing.sort();
+    missing.dedup();
+
+    for id in missing {
+        if scene.strict_materials {
+            problems.push(format!("Material not found: {}", id));
+        } else {
+            eprintln!("Material not found: {}, using default material", id);
+        }
+    }
+
+    if let Some(bake) = &settings.bake {
+        for name in &[&bake.low, &bake.high] {
+            if !scene.meshes.iter().any(|m| &m.name == *name) {
+                problems.push(format!("bake: mesh not found: {}", name));
+            }
+        }
+        if bake.size == 0 {
+            problems.push("bake: size must be positive".to_string());
+        }
+        if bake.distance <= 0.0 {
+            problems.push("bake: distance must be positive".to_string());
+        }
+        if bake.normal_map.is_empty() && bake.ao_map.is_empty() {
+            problems.push("bake: normal_map or ao_map must be set".to_string());
+        }
+        if bake.uv_set != 1 && bake.uv_set != 2 {
+            problems.push("bake: uv_set must be 1 or 2".to_string());
+        }
+    }
+
+    for (i, light) in scene.lights.iter().enumerate() {
+        if light.intensity < 0.0 {
+            problems.push(format!("lights[{}]: intensity must not be negative", i));
+        }
+        if light.falloff != Falloff::InverseSquare && light.ltype != LightType::Point {
+            problems.push(format!(
+                "lights[{}]: falloff is only used by point lights",
+                i
+            ));
+        }
+        if let Some(animation) = &light.animation {
+            if animation.intensity_keys.iter().any(|k| k.1 < 0.0) {
+                problems.push(format!(
+                    "lights[{}]: animation: intensity must not be negative",
+                    i
+                ));
+            }
+            if animation.temperature_keys.iter().any(|k| k.1 <= 0.0) {
+                problems.push(format!(
+                    "lights[{}]: animation: temperature must be positive",
+                    i
+                ));
+            }
+            if let Some(flicker) = &animation.flicker {
+                if flicker.amount < 0.0 || flicker.speed < 0.0 {
+                    problems.push(format!(
+                        "lights[{}]: animation: flicker amount and speed must not be negative",
+                        i
+                    ));
+                }
+            }
+        }
+        //Point lights are not sampled, so their sample count does not matter
+        match light.ltype {
+            LightType::Point => {
+                if light.reference <= 0.0 {
+                    problems.push(format!("lights[{}]: radius must be positive", i));
+                }
+            }
+            LightType::Sphere => {
+                if light.radius <= 0.0 {
+                    problems.push(format!("lights[{}]: radius must be positive", i));
+                }
+                if light.samples == 0 {
+                    problems.push(format!("lights[{}]: samples must be positive", i));
+                }
+            }
+            LightType::Directional => {
+                if light.direction.len() <= 0.0 {
+                    problems.push(format!("lights[{}]: direction must not be zero", i));
+                }
+                if light.angular_radius < 0.0 || light.angular_radius >= 90.0 {
+                    problems.push(format!(
+                        "lights[{}]: angular_radius must be between 0 and 90 degrees",
+                        i
+                    ));
+                }
+                if light.samples == 0 {
+                    problems.push(format!("lights[{}]: samples must be positive", i));
+                }
+            }
+        }
+    }
+
+    for (i, plane) in scene.section_planes.iter().enumerate() {
+        if plane.normal.len() <= 0.0 {
+            problems.push(format!("section_planes[{}]: normal must not be zero", i));
+        }
+    }
+
+    for (i, decal) in scene.decals.iter().enumerate() {
+        if decal.opacity < 0.0 || decal.opacity > 1.0 {
+            problems.push(format!("decals[{}]: opacity must be between 0 and 1", i));
+        }
+        if decal.uv_set.map_or(false, |set| set != 1 && set != 2) {
+            problems.push(format!("decals[{}]: uv_set must be 1 or 2", i));
+        }
+    }
+
+    for (i, blocker) in scene.blockers.iter().enumerate() {
+        let s = &blocker.scale;
+        if s.x == 0.0 || s.y == 0.0 || s.z == 0.0 {
+            problems.push(format!("blockers[{}]: scale must not be zero", i));
+        }
+    }
+
+    problems
+}
+
+//True if the object has a field with the name that is a string
+fn has_string(value: &JsonValue, name: &str) -> bool {
+    matches!(field(value, name), Some(JsonValue::String(_)))
+}
+
+fn field<'a>(value: &'a JsonValue, name: &str) -> Option<&'a JsonValue> {
+    if let JsonValue::Object(fields) = value {
+        for f in fields {
+            if f.0 == name {
+                return Some(&f.1);
+            }
+        }
+    }
+
+    None
+}
+
+fn field_mut<'a>(value: &'a mut JsonValue, name: &str) -> Option<&'a mut JsonValue> {
+    if let JsonValue::Object(fields) = value {
+        for f in fields {
+            if f.0 == name {
+                return Some(&mut f.1);
+            }
+        }
+    }
+
+    None
+}
@@ -14,7 +14,7 @@ pub struct VoxelObject {
 impl VoxelObject {
     pub fn get(&self, x: u32, y: u32, z: u32) -> &Option<Color> {
         if x >= self.width || y >= self.height || z >= self.depth {
-            println!("Out of bounds: {} {} {}", x, y, z);
+            eprintln!("Out of bounds: {} {} {}", x, y, z);
             panic!("AHH!");
         }
 
@@ -47,7 +47,7 @@ pub fn read_voxels(file_name: &str) -> Option<VoxelObject> {
     assert!(name == "SIZE");
 
     let (sx, sy, sz) = read_size_chunk(&mut file);
-    println!("Voxel model size: {}x{}x{}", sx, sy, sz);
+    eprintln!("Voxel model size: {}x{}x{}", sx, sy, sz);
 
     let (name, _content_bytes, _child_bytes) = read_chunk_header(&mut file);
     assert!(name == "XYZI");
@@ -0,0 +1,141 @@
+use burnin;
+use framebuffer::Framebuffer;
+use pfm;
+use random::Random;
+use settings::Settings;
+use std::path::Path;
+use tga;
+
+//Writes the image of a render to a file, one implementation per file format. The writer for the
+//output settings is picked by image_writer().
+pub trait ImageWriter {
+    //Writes the exposed radiance of the framebuffer to the file. Formats that can store text keep
+    //the metadata in the file, the others drop it.
+    fn write(&self, path: &str, framebuffer: &Framebuffer, metadata: &str);
+}
+
+//Writer for the output file of the settings, by the extension of its name. Files ending with
+//".pfm" get floats, everything else is a TGA file. The burn-in lines are stamped into images that
+//are meant to be looked at, not into data formats.
+pub fn image_writer<'a>(settings: &'a Settings, burn_in: Vec<String>) -> Box<ImageWriter + 'a> {
+    let extension = Path::new(&settings.output.filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    match extension.as_ref().map(String::as_str) {
+        Some("pfm") => Box::new(PfmWriter { settings }),
+        _ => Box::new(TgaWriter { settings, burn_in }),
+    }
+}
+
+//Gamma corrected and dithered TGA in the layout of the output settings, with the alpha AOV as
+//alpha channel if the output has alpha
+pub struct TgaWriter<'a> {
+    settings: &'a Settings,
+    burn_in: Vec<String>,
+}
+
+impl<'a> ImageWriter for TgaWriter<'a> {
+    fn write(&self, path: &str, framebuffer: &Framebuffer, metadata: &str) {
+        let output = &self.settings.output;
+        let (w, h) = (framebuffer.width, framebuffer.height);
+
+        let mut pixels = tga_pixels(self.settings, framebuffer);
+        if let Some(burn_in) = &output.burn_in {
+            if !self.burn_in.is_empty() {
+                burnin::stamp(&mut pixels, w, h, &burn_in.corner, &self.burn_in);
+            }
+        }
+
+        let alpha = output
+            .aovs
+            .iter()
+            .position(|aov| aov.name() == "alpha")
+            .map(|i| &framebuffer.aovs[i]);
+        let (w, h) = (w as u16, h as u16);
+        match alpha {
+            Some(alpha) if output.alpha => {
+                let mut bgra = Vec::with_capacity(alpha.len() * 4);
+                for (bgr, a) in pixels.chunks(3).zip(alpha) {
+                    bgra.extend_from_slice(bgr);
+                    bgra.push((a.clamp(0.0, 1.0) * 255.0).round() as u8);
+                }
+                let options = tga_options(self.settings, true);
+                tga::write_tga_with_options(path, w, h, metadata, bgra.as_slice(), &options);
+            }
+            _ => {
+                let options = tga_options(self.settings, false);
+                tga::write_tga_with_options(path, w, h, metadata, &pixels, &options);
+            }
+        }
+    }
+}
+
+//Exposed radiance as linear floats, for compositing and tools that need more than 8 bits. PFM has
+//no room for metadata or alpha.
+pub struct PfmWriter<'a> {
+    settings: &'a Settings,
+}
+
+impl<'a> ImageWriter for PfmWriter<'a> {
+    fn write(&self, path: &str, framebuffer: &Framebuffer, _metadata: &str) {
+        let exposure = self.settings.camera.exposure() as f32;
+        let num_values = (framebuffer.width * framebuffer.height * 3) as usize;
+        let values: Vec<f32> = (0..num_values)
+            .map(|i| framebuffer.pixels.get(i) * exposure)
+            .collect();
+        pfm::write_pfm(path, framebuffer.width, framebuffer.height, 3, &values);
+    }
+}
+
+//Format of the TGA files written for the output settings
+pub fn tga_options(settings: &Settings, alpha: bool) -> tga::TgaOptions {
+    tga::TgaOptions {
+        alpha,
+        rle: settings.output.rle,
+        top_down: settings.output.top_down,
+        order: settings.output.channel_order,
+        bits_per_channel: settings.output.bits_per_channel,
+    }
+}
+
+//Converts the exposed radiance of the framebuffer to 8 bit values in BGR order, dithered for the
+//bits per channel that are written
+fn tga_pixels(settings: &Settings, framebuffer: &Framebuffer) -> Vec<u8> {
+    let exposure = settings.camera.exposure() as f32;
+    let num_pixels = (framebuffer.width * framebuffer.height) as usize;
+
+    let mut pixels = Vec::with_capacity(num_pixels * 3);
+    let mut rand = Random::with_seed(settings.output.frame_seed());
+    let bits = settings.output.bits_per_channel;
+    for i in 0..num_pixels {
+        let fb = &framebuffer.pixels;
+        pixels.push(convert(fb.get(i * 3 + 2) * exposure, bits, &mut rand));
+        pixels.push(convert(fb.get(i * 3 + 1) * exposure, bits, &mut rand));
+        pixels.push(convert(fb.get(i * 3) * exposure, bits, &mut rand));
+    }
+    pixels
+}
+
+//Converts a linear value to 8 bits, with noise of half a step of the given bits per channel
+fn convert(v: f32, bits: u32, rand: &mut Random) -> u8 {
+    let mut result = v;
+
+    //Gamma correction
+    result = result.powf(1.0 / 1.8);
+
+    //Add some slight random noise to reduce banding
+    let r = (rand.random_f() * 2.0 - 1.0) as f32;
+    result = result + (r * (1.0 / (2u32 << bits) as f32));
+
+    if result < 0.0 {
+        result = 0.0;
+    } else if result > 1.0 {
+        result = 1.0;
+    }
+
+    result = result * 255.0;
+
+    result.round() as u8
+}